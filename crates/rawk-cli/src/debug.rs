@@ -0,0 +1,209 @@
+use std::{io, ops::ControlFlow};
+
+use rawk_core::{DebugContext, Debugger, Evaluator, Value};
+use rustyline::{DefaultEditor, error::ReadlineError};
+
+const PROMPT: &str = "(rawk-debug) ";
+
+const HELP: &str = "\
+break LINE       stop before the statements on LINE (b)
+break FUNCTION   stop when FUNCTION is called
+delete [N]       delete breakpoint N, or all breakpoints
+step             run the next statement, entering function calls (s)
+next             run the next statement, stepping over function calls (n)
+continue         run until a breakpoint (c)
+print $0         show the current record (p); also $N for a field
+print NAME       show a variable or the elements of an array
+backtrace        show the function calls in progress (bt)
+help             show this help
+quit             stop the program (q)";
+
+/// How far to run before stopping again.
+enum Mode {
+    Step,
+    /// Stop once the call stack is no deeper than this.
+    Next(usize),
+    Continue,
+}
+
+enum Breakpoint {
+    Line(usize),
+    Function(String),
+}
+
+/// A debugger that reads commands from the terminal, or from standard input
+/// when it is not one.
+struct CliDebugger<'s> {
+    source: &'s str,
+    editor: DefaultEditor,
+    mode: Mode,
+    breakpoints: Vec<Breakpoint>,
+    depth: usize,
+}
+
+impl Debugger for CliDebugger<'_> {
+    fn before_statement(&mut self, context: &mut DebugContext<'_, '_>) -> ControlFlow<()> {
+        let depth = context.call_stack().len();
+        let called = depth > self.depth;
+        self.depth = depth;
+
+        let line = self.line(context.offset());
+        let stop = match self.mode {
+            Mode::Step => true,
+            Mode::Next(limit) => depth <= limit,
+            Mode::Continue => self.breakpoints.iter().any(|breakpoint| match breakpoint {
+                Breakpoint::Line(number) => *number == line,
+                Breakpoint::Function(name) => {
+                    called
+                        && context
+                            .call_stack()
+                            .last()
+                            .is_some_and(|call| call.function == *name)
+                }
+            }),
+        };
+        if !stop {
+            return ControlFlow::Continue(());
+        }
+
+        // Show what the program printed before stopping, which would
+        // otherwise appear only when it ends.
+        print!("{}", context.take_output());
+        self.show_location(context.offset());
+        loop {
+            let command = match self.editor.readline(PROMPT) {
+                Ok(command) => command,
+                Err(ReadlineError::Interrupted) => continue,
+                // Without more commands, let the program run to the end.
+                Err(_) => {
+                    self.breakpoints.clear();
+                    self.mode = Mode::Continue;
+                    return ControlFlow::Continue(());
+                }
+            };
+            let _ = self.editor.add_history_entry(command.as_str());
+            let mut words = command.split_whitespace();
+            let name = words.next().unwrap_or_default();
+            let argument = words.next();
+
+            match (name, argument) {
+                ("", None) => {}
+                ("step" | "s", None) => {
+                    self.mode = Mode::Step;
+                    return ControlFlow::Continue(());
+                }
+                ("next" | "n", None) => {
+                    self.mode = Mode::Next(depth);
+                    return ControlFlow::Continue(());
+                }
+                ("continue" | "c", None) => {
+                    self.mode = Mode::Continue;
+                    return ControlFlow::Continue(());
+                }
+                ("quit" | "q", None) => return ControlFlow::Break(()),
+                ("break" | "b", Some(location)) => self.add_breakpoint(location),
+                ("delete", None) => self.breakpoints.clear(),
+                ("delete", Some(number)) => match number.parse::<usize>() {
+                    Ok(number) if (1..=self.breakpoints.len()).contains(&number) => {
+                        self.breakpoints.remove(number - 1);
+                    }
+                    _ => eprintln!("rawk: no breakpoint {number}"),
+                },
+                ("print" | "p", Some(name)) => print_value(context, name),
+                ("backtrace" | "bt", None) => self.backtrace(context),
+                ("help", None) => println!("{HELP}"),
+                _ => eprintln!("rawk: unknown command {command}; type help for the commands"),
+            }
+        }
+    }
+}
+
+impl CliDebugger<'_> {
+    /// The line number of `offset` in the source.
+    fn line(&self, offset: usize) -> usize {
+        self.source[..offset].matches('\n').count() + 1
+    }
+
+    fn show_location(&self, offset: usize) {
+        let line = self.line(offset);
+        let text = self.source.lines().nth(line - 1).unwrap_or_default();
+        println!("line {line}: {}", text.trim());
+    }
+
+    fn add_breakpoint(&mut self, location: &str) {
+        let breakpoint = match location.parse::<usize>() {
+            Ok(line) => Breakpoint::Line(line),
+            Err(_) => Breakpoint::Function(location.to_string()),
+        };
+        self.breakpoints.push(breakpoint);
+        println!("breakpoint {} at {location}", self.breakpoints.len());
+    }
+
+    fn backtrace(&self, context: &DebugContext<'_, '_>) {
+        let calls = context.call_stack();
+        let mut line = self.line(context.offset());
+        for (index, call) in calls.iter().enumerate().rev() {
+            println!(
+                "#{} {} at line {line}",
+                calls.len() - index - 1,
+                call.function
+            );
+            line = self.line(call.offset);
+        }
+        println!("#{} top level at line {line}", calls.len());
+    }
+}
+
+fn print_value(context: &mut DebugContext<'_, '_>, name: &str) {
+    if let Some(index) = name.strip_prefix('$') {
+        match index.parse::<usize>() {
+            Ok(0) => println!("$0 = {:?}", context.record()),
+            Ok(index) => println!("${index} = {:?}", context.field(index)),
+            Err(_) => eprintln!("rawk: {name} is not a field"),
+        }
+        return;
+    }
+
+    // Show the scalar for a name that holds one, or whose array is empty,
+    // such as after `delete` or a `split` of an empty string.
+    let value = context.variable(name);
+    match context.array(name) {
+        Some(mut elements) if !elements.is_empty() && value == Value::Uninitialized => {
+            elements.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (subscript, value) in elements {
+                println!("{name}[{subscript:?}] = {}", describe(&value));
+            }
+        }
+        _ => println!("{name} = {}", describe(&value)),
+    }
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Uninitialized => "(uninitialized)".to_string(),
+        Value::String(text) => format!("{text:?}"),
+        _ => value.to_string_with("%.6g"),
+    }
+}
+
+/// Run `evaluator` under a command-line debugger for `source`, which starts
+/// stopped before the first statement. The program's output is printed at
+/// each stop and when it finishes.
+pub(crate) fn run<'a>(
+    evaluator: Evaluator<'a>,
+    source: &'a str,
+    statement_offsets: &[usize],
+) -> io::Result<()> {
+    let debugger = CliDebugger {
+        source,
+        editor: DefaultEditor::new().map_err(io::Error::other)?,
+        mode: Mode::Step,
+        breakpoints: Vec::new(),
+        depth: 0,
+    };
+    let output_lines = evaluator.with_debugger(debugger, statement_offsets).eval();
+    for line in output_lines {
+        println!("{line}");
+    }
+    Ok(())
+}
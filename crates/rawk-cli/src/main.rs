@@ -1,7 +1,17 @@
-use std::{io, path};
+use std::{
+    io::{self, Write},
+    path,
+};
 
 use clap::{CommandFactory, Parser};
-use rawk_core::awk::Awk;
+use rawk_core::{
+    CharMode, DEFAULT_MAX_CALL_DEPTH, Evaluator, Lexer, OutputMode, Program, Session,
+    annotate_profile, dump_ast, dump_tokens, format_program, lint_program,
+    parse_command_line_assignment,
+};
+
+mod debug;
+mod repl;
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -13,35 +23,82 @@ struct Args {
     #[arg(short = 'F', long = "field-separator", value_name = "fs")]
     field_separator: Option<String>,
 
-    /// Positional arguments: PROGRAM INPUT or INPUT when using -f
-    #[arg(value_name = "ARGS", num_args = 0..=2)]
+    /// Parse input as comma-separated values (RFC 4180)
+    #[arg(long = "csv")]
+    csv: bool,
+
+    /// Quote output fields as CSV; OFS starts as ","
+    #[arg(long = "csv-output", conflicts_with = "tsv_output")]
+    csv_output: bool,
+
+    /// Escape tabs and newlines in output fields as TSV; OFS starts as a tab
+    #[arg(long = "tsv-output")]
+    tsv_output: bool,
+
+    /// Print the program in canonical layout instead of running it
+    #[arg(long = "pretty-print")]
+    pretty_print: bool,
+
+    /// Print the tokens of the program with their source offsets and exit
+    #[arg(long = "dump-tokens")]
+    dump_tokens: bool,
+
+    /// Print the parsed syntax tree of the program with source offsets and exit
+    #[arg(long = "dump-ast")]
+    dump_ast: bool,
+
+    /// Check the program for common mistakes and exit, failing if any are found
+    #[arg(long = "lint")]
+    lint: bool,
+
+    /// Start an interactive session, reading records from the first operand if given
+    #[arg(long = "repl", conflicts_with = "program_file")]
+    repl: bool,
+
+    /// Run the program under a step debugger that reads commands from standard input
+    #[arg(long = "debug", conflicts_with = "repl")]
+    debug: bool,
+
+    /// Print the program annotated with execution counts and times to standard error after it runs
+    #[arg(long = "profile", conflicts_with_all = ["repl", "debug"])]
+    profile: bool,
+
+    /// Fail once user-defined functions are nested more than depth calls deep
+    #[arg(long = "max-call-depth", value_name = "depth", default_value_t = DEFAULT_MAX_CALL_DEPTH)]
+    max_call_depth: usize,
+
+    /// Positional arguments: PROGRAM [OPERAND...] or [OPERAND...] when using -f
+    #[arg(value_name = "ARGS", num_args = 0..)]
     args: Vec<String>,
 }
 
 fn main() -> io::Result<()> {
     let args = Args::parse();
+    let output_mode = output_mode(&args);
 
-    let (script, input) = if let Some(program_file) = args.program_file {
-        let script = std::fs::read_to_string(program_file)?;
-        match args.args.as_slice() {
-            [input] => (script, input.clone()),
-            _ => {
-                // No input file provided only script, enter interactive mode
-                interactive_mode(&script, args.field_separator);
-
-                return Ok(());
-            }
+    if args.repl {
+        let mut evaluator = Evaluator::new(Program::new(), Vec::new(), "-")
+            .with_arguments(Vec::new())
+            .with_char_mode(CharMode::from_locale(|name| std::env::var(name).ok()))
+            .with_csv(args.csv)
+            .with_output_mode(output_mode)
+            .with_max_call_depth(args.max_call_depth);
+        if let Some(fs) = args.field_separator {
+            evaluator = evaluator.with_field_separator(fs);
         }
-    } else {
-        match args.args.as_slice() {
-            [script, input] => (script.clone(), input.clone()),
-            [script] => {
-                // No input file provided only script, enter interactive mode
-                interactive_mode(script, args.field_separator);
+        return repl::run(
+            Session::new(evaluator),
+            args.args.first().map(String::as_str),
+        );
+    }
 
-                return Ok(());
-            }
-            _ => {
+    let (script, operands) = if let Some(program_file) = args.program_file {
+        let script = std::fs::read_to_string(program_file)?;
+        (script, args.args)
+    } else {
+        match args.args.split_first() {
+            Some((script, operands)) => (script.clone(), operands.to_vec()),
+            None => {
                 let mut cmd = Args::command();
                 cmd.print_help()?;
                 println!();
@@ -50,30 +107,195 @@ fn main() -> io::Result<()> {
         }
     };
 
-    execute(&script, path::Path::new(&input), args.field_separator)?;
+    if args.pretty_print {
+        let formatted = format_program(&script)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        print!("{formatted}");
+        return Ok(());
+    }
+
+    if args.dump_tokens {
+        print!("{}", dump_tokens(&script));
+        return Ok(());
+    }
+
+    if args.dump_ast {
+        let tree = dump_ast(&script)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        print!("{tree}");
+        return Ok(());
+    }
+
+    if args.lint {
+        let diagnostics = lint_program(&script)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        for diagnostic in &diagnostics {
+            println!(
+                "{}:{}: warning: {}",
+                diagnostic.line,
+                diagnostic.column,
+                diagnostic.message()
+            );
+        }
+        if !diagnostics.is_empty() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if args.debug {
+        let mut parser = rawk_core::Parser::new(Lexer::new(&script));
+        let program = parser
+            .try_parse_program()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        // Standard input carries the debugger commands, so records only come
+        // from the operands.
+        let evaluator = evaluator(
+            program,
+            Vec::new(),
+            input_operands(operands),
+            args.field_separator,
+            args.csv,
+            output_mode,
+        )
+        .with_max_call_depth(args.max_call_depth);
+        return debug::run(evaluator, &script, parser.statement_offsets());
+    }
+
+    if args.profile {
+        let program = rawk_core::Parser::new(Lexer::new(&script))
+            .try_parse_program()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        let mut evaluator = evaluator(
+            program,
+            Vec::new(),
+            input_operands(operands),
+            args.field_separator,
+            args.csv,
+            output_mode,
+        )
+        .with_standard_input(io::stdin().lock())
+        .with_max_call_depth(args.max_call_depth)
+        .with_profiling();
+        write_output(evaluator.eval())?;
+        if let Some(profile) = evaluator.profile() {
+            let annotated = annotate_profile(&script, profile)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+            eprint!("{annotated}");
+        }
+        exit_on_runtime_error(&evaluator);
+        return Ok(());
+    }
+
+    execute(
+        &script,
+        operands,
+        args.field_separator,
+        args.csv,
+        output_mode,
+        args.max_call_depth,
+    )?;
 
     Ok(())
 }
 
-fn execute(script: &str, path: &path::Path, field_separator: Option<String>) -> io::Result<()> {
-    let input_lines = std::fs::read_to_string(path)
-        .expect("Failed to read input file")
-        .lines()
-        .map(|line| line.to_string())
-        .collect::<Vec<String>>();
+fn output_mode(args: &Args) -> OutputMode {
+    if args.csv_output {
+        OutputMode::Csv
+    } else if args.tsv_output {
+        OutputMode::Tsv
+    } else {
+        OutputMode::Plain
+    }
+}
 
-    let awk = Awk::new(script)
+/// Run `script` over the operands, reading standard input once `ARGV` names
+/// `-` or no input file is left.
+fn execute(
+    script: &str,
+    operands: Vec<String>,
+    field_separator: Option<String>,
+    csv: bool,
+    output_mode: OutputMode,
+    max_call_depth: usize,
+) -> io::Result<()> {
+    let program = rawk_core::Parser::new(Lexer::new(script))
+        .try_parse_program()
         .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
-    let filename = display_filename(path);
-    let output_lines = awk.run(input_lines, Some(filename), field_separator);
+    let mut evaluator = evaluator(
+        program,
+        Vec::new(),
+        input_operands(operands),
+        field_separator,
+        csv,
+        output_mode,
+    )
+    .with_standard_input(io::stdin().lock())
+    .with_max_call_depth(max_call_depth);
 
-    for line in output_lines {
-        println!("{}", line);
-    }
+    write_output(evaluator.eval())?;
+    exit_on_runtime_error(&evaluator);
 
     Ok(())
 }
 
+/// Write the output lines to standard output, buffered rather than flushed
+/// line by line. A reader that stops early, as `head` does, is not an error.
+fn write_output(lines: Vec<String>) -> io::Result<()> {
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    let written = lines
+        .iter()
+        .try_for_each(|line| writeln!(stdout, "{line}"))
+        .and_then(|()| stdout.flush());
+    match written {
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        written => written,
+    }
+}
+
+/// Exit with status 2, as awk does on a fatal error, once the program has
+/// stopped with a runtime error. The evaluator has already reported it.
+fn exit_on_runtime_error(evaluator: &Evaluator<'_>) {
+    if evaluator.runtime_error().is_some() {
+        std::process::exit(2);
+    }
+}
+
+/// Build an evaluator for `program` with the options of the command line.
+fn evaluator(
+    program: Program<'_>,
+    input_lines: Vec<String>,
+    operands: Vec<String>,
+    field_separator: Option<String>,
+    csv: bool,
+    output_mode: OutputMode,
+) -> Evaluator<'_> {
+    let mut evaluator = Evaluator::new(program, input_lines, "-")
+        .with_arguments(operands)
+        .with_char_mode(CharMode::from_locale(|name| std::env::var(name).ok()))
+        .with_csv(csv)
+        .with_output_mode(output_mode);
+    if let Some(fs) = field_separator {
+        evaluator = evaluator.with_field_separator(fs);
+    }
+    evaluator
+}
+
+/// The operands as the program sees them in `ARGV`, with input files named
+/// relative to the current directory.
+fn input_operands(operands: Vec<String>) -> Vec<String> {
+    operands
+        .into_iter()
+        .map(|operand| {
+            if parse_command_line_assignment(&operand).is_some() || operand == "-" {
+                operand
+            } else {
+                display_filename(path::Path::new(&operand))
+            }
+        })
+        .collect()
+}
+
 fn display_filename(path: &path::Path) -> String {
     let relative = std::env::current_dir()
         .ok()
@@ -82,31 +304,3 @@ fn display_filename(path: &path::Path) -> String {
 
     relative.to_string_lossy().replace('\\', "/")
 }
-
-fn interactive_mode(script: &str, field_separator: Option<String>) {
-    use std::io::Write;
-
-    let awk = match Awk::new(script) {
-        Ok(awk) => awk,
-        Err(err) => {
-            eprintln!("{err}");
-            return;
-        }
-    };
-    let mut input = String::new();
-
-    loop {
-        io::stdout().flush().unwrap();
-
-        input.clear();
-        if io::stdin().read_line(&mut input).is_err() {
-            break;
-        }
-
-        let output_lines = awk.run(vec![input.trim().to_string()], None, field_separator.clone());
-
-        for line in output_lines {
-            println!("{}", line);
-        }
-    }
-}
@@ -0,0 +1,161 @@
+use std::{
+    io::{self, IsTerminal},
+    path::PathBuf,
+};
+
+use rawk_core::{Outcome, Session};
+use rustyline::{DefaultEditor, error::ReadlineError};
+
+const PROMPT: &str = "rawk> ";
+const CONTINUATION_PROMPT: &str = "  ... ";
+
+const HELP: &str = "\
+Type statements to run them, an expression to print its value, or rules and
+function definitions to add them to the program. Input continues on the next
+line while a statement or rule is unfinished.
+
+:load FILE   read records from FILE
+:next [N]    run the rules over the next N records (default 1)
+:run         run the rules over the remaining records and END, then leave
+:vars        list variables and arrays
+:vars NAME   list the elements of array NAME
+:help        show this help
+:quit        leave the session";
+
+/// Read inputs with line editing and history and run them in `session`,
+/// reading records from `file` if given.
+pub(crate) fn run(mut session: Session, file: Option<&str>) -> io::Result<()> {
+    let mut editor = DefaultEditor::new().map_err(io::Error::other)?;
+    let history = history_path();
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+    if let Some(file) = file {
+        print_result(session.open_input(file));
+    }
+
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C drops the unfinished input.
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => {
+                if !buffer.is_empty() {
+                    eprintln!("rawk: unexpected end of input");
+                }
+                break;
+            }
+            Err(err) => return Err(io::Error::other(err)),
+        };
+
+        if buffer.is_empty() && line.trim_start().starts_with(':') {
+            let _ = editor.add_history_entry(line.as_str());
+            if !command(&mut session, line.trim()) {
+                break;
+            }
+            continue;
+        }
+
+        if !buffer.is_empty() {
+            buffer.push('\n');
+        }
+        buffer.push_str(&line);
+        match session.execute(&buffer) {
+            Outcome::Incomplete => continue,
+            Outcome::Output(lines) => print_lines(&lines),
+            Outcome::Error(message) => eprintln!("rawk: {message}"),
+        }
+        if !buffer.trim().is_empty() {
+            let _ = editor.add_history_entry(buffer.as_str());
+        }
+        buffer.clear();
+        if session.has_exited() {
+            break;
+        }
+    }
+
+    // Only save what was typed, not what was piped in.
+    if let Some(history) = &history
+        && io::stdin().is_terminal()
+    {
+        let _ = editor.save_history(history);
+    }
+    Ok(())
+}
+
+/// Run the `:` command `line`, returning `false` once the session should end.
+fn command(session: &mut Session, line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let argument = words.next();
+
+    match (name, argument) {
+        (":help", None) => println!("{HELP}"),
+        (":quit" | ":q", None) => return false,
+        (":load", Some(file)) => print_result(session.open_input(file)),
+        (":next", count) => {
+            let Ok(count) = count.map_or(Ok(1), str::parse::<usize>) else {
+                eprintln!("rawk: :next takes a number of records");
+                return true;
+            };
+            for _ in 0..count {
+                if !session.has_input() {
+                    println!("(end of input)");
+                    break;
+                }
+                print_result(session.next_record());
+                if session.has_exited() {
+                    return false;
+                }
+            }
+        }
+        (":run", None) => {
+            print_result(session.finish());
+            return false;
+        }
+        (":vars", None) => {
+            for (name, value) in session.variables() {
+                println!("{name} = {value}");
+            }
+            for (name, len) in session.arrays() {
+                println!("{name}[] ({len} elements)");
+            }
+        }
+        (":vars", Some(name)) => match session.array(name) {
+            Some(elements) => {
+                for (subscript, value) in elements {
+                    println!("{name}[{subscript:?}] = {value}");
+                }
+            }
+            None => eprintln!("rawk: no array {name}"),
+        },
+        _ => eprintln!("rawk: unknown command {line}; type :help for the commands"),
+    }
+    true
+}
+
+fn print_result(result: Result<Vec<String>, String>) {
+    match result {
+        Ok(lines) => print_lines(&lines),
+        Err(message) => eprintln!("rawk: {message}"),
+    }
+}
+
+fn print_lines(lines: &[String]) {
+    for line in lines {
+        println!("{line}");
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rawk_history"))
+}
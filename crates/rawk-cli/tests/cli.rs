@@ -1,3 +1,5 @@
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use std::process::Command;
 
 fn run_rawk(script: &str) -> std::process::Output {
@@ -151,8 +153,8 @@ fn print_filename() {
 }
 
 #[test]
-fn system_statement_in_script_does_not_break_cli_execution() {
-    let script = r#"{ system("echo ignored"); print $1 }"#;
+fn system_statement_output_is_interleaved_with_program_output() {
+    let script = r#"{ system("echo " NR); print $1 }"#;
 
     let output = run_rawk(script);
 
@@ -162,15 +164,14 @@ fn system_statement_in_script_does_not_break_cli_execution() {
         String::from_utf8_lossy(&output.stderr)
     );
     let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut lines = stdout.lines();
+    let lines: Vec<&str> = stdout.lines().collect();
 
-    assert_eq!(lines.next(), Some("Beth"));
-    assert_eq!(lines.next(), Some("Dan"));
-    assert_eq!(lines.next(), Some("Kathy"));
-    assert_eq!(lines.next(), Some("Mark"));
-    assert_eq!(lines.next(), Some("Mary"));
-    assert_eq!(lines.next(), Some("Susie"));
-    assert!(lines.next().is_none(), "stdout: {stdout}");
+    assert_eq!(
+        lines,
+        vec![
+            "1", "Beth", "2", "Dan", "3", "Kathy", "4", "Mark", "5", "Mary", "6", "Susie"
+        ]
+    );
     assert!(output.stderr.is_empty());
 }
 
@@ -229,3 +230,380 @@ fn field_separator_long_flag_splits_fields() {
     assert!(lines.next().is_none(), "stdout: {stdout}");
     assert!(output.stderr.is_empty());
 }
+
+#[test]
+fn field_separator_t_means_tab() {
+    let output = run_rawk_with_fs("-F", "t", "NR == 3 { print NF, $1 }");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1 Kathy,4.00,10\n");
+}
+
+#[test]
+fn field_separator_octal_and_control_escapes() {
+    let octal = run_rawk_with_fs("-F", r"\054", "NR == 3 { print NF, $1 }");
+    let bell = run_rawk_with_fs("-F", r"\a", "NR == 3 { print NF, $1 }");
+
+    assert!(
+        octal.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&octal.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&octal.stdout), "3 Kathy\n");
+    assert!(
+        bell.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&bell.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&bell.stdout), "1 Kathy,4.00,10\n");
+}
+
+#[test]
+fn multiple_operands_are_processed_in_order_with_assignments() {
+    let data = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/emp.data");
+    let csv = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/emp.csv");
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let output = Command::new(rawk)
+        .arg("FNR == 1 { print FILENAME, tag, ARGC }")
+        .arg("tag=first")
+        .arg(data)
+        .arg("tag=second")
+        .arg(csv)
+        .output()
+        .expect("failed to run rawk");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+
+    assert_eq!(lines.next(), Some("tests/emp.data first 5"));
+    assert_eq!(lines.next(), Some("tests/emp.csv second 5"));
+    assert!(lines.next().is_none(), "stdout: {stdout}");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn csv_flag_parses_quoted_fields_and_multiline_records() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/people.csv");
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let output = Command::new(rawk)
+        .arg("--csv")
+        .arg(r#"NR > 1 { n = split($2, parts, ","); print NR, NF, $1, n, $3 }"#)
+        .arg(path)
+        .output()
+        .expect("failed to run rawk");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+
+    assert_eq!(lines.next(), Some("2 3 Smith, John 1 42"));
+    assert_eq!(lines.next(), Some(r#"3 3 O"Brien, Pat 0 7"#));
+    assert!(lines.next().is_none(), "stdout: {stdout}");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn csv_input_and_output_round_trip_quoted_fields() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/people.csv");
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let output = Command::new(rawk)
+        .arg("--csv")
+        .arg("--csv-output")
+        .arg("{ print $1, $2, $3 }")
+        .arg(path)
+        .output()
+        .expect("failed to run rawk");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let expected = std::fs::read_to_string(path).unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn pretty_print_formats_program_without_running_it() {
+    let script_path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/print.awk");
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let output = Command::new(rawk)
+        .arg("--pretty-print")
+        .arg("-f")
+        .arg(script_path)
+        .output()
+        .expect("failed to run rawk");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "{\n    print\n}\n");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn runtime_errors_fail_the_run() {
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let output = Command::new(rawk)
+        .arg("--max-call-depth")
+        .arg("10")
+        .arg("function f(n) { return n ? 1 + f(n - 1) : 0 } BEGIN { print f(20) }")
+        .output()
+        .expect("failed to run rawk");
+
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "rawk: function call nesting too deep in f (limit 10)\n"
+    );
+}
+
+#[test]
+fn deep_recursion_runs_within_the_default_call_depth() {
+    let output = run_rawk("function f(n) { return n ? 1 + f(n - 1) : 0 } BEGIN { print f(5000) }");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "5000\n");
+}
+
+#[test]
+fn pretty_print_reports_parse_errors() {
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let output = Command::new(rawk)
+        .arg("--pretty-print")
+        .arg("{ print (")
+        .output()
+        .expect("failed to run rawk");
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn dump_tokens_lists_tokens_with_offsets() {
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let output = Command::new(rawk)
+        .arg("--dump-tokens")
+        .arg("{ print $1 }")
+        .output()
+        .expect("failed to run rawk");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "0\tLeftCurlyBrace\t\"{\"\n2\tPrint\t\"print\"\n8\tDollarSign\t\"$\"\n9\tNumber\t\"1\"\n11\tRightCurlyBrace\t\"}\"\n12\tEof\t\"\"\n"
+    );
+}
+
+#[test]
+fn dump_ast_prints_the_parsed_program() {
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let output = Command::new(rawk)
+        .arg("--dump-ast")
+        .arg("{ print a b > c }")
+        .output()
+        .expect("failed to run rawk");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+Rule 0..17
+  Print > 2..15
+    Concatenation 8..11
+      left: Identifier a 8..9
+      right: Identifier b 10..11
+    target: Identifier c 14..15
+"
+    );
+}
+
+#[test]
+fn lint_reports_warnings_and_fails() {
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let output = Command::new(rawk)
+        .arg("--lint")
+        .arg("{ if ($1 = 1) print totl; missing() }")
+        .output()
+        .expect("failed to run rawk");
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "1:7: warning: assignment used as a condition; did you mean ==?\n\
+         1:21: warning: variable totl is used but never assigned\n\
+         1:27: warning: call to undefined function missing\n"
+    );
+}
+
+#[test]
+fn lint_succeeds_on_a_clean_program() {
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let output = Command::new(rawk)
+        .arg("--lint")
+        .arg("{ total += $1 } END { print total }")
+        .output()
+        .expect("failed to run rawk");
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn profile_prints_counts_beside_the_program_after_its_output() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/emp.data");
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let output = Command::new(rawk)
+        .arg("--profile")
+        .arg("function pay(r, h) {\n    return r * h\n}\n$3 > 0 { total += pay($2, $3) }\nEND { print total }")
+        .arg(path)
+        .output()
+        .expect("failed to run rawk");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "337.5\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let counts: Vec<(&str, &str)> = stderr
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let (columns, source) = line.split_at(line.len().min(19));
+            (
+                columns.split_whitespace().next().unwrap_or_default(),
+                source.trim(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        counts,
+        [
+            ("4", "function pay(r, h) {"),
+            ("4", "return r * h"),
+            ("", "}"),
+            ("6", "$3 > 0 { total += pay($2, $3) }"),
+            ("1", "END { print total }"),
+        ]
+    );
+}
+
+#[test]
+fn dash_operand_reads_standard_input_between_files() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/print.awk");
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let mut child = Command::new(rawk)
+        .arg("{ print FILENAME, $0 }")
+        .arg(path)
+        .arg("-")
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn rawk");
+    child
+        .stdin
+        .take()
+        .expect("failed to open stdin")
+        .write_all(b"from stdin\n")
+        .expect("failed to write stdin");
+    let output = child.wait_with_output().expect("failed to wait on rawk");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout,
+        "tests/print.awk { print }\n- from stdin\ntests/print.awk { print }\n"
+    );
+}
+
+#[test]
+fn standard_input_keeps_lines_that_are_not_utf8() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let mut child = Command::new(rawk)
+        .arg("{ print NR \": \" $0 }")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn rawk");
+    child
+        .stdin
+        .take()
+        .expect("failed to open stdin")
+        .write_all(b"a\n\xff\nb\n")
+        .expect("failed to write stdin");
+    let output = child.wait_with_output().expect("failed to wait on rawk");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "1: a\n2: \u{FFFD}\n3: b\n"
+    );
+}
+
+#[test]
+fn deleting_the_only_operand_reads_standard_input() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/emp.data");
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+
+    let mut child = Command::new(rawk)
+        .arg("BEGIN { delete ARGV[1] } { print }")
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn rawk");
+    child
+        .stdin
+        .take()
+        .expect("failed to open stdin")
+        .write_all(b"hi\n")
+        .expect("failed to write stdin");
+    let output = child.wait_with_output().expect("failed to wait on rawk");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\n");
+}
@@ -0,0 +1,123 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const PROGRAM: &str = "\
+function pay(rate, hours) {
+    return rate * hours
+}
+$3 > 0 {
+    total += pay($2, $3)
+    n++
+}
+END { print n, total }";
+
+fn run_rawk_debug(commands: &str) -> std::process::Output {
+    run_rawk_debug_program(PROGRAM, commands)
+}
+
+fn run_rawk_debug_program(program: &str, commands: &str) -> std::process::Output {
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+    let data = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/emp.data");
+    let mut child = Command::new(rawk)
+        .args(["--debug", program, data])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn rawk");
+
+    {
+        let stdin = child.stdin.as_mut().expect("failed to open stdin");
+        stdin
+            .write_all(commands.as_bytes())
+            .expect("failed to write stdin");
+    }
+
+    child.wait_with_output().expect("failed to wait on rawk")
+}
+
+#[test]
+fn debugger_stops_at_breakpoints_and_shows_state() {
+    let commands = "\
+break pay
+continue
+backtrace
+print hours
+print $1
+print NR
+next
+next
+print total
+delete
+break 8
+continue
+";
+
+    let output = run_rawk_debug(commands);
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+line 5: total += pay($2, $3)
+breakpoint 1 at pay
+line 2: return rate * hours
+#0 pay at line 2
+#1 top level at line 5
+hours = 10
+$1 = \"Kathy\"
+NR = 3
+line 6: n++
+line 5: total += pay($2, $3)
+total = 40
+breakpoint 1 at 8
+line 8: END { print n, total }
+4 337.5
+"
+    );
+}
+
+#[test]
+fn quitting_stops_the_program_without_end() {
+    let output = run_rawk_debug("step\nprint total\nquit\n");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "line 5: total += pay($2, $3)\nline 2: return rate * hours\ntotal = (uninitialized)\n"
+    );
+}
+
+#[test]
+fn output_is_shown_at_the_stop_after_it_is_printed() {
+    let program = "BEGIN {\n    print \"one\"\n    printf \"two\"\n    print \"\"\n}";
+    let output = run_rawk_debug_program(program, "step\nstep\nstep\ncontinue\n");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "\
+line 2: print \"one\"
+one
+line 3: printf \"two\"
+line 4: print \"\"
+two
+"
+    );
+}
+
+#[test]
+fn print_shows_an_empty_array_as_uninitialized() {
+    let program = "BEGIN {\n    split(\"\", parts)\n    n++\n}";
+    let output = run_rawk_debug_program(program, "next\nprint parts\ncontinue\n");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "line 2: split(\"\", parts)\nline 3: n++\nparts = (uninitialized)\n"
+    );
+}
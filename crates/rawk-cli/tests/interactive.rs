@@ -42,7 +42,7 @@ fn run_rawk_interactive_from_file(input: &[u8]) -> std::process::Output {
 #[test]
 fn interactive_mode_script_on_command_line() {
     let script = "{ print $1 }";
-    // Send invalid UTF-8 to force interactive mode to exit after the inputs.
+    // Bytes that are not valid UTF-8 are read as U+FFFD.
     let input = b"Beth 4.00 0\nDan 3.75 0\n\xff";
 
     let output = run_rawk_interactive(script, input);
@@ -58,13 +58,14 @@ fn interactive_mode_script_on_command_line() {
 
     assert_eq!(lines.next(), Some("Beth"));
     assert_eq!(lines.next(), Some("Dan"));
+    assert_eq!(lines.next(), Some("\u{FFFD}"));
     assert!(lines.next().is_none());
     assert!(output.stderr.is_empty());
 }
 
 #[test]
 fn interactive_mode_script_from_file() {
-    // Send invalid UTF-8 to force interactive mode to exit after the inputs.
+    // Bytes that are not valid UTF-8 are read as U+FFFD.
     let input = b"Beth 4.00 0\nDan 3.75 0\n\xff";
 
     let output = run_rawk_interactive_from_file(input);
@@ -80,6 +81,7 @@ fn interactive_mode_script_from_file() {
 
     assert_eq!(lines.next(), Some("Beth 4.00 0"));
     assert_eq!(lines.next(), Some("Dan 3.75 0"));
+    assert_eq!(lines.next(), Some("\u{FFFD}"));
     assert!(lines.next().is_none());
     assert!(output.stderr.is_empty());
 }
@@ -110,11 +112,7 @@ fn interactive_mode_reports_parse_errors_from_invalid_script() {
 
     let output = run_rawk_interactive(script, b"");
 
-    assert!(
-        output.status.success(),
-        "stderr: {}",
-        String::from_utf8_lossy(&output.stderr)
-    );
+    assert!(!output.status.success());
     assert!(
         output.stdout.is_empty(),
         "stdout: {}",
@@ -124,3 +122,35 @@ fn interactive_mode_reports_parse_errors_from_invalid_script() {
     let stderr = String::from_utf8_lossy(&output.stderr);
     assert!(stderr.contains("expected statement"), "stderr: {stderr}");
 }
+
+#[test]
+fn standard_input_is_read_to_the_end_once() {
+    let script = "{ print NR, $0 } END { print \"done\", NR }";
+
+    let output = run_rawk_interactive(script, b"one\ntwo\n");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "1 one\n2 two\ndone 2\n");
+}
+
+#[test]
+fn file_named_in_begin_is_read_instead_of_standard_input() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/print.awk");
+    let script =
+        format!("BEGIN {{ ARGV[1] = \"{path}\"; ARGC = 2 }} {{ print FILENAME \": \" $0 }}");
+
+    let output = run_rawk_interactive(&script, b"ignored\n");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, format!("{path}: {{ print }}\n"));
+}
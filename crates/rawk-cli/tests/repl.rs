@@ -0,0 +1,93 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn run_rawk_repl(args: &[&str], input: &str) -> std::process::Output {
+    let rawk = env!("CARGO_BIN_EXE_rawk");
+    let mut child = Command::new(rawk)
+        .arg("--repl")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn rawk");
+
+    {
+        let stdin = child.stdin.as_mut().expect("failed to open stdin");
+        stdin
+            .write_all(input.as_bytes())
+            .expect("failed to write stdin");
+    }
+
+    child.wait_with_output().expect("failed to wait on rawk")
+}
+
+#[test]
+fn repl_keeps_state_and_reads_multi_line_input() {
+    let input = "\
+total = 10
+function add(n) {
+    total += n
+    return total
+}
+add(5)
+print total * 2
+:vars
+";
+
+    let output = run_rawk_repl(&[], input);
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "15\n30\ntotal = 15\n"
+    );
+}
+
+#[test]
+fn repl_steps_through_records_of_a_data_file() {
+    let data = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/emp.data");
+    let input = "\
+$3 > 0 { pay[$1] = $2 * $3 }
+END { print length(pay), \"paid\" }
+:next 4
+:vars pay
+$1
+:run
+";
+
+    let output = run_rawk_repl(&[data], input);
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "pay[\"Kathy\"] = 40\npay[\"Mark\"] = 100\nMark\n4 paid\n"
+    );
+}
+
+#[test]
+fn repl_reports_errors_and_carries_on() {
+    let input = "if (1)\nprint 1\nprint )\n:unknown\nx = 1\nx + 1\n";
+
+    let output = run_rawk_repl(&[], input);
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n2\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("at byte 6: expected statement"),
+        "stderr: {stderr}"
+    );
+    assert!(
+        stderr.contains("unknown command :unknown"),
+        "stderr: {stderr}"
+    );
+}
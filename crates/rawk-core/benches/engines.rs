@@ -0,0 +1,64 @@
+//! Compare the bytecode evaluator with the tree walker on a few programs.
+//!
+//! Run with `cargo bench -p rawk-core --bench engines`.
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use rawk_core::{Evaluator, Lexer, Parser};
+
+const LOOP: &str = "BEGIN { for (i = 0; i < 200000; i++) total += i * 2; print total }";
+
+const CALLS: &str = "function fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2) }
+BEGIN { print fib(20) }";
+
+const FIELDS: &str = "{ for (i = 1; i <= NF; i++) seen[$i] += i; total += $3 }
+END { for (word in seen) words++; print words, total }";
+
+/// Records of five fields for `FIELDS`.
+fn records() -> Vec<String> {
+    (0..10_000)
+        .map(|line| {
+            let fields: Vec<String> = (0..5)
+                .map(|field| (line * 7 + field) % 997)
+                .map(|n| n.to_string())
+                .collect();
+            fields.join(" ")
+        })
+        .collect()
+}
+
+fn bench_program(criterion: &mut Criterion, name: &str, source: &str, input: &[String]) {
+    let mut group = criterion.benchmark_group(name);
+    group.sample_size(20);
+    for tree_walker in [false, true] {
+        let engine = if tree_walker {
+            "tree walker"
+        } else {
+            "bytecode"
+        };
+        group.bench_function(engine, |bencher| {
+            bencher.iter_batched(
+                || {
+                    let program = Parser::new(Lexer::new(source)).parse_program();
+                    let evaluator = Evaluator::new(program, input.to_vec(), "-");
+                    if tree_walker {
+                        evaluator.with_tree_walker()
+                    } else {
+                        evaluator
+                    }
+                },
+                |mut evaluator| evaluator.eval(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn engines(criterion: &mut Criterion) {
+    bench_program(criterion, "loop", LOOP, &[]);
+    bench_program(criterion, "calls", CALLS, &[]);
+    bench_program(criterion, "fields", FIELDS, &records());
+}
+
+criterion_group!(benches, engines);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+//! Measure field access on wide comma-separated records, read with `-F,` and
+//! with `--csv`. Fields are split only as far as the highest one a program
+//! asks for, so `$1` should cost far less than `$40` or `NF`.
+//!
+//! Run with `cargo bench -p rawk-core --bench fields`.
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use rawk_core::{Evaluator, Lexer, Parser};
+
+const PROGRAMS: &[(&str, &str)] = &[
+    ("$1", "{ total += $1 } END { print total }"),
+    ("$40", "{ total += $40 } END { print total }"),
+    ("NF", "{ total += NF } END { print total }"),
+];
+
+/// Records of forty comma-separated numbers.
+fn records() -> Vec<String> {
+    (0..10_000)
+        .map(|line| {
+            let fields: Vec<String> = (0..40)
+                .map(|field| (line * 13 + field) % 9973)
+                .map(|n| n.to_string())
+                .collect();
+            fields.join(",")
+        })
+        .collect()
+}
+
+fn bench_fields(criterion: &mut Criterion, name: &str, csv: bool, input: &[String]) {
+    let mut group = criterion.benchmark_group(name);
+    group.sample_size(20);
+    for (access, source) in PROGRAMS {
+        group.bench_function(*access, |bencher| {
+            bencher.iter_batched(
+                || {
+                    let program = Parser::new(Lexer::new(source)).parse_program();
+                    let evaluator = Evaluator::new(program, input.to_vec(), "-");
+                    if csv {
+                        evaluator.with_csv(true)
+                    } else {
+                        evaluator.with_field_separator(",".to_string())
+                    }
+                },
+                |mut evaluator| evaluator.eval(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn fields(criterion: &mut Criterion) {
+    let input = records();
+    bench_fields(criterion, "-F,", false, &input);
+    bench_fields(criterion, "--csv", true, &input);
+}
+
+criterion_group!(benches, fields);
+criterion_main!(benches);
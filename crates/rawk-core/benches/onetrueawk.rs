@@ -0,0 +1,74 @@
+//! Compare the bytecode evaluator with the tree walker on the onetrueawk
+//! `p.*` programs, run over their `countries` file repeated to a large input.
+//! Programs that write files, start commands or exit within a few records
+//! are left out.
+//!
+//! Run with `cargo bench -p rawk-core --bench onetrueawk`.
+
+use std::fs;
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use rawk_core::{Evaluator, Lexer, Parser};
+
+const TEST_DATA: &str = "tests/onetrueawk-testdata";
+
+/// How many times the countries are repeated.
+const REPEAT: usize = 10_000;
+
+const SKIPPED: &[&str] = &["p.41", "p.47", "p.48", "p.48a", "p.48b", "p.49", "p.50"];
+
+/// The programs to run, by name.
+fn programs() -> Vec<(String, String)> {
+    let mut programs: Vec<(String, String)> = fs::read_dir(TEST_DATA)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?.strip_suffix(".awk")?;
+            (name.starts_with("p.") && !SKIPPED.contains(&name))
+                .then(|| (name.to_string(), fs::read_to_string(&path).unwrap()))
+        })
+        .collect();
+    programs.sort();
+    programs
+}
+
+fn onetrueawk(criterion: &mut Criterion) {
+    let countries = fs::read_to_string(format!("{TEST_DATA}/countries")).unwrap();
+    let input: Vec<String> = countries
+        .lines()
+        .cycle()
+        .take(countries.lines().count() * REPEAT)
+        .map(str::to_string)
+        .collect();
+
+    for (name, source) in programs() {
+        let mut group = criterion.benchmark_group(&name);
+        group.sample_size(10);
+        for tree_walker in [false, true] {
+            let engine = if tree_walker {
+                "tree walker"
+            } else {
+                "bytecode"
+            };
+            group.bench_function(engine, |bencher| {
+                bencher.iter_batched(
+                    || {
+                        let program = Parser::new(Lexer::new(&source)).parse_program();
+                        let evaluator = Evaluator::new(program, input.clone(), "countries");
+                        if tree_walker {
+                            evaluator.with_tree_walker()
+                        } else {
+                            evaluator
+                        }
+                    },
+                    |mut evaluator| evaluator.eval(),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, onetrueawk);
+criterion_main!(benches);
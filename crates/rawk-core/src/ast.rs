@@ -1,11 +1,13 @@
-use std::fmt;
+use std::{cell::Cell, fmt, ops::Deref};
 
-use crate::token::Token;
+use crate::{evaluator::resolve::Variable, token::Token};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program<'a> {
     begin_blocks: Vec<Action<'a>>,
+    begin_file_blocks: Vec<Action<'a>>,
     rules: Vec<Rule<'a>>,
+    end_file_blocks: Vec<Action<'a>>,
     end_blocks: Vec<Action<'a>>,
     function_definitions: Vec<FunctionDefinition<'a>>,
 }
@@ -14,14 +16,20 @@ impl<'a> Program<'a> {
     pub fn new() -> Self {
         Program {
             begin_blocks: vec![],
+            begin_file_blocks: vec![],
             rules: vec![],
+            end_file_blocks: vec![],
             end_blocks: vec![],
             function_definitions: vec![],
         }
     }
 
     pub fn len(&self) -> usize {
-        self.rules.len() + self.begin_blocks.len() + self.end_blocks.len()
+        self.rules.len()
+            + self.begin_blocks.len()
+            + self.begin_file_blocks.len()
+            + self.end_file_blocks.len()
+            + self.end_blocks.len()
     }
 
     pub fn is_empty(&self) -> bool {
@@ -36,6 +44,14 @@ impl<'a> Program<'a> {
         self.end_blocks.push(action);
     }
 
+    pub fn add_begin_file_block(&mut self, action: Action<'a>) {
+        self.begin_file_blocks.push(action);
+    }
+
+    pub fn add_end_file_block(&mut self, action: Action<'a>) {
+        self.end_file_blocks.push(action);
+    }
+
     pub fn add_rule(&mut self, rule: Rule<'a>) {
         self.rules.push(rule);
     }
@@ -52,6 +68,14 @@ impl<'a> Program<'a> {
         self.end_blocks.iter()
     }
 
+    pub fn begin_file_blocks_iter(&self) -> std::slice::Iter<'_, Action<'a>> {
+        self.begin_file_blocks.iter()
+    }
+
+    pub fn end_file_blocks_iter(&self) -> std::slice::Iter<'_, Action<'a>> {
+        self.end_file_blocks.iter()
+    }
+
     pub fn rules_iter(&self) -> std::slice::Iter<'_, Rule<'a>> {
         self.rules.iter()
     }
@@ -61,6 +85,50 @@ impl<'a> Program<'a> {
             .iter()
             .find(|definition| definition.name == name)
     }
+
+    pub fn function_definitions_iter(&self) -> std::slice::Iter<'_, FunctionDefinition<'a>> {
+        self.function_definitions.iter()
+    }
+
+    /// The statement lists of the actions and functions, in the order
+    /// [`Parser::statement_offsets`](crate::Parser::statement_offsets)
+    /// describes them: `BEGIN`, `BEGINFILE`, the other rules, `ENDFILE`,
+    /// `END`, then the functions, each kind in source order.
+    pub(crate) fn statement_lists(&self) -> impl Iterator<Item = &[Statement<'a>]> {
+        fn actions<'s, 'a>(actions: &'s [Action<'a>]) -> impl Iterator<Item = &'s [Statement<'a>]> {
+            actions.iter().map(|action| action.statements.as_slice())
+        }
+
+        actions(&self.begin_blocks)
+            .chain(actions(&self.begin_file_blocks))
+            .chain(self.rules.iter().map(|rule| match rule {
+                Rule::Action(action)
+                | Rule::PatternAction {
+                    action: Some(action),
+                    ..
+                } => action.statements.as_slice(),
+                _ => &[],
+            }))
+            .chain(actions(&self.end_file_blocks))
+            .chain(actions(&self.end_blocks))
+            .chain(
+                self.function_definitions
+                    .iter()
+                    .map(|definition| definition.statements.as_slice()),
+            )
+    }
+
+    /// Add `definition`, replacing any earlier function of the same name.
+    pub(crate) fn define_function(&mut self, definition: FunctionDefinition<'a>) {
+        match self
+            .function_definitions
+            .iter_mut()
+            .find(|existing| existing.name == definition.name)
+        {
+            Some(existing) => *existing = definition,
+            None => self.function_definitions.push(definition),
+        }
+    }
 }
 
 impl<'a> Default for Program<'a> {
@@ -75,8 +143,19 @@ impl<'a> fmt::Display for Program<'a> {
             write!(f, "BEGIN {action}")?;
         }
 
-        // Add space between begin blocks and rules if both exist
-        if !self.begin_blocks.is_empty() && !self.rules.is_empty() {
+        // Add space between begin blocks and what follows if both exist
+        let has_middle = !self.begin_file_blocks.is_empty()
+            || !self.rules.is_empty()
+            || !self.end_file_blocks.is_empty();
+        if !self.begin_blocks.is_empty() && has_middle {
+            write!(f, " ")?;
+        }
+
+        for action in &self.begin_file_blocks {
+            write!(f, "BEGINFILE {action}")?;
+        }
+
+        if !self.begin_file_blocks.is_empty() && !self.rules.is_empty() {
             write!(f, " ")?;
         }
 
@@ -84,8 +163,18 @@ impl<'a> fmt::Display for Program<'a> {
             write!(f, "{rule}")?;
         }
 
+        if (!self.begin_file_blocks.is_empty() || !self.rules.is_empty())
+            && !self.end_file_blocks.is_empty()
+        {
+            write!(f, " ")?;
+        }
+
+        for action in &self.end_file_blocks {
+            write!(f, "ENDFILE {action}")?;
+        }
+
         // Add space between rules and end blocks if both exist
-        if !self.rules.is_empty() && !self.end_blocks.is_empty() {
+        if has_middle && !self.end_blocks.is_empty() {
             write!(f, " ")?;
         }
 
@@ -97,6 +186,81 @@ impl<'a> fmt::Display for Program<'a> {
     }
 }
 
+/// The name of a variable where it appears in a program, along with the
+/// variable it refers to once the evaluator has resolved the program.
+#[derive(Clone)]
+pub struct Name<'a> {
+    text: &'a str,
+    variable: Cell<Option<Variable>>,
+}
+
+impl<'a> Name<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Name {
+            text,
+            variable: Cell::new(None),
+        }
+    }
+
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// The variable the name was resolved to, if it has been.
+    pub(crate) fn variable(&self) -> Option<Variable> {
+        self.variable.get()
+    }
+
+    pub(crate) fn resolve_to(&self, variable: Variable) {
+        self.variable.set(Some(variable));
+    }
+}
+
+impl<'a> From<&'a str> for Name<'a> {
+    fn from(text: &'a str) -> Self {
+        Name::new(text)
+    }
+}
+
+impl Deref for Name<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.text
+    }
+}
+
+/// Names are equal when their text is, wherever they were resolved to.
+impl PartialEq for Name<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl PartialEq<str> for Name<'_> {
+    fn eq(&self, other: &str) -> bool {
+        self.text == other
+    }
+}
+
+impl PartialEq<&str> for Name<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        self.text == *other
+    }
+}
+
+impl fmt::Debug for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.text, f)
+    }
+}
+
+impl fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.text)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Statement<'a> {
     Empty,
@@ -112,15 +276,25 @@ pub enum Statement<'a> {
         target: Expression<'a>,
     },
     Printf(Vec<Expression<'a>>),
+    PrintfRedirect {
+        expressions: Vec<Expression<'a>>,
+        target: Expression<'a>,
+        append: bool,
+    },
+    PrintfPipe {
+        expressions: Vec<Expression<'a>>,
+        target: Expression<'a>,
+    },
     System(Expression<'a>),
     Split {
         string: Expression<'a>,
-        array: &'a str,
+        array: Name<'a>,
         separator: Option<Expression<'a>>,
     },
     Sub {
         pattern: Expression<'a>,
         replacement: Expression<'a>,
+        target: Option<Expression<'a>>,
     },
     Gsub {
         pattern: Expression<'a>,
@@ -128,17 +302,17 @@ pub enum Statement<'a> {
         target: Option<Expression<'a>>,
     },
     Assignment {
-        identifier: &'a str,
+        identifier: Name<'a>,
         value: Expression<'a>,
     },
     SplitAssignment {
-        identifier: &'a str,
+        identifier: Name<'a>,
         string: Expression<'a>,
-        array: &'a str,
+        array: Name<'a>,
         separator: Option<Expression<'a>>,
     },
     ArrayAssignment {
-        identifier: &'a str,
+        identifier: Name<'a>,
         index: Expression<'a>,
         value: Expression<'a>,
     },
@@ -147,31 +321,31 @@ pub enum Statement<'a> {
         value: Expression<'a>,
     },
     AddAssignment {
-        identifier: &'a str,
+        identifier: Name<'a>,
         value: Expression<'a>,
     },
     ArrayAddAssignment {
-        identifier: &'a str,
+        identifier: Name<'a>,
         index: Expression<'a>,
         value: Expression<'a>,
     },
     ArrayPostIncrement {
-        identifier: &'a str,
+        identifier: Name<'a>,
         index: Expression<'a>,
     },
     ArrayPostDecrement {
-        identifier: &'a str,
+        identifier: Name<'a>,
         index: Expression<'a>,
     },
     Delete {
-        identifier: &'a str,
+        identifier: Name<'a>,
         index: Option<Expression<'a>>,
     },
     PreIncrement {
-        identifier: &'a str,
+        identifier: Name<'a>,
     },
     PreDecrement {
-        identifier: &'a str,
+        identifier: Name<'a>,
     },
     If {
         condition: Expression<'a>,
@@ -197,23 +371,58 @@ pub enum Statement<'a> {
         statements: Vec<Statement<'a>>,
     },
     ForIn {
-        variable: &'a str,
-        array: &'a str,
+        variable: Name<'a>,
+        array: Name<'a>,
         statements: Vec<Statement<'a>>,
     },
     Break,
     Continue,
     Return(Option<Expression<'a>>),
     Next,
+    NextFile,
     Exit(Option<Expression<'a>>),
     PostIncrement {
-        identifier: &'a str,
+        identifier: Name<'a>,
     },
     PostDecrement {
-        identifier: &'a str,
+        identifier: Name<'a>,
     },
 }
 
+/// Call `visit` on each statement of `statements` and the statements nested
+/// in them, each before its nested ones, in source order. Empty statements and
+/// the initialization and update of a `for` loop are skipped, matching the
+/// statements the parser records the starts of.
+pub(crate) fn visit_statements<'s, 'a>(
+    statements: &'s [Statement<'a>],
+    visit: &mut impl FnMut(&'s Statement<'a>),
+) {
+    for statement in statements {
+        if *statement == Statement::Empty {
+            continue;
+        }
+        visit(statement);
+        match statement {
+            Statement::If {
+                then_statements, ..
+            } => visit_statements(then_statements, visit),
+            Statement::IfElse {
+                then_statements,
+                else_statements,
+                ..
+            } => {
+                visit_statements(then_statements, visit);
+                visit_statements(else_statements, visit);
+            }
+            Statement::While { statements, .. }
+            | Statement::DoWhile { statements, .. }
+            | Statement::For { statements, .. }
+            | Statement::ForIn { statements, .. } => visit_statements(statements, visit),
+            _ => {}
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Action<'a> {
     pub statements: Vec<Statement<'a>>,
@@ -229,11 +438,13 @@ pub struct FunctionDefinition<'a> {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Rule<'a> {
     Begin(Action<'a>),
+    BeginFile(Action<'a>),
     Action(Action<'a>),
     PatternAction {
         pattern: Option<Expression<'a>>,
         action: Option<Action<'a>>,
     },
+    EndFile(Action<'a>),
     End(Action<'a>),
 }
 
@@ -241,6 +452,7 @@ impl<'a> fmt::Display for Rule<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Rule::Begin(action) => write!(f, "BEGIN {}", action),
+            Rule::BeginFile(action) => write!(f, "BEGINFILE {}", action),
             Rule::Action(action) => write!(f, "{}", action),
             Rule::PatternAction { pattern, action } => match (pattern, action) {
                 (Some(expr), Some(action)) => write!(f, "{} {}", expr, action),
@@ -248,6 +460,7 @@ impl<'a> fmt::Display for Rule<'a> {
                 (None, Some(action)) => write!(f, "{}", action),
                 (None, None) => write!(f, ""),
             },
+            Rule::EndFile(action) => write!(f, "ENDFILE {}", action),
             Rule::End(action) => write!(f, "END {}", action),
         }
     }
@@ -345,6 +558,34 @@ impl<'a> fmt::Display for Statement<'a> {
                     )
                 }
             }
+            Statement::PrintfRedirect {
+                expressions,
+                target,
+                append,
+            } => {
+                let operator = if *append { ">>" } else { ">" };
+                write!(
+                    f,
+                    "printf {} {operator} {target}",
+                    expressions
+                        .iter()
+                        .map(|expr| expr.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
+            Statement::PrintfPipe {
+                expressions,
+                target,
+            } => write!(
+                f,
+                "printf {} | {target}",
+                expressions
+                    .iter()
+                    .map(|expr| expr.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
             Statement::System(command) => write!(f, "system({command})"),
             Statement::Split {
                 string,
@@ -360,7 +601,14 @@ impl<'a> fmt::Display for Statement<'a> {
             Statement::Sub {
                 pattern,
                 replacement,
-            } => write!(f, "sub({}, {})", pattern, replacement),
+                target,
+            } => {
+                write!(f, "sub({}, {}", pattern, replacement)?;
+                if let Some(target) = target {
+                    write!(f, ", {target}")?;
+                }
+                write!(f, ")")
+            }
             Statement::Gsub {
                 pattern,
                 replacement,
@@ -497,6 +745,7 @@ impl<'a> fmt::Display for Statement<'a> {
             Statement::Return(None) => write!(f, "return"),
             Statement::Return(Some(value)) => write!(f, "return {value}"),
             Statement::Next => write!(f, "next"),
+            Statement::NextFile => write!(f, "nextfile"),
             Statement::Exit(None) => write!(f, "exit"),
             Statement::Exit(Some(status)) => write!(f, "exit {status}"),
             Statement::PostIncrement { identifier } => write!(f, "{identifier}++"),
@@ -515,9 +764,9 @@ pub enum Expression<'a> {
     String(&'a str),
     Regex(&'a str),
     Field(Box<Expression<'a>>),
-    Identifier(&'a str),
+    Identifier(Name<'a>),
     ArrayAccess {
-        identifier: &'a str,
+        identifier: Name<'a>,
         index: Box<Expression<'a>>,
     },
     Length(Option<Box<Expression<'a>>>),
@@ -652,6 +901,7 @@ mod tests {
         let expected_string = "$3 > 5";
         let program = Program {
             begin_blocks: vec![],
+            begin_file_blocks: vec![],
             rules: vec![Rule::PatternAction {
                 pattern: Some(Expression::Infix {
                     left: Box::new(Expression::Field(Box::new(Expression::Number(3.0)))),
@@ -660,6 +910,7 @@ mod tests {
                 }),
                 action: None,
             }],
+            end_file_blocks: vec![],
             end_blocks: vec![],
             function_definitions: vec![],
         };
@@ -674,7 +925,9 @@ mod tests {
             begin_blocks: vec![Action {
                 statements: vec![Statement::Print(vec![])],
             }],
+            begin_file_blocks: vec![],
             rules: vec![],
+            end_file_blocks: vec![],
             end_blocks: vec![],
             function_definitions: vec![],
         };
@@ -688,7 +941,9 @@ mod tests {
         let expected_string = "END { print }";
         let program = Program {
             begin_blocks: vec![],
+            begin_file_blocks: vec![],
             rules: vec![],
+            end_file_blocks: vec![],
             end_blocks: vec![Action {
                 statements: vec![Statement::Print(vec![])],
             }],
@@ -722,12 +977,14 @@ mod tests {
         let expected_string = "{ print }";
         let program = Program {
             begin_blocks: vec![],
+            begin_file_blocks: vec![],
             rules: vec![Rule::PatternAction {
                 pattern: None,
                 action: Some(Action {
                     statements: vec![Statement::Print(vec![])],
                 }),
             }],
+            end_file_blocks: vec![],
             end_blocks: vec![],
             function_definitions: vec![],
         };
@@ -744,6 +1001,7 @@ mod tests {
             begin_blocks: vec![Action {
                 statements: vec![Statement::Print(vec![])],
             }],
+            begin_file_blocks: vec![],
             rules: vec![Rule::PatternAction {
                 pattern: Some(Expression::Infix {
                     left: Box::new(Expression::Field(Box::new(Expression::Number(1.0)))),
@@ -752,7 +1010,7 @@ mod tests {
                 }),
                 action: Some(Action {
                     statements: vec![Statement::Print(vec![
-                        Expression::Identifier("NF"),
+                        Expression::Identifier("NF".into()),
                         Expression::String(" "),
                         Expression::Field(Box::new(Expression::Number(2.0))),
                         Expression::String(" "),
@@ -760,8 +1018,9 @@ mod tests {
                     ])],
                 }),
             }],
+            end_file_blocks: vec![],
             end_blocks: vec![Action {
-                statements: vec![Statement::Print(vec![Expression::String("hello".into())])],
+                statements: vec![Statement::Print(vec![Expression::String("hello")])],
             }],
             function_definitions: vec![],
         };
@@ -779,9 +1038,9 @@ mod tests {
     #[test]
     fn test_assignment_statement_display() {
         let statement = Statement::Assignment {
-            identifier: "pop",
+            identifier: "pop".into(),
             value: Expression::Infix {
-                left: Box::new(Expression::Identifier("pop")),
+                left: Box::new(Expression::Identifier("pop".into())),
                 operator: Token::new(TokenKind::Plus, "+", 0),
                 right: Box::new(Expression::Field(Box::new(Expression::Number(3.0)))),
             },
@@ -793,7 +1052,7 @@ mod tests {
     #[test]
     fn test_add_assignment_statement_display() {
         let statement = Statement::AddAssignment {
-            identifier: "pop",
+            identifier: "pop".into(),
             value: Expression::Field(Box::new(Expression::Number(3.0))),
         };
 
@@ -802,14 +1061,18 @@ mod tests {
 
     #[test]
     fn test_pre_increment_statement_display() {
-        let statement = Statement::PreIncrement { identifier: "n" };
+        let statement = Statement::PreIncrement {
+            identifier: "n".into(),
+        };
 
         assert_eq!("++n", statement.to_string());
     }
 
     #[test]
     fn test_pre_decrement_statement_display() {
-        let statement = Statement::PreDecrement { identifier: "n" };
+        let statement = Statement::PreDecrement {
+            identifier: "n".into(),
+        };
 
         assert_eq!("--n", statement.to_string());
     }
@@ -819,10 +1082,12 @@ mod tests {
         let action = Action {
             statements: vec![
                 Statement::AddAssignment {
-                    identifier: "pop",
+                    identifier: "pop".into(),
                     value: Expression::Field(Box::new(Expression::Number(3.0))),
                 },
-                Statement::PreIncrement { identifier: "n" },
+                Statement::PreIncrement {
+                    identifier: "n".into(),
+                },
             ],
         };
 
@@ -905,7 +1170,7 @@ mod tests {
     #[test]
     fn test_concatenation_expression_display() {
         let expression = Expression::Concatenation {
-            left: Box::new(Expression::Identifier("s")),
+            left: Box::new(Expression::Identifier("s".into())),
             right: Box::new(Expression::Substr {
                 string: Box::new(Expression::Field(Box::new(Expression::Number(1.0)))),
                 start: Box::new(Expression::Number(1.0)),
@@ -919,15 +1184,15 @@ mod tests {
     #[test]
     fn test_expression_display_for_not_increment_decrement_and_ternary() {
         let statement = Statement::Print(vec![
-            Expression::Not(Box::new(Expression::Identifier("x"))),
-            Expression::PreIncrement(Box::new(Expression::Identifier("x"))),
-            Expression::PreDecrement(Box::new(Expression::Identifier("x"))),
-            Expression::PostIncrement(Box::new(Expression::Identifier("x"))),
-            Expression::PostDecrement(Box::new(Expression::Identifier("x"))),
+            Expression::Not(Box::new(Expression::Identifier("x".into()))),
+            Expression::PreIncrement(Box::new(Expression::Identifier("x".into()))),
+            Expression::PreDecrement(Box::new(Expression::Identifier("x".into()))),
+            Expression::PostIncrement(Box::new(Expression::Identifier("x".into()))),
+            Expression::PostDecrement(Box::new(Expression::Identifier("x".into()))),
             Expression::Ternary {
-                condition: Box::new(Expression::Identifier("x")),
-                then_expr: Box::new(Expression::Identifier("y")),
-                else_expr: Box::new(Expression::Identifier("z")),
+                condition: Box::new(Expression::Identifier("x".into())),
+                then_expr: Box::new(Expression::Identifier("y".into())),
+                else_expr: Box::new(Expression::Identifier("z".into())),
             },
         ]);
 
@@ -941,12 +1206,12 @@ mod tests {
     fn test_if_statement_display() {
         let statement = Statement::If {
             condition: Expression::Infix {
-                left: Box::new(Expression::Identifier("maxpop")),
+                left: Box::new(Expression::Identifier("maxpop".into())),
                 operator: Token::new(TokenKind::LessThan, "<", 0),
                 right: Box::new(Expression::Field(Box::new(Expression::Number(3.0)))),
             },
             then_statements: vec![Statement::Assignment {
-                identifier: "maxpop",
+                identifier: "maxpop".into(),
                 value: Expression::Field(Box::new(Expression::Number(3.0))),
             }],
         };
@@ -958,15 +1223,17 @@ mod tests {
     fn test_while_statement_display() {
         let statement = Statement::While {
             condition: Expression::Infix {
-                left: Box::new(Expression::Identifier("i")),
+                left: Box::new(Expression::Identifier("i".into())),
                 operator: Token::new(TokenKind::LessThanOrEqual, "<=", 0),
-                right: Box::new(Expression::Identifier("NF")),
+                right: Box::new(Expression::Identifier("NF".into())),
             },
             statements: vec![
                 Statement::Print(vec![Expression::Field(Box::new(Expression::Identifier(
-                    "i",
+                    "i".into(),
                 )))]),
-                Statement::PostIncrement { identifier: "i" },
+                Statement::PostIncrement {
+                    identifier: "i".into(),
+                },
             ],
         };
 
@@ -977,15 +1244,17 @@ mod tests {
     fn test_do_while_statement_display() {
         let statement = Statement::DoWhile {
             condition: Expression::Infix {
-                left: Box::new(Expression::Identifier("i")),
+                left: Box::new(Expression::Identifier("i".into())),
                 operator: Token::new(TokenKind::LessThanOrEqual, "<=", 0),
-                right: Box::new(Expression::Identifier("NF")),
+                right: Box::new(Expression::Identifier("NF".into())),
             },
             statements: vec![
                 Statement::Print(vec![Expression::Field(Box::new(Expression::Identifier(
-                    "i",
+                    "i".into(),
                 )))]),
-                Statement::PostIncrement { identifier: "i" },
+                Statement::PostIncrement {
+                    identifier: "i".into(),
+                },
             ],
         };
 
@@ -999,17 +1268,19 @@ mod tests {
     fn test_for_statement_display() {
         let statement = Statement::For {
             init: Box::new(Statement::Assignment {
-                identifier: "i",
+                identifier: "i".into(),
                 value: Expression::Number(1.0),
             }),
             condition: Expression::Infix {
-                left: Box::new(Expression::Identifier("i")),
+                left: Box::new(Expression::Identifier("i".into())),
                 operator: Token::new(TokenKind::LessThanOrEqual, "<=", 0),
-                right: Box::new(Expression::Identifier("NF")),
+                right: Box::new(Expression::Identifier("NF".into())),
             },
-            update: Box::new(Statement::PostIncrement { identifier: "i" }),
+            update: Box::new(Statement::PostIncrement {
+                identifier: "i".into(),
+            }),
             statements: vec![Statement::Print(vec![Expression::Field(Box::new(
-                Expression::Identifier("i"),
+                Expression::Identifier("i".into()),
             ))])],
         };
 
@@ -1021,7 +1292,9 @@ mod tests {
 
     #[test]
     fn test_post_decrement_statement_display() {
-        let statement = Statement::PostDecrement { identifier: "n" };
+        let statement = Statement::PostDecrement {
+            identifier: "n".into(),
+        };
 
         assert_eq!("n--", statement.to_string());
     }
@@ -1042,7 +1315,7 @@ mod tests {
 
     #[test]
     fn test_exit_statement_with_status_display() {
-        let statement = Statement::Exit(Some(Expression::Identifier("NR")));
+        let statement = Statement::Exit(Some(Expression::Identifier("NR".into())));
 
         assert_eq!("exit NR", statement.to_string());
     }
@@ -1062,7 +1335,7 @@ mod tests {
     fn test_print_pipe_statement_display() {
         let statement = Statement::PrintPipe {
             expressions: vec![
-                Expression::Identifier("c"),
+                Expression::Identifier("c".into()),
                 Expression::String(":"),
                 Expression::Number(1.0),
             ],
@@ -1072,16 +1345,43 @@ mod tests {
         assert_eq!(r#"print c, ":", 1 | "sort""#, statement.to_string());
     }
 
+    #[test]
+    fn test_printf_redirect_statement_display() {
+        let statement = Statement::PrintfRedirect {
+            expressions: vec![
+                Expression::String("%s\\n"),
+                Expression::Field(Box::new(Expression::Number(1.0))),
+            ],
+            target: Expression::String("out"),
+            append: true,
+        };
+
+        assert_eq!(r#"printf "%s\n", $1 >> "out""#, statement.to_string());
+    }
+
+    #[test]
+    fn test_printf_pipe_statement_display() {
+        let statement = Statement::PrintfPipe {
+            expressions: vec![
+                Expression::String("%d\\n"),
+                Expression::Identifier("n".into()),
+            ],
+            target: Expression::String("sort"),
+        };
+
+        assert_eq!(r#"printf "%d\n", n | "sort""#, statement.to_string());
+    }
+
     #[test]
     fn test_for_in_statement_display() {
         let statement = Statement::ForIn {
-            variable: "name",
-            array: "area",
+            variable: "name".into(),
+            array: "area".into(),
             statements: vec![Statement::Print(vec![Expression::Concatenation {
-                left: Box::new(Expression::Identifier("name")),
+                left: Box::new(Expression::Identifier("name".into())),
                 right: Box::new(Expression::ArrayAccess {
-                    identifier: "area",
-                    index: Box::new(Expression::Identifier("name")),
+                    identifier: "area".into(),
+                    index: Box::new(Expression::Identifier("name".into())),
                 }),
             }])],
         };
@@ -1095,7 +1395,7 @@ mod tests {
     #[test]
     fn test_array_access_expression_display() {
         let expression = Expression::ArrayAccess {
-            identifier: "pop",
+            identifier: "pop".into(),
             index: Box::new(Expression::String("Asia")),
         };
 
@@ -1105,7 +1405,7 @@ mod tests {
     #[test]
     fn test_array_add_assignment_display() {
         let statement = Statement::ArrayAddAssignment {
-            identifier: "pop",
+            identifier: "pop".into(),
             index: Expression::String("Asia"),
             value: Expression::Field(Box::new(Expression::Number(3.0))),
         };
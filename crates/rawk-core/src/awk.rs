@@ -1,4 +1,6 @@
-use crate::{Evaluator, Lexer, ParseError, Parser, Program};
+use std::collections::HashMap;
+
+use crate::{CharMode, Evaluator, Lexer, OutputMode, ParseError, Parser, Program};
 
 /// High-level wrapper for compiling and running an AWK script.
 ///
@@ -36,8 +38,26 @@ use crate::{Evaluator, Lexer, ParseError, Parser, Program};
 /// let output = awk.run(vec!["Alice,30,engineer".into()], None, Some(",".into()));
 /// assert_eq!(output, vec!["Alice".to_string()]);
 /// ```
+///
+/// Supply the `ARGV` operands and the `ENVIRON` array explicitly:
+///
+/// ```
+/// use rawk_core::awk::Awk;
+///
+/// let awk = Awk::new(r#"{ print greeting, ENVIRON["USER"], $0 }"#)
+///     .unwrap()
+///     .with_arguments(vec!["greeting=hello".into(), "-".into()])
+///     .with_environ([("USER".to_string(), "alice".to_string())].into());
+/// let output = awk.run(vec!["world".into()], None, None);
+/// assert_eq!(output, vec!["hello alice world".to_string()]);
+/// ```
 pub struct Awk {
     program: Program<'static>,
+    arguments: Option<Vec<String>>,
+    environ: Option<HashMap<String, String>>,
+    char_mode: CharMode,
+    csv: bool,
+    output_mode: OutputMode,
 }
 
 impl Awk {
@@ -53,7 +73,64 @@ impl Awk {
         let parser: &'static mut Parser<'static> = Box::leak(Box::new(Parser::new(lexer)));
         let program = parser.try_parse_program()?;
 
-        Ok(Self { program })
+        Ok(Self {
+            program,
+            arguments: None,
+            environ: None,
+            char_mode: CharMode::default(),
+            csv: false,
+            output_mode: OutputMode::default(),
+        })
+    }
+
+    /// Set the operands exposed as `ARGV[1]`, `ARGV[2]`, … to the script.
+    ///
+    /// Operands are processed in order by the main input loop, including any
+    /// edits a `BEGIN` action makes to `ARGV` and `ARGC`. An operand of the form
+    /// `name=value` assigns a variable, `-` (or the `filename` passed to
+    /// [`Awk::run`]) reads the in-memory input, and any other operand is read
+    /// as a file. By default `ARGV` holds just the `filename` passed to `run`.
+    pub fn with_arguments(mut self, arguments: Vec<String>) -> Self {
+        self.arguments = Some(arguments);
+        self
+    }
+
+    /// Set the contents of the `ENVIRON` array instead of inheriting the
+    /// environment of the current process.
+    pub fn with_environ(mut self, environ: HashMap<String, String>) -> Self {
+        self.environ = Some(environ);
+        self
+    }
+
+    /// Choose whether string built-ins and `printf` count code points or
+    /// bytes. Defaults to [`CharMode::Utf8`].
+    pub fn with_char_mode(mut self, char_mode: CharMode) -> Self {
+        self.char_mode = char_mode;
+        self
+    }
+
+    /// Parse input as CSV (RFC 4180) instead of splitting records with `FS`.
+    ///
+    /// Quoted fields may contain commas, doubled quotes and newlines, and
+    /// `split(s, a, ",")` follows the same rules.
+    ///
+    /// ```
+    /// use rawk_core::awk::Awk;
+    ///
+    /// let awk = Awk::new(r#"{ print $1 "|" $2 }"#).unwrap().with_csv(true);
+    /// let output = awk.run(vec![r#""Smith, John",42"#.into()], None, None);
+    /// assert_eq!(output, vec!["Smith, John|42".to_string()]);
+    /// ```
+    pub fn with_csv(mut self, csv: bool) -> Self {
+        self.csv = csv;
+        self
+    }
+
+    /// Quote or escape the fields written by `print` for CSV or TSV output.
+    /// See [`OutputMode`].
+    pub fn with_output_mode(mut self, output_mode: OutputMode) -> Self {
+        self.output_mode = output_mode;
+        self
     }
 
     /// Execute the compiled program against the given input lines.
@@ -62,7 +139,8 @@ impl Awk {
     ///   Pass `None` to use the default value `"-"` (conventional stdin placeholder).
     /// - `field_separator` — overrides the `FS` built-in variable used to split each
     ///   input record into fields (`$1`, `$2`, …). Pass `None` to use the default `" "`,
-    ///   which splits on runs of whitespace.
+    ///   which splits on runs of whitespace. As with `-F`, `"t"` means a tab and escape
+    ///   sequences such as `\t` are processed.
     pub fn run(
         &self,
         input: Vec<String>,
@@ -70,10 +148,19 @@ impl Awk {
         field_separator: Option<String>,
     ) -> Vec<String> {
         let filename = filename.unwrap_or_else(|| "-".to_string());
-        let mut evaluator = Evaluator::new(self.program.clone(), input, filename);
+        let mut evaluator = Evaluator::new(self.program.clone(), input, filename)
+            .with_char_mode(self.char_mode)
+            .with_csv(self.csv)
+            .with_output_mode(self.output_mode);
         if let Some(fs) = field_separator {
             evaluator = evaluator.with_field_separator(fs);
         }
+        if let Some(arguments) = &self.arguments {
+            evaluator = evaluator.with_arguments(arguments.clone());
+        }
+        if let Some(environ) = &self.environ {
+            evaluator = evaluator.with_environ(environ.clone());
+        }
 
         evaluator.eval()
     }
@@ -0,0 +1,144 @@
+/// How string built-ins count characters.
+///
+/// `length`, `substr`, `index`, `match` (with `RSTART` and `RLENGTH`), `printf`
+/// field widths and precisions and `%c` all measure text in the same unit: a
+/// Unicode code point in [`CharMode::Utf8`] and a byte in [`CharMode::Bytes`],
+/// which mirrors running a traditional AWK under `LC_ALL=C`.
+///
+/// # Examples
+///
+/// ```
+/// use rawk_core::CharMode;
+///
+/// assert_eq!(CharMode::from_locale(|_| None), CharMode::Utf8);
+/// assert_eq!(
+///     CharMode::from_locale(|name| (name == "LC_ALL").then(|| "C".to_string())),
+///     CharMode::Bytes
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CharMode {
+    #[default]
+    Utf8,
+    Bytes,
+}
+
+impl CharMode {
+    /// Pick the mode for a locale described by the POSIX environment
+    /// variables, consulted in the order `LC_ALL`, `LC_CTYPE`, `LANG`: the `C`
+    /// and `POSIX` locales count bytes and every other locale counts code
+    /// points.
+    pub fn from_locale(var: impl Fn(&str) -> Option<String>) -> Self {
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .into_iter()
+            .filter_map(var)
+            .find(|value| !value.is_empty());
+        match locale.as_deref() {
+            Some("C" | "POSIX") => CharMode::Bytes,
+            _ => CharMode::Utf8,
+        }
+    }
+
+    /// The number of characters in `text`.
+    pub(crate) fn len(self, text: &str) -> usize {
+        match self {
+            CharMode::Utf8 => text.chars().count(),
+            CharMode::Bytes => text.len(),
+        }
+    }
+
+    /// The `count` characters of `text` starting at the zero-based character
+    /// position `start`. In byte mode a multi-byte sequence cut in half is
+    /// replaced with U+FFFD.
+    pub(crate) fn slice(self, text: &str, start: usize, count: usize) -> String {
+        match self {
+            CharMode::Utf8 => text.chars().skip(start).take(count).collect(),
+            CharMode::Bytes => {
+                let bytes = text.as_bytes();
+                let start = start.min(bytes.len());
+                let end = start.saturating_add(count).min(bytes.len());
+                String::from_utf8_lossy(&bytes[start..end]).into_owned()
+            }
+        }
+    }
+
+    /// The first character of `text`, or an empty string.
+    pub(crate) fn first(self, text: &str) -> String {
+        self.slice(text, 0, 1)
+    }
+
+    /// The character with code `code`. Byte mode keeps only the low eight
+    /// bits, read as Latin-1.
+    pub(crate) fn char_for_code(self, code: f64) -> String {
+        let code = match self {
+            CharMode::Utf8 => code as u32,
+            CharMode::Bytes => code as u32 & 0xff,
+        };
+        char::from_u32(code).unwrap_or('\0').to_string()
+    }
+
+    pub(crate) fn to_lowercase(self, text: &str) -> String {
+        match self {
+            CharMode::Utf8 => text.to_lowercase(),
+            CharMode::Bytes => text.to_ascii_lowercase(),
+        }
+    }
+
+    pub(crate) fn to_uppercase(self, text: &str) -> String {
+        match self {
+            CharMode::Utf8 => text.to_uppercase(),
+            CharMode::Bytes => text.to_ascii_uppercase(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_mode_counts_code_points() {
+        let mode = CharMode::Utf8;
+
+        assert_eq!(mode.len("Åsa 東京"), 6);
+        assert_eq!(mode.slice("Åsa 東京", 4, 2), "東京");
+        assert_eq!(mode.first("Östen"), "Ö");
+        assert_eq!(mode.char_for_code(26481.0), "東");
+    }
+
+    #[test]
+    fn byte_mode_counts_bytes() {
+        let mode = CharMode::Bytes;
+
+        assert_eq!(mode.len("Åsa"), 4);
+        assert_eq!(mode.slice("Åsa", 2, 2), "sa");
+        assert_eq!(mode.slice("Åsa", 0, 1), "\u{fffd}");
+        assert_eq!(mode.char_for_code(321.0), "A");
+        assert_eq!(mode.to_uppercase("åsa"), "åSA");
+    }
+
+    #[test]
+    fn locale_selects_mode_in_posix_precedence_order() {
+        let env = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        assert_eq!(
+            CharMode::from_locale(env(&[("LANG", "C")])),
+            CharMode::Bytes
+        );
+        assert_eq!(
+            CharMode::from_locale(env(&[("LC_ALL", "sv_SE.UTF-8"), ("LANG", "C")])),
+            CharMode::Utf8
+        );
+        assert_eq!(
+            CharMode::from_locale(env(&[("LC_ALL", ""), ("LC_CTYPE", "POSIX")])),
+            CharMode::Bytes
+        );
+    }
+}
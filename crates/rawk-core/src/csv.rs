@@ -0,0 +1,184 @@
+//! Reading and writing CSV (RFC 4180) and TSV data.
+
+/// How `print` joins its arguments and how `$0` is rebuilt after a field
+/// assignment.
+///
+/// [`OutputMode::Csv`] quotes any field containing `OFS`, a double quote or a
+/// line break, doubling embedded quotes. [`OutputMode::Tsv`] escapes
+/// backslashes, tabs and line breaks as `\\`, `\t`, `\n` and `\r`. Choosing
+/// either mode also sets `OFS` to `,` or a tab, which the program may change.
+///
+/// # Examples
+///
+/// ```
+/// use rawk_core::{OutputMode, awk::Awk};
+///
+/// let awk = Awk::new(r#"{ print $1, "say \"hi\"" }"#)
+///     .unwrap()
+///     .with_output_mode(OutputMode::Csv);
+/// let output = awk.run(vec!["Smith, John".into()], None, Some("\t".into()));
+/// assert_eq!(output, vec![r#""Smith, John","say ""hi""""#.to_string()]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    #[default]
+    Plain,
+    Csv,
+    Tsv,
+}
+
+impl OutputMode {
+    /// The `OFS` this mode starts with, if it changes the default.
+    pub(crate) fn field_separator(self) -> Option<&'static str> {
+        match self {
+            OutputMode::Plain => None,
+            OutputMode::Csv => Some(","),
+            OutputMode::Tsv => Some("\t"),
+        }
+    }
+
+    /// Join output fields with `separator`, quoting or escaping each one as
+    /// this mode requires.
+    pub(crate) fn join<S: AsRef<str>>(self, fields: &[S], separator: &str) -> String {
+        match self {
+            OutputMode::Plain => fields
+                .iter()
+                .map(AsRef::as_ref)
+                .collect::<Vec<&str>>()
+                .join(separator),
+            OutputMode::Csv => fields
+                .iter()
+                .map(|field| quote_csv_field(field.as_ref(), separator))
+                .collect::<Vec<String>>()
+                .join(separator),
+            OutputMode::Tsv => fields
+                .iter()
+                .map(|field| escape_tsv_field(field.as_ref()))
+                .collect::<Vec<String>>()
+                .join(separator),
+        }
+    }
+}
+
+/// Split a CSV record into its fields.
+///
+/// Fields are separated by commas. A field that starts with a double quote
+/// runs to the matching closing quote and may contain commas, newlines and
+/// doubled quotes (`""`), which stand for a single quote. Anything between a
+/// closing quote and the next comma is kept as is. An empty record has no
+/// fields.
+pub(crate) fn split_csv(record: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut rest = (!record.is_empty()).then_some(0);
+    while let Some(start) = rest {
+        let mut field = String::new();
+        rest = read_csv_field(&record[start..], &mut field).map(|next| start + next);
+        fields.push(field);
+    }
+    fields
+}
+
+/// Read the CSV field at the start of `text` into `field`, as
+/// [`split_csv`] splits them, and return where the field after it starts,
+/// or `None` when it is the last.
+pub(crate) fn read_csv_field(text: &str, field: &mut String) -> Option<usize> {
+    let mut chars = text.char_indices().peekable();
+    let mut at_field_start = true;
+    let mut in_quotes = false;
+
+    while let Some((index, ch)) = chars.next() {
+        if in_quotes {
+            if ch != '"' {
+                field.push(ch);
+            } else if chars.next_if(|&(_, next)| next == '"').is_some() {
+                field.push('"');
+            } else {
+                in_quotes = false;
+            }
+            continue;
+        }
+
+        match ch {
+            ',' => return Some(index + 1),
+            '"' if at_field_start => in_quotes = true,
+            _ => field.push(ch),
+        }
+        at_field_start = false;
+    }
+    None
+}
+
+/// Quote `field` for CSV output when it contains `separator`, a double quote
+/// or a line break.
+pub(crate) fn quote_csv_field(field: &str, separator: &str) -> String {
+    let needs_quotes =
+        (!separator.is_empty() && field.contains(separator)) || field.contains(['"', '\n', '\r']);
+    if needs_quotes {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Escape the characters that cannot appear literally in a TSV field.
+pub(crate) fn escape_tsv_field(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for ch in field.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Whether `record` ends inside a quoted field, so that the next input line
+/// belongs to the same record.
+pub(crate) fn has_open_quote(record: &str) -> bool {
+    record.chars().filter(|&ch| ch == '"').count() % 2 == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_csv_handles_quoted_fields_and_doubled_quotes() {
+        assert_eq!(
+            split_csv(r#""Smith, John",42,"say ""hi""",,plain"#),
+            vec!["Smith, John", "42", r#"say "hi""#, "", "plain"]
+        );
+        assert_eq!(split_csv("a,"), vec!["a", ""]);
+        assert_eq!(split_csv("\"two\nlines\",x"), vec!["two\nlines", "x"]);
+        assert!(split_csv("").is_empty());
+    }
+
+    #[test]
+    fn csv_output_quotes_only_fields_that_need_it() {
+        let fields = ["plain", "a,b", r#"say "hi""#, "two\nlines", ""];
+
+        assert_eq!(
+            OutputMode::Csv.join(&fields, ","),
+            "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\","
+        );
+        assert_eq!(split_csv(&OutputMode::Csv.join(&fields, ",")), fields);
+    }
+
+    #[test]
+    fn tsv_output_escapes_tabs_newlines_and_backslashes() {
+        assert_eq!(
+            OutputMode::Tsv.join(&["a\tb", "c\nd", "e\\f"], "\t"),
+            "a\\tb\tc\\nd\te\\\\f"
+        );
+    }
+
+    #[test]
+    fn has_open_quote_detects_records_continued_on_next_line() {
+        assert!(has_open_quote(r#"1,"first line"#));
+        assert!(!has_open_quote(r#"1,"say ""hi""","x""#));
+        assert!(!has_open_quote("plain,fields"));
+    }
+}
@@ -0,0 +1,164 @@
+use std::ops::ControlFlow;
+
+use crate::{Evaluator, Value};
+
+/// Hooks the evaluator calls while it runs a program, for building debuggers.
+/// Install one with [`Evaluator::with_debugger`].
+pub trait Debugger {
+    /// Called before each statement of the program runs. Returning
+    /// [`ControlFlow::Break`] stops the program the way cancellation does,
+    /// without running its `END` actions.
+    fn before_statement(&mut self, context: &mut DebugContext<'_, '_>) -> ControlFlow<()>;
+}
+
+/// A call of a user-defined function that has not returned yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub function: String,
+    /// The source offset of the statement that made the call.
+    pub offset: usize,
+}
+
+/// The state of a program paused before a statement, handed to
+/// [`Debugger::before_statement`].
+pub struct DebugContext<'e, 'a> {
+    evaluator: &'e mut Evaluator<'a>,
+    offset: usize,
+}
+
+impl<'e, 'a> DebugContext<'e, 'a> {
+    pub(crate) fn new(evaluator: &'e mut Evaluator<'a>, offset: usize) -> Self {
+        Self { evaluator, offset }
+    }
+
+    /// The source offset of the statement about to run.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The user-defined function calls in progress, outermost first.
+    pub fn call_stack(&self) -> &[Call] {
+        self.evaluator.call_stack()
+    }
+
+    /// The current record, `$0`.
+    pub fn record(&mut self) -> String {
+        self.evaluator.current_record()
+    }
+
+    /// The field `$index` of the current record.
+    pub fn field(&mut self, index: usize) -> String {
+        self.evaluator.field_text(index)
+    }
+
+    /// The value of the variable `name` as the statement would see it: a
+    /// parameter of the current function, a special variable such as `NR`, or
+    /// a global.
+    pub fn variable(&mut self, name: &str) -> Value {
+        self.evaluator.variable_value(name)
+    }
+
+    /// The output the program has printed since the last stop, which the
+    /// evaluator would otherwise return only when the program ends.
+    pub fn take_output(&mut self) -> String {
+        self.evaluator.take_output()
+    }
+
+    /// The elements of the array `name` as the statement would see it, in no
+    /// particular order, or `None` if there is no such array.
+    pub fn array(&self, name: &str) -> Option<Vec<(String, Value)>> {
+        self.evaluator.visible_array_elements(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{Lexer, Parser};
+
+    /// The offset, call stack and value of `x` at a stop.
+    type Stop = (usize, Vec<String>, String);
+
+    /// Records where the program stopped, stopping it after `limit` stops.
+    struct Recorder {
+        stops: Rc<RefCell<Vec<Stop>>>,
+        limit: usize,
+    }
+
+    impl Debugger for Recorder {
+        fn before_statement(&mut self, context: &mut DebugContext<'_, '_>) -> ControlFlow<()> {
+            let functions = context
+                .call_stack()
+                .iter()
+                .map(|call| call.function.clone())
+                .collect();
+            let x = context.variable("x").to_string_with("%.6g");
+            self.stops
+                .borrow_mut()
+                .push((context.offset(), functions, x));
+            if self.stops.borrow().len() >= self.limit {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }
+    }
+
+    fn stops(source: &str, input: &str, limit: usize) -> (Vec<Stop>, Vec<String>) {
+        let mut parser = Parser::new(Lexer::new(source));
+        let program = parser.try_parse_program().unwrap();
+        let recorded = Rc::new(RefCell::new(Vec::new()));
+        let recorder = Recorder {
+            stops: Rc::clone(&recorded),
+            limit,
+        };
+        let mut evaluator = Evaluator::new(program, vec![input.to_string()], "-")
+            .with_debugger(recorder, parser.statement_offsets());
+        let output = evaluator.eval();
+        let stops = recorded.borrow().clone();
+        (stops, output)
+    }
+
+    #[test]
+    fn statements_are_reported_at_their_source_offsets() {
+        let source = "END { print x }\nfunction f(x) {\n  return x * 2\n}\n{ for (i = 0; i < 1; i++) x = f($1) }\nBEGIN { if (0) ; else if (1) x = 1 }";
+        let (stops, output) = stops(source, "21", usize::MAX);
+
+        let statements: Vec<&str> = stops
+            .iter()
+            .map(|(offset, _, _)| source[*offset..].split_whitespace().next().unwrap())
+            .collect();
+        assert_eq!(statements, ["if", "if", "x", "for", "x", "return", "print"]);
+        assert_eq!(output, ["42"]);
+    }
+
+    #[test]
+    fn context_shows_calls_and_the_variables_in_scope() {
+        let source = "function f(x) { return g(x + 1) }\nfunction g(y) { return y }\nBEGIN { x = 5; print f(x) }";
+        let (stops, _) = stops(source, "", usize::MAX);
+
+        let scopes: Vec<(Vec<String>, String)> = stops
+            .into_iter()
+            .map(|(_, functions, x)| (functions, x))
+            .collect();
+        assert_eq!(
+            scopes,
+            [
+                (vec![], "".to_string()),
+                (vec![], "5".to_string()),
+                (vec!["f".to_string()], "5".to_string()),
+                (vec!["f".to_string(), "g".to_string()], "5".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn breaking_stops_the_program_without_end_actions() {
+        let (stops, output) = stops("BEGIN { print 1; print 2 }\nEND { print 3 }", "", 2);
+
+        assert_eq!(stops.len(), 2);
+        assert_eq!(output, ["1"]);
+    }
+}
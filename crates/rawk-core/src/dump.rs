@@ -0,0 +1,613 @@
+//! Debugging views of how a program is read, used by `--dump-tokens` and
+//! `--dump-ast`.
+
+use std::cmp::Reverse;
+use std::fmt::Write;
+
+use crate::ast::{Action, Expression, Rule, Statement};
+use crate::parser::{Item, ItemKind, Node, Spanned};
+use crate::{Lexer, Parser, parse_error::ParseError, token::TokenKind};
+
+/// List the tokens of `source` one per line as their start offset, kind and
+/// literal text, ending with `Eof`.
+///
+/// The tokens are those the parser reads, so a `/` is shown as the start of
+/// a regular expression only where the grammar allows one. If the program
+/// does not parse, the list stops at the token where parsing failed.
+///
+/// # Examples
+///
+/// ```
+/// use rawk_core::dump_tokens;
+///
+/// assert_eq!(
+///     dump_tokens("$1 ~ /a/"),
+///     "0\tDollarSign\t\"$\"\n1\tNumber\t\"1\"\n3\tTilde\t\"~\"\n6\tRegex\t\"a\"\n8\tEof\t\"\"\n"
+/// );
+/// ```
+pub fn dump_tokens(source: &str) -> String {
+    let mut parser = Parser::new(Lexer::new(source));
+    let _ = parser.try_parse_items();
+
+    let mut output = String::new();
+    for token in parser.tokens() {
+        let _ = writeln!(
+            output,
+            "{}\t{:?}\t{:?}",
+            token.span.start, token.kind, token.literal
+        );
+        if token.kind == TokenKind::Eof {
+            break;
+        }
+    }
+    output
+}
+
+/// Show the syntax tree parsed from `source` as an indented tree, rules and
+/// functions in source order. Each line is one node with the range of the
+/// source it was read from, as byte offsets `start..end`; a child that fills
+/// a named part of its parent is prefixed with the name.
+///
+/// # Examples
+///
+/// ```
+/// use rawk_core::dump_ast;
+///
+/// assert_eq!(
+///     dump_ast("$1 > 2 { n++ }").unwrap(),
+///     "\
+/// Rule 0..14
+///   pattern: Infix > 0..6
+///     left: Field 0..2
+///       Number 1 1..2
+///     right: Number 2 5..6
+///   PostIncrement n 9..12
+/// "
+/// );
+/// ```
+pub fn dump_ast(source: &str) -> Result<String, ParseError<'_>> {
+    let mut parser = Parser::new(Lexer::new(source)).recording_spans();
+    let items = parser.try_parse_items()?;
+
+    let mut dump = AstDump {
+        spans: parser.spans(),
+        output: String::new(),
+        depth: 0,
+    };
+    for item in &items {
+        dump.item(source, item);
+    }
+    Ok(dump.output)
+}
+
+/// A part of a node shown below it.
+enum Child<'n, 'a> {
+    Expression(&'static str, &'n Expression<'a>),
+    Statement(&'static str, &'n Statement<'a>),
+    Block(&'static str, &'n [Statement<'a>]),
+}
+
+struct AstDump<'s, 'a> {
+    /// The nodes the parser built, looked up by value within the range of
+    /// their parent, since the syntax tree does not keep their positions.
+    spans: &'s [Spanned<'a>],
+    output: String,
+    depth: usize,
+}
+
+impl<'a> AstDump<'_, 'a> {
+    fn item(&mut self, source: &str, item: &Item<'a>) {
+        // An action ends at its closing brace, which the item stops before.
+        let end = item.end + usize::from(source[item.end..].starts_with('}'));
+        let span = Some((item.start, end));
+        match &item.kind {
+            ItemKind::Function(definition) => {
+                let header = format!(
+                    "Function {}({})",
+                    definition.name,
+                    definition.parameters.join(", ")
+                );
+                self.node(
+                    "",
+                    &header,
+                    span,
+                    &[Child::Block("", &definition.statements)],
+                );
+            }
+            ItemKind::Rule(rule) => match rule {
+                Rule::Begin(action) => self.action("BEGIN", action, span),
+                Rule::BeginFile(action) => self.action("BEGINFILE", action, span),
+                Rule::EndFile(action) => self.action("ENDFILE", action, span),
+                Rule::End(action) => self.action("END", action, span),
+                Rule::Action(action) => self.action("Rule", action, span),
+                Rule::PatternAction { pattern, action } => {
+                    let mut children = Vec::new();
+                    if let Some(pattern) = pattern {
+                        children.push(Child::Expression("pattern", pattern));
+                    }
+                    if let Some(action) = action {
+                        children.push(Child::Block("", &action.statements));
+                    }
+                    self.node("", "Rule", span, &children);
+                }
+            },
+        }
+    }
+
+    fn action(&mut self, label: &str, action: &Action<'a>, span: Option<(usize, usize)>) {
+        self.node("", label, span, &[Child::Block("", &action.statements)]);
+    }
+
+    /// Write a node and below it its children, each looked up after the one
+    /// before it and within `span`.
+    fn node(
+        &mut self,
+        role: &str,
+        label: &str,
+        span: Option<(usize, usize)>,
+        children: &[Child<'_, 'a>],
+    ) {
+        let indent = "  ".repeat(self.depth);
+        let role = if role.is_empty() {
+            String::new()
+        } else {
+            format!("{role}: ")
+        };
+        let range = span.map_or("?".to_string(), |(start, end)| format!("{start}..{end}"));
+        let _ = writeln!(self.output, "{indent}{role}{label} {range}");
+
+        let within = span.unwrap_or((0, usize::MAX));
+        let mut from = within.0;
+        self.depth += 1;
+        for child in children {
+            match child {
+                Child::Expression(role, expression) => {
+                    from = self.expression(role, expression, from, within);
+                }
+                Child::Statement(role, statement) => {
+                    from = self.statement(role, statement, from, within);
+                }
+                Child::Block("", statements) => {
+                    for statement in *statements {
+                        from = self.statement("", statement, from, within);
+                    }
+                }
+                Child::Block(role, statements) => {
+                    let _ = writeln!(self.output, "{}{role}:", "  ".repeat(self.depth));
+                    self.depth += 1;
+                    for statement in *statements {
+                        from = self.statement("", statement, from, within);
+                    }
+                    self.depth -= 1;
+                }
+            }
+        }
+        self.depth -= 1;
+    }
+
+    /// The range of the first node recorded inside `within` that `matches`,
+    /// preferring one that starts at or after `from`. Among nodes starting
+    /// at the same offset the widest is taken, so that a parenthesized
+    /// expression includes its parentheses.
+    fn find(
+        &self,
+        matches: impl Fn(&Node<'a>) -> bool,
+        from: usize,
+        within: (usize, usize),
+    ) -> Option<(usize, usize)> {
+        let candidates = self.spans.iter().filter(|spanned| {
+            spanned.start >= within.0 && spanned.end <= within.1 && matches(&spanned.node)
+        });
+        let first = |spans: &mut dyn Iterator<Item = &Spanned<'a>>| {
+            spans
+                .min_by_key(|spanned| (spanned.start, Reverse(spanned.end)))
+                .map(|spanned| (spanned.start, spanned.end))
+        };
+        first(&mut candidates.clone().filter(|spanned| spanned.start >= from))
+            .or_else(|| first(&mut candidates.clone()))
+    }
+
+    /// Write `expression` and return where the next sibling is looked up.
+    fn expression(
+        &mut self,
+        role: &str,
+        expression: &Expression<'a>,
+        from: usize,
+        within: (usize, usize),
+    ) -> usize {
+        let span = self.find(
+            |node| matches!(node, Node::Expression(recorded) if recorded == expression),
+            from,
+            within,
+        );
+        let (label, children) = expression_parts(expression);
+        self.node(role, &label, span, &children);
+        span.map_or(from, |(_, end)| end)
+    }
+
+    /// Write `statement` and return where the next sibling is looked up.
+    fn statement(
+        &mut self,
+        role: &str,
+        statement: &Statement<'a>,
+        from: usize,
+        within: (usize, usize),
+    ) -> usize {
+        let span = self.find(
+            |node| matches!(node, Node::Statement(recorded) if recorded == statement),
+            from,
+            within,
+        );
+        let (label, children) = statement_parts(statement);
+        self.node(role, &label, span, &children);
+        span.map_or(from, |(_, end)| end)
+    }
+}
+
+fn expression_parts<'n, 'a>(expression: &'n Expression<'a>) -> (String, Vec<Child<'n, 'a>>) {
+    use Child::Expression as E;
+
+    match expression {
+        Expression::Number(value) => (format!("Number {value}"), vec![]),
+        Expression::HexNumber { literal, .. } => (format!("Number {literal}"), vec![]),
+        Expression::String(value) => (format!("String \"{value}\""), vec![]),
+        Expression::Regex(value) => (format!("Regex /{value}/"), vec![]),
+        Expression::Field(index) => ("Field".to_string(), vec![E("", index)]),
+        Expression::Identifier(name) => (format!("Identifier {name}"), vec![]),
+        Expression::ArrayAccess { identifier, index } => {
+            (format!("ArrayAccess {identifier}"), vec![E("index", index)])
+        }
+        Expression::Length(value) => (
+            "Length".to_string(),
+            value.iter().map(|value| E("", value)).collect(),
+        ),
+        Expression::Substr {
+            string,
+            start,
+            length,
+        } => {
+            let mut children = vec![E("string", string), E("start", start)];
+            if let Some(length) = length {
+                children.push(E("length", length));
+            }
+            ("Substr".to_string(), children)
+        }
+        Expression::Rand => ("Rand".to_string(), vec![]),
+        Expression::FunctionCall { name, args } => (
+            format!("Call {name}"),
+            args.iter().map(|arg| E("", arg)).collect(),
+        ),
+        Expression::Not(value) => ("Not".to_string(), vec![E("", value)]),
+        Expression::PreIncrement(value) => ("PreIncrement".to_string(), vec![E("", value)]),
+        Expression::PreDecrement(value) => ("PreDecrement".to_string(), vec![E("", value)]),
+        Expression::PostIncrement(value) => ("PostIncrement".to_string(), vec![E("", value)]),
+        Expression::PostDecrement(value) => ("PostDecrement".to_string(), vec![E("", value)]),
+        Expression::Ternary {
+            condition,
+            then_expr,
+            else_expr,
+        } => (
+            "Ternary".to_string(),
+            vec![
+                E("condition", condition),
+                E("then", then_expr),
+                E("else", else_expr),
+            ],
+        ),
+        Expression::Concatenation { left, right } => (
+            "Concatenation".to_string(),
+            vec![E("left", left), E("right", right)],
+        ),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => (
+            format!("Infix {}", operator.literal),
+            vec![E("left", left), E("right", right)],
+        ),
+    }
+}
+
+fn statement_parts<'n, 'a>(statement: &'n Statement<'a>) -> (String, Vec<Child<'n, 'a>>) {
+    use Child::{Block, Expression as E, Statement as S};
+
+    let list = |expressions: &'n [Expression<'a>]| {
+        expressions
+            .iter()
+            .map(|expression| E("", expression))
+            .collect::<Vec<_>>()
+    };
+    let with_target = |expressions: &'n [Expression<'a>], target: &'n Expression<'a>| {
+        let mut children = list(expressions);
+        children.push(E("target", target));
+        children
+    };
+    let redirect = |append: bool| if append { ">>" } else { ">" };
+
+    match statement {
+        Statement::Empty => ("Empty".to_string(), vec![]),
+        Statement::Expression(expression) => ("Expression".to_string(), vec![E("", expression)]),
+        Statement::Print(expressions) => ("Print".to_string(), list(expressions)),
+        Statement::PrintRedirect {
+            expressions,
+            target,
+            append,
+        } => (
+            format!("Print {}", redirect(*append)),
+            with_target(expressions, target),
+        ),
+        Statement::PrintPipe {
+            expressions,
+            target,
+        } => ("Print |".to_string(), with_target(expressions, target)),
+        Statement::Printf(expressions) => ("Printf".to_string(), list(expressions)),
+        Statement::PrintfRedirect {
+            expressions,
+            target,
+            append,
+        } => (
+            format!("Printf {}", redirect(*append)),
+            with_target(expressions, target),
+        ),
+        Statement::PrintfPipe {
+            expressions,
+            target,
+        } => ("Printf |".to_string(), with_target(expressions, target)),
+        Statement::System(command) => ("System".to_string(), vec![E("", command)]),
+        Statement::Split {
+            string,
+            array,
+            separator,
+        } => {
+            let mut children = vec![E("string", string)];
+            children.extend(separator.iter().map(|separator| E("separator", separator)));
+            (format!("Split {array}"), children)
+        }
+        Statement::Sub {
+            pattern,
+            replacement,
+            target,
+        }
+        | Statement::Gsub {
+            pattern,
+            replacement,
+            target,
+        } => {
+            let name = if matches!(statement, Statement::Sub { .. }) {
+                "Sub"
+            } else {
+                "Gsub"
+            };
+            let mut children = vec![E("pattern", pattern), E("replacement", replacement)];
+            children.extend(target.iter().map(|target| E("target", target)));
+            (name.to_string(), children)
+        }
+        Statement::Assignment { identifier, value } => {
+            (format!("Assignment {identifier}"), vec![E("value", value)])
+        }
+        Statement::SplitAssignment {
+            identifier,
+            string,
+            array,
+            separator,
+        } => {
+            let mut children = vec![E("string", string)];
+            children.extend(separator.iter().map(|separator| E("separator", separator)));
+            (format!("SplitAssignment {identifier} {array}"), children)
+        }
+        Statement::ArrayAssignment {
+            identifier,
+            index,
+            value,
+        } => (
+            format!("ArrayAssignment {identifier}"),
+            vec![E("index", index), E("value", value)],
+        ),
+        Statement::FieldAssignment { field, value } => (
+            "FieldAssignment".to_string(),
+            vec![E("field", field), E("value", value)],
+        ),
+        Statement::AddAssignment { identifier, value } => (
+            format!("AddAssignment {identifier}"),
+            vec![E("value", value)],
+        ),
+        Statement::ArrayAddAssignment {
+            identifier,
+            index,
+            value,
+        } => (
+            format!("ArrayAddAssignment {identifier}"),
+            vec![E("index", index), E("value", value)],
+        ),
+        Statement::ArrayPostIncrement { identifier, index } => (
+            format!("ArrayPostIncrement {identifier}"),
+            vec![E("index", index)],
+        ),
+        Statement::ArrayPostDecrement { identifier, index } => (
+            format!("ArrayPostDecrement {identifier}"),
+            vec![E("index", index)],
+        ),
+        Statement::Delete { identifier, index } => (
+            format!("Delete {identifier}"),
+            index.iter().map(|index| E("index", index)).collect(),
+        ),
+        Statement::PreIncrement { identifier } => (format!("PreIncrement {identifier}"), vec![]),
+        Statement::PreDecrement { identifier } => (format!("PreDecrement {identifier}"), vec![]),
+        Statement::PostIncrement { identifier } => (format!("PostIncrement {identifier}"), vec![]),
+        Statement::PostDecrement { identifier } => (format!("PostDecrement {identifier}"), vec![]),
+        Statement::If {
+            condition,
+            then_statements,
+        } => (
+            "If".to_string(),
+            vec![E("condition", condition), Block("then", then_statements)],
+        ),
+        Statement::IfElse {
+            condition,
+            then_statements,
+            else_statements,
+        } => (
+            "If".to_string(),
+            vec![
+                E("condition", condition),
+                Block("then", then_statements),
+                Block("else", else_statements),
+            ],
+        ),
+        Statement::While {
+            condition,
+            statements,
+        } => (
+            "While".to_string(),
+            vec![E("condition", condition), Block("body", statements)],
+        ),
+        Statement::DoWhile {
+            condition,
+            statements,
+        } => (
+            "Do".to_string(),
+            vec![Block("body", statements), E("condition", condition)],
+        ),
+        Statement::For {
+            init,
+            condition,
+            update,
+            statements,
+        } => (
+            "For".to_string(),
+            vec![
+                S("init", init),
+                E("condition", condition),
+                S("update", update),
+                Block("body", statements),
+            ],
+        ),
+        Statement::ForIn {
+            variable,
+            array,
+            statements,
+        } => (
+            format!("ForIn {variable} {array}"),
+            vec![Block("body", statements)],
+        ),
+        Statement::Break => ("Break".to_string(), vec![]),
+        Statement::Continue => ("Continue".to_string(), vec![]),
+        Statement::Return(value) => (
+            "Return".to_string(),
+            value.iter().map(|value| E("", value)).collect(),
+        ),
+        Statement::Next => ("Next".to_string(), vec![]),
+        Statement::NextFile => ("NextFile".to_string(), vec![]),
+        Statement::Exit(status) => (
+            "Exit".to_string(),
+            status.iter().map(|status| E("", status)).collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_tokens_shows_division_outside_regex_context() {
+        let dump = dump_tokens("{ print a / 2 / 3 }");
+
+        assert!(dump.contains("10\tDivision\t\"/\""), "{dump}");
+        assert!(!dump.contains("Regex"), "{dump}");
+    }
+
+    #[test]
+    fn dump_tokens_stops_at_parse_error() {
+        let dump = dump_tokens("{ print ( }\n{ print }");
+
+        assert!(dump.contains("10\tRightCurlyBrace"), "{dump}");
+        assert!(!dump.contains("12\tLeftCurlyBrace"), "{dump}");
+    }
+
+    #[test]
+    fn dump_ast_shows_every_node_with_its_source_range() {
+        let source = "\
+function f(a) { return a (1) }
+BEGIN { x -= 2; for (;;) break }
+/re/, NR == 3 { print -x, \"s\" > \"out\"; if (x) ; else y = substr($0, 1) }
+";
+
+        assert_eq!(
+            dump_ast(source).unwrap(),
+            "\
+Function f(a) 0..30
+  Return 16..28
+    Concatenation 23..28
+      left: Identifier a 23..24
+      right: Number 1 25..28
+BEGIN 31..63
+  Assignment x 39..45
+    value: Infix - 39..45
+      left: Identifier x 39..40
+      right: Number 2 44..45
+  For 47..61
+    init: Empty 52..52
+    condition: Number 1 53..53
+    update: Empty 54..54
+    body:
+      Break 56..61
+Rule 64..136
+  pattern: Infix , 64..77
+    left: Regex /re/ 64..68
+    right: Infix == 70..77
+      left: Identifier NR 70..72
+      right: Number 3 76..77
+  Print > 80..101
+    Infix - 86..88
+      left: Number 0 86..87
+      right: Identifier x 87..88
+    String \"s\" 90..93
+    target: String \"out\" 96..101
+  If 103..134
+    condition: Identifier x 107..108
+    then:
+      Empty 110..111
+    else:
+      Assignment y 117..134
+        value: Substr 121..134
+          string: Field 128..130
+            Number 0 129..130
+          start: Number 1 132..133
+"
+        );
+    }
+
+    #[test]
+    fn dump_ast_finds_the_range_of_every_node_in_the_test_programs() {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/onetrueawk-testdata");
+        let mut dumped = 0;
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "awk") {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).unwrap();
+            let Ok(dump) = dump_ast(&source) else {
+                continue;
+            };
+
+            for line in dump.lines().filter(|line| !line.ends_with(':')) {
+                let (_, range) = line.rsplit_once(' ').unwrap();
+                let (start, end) = range
+                    .split_once("..")
+                    .unwrap_or_else(|| panic!("{}: no range in {line:?}", path.display()));
+                let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                assert!(
+                    start <= end && end <= source.len(),
+                    "{}: {line}",
+                    path.display()
+                );
+            }
+            dumped += 1;
+        }
+
+        assert!(dumped > 100, "only {dumped} programs parsed");
+    }
+}
@@ -1,48 +1,270 @@
 use crate::{
-    Action, Program, Rule,
-    ast::{Expression, Statement},
+    Action, CancellationToken, CharMode, OutputMode, Program, Rule,
+    ast::{Expression, Name, Statement, visit_statements},
+    csv,
+    debug::{Call, DebugContext, Debugger},
+    field_pattern::FieldPattern,
+    field_widths::FieldWidths,
+    profile::{Profile, Profiler, address},
+    record::Record,
     token::TokenKind,
+    value::{Value, format_number},
 };
 use regex::Regex;
-use std::cell::Cell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{HashMap, hash_map::Entry};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::rc::Rc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+mod compile;
+pub(crate) mod resolve;
+mod vm;
+
+use compile::{Compiled, Pattern};
+use resolve::{Special, Variable};
+
+/// Default limit on nested user-defined function calls, well above what
+/// recursive scripts need while still stopping a runaway recursive function
+/// before it uses up memory.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 100_000;
+
+/// A function call moves to a new stack segment of `STACK_SEGMENT_SIZE` bytes
+/// once less than `STACK_RED_ZONE` bytes of the current one are left, so the
+/// depth of recursion is bounded by the call depth limit rather than by the
+/// stack of the thread running the program.
+const STACK_RED_ZONE: usize = 256 * 1024;
+const STACK_SEGMENT_SIZE: usize = 4 * 1024 * 1024;
+
+/// Run `call`, the body of a function call, with room on the stack.
+fn with_stack<T>(call: impl FnOnce() -> T) -> T {
+    stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, call)
+}
 
-struct FunctionCallResult {
-    value: String,
-    output: Vec<String>,
+/// How many steps run between two checks of the wall-clock deadline.
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+/// How records are split into fields: by `FS`, by the fixed columns of
+/// `FIELDWIDTHS`, or by the matches of `FPAT`. Assigning one of these
+/// variables selects its mode.
+enum FieldMode {
+    Separator,
+    Widths(FieldWidths),
+    Pattern(Box<FieldPattern>),
+}
+
+/// The pattern `patsplit` uses when neither an argument nor `FPAT` gives one.
+const DEFAULT_FIELD_PATTERN: &str = "[^[:space:]]+";
+
+/// An associative array. Arrays are shared by reference so that function
+/// parameters alias the caller's array.
+type Array = Rc<RefCell<HashMap<String, Value>>>;
+
+/// A function parameter or local variable. It holds a scalar value and, once
+/// used as an array, the array it refers to.
+#[derive(Default)]
+struct Local {
+    value: Value,
+    array: Option<Array>,
+}
+
+/// A global variable: its scalar value once assigned, and the array it names
+/// once used as one.
+#[derive(Default)]
+struct Global {
+    value: Option<Value>,
+    array: Option<Array>,
+}
+
+/// The parameters of a running function call, in the order they are declared.
+type Frame<'a> = Vec<(&'a str, Local)>;
+
+/// The number of the first rule of each kind. The rules of a program are
+/// numbered through its `BEGIN` actions, `BEGINFILE` actions, other rules,
+/// `ENDFILE` actions and `END` actions in turn, each kind in source order, as
+/// in [`Profile::rules`].
+#[derive(Clone, Copy)]
+struct RuleNumbers {
+    begin_file: usize,
+    rules: usize,
+    end_file: usize,
+    end: usize,
+}
+
+impl RuleNumbers {
+    fn new(program: &Program<'_>) -> Self {
+        let begin_file = program.begin_blocks_iter().len();
+        let rules = begin_file + program.begin_file_blocks_iter().len();
+        let end_file = rules + program.rules_iter().len();
+        let end = end_file + program.end_file_blocks_iter().len();
+        Self {
+            begin_file,
+            rules,
+            end_file,
+            end,
+        }
+    }
+}
+
+/// Where the records of the open input file come from.
+enum Input {
+    /// The lines given to [`Evaluator::new`], from `input_cursor` on.
+    Lines,
+    /// The reader given to [`Evaluator::with_standard_input`].
+    Standard,
+    File(BufReader<File>),
+}
+
+/// A command started by `print | command`, kept running until it is closed.
+struct OutputPipe {
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    /// Collects what the command writes, so that it never blocks on a full
+    /// pipe while rawk is still writing to it.
+    stdout: JoinHandle<Vec<u8>>,
+}
+
+impl OutputPipe {
+    fn spawn(command: &str) -> std::io::Result<OutputPipe> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let stdin = BufWriter::new(child.stdin.take().expect("stdin is piped"));
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let stdout = std::thread::spawn(move || {
+            let mut written = Vec::new();
+            let _ = stdout.read_to_end(&mut written);
+            written
+        });
+        Ok(OutputPipe {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    /// Close the command's standard input and wait for it to finish,
+    /// returning what it wrote and its exit status.
+    fn close(self) -> (String, f64) {
+        let OutputPipe {
+            mut child,
+            mut stdin,
+            stdout,
+        } = self;
+        let _ = stdin.flush();
+        drop(stdin);
+        let written = stdout.join().unwrap_or_default();
+        let status = child
+            .wait()
+            .ok()
+            .and_then(|status| status.code())
+            .map_or(-1.0, f64::from);
+        (String::from_utf8_lossy(&written).into_owned(), status)
+    }
 }
 
-struct ComparisonOperand {
-    text: String,
-    numeric: Option<f64>,
+struct FunctionCallResult {
+    value: Value,
+    output: Vec<String>,
 }
 
 pub struct Evaluator<'a> {
-    program: Program<'a>,
+    /// Shared so that actions and functions can run while borrowed from it.
+    program: Rc<Program<'a>>,
     input_lines: Vec<String>,
+    /// Read in place of `input_lines` when the main loop first reaches them.
+    standard_input: Option<Box<dyn BufRead + 'a>>,
+    input_filename: String,
+    input: Input,
     input_cursor: usize,
+    input_open: bool,
+    argv_index: usize,
+    opened_file_operand: bool,
     current_line_number: Cell<usize>,
-    current_line: Option<String>,
+    file_line_number: usize,
+    record: Option<Record>,
     field_separator: String,
+    field_mode: FieldMode,
     output_field_separator: String,
     output_record_separator: String,
+    conversion_format: String,
+    output_format: String,
+    subscript_separator: String,
     current_filename: String,
-    variables: HashMap<String, String>,
-    numeric_variables: HashMap<String, f64>,
-    array_variables: HashMap<String, String>,
-    array_aliases: HashMap<String, String>,
-    argv: Vec<String>,
-    pipe_outputs: HashMap<String, Vec<String>>,
+    globals: Vec<Global>,
+    /// The index in `globals` of each global name.
+    global_slots: HashMap<String, usize>,
+    frames: Vec<Frame<'a>>,
+    /// The commands written to by `print |` and `printf |`, by the command
+    /// line they were started with, kept running until closed or the program
+    /// ends.
+    output_pipes: HashMap<String, OutputPipe>,
+    /// The files written by `print >` and `print >>`, by the name they were
+    /// opened with, kept open until closed or the program ends.
+    output_files: HashMap<String, BufWriter<File>>,
     rng_state: Cell<u64>,
+    rand_seed: f64,
     printf_buffer: String,
     expression_output: Vec<String>,
+    /// The output of [`Evaluator::eval`] so far. While a debugger is
+    /// installed, each statement adds its output here as it finishes, so
+    /// that the debugger can show it at the next stop.
+    output: Vec<String>,
     exited: bool,
     next_record: bool,
+    next_file: bool,
     break_loop: bool,
     continue_loop: bool,
-    return_value: Option<String>,
+    return_value: Option<Value>,
     has_output: bool,
     runtime_error: Option<String>,
+    max_call_depth: usize,
+    step_limit: Option<u64>,
+    steps: u64,
+    deadline: Option<Instant>,
+    cancellation: Option<CancellationToken>,
+    cancelled: bool,
+    char_mode: CharMode,
+    csv: bool,
+    output_mode: OutputMode,
+    debugger: Option<Box<dyn Debugger + 'a>>,
+    /// The source offset of each statement of the program, in the order of
+    /// [`visit_statements`], while a debugger is installed.
+    statement_offsets: Vec<usize>,
+    /// The index in `statement_offsets` of each statement, keyed by its
+    /// address, for the tree walker.
+    statement_indices: HashMap<usize, usize>,
+    /// The source offset of the statement being run, while a debugger is
+    /// installed.
+    statement_offset: usize,
+    call_stack: Vec<Call>,
+    profiler: Option<Profiler>,
+    tree_walker: bool,
+    /// Whether each name in the program holds the variable it resolves to,
+    /// which happens once [`Evaluator::eval`] has started.
+    resolved: bool,
+    /// The program compiled to bytecode, once [`Evaluator::eval`] has
+    /// started.
+    compiled: Option<Rc<Compiled<'a>>>,
+    /// The operand stack of the bytecode.
+    stack: Vec<Value>,
+    /// The remaining subscripts of each running `for`-`in` loop.
+    key_iterators: Vec<std::vec::IntoIter<String>>,
+    /// The arrays of the variables being passed to a function.
+    argument_arrays: Vec<Array>,
+    /// The regular expressions the bytecode has computed at run time, by
+    /// text, so each is compiled once.
+    dynamic_patterns: HashMap<String, Rc<Pattern>>,
+    /// Emptied frames of returned calls, kept for the next calls to fill.
+    spare_frames: Vec<Frame<'a>>,
 }
 
 impl<'a> Evaluator<'a> {
@@ -52,50 +274,259 @@ impl<'a> Evaluator<'a> {
         current_filename: impl Into<String>,
     ) -> Self {
         let current_filename = current_filename.into();
-        Self {
-            program,
+        let mut evaluator = Self {
+            program: Rc::new(program),
             input_lines,
+            standard_input: None,
+            input_filename: current_filename.clone(),
+            input: Input::Lines,
             input_cursor: 0,
+            input_open: false,
+            argv_index: 1,
+            opened_file_operand: false,
             current_line_number: Cell::new(0),
-            current_line: None,
+            file_line_number: 0,
+            record: None,
             field_separator: " ".to_string(),
+            field_mode: FieldMode::Separator,
             output_field_separator: " ".to_string(),
             output_record_separator: "\n".to_string(),
+            conversion_format: "%.6g".to_string(),
+            output_format: "%.6g".to_string(),
+            subscript_separator: "\u{1c}".to_string(),
             current_filename: current_filename.clone(),
-            variables: HashMap::new(),
-            numeric_variables: HashMap::new(),
-            array_variables: HashMap::new(),
-            array_aliases: HashMap::new(),
-            argv: vec!["rawk".to_string(), current_filename],
-            pipe_outputs: HashMap::new(),
+            globals: Vec::new(),
+            global_slots: HashMap::new(),
+            frames: Vec::new(),
+            output_pipes: HashMap::new(),
+            output_files: HashMap::new(),
             rng_state: Cell::new(9),
+            rand_seed: 0.0,
             printf_buffer: String::new(),
             expression_output: Vec::new(),
+            output: Vec::new(),
             exited: false,
             next_record: false,
+            next_file: false,
             break_loop: false,
             continue_loop: false,
             return_value: None,
             has_output: false,
             runtime_error: None,
-        }
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            step_limit: None,
+            steps: 0,
+            deadline: None,
+            cancellation: None,
+            cancelled: false,
+            char_mode: CharMode::default(),
+            csv: false,
+            output_mode: OutputMode::default(),
+            debugger: None,
+            statement_offsets: Vec::new(),
+            statement_indices: HashMap::new(),
+            statement_offset: 0,
+            call_stack: Vec::new(),
+            profiler: None,
+            tree_walker: false,
+            resolved: false,
+            compiled: None,
+            stack: Vec::new(),
+            key_iterators: Vec::new(),
+            argument_arrays: Vec::new(),
+            dynamic_patterns: HashMap::new(),
+            spare_frames: Vec::new(),
+        };
+        evaluator.set_arguments(vec![current_filename]);
+        evaluator.set_environ(std::env::vars());
+        evaluator
     }
 
+    /// Set `FS` the way `-F` does: `t` stands for a tab and escape sequences
+    /// such as `\t` are processed.
     pub fn with_field_separator(mut self, fs: String) -> Self {
-        self.field_separator = fs;
+        self.field_separator = if fs == "t" {
+            "\t".to_string()
+        } else {
+            unescape_awk_string(&fs)
+        };
+        self
+    }
+
+    /// Replace the operands exposed as `ARGV[1]`, `ARGV[2]`, … and processed by
+    /// the main input loop.
+    ///
+    /// Operands of the form `name=value` are treated as variable assignments
+    /// when the main loop reaches them; every other operand names an input
+    /// file. The operand equal to the filename given to [`Evaluator::new`] (or
+    /// `-`) reads the in-memory input lines instead of the file system.
+    pub fn with_arguments(mut self, arguments: Vec<String>) -> Self {
+        self.set_arguments(arguments);
+        self
+    }
+
+    /// Read the input lines from `input`, such as standard input, instead of
+    /// taking them from [`Evaluator::new`]. It is read a line at a time as
+    /// records are needed, once `ARGV` is found to name `-` or no input file
+    /// at all, after the `BEGIN` actions have run. Bytes that are not valid
+    /// UTF-8 are replaced with U+FFFD.
+    pub fn with_standard_input(mut self, input: impl BufRead + 'a) -> Self {
+        self.standard_input = Some(Box::new(input));
+        self
+    }
+
+    /// Choose whether string built-ins and `printf` count code points or
+    /// bytes. Defaults to [`CharMode::Utf8`].
+    pub fn with_char_mode(mut self, mode: CharMode) -> Self {
+        self.char_mode = mode;
+        self
+    }
+
+    /// Read input as CSV: records may span lines inside quoted fields and
+    /// are split into fields following RFC 4180 instead of by `FS`. In this
+    /// mode `split(s, a, ",")` splits `s` the same way.
+    pub fn with_csv(mut self, csv: bool) -> Self {
+        self.csv = csv;
+        self
+    }
+
+    /// Quote or escape the fields written by `print` so that CSV or TSV
+    /// output can be read back unchanged. See [`OutputMode`].
+    pub fn with_output_mode(mut self, mode: OutputMode) -> Self {
+        self.output_mode = mode;
+        if let Some(separator) = mode.field_separator() {
+            self.output_field_separator = separator.to_string();
+        }
+        self
+    }
+
+    /// Limit how deeply user-defined functions may call each other. Exceeding
+    /// the limit stops the program with a runtime error instead of overflowing
+    /// the stack. Defaults to [`DEFAULT_MAX_CALL_DEPTH`].
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    /// Stop the program with a runtime error once it has executed `steps`
+    /// steps. Every statement, loop iteration and input record counts as one
+    /// step.
+    pub fn with_step_limit(mut self, steps: u64) -> Self {
+        self.step_limit = Some(steps);
+        self
+    }
+
+    /// Stop the program with a runtime error once `deadline` has passed.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Check `token` while running: in the main record loop, on every loop
+    /// iteration and on every function call. Once the token is cancelled the
+    /// program stops without running its `END` actions, [`Evaluator::eval`]
+    /// returns the output produced so far and [`Evaluator::was_cancelled`]
+    /// reports `true`.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Replace the contents of the `ENVIRON` array, which defaults to the
+    /// environment of the current process.
+    pub fn with_environ<K, V>(mut self, environ: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.set_environ(environ);
+        self
+    }
+
+    /// Call `debugger` before each statement of the program runs.
+    /// `statement_offsets` gives the source offset of each statement, as
+    /// returned by [`Parser::statement_offsets`](crate::Parser::statement_offsets)
+    /// for the program.
+    pub fn with_debugger(
+        mut self,
+        debugger: impl Debugger + 'a,
+        statement_offsets: &[usize],
+    ) -> Self {
+        for statements in self.program.statement_lists() {
+            visit_statements(statements, &mut |statement| {
+                let index = self.statement_indices.len();
+                self.statement_indices.insert(address(statement), index);
+            });
+        }
+        self.statement_offsets = statement_offsets.to_vec();
+        self.debugger = Some(Box::new(debugger));
+        self
+    }
+
+    /// Count the runs of each rule, statement and function of the program
+    /// and the time spent in them, for [`Evaluator::profile`].
+    pub fn with_profiling(mut self) -> Self {
+        self.profiler = Some(Profiler::new(&self.program));
+        self
+    }
+
+    /// Run the program by walking its syntax tree instead of compiling it to
+    /// bytecode first. This is slower, and is kept as the reference the
+    /// bytecode is tested against.
+    pub fn with_tree_walker(mut self) -> Self {
+        self.tree_walker = true;
         self
     }
 
+    fn set_arguments(&mut self, arguments: Vec<String>) {
+        let argv = Name::new("ARGV");
+        self.clear_array(&argv);
+        self.set_array_element(&argv, "0", Value::String("rawk".to_string()));
+        for (index, argument) in arguments.into_iter().enumerate() {
+            self.set_array_element(&argv, &(index + 1).to_string(), Value::from_input(argument));
+        }
+        let argc = self.array_len(&argv);
+        self.set_variable(&Name::new("ARGC"), Value::Number(argc as f64));
+    }
+
+    fn set_environ<K, V>(&mut self, environ: impl IntoIterator<Item = (K, V)>)
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let environ_array = Name::new("ENVIRON");
+        self.clear_array(&environ_array);
+        for (name, value) in environ {
+            self.set_array_element(&environ_array, &name.into(), Value::from_input(value));
+        }
+    }
+
     pub fn runtime_error(&self) -> Option<&str> {
         self.runtime_error.as_deref()
     }
 
-    pub fn eval(&mut self) -> Vec<String> {
-        let mut output_lines: Vec<String> = Vec::new();
+    /// The costs gathered so far, if profiling was turned on with
+    /// [`Evaluator::with_profiling`].
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profiler.as_ref().map(Profiler::profile)
+    }
+
+    /// Whether the last [`Evaluator::eval`] was stopped through its
+    /// [`CancellationToken`].
+    pub fn was_cancelled(&self) -> bool {
+        self.cancelled
+    }
 
-        let begin_actions: Vec<Action<'a>> = self.program.begin_blocks_iter().cloned().collect();
-        for action in begin_actions.iter() {
-            output_lines.extend(self.eval_action(action, None));
+    pub fn eval(&mut self) -> Vec<String> {
+        self.resolve();
+        self.compile();
+        self.output.clear();
+
+        let program = Rc::clone(&self.program);
+        let numbers = RuleNumbers::new(&program);
+        for (number, action) in program.begin_blocks_iter().enumerate() {
+            let output = self.eval_action(Some(number), action);
+            self.output.extend(output);
             if self.exited || self.runtime_error.is_some() {
                 break;
             }
@@ -106,24 +537,49 @@ impl<'a> Evaluator<'a> {
             return vec![];
         }
 
-        let rules: Vec<Rule<'a>> = self.program.rules_iter().cloned().collect();
+        let rules: Vec<&Rule<'a>> = program.rules_iter().collect();
+        let begin_file_actions: Vec<&Action<'a>> = program.begin_file_blocks_iter().collect();
+        let end_file_actions: Vec<&Action<'a>> = program.end_file_blocks_iter().collect();
         let mut range_state = vec![false; rules.len()];
-        while let Some(input_line) = self.read_next_input_record() {
-            if self.exited {
+        let reads_input = !rules.is_empty()
+            || !begin_file_actions.is_empty()
+            || !end_file_actions.is_empty()
+            || program.end_blocks_iter().next().is_some();
+        while reads_input && !self.exited {
+            if self.poll_cancellation() {
                 break;
             }
+            if !self.input_open {
+                if !self.open_next_input_file() {
+                    break;
+                }
+                // `nextfile` in BEGINFILE skips the whole file without
+                // running ENDFILE.
+                let output = self.eval_file_actions(numbers.begin_file, &begin_file_actions);
+                self.output.extend(output);
+                if self.next_file {
+                    self.next_file = false;
+                    self.input_open = false;
+                }
+                continue;
+            }
+            if !self.read_record_from_current_file() {
+                let output = self.eval_file_actions(numbers.end_file, &end_file_actions);
+                self.output.extend(output);
+                continue;
+            }
+            self.count_step();
 
             for (rule_idx, rule) in rules.iter().enumerate() {
                 if self.exited || self.runtime_error.is_some() {
                     break;
                 }
-                // Rules are evaluated against the original record text for this
-                // interpreter's current semantics.
-                output_lines.extend(self.eval_rule_for_line(
+                let output = self.eval_rule_for_line(
+                    numbers.rules + rule_idx,
                     rule,
-                    &input_line,
                     &mut range_state[rule_idx],
-                ));
+                );
+                self.output.extend(output);
                 if self.next_record {
                     self.next_record = false;
                     break;
@@ -132,6 +588,12 @@ impl<'a> Evaluator<'a> {
             if self.runtime_error.is_some() {
                 break;
             }
+            if self.next_file {
+                self.next_file = false;
+                self.input_open = false;
+                let output = self.eval_file_actions(numbers.end_file, &end_file_actions);
+                self.output.extend(output);
+            }
         }
 
         if let Some(ref err) = self.runtime_error {
@@ -139,20 +601,28 @@ impl<'a> Evaluator<'a> {
             return vec![];
         }
 
-        if !self.exited {
-            self.current_line_number.set(self.input_lines.len());
-        }
-        self.current_line = None;
+        self.record = None;
 
-        let end_actions: Vec<Action<'a>> = self.program.end_blocks_iter().cloned().collect();
+        let end_actions: Vec<&Action<'a>> = if self.cancelled {
+            Vec::new()
+        } else {
+            program.end_blocks_iter().collect()
+        };
         self.exited = false;
-        for action in end_actions.iter() {
-            output_lines.extend(self.eval_action(action, None));
-            if self.exited {
+        for (number, action) in end_actions.into_iter().enumerate() {
+            let output = self.eval_action(Some(numbers.end + number), action);
+            self.output.extend(output);
+            if self.exited || self.runtime_error.is_some() {
                 break;
             }
         }
 
+        if let Some(ref err) = self.runtime_error {
+            eprintln!("rawk: {err}");
+            return vec![];
+        }
+
+        let mut output_lines = std::mem::take(&mut self.output);
         if !self.printf_buffer.is_empty() {
             let pending_printf = std::mem::take(&mut self.printf_buffer);
             self.append_generated_output(&mut output_lines, vec![pending_printf]);
@@ -161,42 +631,378 @@ impl<'a> Evaluator<'a> {
         for line in self.flush_pipe_outputs() {
             self.append_generated_output(&mut output_lines, vec![line]);
         }
+        self.close_output_files();
 
         normalize_output_lines(output_lines)
     }
 
-    fn read_next_input_record(&mut self) -> Option<String> {
-        let input_line = self.input_lines.get(self.input_cursor)?.clone();
-        self.input_cursor += 1;
-        self.current_line_number.set(self.input_cursor);
-        self.current_line = Some(input_line.clone());
-        self.variables.remove("NF");
-        self.numeric_variables.remove("NF");
-        Some(input_line)
+    /// The program being run, which a [`Session`](crate::Session) extends
+    /// between inputs.
+    pub(crate) fn program_mut(&mut self) -> &mut Program<'a> {
+        self.resolved = false;
+        self.compiled = None;
+        Rc::make_mut(&mut self.program)
+    }
+
+    /// Resolve the names in the program to the variables they refer to.
+    fn resolve(&mut self) {
+        if self.resolved {
+            return;
+        }
+        let program = Rc::clone(&self.program);
+        resolve::resolve_program(&program, &mut |name| self.global_slot(name));
+        self.resolved = true;
+    }
+
+    /// Compile the program to bytecode, unless it runs on the tree walker.
+    fn compile(&mut self) {
+        if self.compiled.is_some() || self.tree_walker {
+            return;
+        }
+        let program = Rc::clone(&self.program);
+        let compiled = compile::compile(&program, &mut |name| self.global_slot(name));
+        self.compiled = Some(Rc::new(compiled));
+    }
+
+    /// Run `action` on its own, the way a `BEGIN` action runs.
+    pub(crate) fn run_action(&mut self, action: &Action<'_>) -> Result<Vec<String>, String> {
+        let output = self.eval_action(None, action);
+        self.finish_step(output)
+    }
+
+    /// Open `filename` as the input of the main loop, in place of the
+    /// operands in `ARGV`, and run the `BEGINFILE` actions.
+    pub(crate) fn open_input(&mut self, filename: String) -> Result<Vec<String>, String> {
+        self.opened_file_operand = true;
+        let output = if self.open_input_file(filename) {
+            let program = Rc::clone(&self.program);
+            let begin_file_actions: Vec<&Action<'a>> = program.begin_file_blocks_iter().collect();
+            self.eval_file_actions(RuleNumbers::new(&program).begin_file, &begin_file_actions)
+        } else {
+            Vec::new()
+        };
+        self.finish_step(output)
+    }
+
+    /// Whether the input opened by [`Evaluator::open_input`] may have more
+    /// records.
+    pub(crate) fn has_input(&self) -> bool {
+        self.input_open
+    }
+
+    /// Read the next input record and run the rules over it, keeping the
+    /// state of range patterns in `range_state`. Once the input runs out the
+    /// `ENDFILE` actions run instead.
+    pub(crate) fn run_next_record(
+        &mut self,
+        range_state: &mut Vec<bool>,
+    ) -> Result<Vec<String>, String> {
+        if !self.input_open {
+            return Ok(Vec::new());
+        }
+        let program = Rc::clone(&self.program);
+        let numbers = RuleNumbers::new(&program);
+        let end_file_actions: Vec<&Action<'a>> = program.end_file_blocks_iter().collect();
+        if !self.read_record_from_current_file() {
+            let output = self.eval_file_actions(numbers.end_file, &end_file_actions);
+            return self.finish_step(output);
+        }
+        self.count_step();
+
+        range_state.resize(program.rules_iter().len(), false);
+        let mut output = Vec::new();
+        for (rule_idx, (rule, range_active)) in
+            program.rules_iter().zip(range_state.iter_mut()).enumerate()
+        {
+            if self.exited || self.runtime_error.is_some() {
+                break;
+            }
+            output.extend(self.eval_rule_for_line(numbers.rules + rule_idx, rule, range_active));
+            if self.next_record {
+                self.next_record = false;
+                break;
+            }
+        }
+        if self.next_file {
+            self.next_file = false;
+            self.input_open = false;
+            output.extend(self.eval_file_actions(numbers.end_file, &end_file_actions));
+        }
+        self.finish_step(output)
+    }
+
+    /// Run the `END` actions.
+    pub(crate) fn run_end_actions(&mut self) -> Result<Vec<String>, String> {
+        self.record = None;
+        self.exited = false;
+        let program = Rc::clone(&self.program);
+        let numbers = RuleNumbers::new(&program);
+        let mut output = Vec::new();
+        for (number, action) in program.end_blocks_iter().enumerate() {
+            output.extend(self.eval_action(Some(numbers.end + number), action));
+            if self.exited || self.runtime_error.is_some() {
+                break;
+            }
+        }
+        self.finish_step(output)
+    }
+
+    /// Whether an `exit` statement has run.
+    pub(crate) fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// The global scalar variables that have been assigned, sorted by name.
+    pub(crate) fn global_variables(&self) -> Vec<(&str, &Value)> {
+        let mut variables: Vec<(&str, &Value)> = self
+            .global_slots
+            .iter()
+            .filter_map(|(name, &slot)| Some((name.as_str(), self.globals[slot].value.as_ref()?)))
+            .collect();
+        variables.sort_by_key(|(name, _)| *name);
+        variables
+    }
+
+    /// The global arrays and their number of elements, sorted by name.
+    pub(crate) fn global_arrays(&self) -> Vec<(&str, usize)> {
+        let mut arrays: Vec<(&str, usize)> = self
+            .global_slots
+            .iter()
+            .filter_map(|(name, &slot)| {
+                let global = &self.globals[slot];
+                let len = global.array.as_ref()?.borrow().len();
+                // Passing a scalar to a function sets up an array in case the
+                // function uses it as one.
+                (len > 0 || global.value.is_none()).then_some((name.as_str(), len))
+            })
+            .collect();
+        arrays.sort_by_key(|(name, _)| *name);
+        arrays
+    }
+
+    /// The elements of the global array `name`, in no particular order.
+    pub(crate) fn array_elements(&self, name: &str) -> Option<Vec<(String, Value)>> {
+        self.global(name)?.array.as_ref().map(|array| {
+            array
+                .borrow()
+                .iter()
+                .map(|(subscript, value)| (subscript.clone(), value.clone()))
+                .collect()
+        })
+    }
+
+    /// Collect the output still held back after running a piece of the
+    /// program on its own, and return any runtime error instead, clearing it
+    /// so that evaluation can go on.
+    fn finish_step(&mut self, mut output: Vec<String>) -> Result<Vec<String>, String> {
+        self.next_record = false;
+        self.clear_loop_control_flags();
+        if let Some(err) = self.runtime_error.take() {
+            self.printf_buffer.clear();
+            return Err(err);
+        }
+        if !self.printf_buffer.is_empty() {
+            output.push(std::mem::take(&mut self.printf_buffer));
+        }
+        output.extend(self.flush_pipe_outputs());
+        Ok(normalize_output_lines(output))
+    }
+
+    fn read_next_input_record(&mut self) -> bool {
+        loop {
+            if self.read_record_from_current_file() {
+                return true;
+            }
+
+            if !self.open_next_input_file() {
+                return false;
+            }
+        }
+    }
+
+    /// Read the next record of the open input file, closing the file once it
+    /// runs out.
+    fn read_record_from_current_file(&mut self) -> bool {
+        if !self.input_open {
+            return false;
+        }
+        let Some(mut input_line) = self.read_input_line() else {
+            self.input_open = false;
+            return false;
+        };
+        while self.csv && csv::has_open_quote(&input_line) {
+            let Some(next_line) = self.read_input_line() else {
+                break;
+            };
+            input_line.push('\n');
+            input_line.push_str(&next_line);
+        }
+        self.file_line_number += 1;
+        self.current_line_number
+            .set(self.current_line_number.get() + 1);
+        self.set_record(input_line);
+        true
+    }
+
+    /// Read the next line of the open input file without its line ending.
+    /// A failed read stops the program with a runtime error.
+    fn read_input_line(&mut self) -> Option<String> {
+        let reader: &mut dyn BufRead = match &mut self.input {
+            Input::Lines => {
+                let line = self.input_lines.get(self.input_cursor).cloned()?;
+                self.input_cursor += 1;
+                return Some(line);
+            }
+            Input::Standard => self.standard_input.as_mut()?,
+            Input::File(reader) => reader,
+        };
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.ends_with(b"\n") {
+                    line.pop();
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                }
+                Some(String::from_utf8_lossy(&line).into_owned())
+            }
+            Err(err) => {
+                self.runtime_error = Some(format!("can't read {}: {err}", self.current_filename));
+                None
+            }
+        }
+    }
+
+    /// Run BEGINFILE or ENDFILE actions, which see the `FILENAME` and `FNR`
+    /// of the file being opened or closed. `first` is the number of the first
+    /// action.
+    fn eval_file_actions(&mut self, first: usize, actions: &[&Action<'a>]) -> Vec<String> {
+        let mut output = Vec::new();
+        for (number, action) in (first..).zip(actions) {
+            output.extend(self.eval_action(Some(number), action));
+            if self.exited || self.runtime_error.is_some() || self.next_file {
+                break;
+            }
+        }
+        self.next_record = false;
+        output
+    }
+
+    /// Advance through `ARGV` to the next input file, applying any
+    /// `name=value` operands on the way. Reads the in-memory input when no
+    /// file operand is present at all.
+    fn open_next_input_file(&mut self) -> bool {
+        if self.runtime_error.is_some() {
+            return false;
+        }
+
+        loop {
+            let argc = self
+                .eval_identifier_expression(&Name::new("ARGC"))
+                .to_number();
+            if (self.argv_index as f64) >= argc {
+                break;
+            }
+            let operand = self
+                .find_array(&Name::new("ARGV"))
+                .and_then(|argv| argv.borrow().get(&self.argv_index.to_string()).cloned())
+                .map(|value| self.to_text(&value))
+                .unwrap_or_default();
+            self.argv_index += 1;
+
+            if operand.is_empty() {
+                continue;
+            }
+            if let Some((name, value)) = parse_command_line_assignment(&operand) {
+                self.set_variable(
+                    &Name::new(name),
+                    Value::from_input(unescape_awk_string(value)),
+                );
+                continue;
+            }
+
+            self.opened_file_operand = true;
+            return self.open_input_file(operand);
+        }
+
+        if self.opened_file_operand {
+            return false;
+        }
+        self.opened_file_operand = true;
+        let filename = self.input_filename.clone();
+        self.open_input_file(filename)
+    }
+
+    fn open_input_file(&mut self, filename: String) -> bool {
+        if filename == "-" || filename == self.input_filename {
+            self.input = if self.standard_input.is_some() {
+                Input::Standard
+            } else {
+                Input::Lines
+            };
+        } else {
+            match File::open(&filename) {
+                Ok(file) => self.input = Input::File(BufReader::new(file)),
+                Err(_) => {
+                    self.runtime_error = Some(format!("can't open file {filename}"));
+                    return false;
+                }
+            }
+        }
+
+        self.current_filename = filename;
+        self.input_cursor = 0;
+        self.file_line_number = 0;
+        self.input_open = true;
+        true
     }
 
+    /// Run rule `number` of the program over the current record.
     fn eval_rule_for_line(
         &mut self,
+        number: usize,
+        rule: &Rule,
+        range_active: &mut bool,
+    ) -> Vec<String> {
+        if self.profiler.is_some() {
+            let started = Instant::now();
+            let output = self.eval_rule_match(number, rule, range_active);
+            if let Some(profiler) = &mut self.profiler {
+                profiler.add_rule(number, started.elapsed());
+            }
+            return output;
+        }
+        self.eval_rule_match(number, rule, range_active)
+    }
+
+    fn eval_rule_match(
+        &mut self,
+        number: usize,
         rule: &Rule,
-        input_line: &str,
         range_active: &mut bool,
     ) -> Vec<String> {
         match rule {
-            Rule::Action(action) => self.eval_action(action, Some(input_line)),
+            Rule::Action(action) => self.eval_action_statements(Some(number), action),
             Rule::PatternAction { pattern, action } => {
-                let matches = match pattern.as_ref() {
-                    Some(expr) => self.eval_pattern_condition(expr, range_active),
-                    None => true,
+                let compiled = self.compiled.clone();
+                let matches = match (pattern.as_ref(), compiled.as_deref()) {
+                    (None, _) => true,
+                    (Some(_), Some(compiled)) => {
+                        self.test_rule_pattern(compiled, &compiled.rules[number], range_active)
+                    }
+                    (Some(expr), None) => self.eval_pattern_condition(expr, range_active),
                 };
                 if !matches {
                     return Vec::new();
                 }
 
                 if let Some(action) = action {
-                    self.eval_action(action, Some(input_line))
+                    self.eval_action_statements(Some(number), action)
                 } else {
                     let mut output = Vec::new();
-                    output.push(input_line.to_string());
+                    output.push(self.record_text().unwrap_or_default().to_string());
                     self.append_output_record_separator(&mut output);
                     output
                 }
@@ -241,14 +1047,56 @@ impl<'a> Evaluator<'a> {
             || self.break_loop
             || self.continue_loop
             || self.return_value.is_some()
+            || self.runtime_error.is_some()
     }
 
     fn should_break_loop_iteration(&self) -> bool {
-        self.exited || self.next_record || self.break_loop || self.return_value.is_some()
+        self.exited
+            || self.next_record
+            || self.break_loop
+            || self.return_value.is_some()
+            || self.runtime_error.is_some()
     }
 
     fn should_break_function_body(&self) -> bool {
-        self.exited || self.next_record || self.return_value.is_some()
+        self.exited
+            || self.next_record
+            || self.return_value.is_some()
+            || self.runtime_error.is_some()
+    }
+
+    /// Stop the program like `exit` would, minus the `END` actions, once the
+    /// cancellation token has been triggered.
+    fn poll_cancellation(&mut self) -> bool {
+        if !self.cancelled
+            && self
+                .cancellation
+                .as_ref()
+                .is_some_and(CancellationToken::is_cancelled)
+        {
+            self.cancelled = true;
+            self.exited = true;
+        }
+        self.cancelled
+    }
+
+    /// Account for one step of execution, recording a runtime error once the
+    /// step limit or the deadline has been exceeded.
+    fn count_step(&mut self) {
+        self.steps += 1;
+        if self.runtime_error.is_some() {
+            return;
+        }
+
+        if self.step_limit.is_some_and(|limit| self.steps > limit) {
+            self.runtime_error = Some("step limit exceeded".to_string());
+        } else if self.steps.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.runtime_error = Some("deadline exceeded".to_string());
+        }
     }
 
     fn clear_loop_control_flags(&mut self) {
@@ -256,15 +1104,15 @@ impl<'a> Evaluator<'a> {
         self.continue_loop = false;
     }
 
-    fn eval_statement_block(
-        &mut self,
-        statements: &[Statement<'_>],
-        input_line: Option<&str>,
-    ) -> Vec<String> {
+    fn eval_statement_block(&mut self, statements: &[Statement<'_>]) -> Vec<String> {
+        self.count_step();
+        if self.poll_cancellation() {
+            return Vec::new();
+        }
         let mut output = Vec::new();
 
         for statement in statements {
-            let statement_output = self.eval_statement(statement, input_line);
+            let statement_output = self.eval_statement(statement);
             self.append_local_output(&mut output, statement_output);
             if self.should_break_statement_sequence() {
                 break;
@@ -274,11 +1122,35 @@ impl<'a> Evaluator<'a> {
         output
     }
 
-    fn eval_action(&mut self, action: &Action, input_line: Option<&str>) -> Vec<String> {
-        let mut output = Vec::new();
-
+    /// Run the action of a `BEGIN`, `BEGINFILE`, `ENDFILE` or `END` rule.
+    /// `number` is the number of the rule, or `None` for an action from
+    /// outside the program.
+    fn eval_action(&mut self, number: Option<usize>, action: &Action) -> Vec<String> {
+        if let Some(number) = number
+            && self.profiler.is_some()
+        {
+            let started = Instant::now();
+            let output = self.eval_action_statements(Some(number), action);
+            if let Some(profiler) = &mut self.profiler {
+                profiler.add_rule(number, started.elapsed());
+            }
+            return output;
+        }
+        self.eval_action_statements(number, action)
+    }
+
+    fn eval_action_statements(&mut self, number: Option<usize>, action: &Action) -> Vec<String> {
+        if let (Some(compiled), Some(number)) = (self.compiled.clone(), number) {
+            let code = compiled.rules[number]
+                .action
+                .as_ref()
+                .expect("the action of a compiled rule is compiled");
+            return self.run_code(&compiled, code);
+        }
+        let mut output = Vec::new();
+
         for statement in &action.statements {
-            let statement_output = self.eval_statement(statement, input_line);
+            let statement_output = self.eval_statement(statement);
             if statement_output.is_empty() {
                 if self.should_break_statement_sequence() {
                     break;
@@ -294,6 +1166,77 @@ impl<'a> Evaluator<'a> {
         output
     }
 
+    /// Call the debugger before `statement` runs, if it is a statement of the
+    /// program.
+    fn enter_statement(&mut self, statement: &Statement<'_>) {
+        if let Some(&index) = self.statement_indices.get(&address(statement)) {
+            self.enter_statement_at(index);
+        }
+    }
+
+    /// Call the debugger before statement `index` of the program runs.
+    fn enter_statement_at(&mut self, index: usize) {
+        let Some(&offset) = self.statement_offsets.get(index) else {
+            return;
+        };
+        self.statement_offset = offset;
+        let Some(mut debugger) = self.debugger.take() else {
+            return;
+        };
+        let flow = debugger.before_statement(&mut DebugContext::new(self, offset));
+        self.debugger = Some(debugger);
+        if flow.is_break() {
+            self.cancelled = true;
+            self.exited = true;
+        }
+    }
+
+    pub(crate) fn call_stack(&self) -> &[Call] {
+        &self.call_stack
+    }
+
+    pub(crate) fn current_record(&mut self) -> String {
+        self.record_text().unwrap_or_default().to_string()
+    }
+
+    pub(crate) fn field_text(&mut self, index: usize) -> String {
+        if index == 0 {
+            return self.current_record();
+        }
+        self.split_record(Some(index));
+        self.record
+            .as_ref()
+            .and_then(|record| record.field(index))
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// The value of the variable `name`, without the side effects of reading
+    /// `getline`.
+    pub(crate) fn variable_value(&mut self, name: &str) -> Value {
+        if name == "getline" {
+            return Value::Uninitialized;
+        }
+        self.eval_identifier_expression(&Name::new(name))
+    }
+
+    /// The output printed since the last call, leaving out a `printf` line
+    /// that is not finished yet.
+    pub(crate) fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.output).concat()
+    }
+
+    /// The elements of the local or global array `name`.
+    pub(crate) fn visible_array_elements(&self, name: &str) -> Option<Vec<(String, Value)>> {
+        self.find_array(&Name::new(name)).map(|array| {
+            array
+                .borrow()
+                .iter()
+                .map(|(subscript, value)| (subscript.clone(), value.clone()))
+                .collect()
+        })
+    }
+
     fn append_generated_output(&mut self, output: &mut Vec<String>, generated: Vec<String>) {
         if generated.is_empty() {
             return;
@@ -309,11 +1252,36 @@ impl<'a> Evaluator<'a> {
         output.extend(generated);
     }
 
-    fn eval_statement(
-        &mut self,
-        statement: &Statement<'_>,
-        input_line: Option<&str>,
-    ) -> Vec<String> {
+    fn eval_statement(&mut self, statement: &Statement<'_>) -> Vec<String> {
+        self.count_step();
+        if self.debugger.is_some() {
+            self.enter_statement(statement);
+            if self.cancelled {
+                return Vec::new();
+            }
+        }
+        let profiled = self
+            .profiler
+            .as_ref()
+            .and_then(|profiler| profiler.statement(statement));
+        let output = if let Some(index) = profiled {
+            let started = Instant::now();
+            let output = self.eval_statement_kind(statement);
+            if let Some(profiler) = &mut self.profiler {
+                profiler.add_statement(index, started.elapsed());
+            }
+            output
+        } else {
+            self.eval_statement_kind(statement)
+        };
+        if self.debugger.is_some() {
+            self.output.extend(output);
+            return Vec::new();
+        }
+        output
+    }
+
+    fn eval_statement_kind(&mut self, statement: &Statement<'_>) -> Vec<String> {
         let output = match statement {
             Statement::Empty => Vec::new(),
             Statement::Expression(expression) => match expression {
@@ -325,12 +1293,12 @@ impl<'a> Evaluator<'a> {
                     Vec::new()
                 }
             },
-            Statement::Print(expressions) => self.eval_print_statement(expressions, input_line),
+            Statement::Print(expressions) => self.eval_print_statement(expressions),
             Statement::PrintPipe {
                 expressions,
                 target,
             } => {
-                self.eval_print_pipe(expressions, target, input_line);
+                self.eval_print_pipe(expressions, target);
                 Vec::new()
             }
             Statement::PrintRedirect {
@@ -338,10 +1306,25 @@ impl<'a> Evaluator<'a> {
                 target,
                 append,
             } => {
-                self.eval_print_redirect(expressions, target, *append, input_line);
+                self.eval_print_redirect(expressions, target, *append);
                 Vec::new()
             }
             Statement::Printf(expressions) => self.eval_printf_statement(expressions),
+            Statement::PrintfRedirect {
+                expressions,
+                target,
+                append,
+            } => {
+                self.eval_printf_redirect(expressions, target, *append);
+                Vec::new()
+            }
+            Statement::PrintfPipe {
+                expressions,
+                target,
+            } => {
+                self.eval_printf_pipe(expressions, target);
+                Vec::new()
+            }
             Statement::System(command) => {
                 self.eval_system(command);
                 Vec::new()
@@ -357,8 +1340,9 @@ impl<'a> Evaluator<'a> {
             Statement::Sub {
                 pattern,
                 replacement,
+                target,
             } => {
-                self.eval_sub(pattern, replacement);
+                self.eval_substitution(pattern, replacement, target.as_ref(), false);
                 Vec::new()
             }
             Statement::Gsub {
@@ -366,11 +1350,12 @@ impl<'a> Evaluator<'a> {
                 replacement,
                 target,
             } => {
-                self.eval_gsub(pattern, replacement, target.as_ref());
+                self.eval_substitution(pattern, replacement, target.as_ref(), true);
                 Vec::new()
             }
             Statement::Assignment { identifier, value } => {
-                self.eval_assignment(identifier, value);
+                let value = self.eval_expression(value);
+                self.set_variable(identifier, value);
                 Vec::new()
             }
             Statement::SplitAssignment {
@@ -379,7 +1364,8 @@ impl<'a> Evaluator<'a> {
                 array,
                 separator,
             } => {
-                self.eval_split_assignment(identifier, string, array, separator.as_ref());
+                let count = self.eval_split(string, array, separator.as_ref());
+                self.set_variable(identifier, Value::Number(count as f64));
                 Vec::new()
             }
             Statement::ArrayAssignment {
@@ -387,15 +1373,20 @@ impl<'a> Evaluator<'a> {
                 index,
                 value,
             } => {
-                self.eval_array_assignment(identifier, index, value);
+                let (array, key) = self.array_key(identifier, index);
+                let value = self.eval_expression(value);
+                array.borrow_mut().insert(key, value);
                 Vec::new()
             }
             Statement::FieldAssignment { field, value } => {
-                self.eval_field_assignment(field, value);
+                let value = self.eval_expression(value);
+                self.assign_field(field, value);
                 Vec::new()
             }
             Statement::AddAssignment { identifier, value } => {
-                self.eval_add_assignment(identifier, value);
+                let increment = self.eval_expression(value).to_number();
+                let current = self.eval_identifier_expression(identifier).to_number();
+                self.set_variable(identifier, Value::Number(current + increment));
                 Vec::new()
             }
             Statement::ArrayAddAssignment {
@@ -403,35 +1394,33 @@ impl<'a> Evaluator<'a> {
                 index,
                 value,
             } => {
-                self.eval_array_add_assignment(identifier, index, value);
+                let (array, key) = self.array_key(identifier, index);
+                let increment = self.eval_expression(value).to_number();
+                let mut array = array.borrow_mut();
+                let element = array.entry(key).or_default();
+                *element = Value::Number(element.to_number() + increment);
                 Vec::new()
             }
             Statement::ArrayPostIncrement { identifier, index } => {
-                self.eval_array_post_increment(identifier, index, 1.0);
+                self.eval_array_increment(identifier, index, 1.0);
                 Vec::new()
             }
             Statement::ArrayPostDecrement { identifier, index } => {
-                self.eval_array_post_increment(identifier, index, -1.0);
+                self.eval_array_increment(identifier, index, -1.0);
                 Vec::new()
             }
             Statement::Delete { identifier, index } => {
                 self.eval_delete(identifier, index.as_ref());
                 Vec::new()
             }
-            Statement::PreIncrement { identifier } => {
-                self.eval_pre_increment(identifier);
-                Vec::new()
-            }
-            Statement::PreDecrement { identifier } => {
-                self.eval_pre_decrement(identifier);
-                Vec::new()
-            }
-            Statement::PostIncrement { identifier } => {
-                self.eval_post_increment(identifier);
+            Statement::PreIncrement { identifier } | Statement::PostIncrement { identifier } => {
+                let current = self.eval_identifier_expression(identifier).to_number();
+                self.set_variable(identifier, Value::Number(current + 1.0));
                 Vec::new()
             }
-            Statement::PostDecrement { identifier } => {
-                self.eval_post_decrement(identifier);
+            Statement::PreDecrement { identifier } | Statement::PostDecrement { identifier } => {
+                let current = self.eval_identifier_expression(identifier).to_number();
+                self.set_variable(identifier, Value::Number(current - 1.0));
                 Vec::new()
             }
             Statement::If {
@@ -439,15 +1428,7 @@ impl<'a> Evaluator<'a> {
                 then_statements,
             } => {
                 if self.eval_condition(condition) {
-                    let mut output = Vec::new();
-                    for statement in then_statements {
-                        let statement_output = self.eval_statement(statement, input_line);
-                        self.append_local_output(&mut output, statement_output);
-                        if self.should_break_statement_sequence() {
-                            break;
-                        }
-                    }
-                    output
+                    self.eval_statement_block(then_statements)
                 } else {
                     Vec::new()
                 }
@@ -462,15 +1443,7 @@ impl<'a> Evaluator<'a> {
                 } else {
                     else_statements
                 };
-                let mut output = Vec::new();
-                for statement in branch {
-                    let statement_output = self.eval_statement(statement, input_line);
-                    self.append_local_output(&mut output, statement_output);
-                    if self.should_break_statement_sequence() {
-                        break;
-                    }
-                }
-                output
+                self.eval_statement_block(branch)
             }
             Statement::While {
                 condition,
@@ -478,7 +1451,7 @@ impl<'a> Evaluator<'a> {
             } => {
                 let mut output = Vec::new();
                 while self.eval_condition(condition) {
-                    let statement_output = self.eval_statement_block(statements, input_line);
+                    let statement_output = self.eval_statement_block(statements);
                     self.append_local_output(&mut output, statement_output);
                     if self.should_break_loop_iteration() {
                         break;
@@ -496,7 +1469,7 @@ impl<'a> Evaluator<'a> {
             } => {
                 let mut output = Vec::new();
                 loop {
-                    let statement_output = self.eval_statement_block(statements, input_line);
+                    let statement_output = self.eval_statement_block(statements);
                     self.append_local_output(&mut output, statement_output);
                     if self.should_break_loop_iteration() {
                         break;
@@ -518,23 +1491,18 @@ impl<'a> Evaluator<'a> {
                 statements,
             } => {
                 let mut output = Vec::new();
-                let init_output = self.eval_statement(init, input_line);
+                let init_output = self.eval_statement(init);
                 self.append_local_output(&mut output, init_output);
                 if self.should_break_statement_sequence() {
                     return output;
                 }
                 while self.eval_condition(condition) {
-                    for statement in statements {
-                        let statement_output = self.eval_statement(statement, input_line);
-                        self.append_local_output(&mut output, statement_output);
-                        if self.should_break_statement_sequence() {
-                            break;
-                        }
-                    }
+                    let statement_output = self.eval_statement_block(statements);
+                    self.append_local_output(&mut output, statement_output);
                     if self.should_break_loop_iteration() {
                         break;
                     }
-                    let update_output = self.eval_statement(update, input_line);
+                    let update_output = self.eval_statement(update);
                     self.append_local_output(&mut output, update_output);
                     if self.continue_loop {
                         self.continue_loop = false;
@@ -555,14 +1523,9 @@ impl<'a> Evaluator<'a> {
                 keys.sort();
                 let mut output = Vec::new();
                 for key in keys {
-                    self.set_variable_text(variable, key);
-                    for statement in statements {
-                        let statement_output = self.eval_statement(statement, input_line);
-                        self.append_local_output(&mut output, statement_output);
-                        if self.should_break_statement_sequence() {
-                            break;
-                        }
-                    }
+                    self.set_variable(variable, Value::from_input(key));
+                    let statement_output = self.eval_statement_block(statements);
+                    self.append_local_output(&mut output, statement_output);
                     if self.should_break_loop_iteration() {
                         break;
                     }
@@ -595,6 +1558,11 @@ impl<'a> Evaluator<'a> {
                 self.next_record = true;
                 Vec::new()
             }
+            Statement::NextFile => {
+                self.next_record = true;
+                self.next_file = true;
+                Vec::new()
+            }
             Statement::Exit(status) => {
                 if let Some(status) = status {
                     let _ = self.eval_expression(status);
@@ -609,36 +1577,38 @@ impl<'a> Evaluator<'a> {
         expression_output
     }
 
-    fn eval_print(&mut self, expressions: &[Expression<'_>], input_line: Option<&str>) -> String {
+    /// Convert a value to text using `CONVFMT`.
+    fn to_text(&self, value: &Value) -> String {
+        value.to_string_with(&self.conversion_format)
+    }
+
+    /// Convert a value to text for `print`, which formats numbers with `OFMT`.
+    fn to_output_text(&self, value: Value) -> String {
+        match value {
+            Value::Number(number) => format_number(number, &self.output_format),
+            other => other.into_string_with(&self.conversion_format),
+        }
+    }
+
+    fn eval_print(&mut self, expressions: &[Expression<'_>]) -> String {
         if expressions.is_empty() {
-            return self
-                .current_line
-                .as_deref()
-                .or(input_line)
-                .unwrap_or("")
-                .to_string();
+            return self.record_text().unwrap_or_default().to_string();
         }
 
         let parts = expressions
             .iter()
-            .map(|expr| self.eval_expression(expr))
+            .map(|expr| {
+                let value = self.eval_expression(expr);
+                self.to_output_text(value)
+            })
             .collect::<Vec<String>>();
-        parts.join(&self.output_field_separator)
+        self.join_fields(&parts)
     }
 
-    fn eval_print_statement(
-        &mut self,
-        expressions: &[Expression<'_>],
-        input_line: Option<&str>,
-    ) -> Vec<String> {
+    fn eval_print_statement(&mut self, expressions: &[Expression<'_>]) -> Vec<String> {
         let pending_printf = std::mem::take(&mut self.printf_buffer);
         if expressions.is_empty() {
-            let line = self
-                .current_line
-                .as_deref()
-                .or(input_line)
-                .unwrap_or("")
-                .to_string();
+            let line = self.record_text().unwrap_or_default().to_string();
             let mut output = vec![format!("{pending_printf}{line}")];
             self.append_output_record_separator(&mut output);
             return output;
@@ -647,18 +1617,21 @@ impl<'a> Evaluator<'a> {
         let mut output = Vec::new();
         let mut parts = Vec::new();
         for expression in expressions {
-            parts.push(self.eval_expression(expression));
+            let value = self.eval_expression(expression);
+            parts.push(self.to_output_text(value));
             output.extend(self.take_expression_output());
         }
-        let rendered = format!(
-            "{pending_printf}{}",
-            parts.join(&self.output_field_separator)
-        );
+        let rendered = format!("{pending_printf}{}", self.join_fields(&parts));
         output.push(rendered);
         self.append_output_record_separator(&mut output);
         output
     }
 
+    /// Join output fields with `OFS` as the output mode requires.
+    fn join_fields(&self, fields: &[String]) -> String {
+        self.output_mode.join(fields, &self.output_field_separator)
+    }
+
     fn take_expression_output(&mut self) -> Vec<String> {
         std::mem::take(&mut self.expression_output)
     }
@@ -670,42 +1643,40 @@ impl<'a> Evaluator<'a> {
         );
 
         let format = self.eval_expression(&expressions[0]);
-        let format = unescape_awk_string(&format);
-        let args: Vec<String> = expressions
+        let format = unescape_awk_string(&self.to_text(&format));
+        let args: Vec<Value> = expressions
             .iter()
             .skip(1)
-            .map(|expr| unescape_awk_string(&self.eval_printf_argument(expr)))
+            .map(|expr| self.eval_expression(expr))
             .collect();
 
-        format_printf(&format, &args)
-    }
-
-    fn eval_printf_argument(&mut self, expression: &Expression<'_>) -> String {
-        match expression {
-            Expression::Number(_)
-            | Expression::HexNumber { .. }
-            | Expression::Infix { .. }
-            | Expression::Rand
-            | Expression::Length(_) => self
-                .eval_numeric_expression(expression)
-                .map(|value| value.to_string())
-                .unwrap_or_else(|| self.eval_expression(expression)),
-            _ => self.eval_expression(expression),
-        }
+        format_printf(&format, &args, &self.conversion_format, self.char_mode)
     }
 
     fn eval_printf_statement(&mut self, expressions: &[Expression<'_>]) -> Vec<String> {
         let rendered = self.eval_printf(expressions);
+        self.buffer_printf(rendered)
+    }
+
+    /// Add the output of `printf` to the text held back until a newline, and
+    /// return the lines it completes.
+    fn buffer_printf(&mut self, rendered: String) -> Vec<String> {
         if rendered.is_empty() {
             return Vec::new();
         }
+        // Only the text just added can finish a line.
+        let start = self.printf_buffer.len();
         self.printf_buffer.push_str(&rendered);
+        let Some(end) = self.printf_buffer[start..].rfind('\n') else {
+            return Vec::new();
+        };
+        let end = start + end;
         let mut output = Vec::new();
-        while let Some(index) = self.printf_buffer.find('\n') {
-            output.push(self.printf_buffer[..index].trim_end().to_string());
+        for line in self.printf_buffer[..end].split('\n') {
+            output.push(line.trim_end().to_string());
             output.push("\n".to_string());
-            self.printf_buffer = self.printf_buffer[index + 1..].to_string();
         }
+        self.printf_buffer.drain(..=end);
         output
     }
 
@@ -713,236 +1684,317 @@ impl<'a> Evaluator<'a> {
         &mut self,
         expressions: &[Expression<'_>],
         target: &Expression<'_>,
-        _append: bool,
-        input_line: Option<&str>,
+        append: bool,
     ) {
-        let _rendered = self.eval_print(expressions, input_line);
-        let _target = self.eval_expression(target);
+        let mut rendered = self.eval_print(expressions);
+        rendered.push_str(&self.output_record_separator);
+        let target = self.eval_expression(target);
+        let target = self.to_text(&target);
+        self.write_to_file(target, &rendered, append);
+    }
+
+    fn eval_print_pipe(&mut self, expressions: &[Expression<'_>], target: &Expression<'_>) {
+        let mut rendered = self.eval_print(expressions);
+        rendered.push_str(&self.output_record_separator);
+        let target = self.eval_expression(target);
+        let target = self.to_text(&target);
+        self.write_to_pipe(target, rendered);
     }
 
-    fn eval_print_pipe(
+    fn eval_printf_redirect(
         &mut self,
         expressions: &[Expression<'_>],
         target: &Expression<'_>,
-        input_line: Option<&str>,
+        append: bool,
     ) {
-        let mut rendered = self.eval_print(expressions, input_line);
-        rendered.push_str(&self.output_record_separator);
+        let rendered = self.eval_printf(expressions);
         let target = self.eval_expression(target);
-        self.pipe_outputs.entry(target).or_default().push(rendered);
-    }
-
-    fn eval_assignment(&mut self, identifier: &str, value: &Expression<'_>) {
-        match value {
-            Expression::String(value) => {
-                self.set_variable_text(identifier, unescape_awk_string(value))
-            }
-            Expression::Infix {
-                left,
-                operator,
-                right,
-            } if operator.kind == TokenKind::Assign => {
-                let assigned_value = self.eval_assignment_infix(left, right);
-                self.set_variable_text(identifier, assigned_value);
-            }
-            _ if expression_has_precise_numeric_value(value) => {
-                let assigned_value = self.eval_numeric_expression(value).unwrap_or(0.0);
-                self.set_variable_numeric(identifier, assigned_value);
-            }
-            _ => {
-                let assigned_value = self.eval_expression(value);
-                self.set_variable_text(identifier, assigned_value);
+        let target = self.to_text(&target);
+        self.write_to_file(target, &rendered, append);
+    }
+
+    /// Write `text` to the file named `target`, opening it first if it is not
+    /// open yet: truncated by `>`, or for appending by `>>`. Later writes of
+    /// either kind add to the open file.
+    fn write_to_file(&mut self, target: String, text: &str, append: bool) {
+        let file = match self.output_files.entry(target) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let opened = if append {
+                    OpenOptions::new()
+                        .append(true)
+                        .create(true)
+                        .open(entry.key())
+                } else {
+                    File::create(entry.key())
+                };
+                match opened {
+                    Ok(file) => entry.insert(BufWriter::new(file)),
+                    Err(_) => {
+                        self.runtime_error = Some(format!("can't redirect to {}", entry.key()));
+                        return;
+                    }
+                }
             }
+        };
+        if file.write_all(text.as_bytes()).is_err() {
+            self.runtime_error = Some("write error on redirected output".to_string());
         }
     }
 
-    fn eval_add_assignment(&mut self, identifier: &str, value: &Expression<'_>) {
-        let current = parse_awk_numeric(&self.eval_identifier_expression(identifier));
-        let increment = self.eval_numeric_expression(value).unwrap_or(0.0);
-        self.set_variable_numeric(identifier, current + increment);
+    /// Flush and close every file opened by a redirection.
+    fn close_output_files(&mut self) {
+        for (_, mut file) in self.output_files.drain() {
+            let _ = file.flush();
+        }
     }
 
-    fn eval_array_assignment(
-        &mut self,
-        identifier: &str,
-        index: &Expression<'_>,
-        value: &Expression<'_>,
-    ) {
-        let key = self.array_key(identifier, index);
-        let assigned_value = self.eval_expression(value);
-        self.array_variables.insert(key, assigned_value);
+    fn eval_printf_pipe(&mut self, expressions: &[Expression<'_>], target: &Expression<'_>) {
+        let rendered = self.eval_printf(expressions);
+        let target = self.eval_expression(target);
+        let target = self.to_text(&target);
+        self.write_to_pipe(target, rendered);
+    }
+
+    /// Write `text` to the command `target`, starting it with `sh -c` first
+    /// if it is not running yet. Text a command no longer reads is dropped.
+    fn write_to_pipe(&mut self, target: String, text: String) {
+        let pipe = match self.output_pipes.entry(target) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match OutputPipe::spawn(entry.key()) {
+                Ok(pipe) => entry.insert(pipe),
+                Err(_) => {
+                    self.runtime_error = Some(format!("can't open pipe {}", entry.key()));
+                    return;
+                }
+            },
+        };
+        if let Err(err) = pipe.stdin.write_all(text.as_bytes())
+            && err.kind() != ErrorKind::BrokenPipe
+        {
+            self.runtime_error = Some("write error on output pipe".to_string());
+        }
     }
 
-    fn eval_array_add_assignment(
-        &mut self,
-        identifier: &str,
-        index: &Expression<'_>,
-        value: &Expression<'_>,
-    ) {
-        let key = self.array_key(identifier, index);
-        let current = self
-            .array_variables
-            .get(&key)
-            .map(|value| parse_awk_numeric(value))
-            .unwrap_or(0.0);
-        let increment = self.eval_numeric_expression(value).unwrap_or(0.0);
-        self.array_variables
-            .insert(key, (current + increment).to_string());
-    }
-
-    fn eval_split_assignment(
+    fn eval_split(
         &mut self,
-        identifier: &str,
         string: &Expression<'_>,
-        array: &str,
+        array: &Name<'_>,
         separator: Option<&Expression<'_>>,
-    ) {
-        let count = self.eval_split(string, array, separator);
-        self.set_variable_numeric(identifier, count as f64);
+    ) -> usize {
+        let source = self.eval_expression(string);
+        let source = self.to_text(&source);
+        let fields = self.split_source(&source, separator);
+        let array = self.array(array);
+        fill_split_array(&array, fields)
     }
 
-    fn eval_split(
+    /// Store the matches of `pattern` (or `FPAT`) in `array` and, when given,
+    /// the text around them in `separators`: index 0 holds the text before
+    /// the first match and index `i` the text after match `i`.
+    fn eval_patsplit(
         &mut self,
         string: &Expression<'_>,
-        array: &str,
-        separator: Option<&Expression<'_>>,
+        array: &Name<'_>,
+        pattern: Option<&Expression<'_>>,
+        separators: Option<&Name<'_>>,
     ) -> usize {
         let source = self.eval_expression(string);
-        let array = self.resolve_array_identifier(array).to_string();
-        let fields = self.split_source(&source, separator);
-        let prefix = format!("{array}\u{1f}");
-        self.array_variables
-            .retain(|key, _| !key.starts_with(&prefix));
-        for (idx, value) in fields.iter().enumerate() {
-            let key = format!("{array}\u{1f}{}", idx + 1);
-            self.array_variables.insert(key, value.clone());
+        let source = self.to_text(&source);
+        let pattern = pattern.map(|pattern| self.eval_regex_pattern(pattern));
+        let array = self.array(array);
+        let separators = separators.map(|separators| self.array(separators));
+        self.patsplit(&source, pattern, &array, separators.as_ref())
+    }
+
+    /// Split `source` for `patsplit` by `pattern`, or by `FPAT` when left
+    /// out, filling `array` and `separators` as [`Self::eval_patsplit`]
+    /// describes.
+    fn patsplit(
+        &mut self,
+        source: &str,
+        pattern: Option<String>,
+        array: &Array,
+        separators: Option<&Array>,
+    ) -> usize {
+        let pattern = match pattern {
+            Some(pattern) => pattern,
+            None => match self.global("FPAT").and_then(|global| global.value.as_ref()) {
+                Some(value) => self.to_text(value),
+                None => String::new(),
+            },
+        };
+        let pattern = if pattern.is_empty() {
+            DEFAULT_FIELD_PATTERN.to_string()
+        } else {
+            pattern
+        };
+        let Some(field_pattern) = FieldPattern::new(&pattern) else {
+            self.runtime_error = Some(format!("invalid patsplit pattern \"{pattern}\""));
+            return 0;
+        };
+
+        let (fields, separator_texts) = field_pattern.split(source);
+        let count = fill_split_array(array, fields);
+        if let Some(separators) = separators {
+            let mut separators = separators.borrow_mut();
+            separators.clear();
+            for (index, separator) in separator_texts.into_iter().enumerate() {
+                separators.insert(index.to_string(), Value::from_input(separator));
+            }
         }
-        fields.len()
+        count
     }
 
-    fn eval_array_post_increment(&mut self, identifier: &str, index: &Expression<'_>, delta: f64) {
-        let key = self.array_key(identifier, index);
-        let current = self
-            .array_variables
-            .get(&key)
-            .map(|value| parse_awk_numeric(value))
-            .unwrap_or(0.0);
-        self.array_variables
-            .insert(key, format_awk_number(current + delta));
+    fn eval_array_increment(
+        &mut self,
+        identifier: &Name<'_>,
+        index: &Expression<'_>,
+        delta: f64,
+    ) -> (f64, f64) {
+        let (array, key) = self.array_key(identifier, index);
+        let mut array = array.borrow_mut();
+        let element = array.entry(key).or_default();
+        let current = element.to_number();
+        *element = Value::Number(current + delta);
+        (current, current + delta)
     }
 
-    fn eval_delete(&mut self, identifier: &str, index: Option<&Expression<'_>>) {
+    fn eval_delete(&mut self, identifier: &Name<'_>, index: Option<&Expression<'_>>) {
         if let Some(index) = index {
-            let key = self.array_key(identifier, index);
-            self.array_variables.remove(&key);
+            let (array, key) = self.array_key(identifier, index);
+            array.borrow_mut().remove(&key);
             return;
         }
 
-        let identifier = self.resolve_array_identifier(identifier);
-        let prefix = format!("{identifier}\u{1f}");
-        self.array_variables
-            .retain(|key, _| !key.starts_with(&prefix));
+        self.clear_array(identifier);
     }
 
-    fn eval_field_assignment(&mut self, field: &Expression<'_>, value: &Expression<'_>) {
-        let assigned_value = self.eval_expression(value);
-        self.assign_field(field, assigned_value);
+    fn field_index(&mut self, field: &Expression<'_>) -> i64 {
+        self.eval_expression(field).to_number() as i64
     }
 
-    fn assign_field(&mut self, field: &Expression<'_>, value: String) {
-        let line = match self.current_line.as_ref() {
-            Some(value) => value.clone(),
-            None => return,
-        };
+    fn assign_field(&mut self, field: &Expression<'_>, value: Value) {
+        let index = self.field_index(field);
+        let value = self.to_text(&value);
+        self.set_field(index, value);
+    }
 
-        let index = self.eval_numeric_expression(field).unwrap_or(0.0) as i64;
+    fn set_field(&mut self, index: i64, value: String) {
         if index == 0 {
-            self.current_line = Some(value);
+            self.set_record(value);
+        } else if index > 0 {
+            self.split_record(None);
+            self.record
+                .get_or_insert_default()
+                .set_field(index as usize, value);
+        }
+    }
+
+    /// Make `text` the current record, to be split into fields when they
+    /// are needed.
+    fn set_record(&mut self, text: String) {
+        match &mut self.record {
+            Some(record) => record.reset(text),
+            None => self.record = Some(Record::new(text)),
+        }
+    }
+
+    /// Split the current record as far as field `index`, or into all its
+    /// fields with `None`.
+    fn split_record(&mut self, index: Option<usize>) {
+        let Some(record) = &self.record else {
+            return;
+        };
+        if record.is_split(index) {
             return;
         }
-        if index < 0 {
+        // CSV fields and fields separated by whitespace or a single character
+        // are split one at a time; other kinds all at once.
+        let csv = self.csv && matches!(self.field_mode, FieldMode::Separator);
+        let mut separator = self.field_separator.chars();
+        let separator = match (&self.field_mode, separator.next(), separator.next()) {
+            (FieldMode::Separator, Some(separator), None) if !self.csv => Some(separator),
+            _ => None,
+        };
+        let fields = match separator {
+            None if !csv => self.split_fields(record.text()),
+            _ => Vec::new(),
+        };
+        let Some(record) = &mut self.record else {
             return;
+        };
+        match separator {
+            _ if csv => record.split_csv(index),
+            Some(' ') => record.split_whitespace(index),
+            Some(separator) => record.split_on(separator, index),
+            None => record.set_fields(fields),
         }
+    }
 
-        let mut fields = self.split_line_into_fields(&line);
-        while fields.len() < index as usize {
-            fields.push(String::new());
+    /// The text of `$0`, first rebuilding it with `OFS` if a field or `NF`
+    /// was assigned since it was last read.
+    fn record_text(&mut self) -> Option<&str> {
+        let record = self.record.as_mut()?;
+        if record.is_dirty() {
+            let text = self
+                .output_mode
+                .join(record.fields(), &self.output_field_separator);
+            record.rebuild(text);
         }
-        fields[(index - 1) as usize] = value;
-        self.set_variable_numeric("NF", fields.len() as f64);
-        self.current_line = Some(fields.join(&self.output_field_separator));
+        self.record.as_ref().map(Record::text)
     }
 
-    fn eval_assignment_infix(&mut self, left: &Expression<'_>, right: &Expression<'_>) -> String {
-        let assigned_value = if let Expression::String(value) = right {
-            unescape_awk_string(value)
-        } else if let Expression::Infix {
-            left: nested_left,
-            operator: nested_operator,
-            right: nested_right,
-        } = right
-        {
-            if nested_operator.kind == TokenKind::Assign {
-                self.eval_assignment_infix(nested_left, nested_right)
-            } else {
-                self.eval_expression(right)
-            }
-        } else {
-            self.eval_expression(right)
-        };
-
-        match left {
+    /// Store `value` into the variable, array element or field named by
+    /// `target`, returning the stored value.
+    fn assign(&mut self, target: &Expression<'_>, value: Value) -> Value {
+        match target {
             Expression::Identifier(identifier) => {
-                if expression_has_precise_numeric_value(right) {
-                    let assigned_numeric = self.eval_numeric_expression(right).unwrap_or(0.0);
-                    self.set_variable_numeric(identifier, assigned_numeric);
-                    format_awk_number(assigned_numeric)
-                } else {
-                    self.set_variable_text(identifier, assigned_value.clone());
-                    assigned_value
-                }
+                self.set_variable(identifier, value.clone());
+                value
             }
             Expression::Field(field) => {
-                self.assign_field(field, assigned_value.clone());
-                assigned_value
+                self.assign_field(field, value.clone());
+                value
+            }
+            Expression::ArrayAccess { identifier, index } => {
+                let (array, key) = self.array_key(identifier, index);
+                array.borrow_mut().insert(key, value.clone());
+                value
             }
-            _ => assigned_value,
+            _ => value,
         }
     }
 
-    fn set_special_variable(&mut self, identifier: &str, value: &str) {
-        if identifier == "FS" {
-            self.field_separator = unescape_awk_string(value);
-        } else if identifier == "OFS" {
-            self.output_field_separator = unescape_awk_string(value);
-        } else if identifier == "ORS" {
-            self.output_record_separator = unescape_awk_string(value);
-        } else if identifier == "NF" {
-            self.set_number_of_fields(value);
+    fn eval_assignment_infix(
+        &mut self,
+        left: &Expression<'_>,
+        operator: &TokenKind,
+        right: &Expression<'_>,
+    ) -> Value {
+        let right_value = self.eval_expression(right);
+        if *operator == TokenKind::Assign {
+            return self.assign(left, right_value);
         }
-    }
 
-    fn set_number_of_fields(&mut self, value: &str) {
-        let line = match self.current_line.as_ref() {
-            Some(value) => value.clone(),
-            None => return,
+        let current = self.eval_expression(left).to_number();
+        let right_value = right_value.to_number();
+        let updated = match operator {
+            TokenKind::AddAssign => current + right_value,
+            TokenKind::SubtractAssign => current - right_value,
+            TokenKind::MultiplyAssign => current * right_value,
+            TokenKind::DivideAssign => current / right_value,
+            TokenKind::ModuloAssign => current % right_value,
+            TokenKind::PowerAssign => current.powf(right_value),
+            _ => unreachable!(),
         };
+        self.assign(left, Value::Number(updated))
+    }
 
-        let target_nf = parse_awk_numeric(value).trunc().max(0.0) as usize;
-        let mut fields = self.split_fields(&line);
-        if fields.len() > target_nf {
-            fields.truncate(target_nf);
-        } else {
-            while fields.len() < target_nf {
-                fields.push(String::new());
-            }
-        }
-
-        self.current_line = Some(fields.join(&self.output_field_separator));
-        self.variables
-            .insert("NF".to_string(), target_nf.to_string());
-        self.numeric_variables
-            .insert("NF".to_string(), target_nf as f64);
+    fn set_number_of_fields(&mut self, value: f64) {
+        let count = value.trunc().max(0.0) as usize;
+        self.split_record(None);
+        self.record.get_or_insert_default().set_field_count(count);
     }
 
     fn append_output_record_separator(&self, output: &mut Vec<String>) {
@@ -952,114 +2004,137 @@ impl<'a> Evaluator<'a> {
         output.push(self.output_record_separator.clone());
     }
 
+    /// Close every command still open for output, in the order of their
+    /// command lines, and return what they wrote.
     fn flush_pipe_outputs(&mut self) -> Vec<String> {
-        let mut output = Vec::new();
-        let mut keys: Vec<String> = self.pipe_outputs.keys().cloned().collect();
-        keys.sort();
-
-        for key in keys {
-            let mut lines = self.pipe_outputs.remove(&key).unwrap_or_default();
-            if key == "sort" {
-                lines.sort();
-            }
-            output.extend(lines);
-        }
-
-        output
-    }
+        let mut pipes: Vec<(String, OutputPipe)> = self.output_pipes.drain().collect();
+        pipes.sort_by(|(left, _), (right, _)| left.cmp(right));
 
-    fn eval_pre_increment(&mut self, identifier: &str) {
-        let current = parse_awk_numeric(&self.eval_identifier_expression(identifier));
-        self.set_variable_numeric(identifier, current + 1.0);
-    }
-
-    fn eval_pre_decrement(&mut self, identifier: &str) {
-        let current = parse_awk_numeric(&self.eval_identifier_expression(identifier));
-        self.set_variable_numeric(identifier, current - 1.0);
-    }
-
-    fn eval_post_increment(&mut self, identifier: &str) {
-        let current = parse_awk_numeric(&self.eval_identifier_expression(identifier));
-        self.set_variable_numeric(identifier, current + 1.0);
+        pipes
+            .into_iter()
+            .map(|(_, pipe)| pipe.close().0)
+            .filter(|written| !written.is_empty())
+            .collect()
     }
 
-    fn eval_post_decrement(&mut self, identifier: &str) {
-        let current = parse_awk_numeric(&self.eval_identifier_expression(identifier));
-        self.set_variable_numeric(identifier, current - 1.0);
+    fn eval_regex_pattern(&mut self, pattern: &Expression<'_>) -> String {
+        match pattern {
+            Expression::Regex(value) => value.to_string(),
+            _ => {
+                let value = self.eval_expression(pattern);
+                self.to_text(&value)
+            }
+        }
     }
 
-    fn eval_gsub(
+    /// Implement `sub` and `gsub`: replace the first (or, when `global`, every)
+    /// match of `pattern` in `target`, which defaults to `$0`, and return the
+    /// number of replacements.
+    fn eval_substitution(
         &mut self,
         pattern: &Expression<'_>,
         replacement: &Expression<'_>,
         target: Option<&Expression<'_>>,
-    ) {
-        let pattern = match pattern {
-            Expression::Regex(value) => value.to_string(),
-            _ => self.eval_expression(pattern),
-        };
-        if Regex::new(&pattern).is_err() {
+        global: bool,
+    ) -> usize {
+        let pattern = self.eval_regex_pattern(pattern);
+        let Ok(regex) = Regex::new(&pattern) else {
             self.exited = true;
-            return;
-        }
-        let replacement = unescape_awk_string(&self.eval_expression(replacement));
-        match target {
-            Some(Expression::Identifier(identifier)) => {
-                let value = self.eval_identifier_expression(identifier);
-                let replaced = awk_gsub_replace_all(&value, &pattern, &replacement);
-                self.set_variable_text(identifier, replaced);
-            }
-            Some(Expression::Field(inner)) => {
-                let line = self.eval_field_expression(inner);
-                let replaced = awk_gsub_replace_all(&line, &pattern, &replacement);
-                self.assign_field(inner, replaced);
-            }
-            Some(other) => {
-                let value = self.eval_expression(other);
-                let _ = awk_gsub_replace_all(&value, &pattern, &replacement);
-            }
-            None => {
-                let line = match self.current_line.as_ref() {
-                    Some(value) => value.clone(),
-                    None => return,
-                };
-                let replaced = awk_gsub_replace_all(&line, &pattern, &replacement);
-                self.current_line = Some(replaced);
+            return 0;
+        };
+        let replacement = self.eval_expression(replacement);
+        let replacement = unescape_awk_string(&self.to_text(&replacement));
+        let text = match target {
+            Some(target) => {
+                let value = self.eval_expression(target);
+                self.to_text(&value)
             }
-        }
-    }
-
-    fn eval_sub(&mut self, pattern: &Expression<'_>, replacement: &Expression<'_>) {
-        let line = match self.current_line.as_ref() {
-            Some(value) => value.clone(),
-            None => return,
+            None => match self.record_text() {
+                Some(line) => line.to_string(),
+                None => return 0,
+            },
         };
 
-        let pattern = match pattern {
-            Expression::Regex(value) => value.to_string(),
-            _ => self.eval_expression(pattern),
-        };
-        if Regex::new(&pattern).is_err() {
-            self.exited = true;
-            return;
+        let (replaced, count) = awk_substitute(&text, &regex, &replacement, global);
+        if count > 0 {
+            match target {
+                Some(target) => {
+                    self.assign(target, Value::String(replaced));
+                }
+                None => self.set_record(replaced),
+            }
         }
-        let replacement = unescape_awk_string(&self.eval_expression(replacement));
-        let replaced = awk_sub_replace_first(&line, &pattern, &replacement);
-        self.current_line = Some(replaced);
+        count
     }
 
-    fn eval_system(&mut self, command: &Expression<'_>) {
-        let _command = self.eval_expression(command);
+    /// Run `command` through `sh -c`, passing its standard output on as
+    /// program output, and return its exit status.
+    fn eval_system(&mut self, command: &Expression<'_>) -> f64 {
+        let command = self.eval_expression(command);
+        let command = self.to_text(&command);
+        self.system(&command)
     }
 
-    fn eval_expression(&mut self, expression: &Expression) -> String {
-        match expression {
-            Expression::String(value) => unescape_awk_string(value),
-            Expression::Number(value) => format_awk_number(*value),
-            Expression::HexNumber { value, .. } => format_awk_number(*value),
-            Expression::Regex(value) => value.to_string(),
-            Expression::Field(inner) => self.eval_field_expression(inner),
-            Expression::Identifier(identifier) => self.eval_identifier_expression(identifier),
+    /// Run `command` for `system`, as [`Self::eval_system`] describes.
+    fn system(&mut self, command: &str) -> f64 {
+        let pending_printf = std::mem::take(&mut self.printf_buffer);
+        if !pending_printf.is_empty() {
+            self.expression_output.push(pending_printf);
+        }
+
+        match std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stderr(std::process::Stdio::inherit())
+            .output()
+        {
+            Ok(output) => {
+                let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+                if !stdout.is_empty() {
+                    self.expression_output.push(stdout);
+                }
+                output.status.code().map(f64::from).unwrap_or(-1.0)
+            }
+            Err(_) => -1.0,
+        }
+    }
+
+    /// Close the output file or command named `target`. A command gets the
+    /// end of its input and is waited for, its output is passed on as program
+    /// output, and its exit status is returned. Returns `-1` when nothing by
+    /// that name is open.
+    fn eval_close(&mut self, target: &str) -> f64 {
+        if let Some(mut file) = self.output_files.remove(target) {
+            return if file.flush().is_ok() { 0.0 } else { -1.0 };
+        }
+        let Some(pipe) = self.output_pipes.remove(target) else {
+            return -1.0;
+        };
+
+        let pending_printf = std::mem::take(&mut self.printf_buffer);
+        if !pending_printf.is_empty() {
+            self.expression_output.push(pending_printf);
+        }
+        let (written, status) = pipe.close();
+        if !written.is_empty() {
+            self.expression_output.push(written);
+        }
+        status
+    }
+
+    fn eval_expression(&mut self, expression: &Expression) -> Value {
+        match expression {
+            Expression::String(value) => Value::String(unescape_awk_string(value)),
+            Expression::Number(value) => Value::Number(*value),
+            Expression::HexNumber { value, .. } => Value::Number(*value),
+            Expression::Regex(pattern) => {
+                let matched = self
+                    .record_text()
+                    .is_some_and(|line| awk_regex_matches(line, pattern));
+                Value::Number(if matched { 1.0 } else { 0.0 })
+            }
+            Expression::Field(inner) => self.eval_field_expression(inner),
+            Expression::Identifier(identifier) => self.eval_identifier_expression(identifier),
             Expression::ArrayAccess { identifier, index } => {
                 self.eval_array_access(identifier, index)
             }
@@ -1069,19 +2144,17 @@ impl<'a> Evaluator<'a> {
                 start,
                 length,
             } => self.eval_substr_expression(string, start, length.as_deref()),
-            Expression::Rand => format_awk_number(self.eval_rand()),
+            Expression::Rand => Value::Number(self.eval_rand()),
             Expression::FunctionCall { name, args } => {
                 let result = self.eval_user_defined_function_call(name, args);
                 self.expression_output.extend(result.output);
                 result.value
             }
-            Expression::Not(expression) => {
-                if self.eval_condition(expression) {
-                    "0".to_string()
-                } else {
-                    "1".to_string()
-                }
-            }
+            Expression::Not(expression) => Value::Number(if self.eval_condition(expression) {
+                0.0
+            } else {
+                1.0
+            }),
             Expression::PreIncrement(target) => self.eval_increment_expression(target, 1.0, true),
             Expression::PreDecrement(target) => self.eval_increment_expression(target, -1.0, true),
             Expression::PostIncrement(target) => self.eval_increment_expression(target, 1.0, false),
@@ -1100,29 +2173,65 @@ impl<'a> Evaluator<'a> {
                 }
             }
             Expression::Concatenation { left, right } => {
-                let mut value = self.eval_expression(left);
-                value.push_str(&self.eval_expression(right));
-                value
+                let left = self.eval_expression(left);
+                let mut value = left.into_string_with(&self.conversion_format);
+                let right = self.eval_expression(right);
+                value.push_str(&self.to_text(&right));
+                Value::String(value)
             }
             Expression::Infix {
                 left,
                 operator,
                 right,
-            } => {
-                if operator.kind == TokenKind::Assign {
-                    self.eval_assignment_infix(left, right)
-                } else if let Some(value) =
-                    self.eval_regex_match(left, operator.kind.clone(), right)
-                {
-                    format_awk_number(if value { 1.0 } else { 0.0 })
-                } else if let Some(value) = self.eval_membership(left, operator.kind.clone(), right)
-                {
-                    format_awk_number(if value { 1.0 } else { 0.0 })
-                } else {
-                    self.eval_numeric_infix(left, operator, right)
-                        .map(format_awk_number)
-                        .unwrap_or_else(|| "not implemented".to_string())
-                }
+            } => self.eval_infix(left, &operator.kind, right),
+        }
+    }
+
+    fn eval_infix(
+        &mut self,
+        left: &Expression<'_>,
+        operator: &TokenKind,
+        right: &Expression<'_>,
+    ) -> Value {
+        let truth = |value: bool| Value::Number(if value { 1.0 } else { 0.0 });
+
+        match operator {
+            TokenKind::Assign
+            | TokenKind::AddAssign
+            | TokenKind::SubtractAssign
+            | TokenKind::MultiplyAssign
+            | TokenKind::DivideAssign
+            | TokenKind::ModuloAssign
+            | TokenKind::PowerAssign => self.eval_assignment_infix(left, operator, right),
+            TokenKind::And => truth(self.eval_condition(left) && self.eval_condition(right)),
+            TokenKind::Or => truth(self.eval_condition(left) || self.eval_condition(right)),
+            TokenKind::Tilde | TokenKind::NoMatch => {
+                truth(self.eval_regex_match(left, operator, right))
+            }
+            TokenKind::In => truth(self.eval_membership(left, right)),
+            TokenKind::Equal
+            | TokenKind::NotEqual
+            | TokenKind::GreaterThan
+            | TokenKind::GreaterThanOrEqual
+            | TokenKind::LessThan
+            | TokenKind::LessThanOrEqual => truth(self.eval_comparison(left, operator, right)),
+            TokenKind::Comma => {
+                let value = self.eval_array_subscript_from_infix(left, right);
+                Value::String(value)
+            }
+            _ => {
+                let left_value = self.eval_expression(left).to_number();
+                let right_value = self.eval_expression(right).to_number();
+                let value = match operator {
+                    TokenKind::Plus => left_value + right_value,
+                    TokenKind::Minus => left_value - right_value,
+                    TokenKind::Asterisk => left_value * right_value,
+                    TokenKind::Division => left_value / right_value,
+                    TokenKind::Percent => left_value % right_value,
+                    TokenKind::Caret => left_value.powf(right_value),
+                    _ => 0.0,
+                };
+                Value::Number(value)
             }
         }
     }
@@ -1132,111 +2241,234 @@ impl<'a> Evaluator<'a> {
         target: &Expression<'_>,
         delta: f64,
         return_new: bool,
-    ) -> String {
-        match target {
-            Expression::Identifier(identifier) => {
-                let current = parse_awk_numeric(&self.eval_identifier_expression(identifier));
-                let updated = current + delta;
-                self.set_variable_numeric(identifier, updated);
-                if return_new {
-                    format_awk_number(updated)
-                } else {
-                    format_awk_number(current)
-                }
+    ) -> Value {
+        let (current, updated) = match target {
+            Expression::ArrayAccess { identifier, index } => {
+                self.eval_array_increment(identifier, index, delta)
             }
-            Expression::Field(field) => {
-                let current = parse_awk_numeric(&self.eval_field_expression(field));
-                let updated = current + delta;
-                self.assign_field(field, format_awk_number(updated));
-                if return_new {
-                    format_awk_number(updated)
+            Expression::Identifier(_) | Expression::Field(_) => {
+                let current = self.eval_expression(target).to_number();
+                self.assign(target, Value::Number(current + delta));
+                (current, current + delta)
+            }
+            _ => return Value::Number(0.0),
+        };
+
+        Value::Number(if return_new { updated } else { current })
+    }
+
+    fn eval_identifier_expression(&mut self, identifier: &Name<'_>) -> Value {
+        let variable = self.variable_of(identifier);
+        self.load_variable(variable)
+    }
+
+    fn load_variable(&mut self, variable: Variable) -> Value {
+        match variable {
+            Variable::Local(index) => self.local(index).value.clone(),
+            Variable::Global(slot) => self.globals[slot].value.clone().unwrap_or_default(),
+            Variable::Special(special) => self.load_special(special),
+        }
+    }
+
+    fn load_special(&mut self, special: Special) -> Value {
+        match special {
+            Special::Getline => Value::Number(self.eval_getline()),
+            Special::FieldSeparator => Value::String(self.field_separator.clone()),
+            Special::OutputFieldSeparator => Value::String(self.output_field_separator.clone()),
+            Special::OutputRecordSeparator => Value::String(self.output_record_separator.clone()),
+            Special::ConversionFormat => Value::String(self.conversion_format.clone()),
+            Special::OutputFormat => Value::String(self.output_format.clone()),
+            Special::SubscriptSeparator => Value::String(self.subscript_separator.clone()),
+            Special::FieldCount => {
+                self.split_record(None);
+                Value::Number(
+                    self.record
+                        .as_ref()
+                        .map_or(0, |record| record.fields().len()) as f64,
+                )
+            }
+            Special::RecordNumber => Value::Number(self.current_line_number.get() as f64),
+            Special::FileRecordNumber => Value::Number(self.file_line_number as f64),
+            Special::Filename => {
+                if !self.opened_file_operand {
+                    Value::String(String::new())
                 } else {
-                    format_awk_number(current)
+                    Value::String(self.current_filename.clone())
                 }
             }
-            _ => "0".to_string(),
+            Special::FieldWidths | Special::FieldPattern => self
+                .global(special.name())
+                .and_then(|global| global.value.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Assign a scalar variable, applying the side effects of special
+    /// variables such as `FS` and `NF`.
+    fn set_variable(&mut self, identifier: &Name<'_>, value: Value) {
+        let variable = self.variable_of(identifier);
+        self.store_variable(variable, value);
+    }
+
+    fn store_variable(&mut self, variable: Variable, value: Value) {
+        match variable {
+            Variable::Local(index) => self.local(index).value = value,
+            Variable::Global(slot) => self.globals[slot].value = Some(value),
+            Variable::Special(special) => self.store_special(special, value),
         }
     }
 
-    fn eval_identifier_expression(&mut self, identifier: &str) -> String {
-        match identifier {
-            "getline" => self.eval_getline(),
-            "FS" => self.field_separator.clone(),
-            "OFS" => self.output_field_separator.clone(),
-            "ORS" => self.output_record_separator.clone(),
-            "NF" => {
-                if let Some(value) = self.variables.get("NF") {
-                    return value.clone();
+    fn store_special(&mut self, special: Special, value: Value) {
+        // The current record keeps the fields it would have had under the old
+        // field splitting.
+        if matches!(
+            special,
+            Special::FieldSeparator | Special::FieldWidths | Special::FieldPattern
+        ) {
+            self.split_record(None);
+        }
+        match special {
+            Special::FieldCount => self.set_number_of_fields(value.to_number()),
+            Special::FieldSeparator => {
+                self.field_separator = unescape_awk_string(&self.to_text(&value));
+                self.field_mode = FieldMode::Separator;
+            }
+            Special::FieldWidths => {
+                let text = self.to_text(&value);
+                match FieldWidths::parse(&text) {
+                    Some(widths) => self.field_mode = FieldMode::Widths(widths),
+                    None => {
+                        self.runtime_error = Some(format!("invalid FIELDWIDTHS value \"{text}\""));
+                    }
                 }
-                let line = match self.current_line.as_deref() {
-                    Some(value) => value,
-                    None => return "0".to_string(),
-                };
-                let field_count = self.split_line_into_fields(line).len();
-                field_count.to_string()
+                self.set_global(special.name(), value);
             }
-            "NR" => match self.current_line.as_ref() {
-                Some(_) => self.current_line_number.get().to_string(),
-                None => self.current_line_number.get().to_string(),
-            },
-            "FNR" => self.current_line_number.get().to_string(),
-            "FILENAME" => {
-                if self.current_line.is_none() && self.current_line_number.get() == 0 {
-                    String::new()
-                } else {
-                    self.current_filename.clone()
+            Special::FieldPattern => {
+                let text = self.to_text(&value);
+                match FieldPattern::new(&text) {
+                    Some(pattern) => self.field_mode = FieldMode::Pattern(Box::new(pattern)),
+                    None => {
+                        self.runtime_error = Some(format!("invalid FPAT value \"{text}\""));
+                    }
                 }
+                self.set_global(special.name(), value);
             }
-            "ARGC" => self.argv.len().to_string(),
-            _ => self.variables.get(identifier).cloned().unwrap_or_default(),
+            Special::OutputFieldSeparator => {
+                // A record rebuilt later still uses the `OFS` in effect when
+                // its fields were assigned.
+                self.record_text();
+                self.output_field_separator = unescape_awk_string(&self.to_text(&value));
+            }
+            Special::OutputRecordSeparator => {
+                self.output_record_separator = unescape_awk_string(&self.to_text(&value))
+            }
+            Special::ConversionFormat => self.conversion_format = self.to_text(&value),
+            Special::OutputFormat => self.output_format = self.to_text(&value),
+            Special::SubscriptSeparator => self.subscript_separator = self.to_text(&value),
+            Special::RecordNumber => self
+                .current_line_number
+                .set(value.to_number().max(0.0) as usize),
+            Special::FileRecordNumber => {
+                self.file_line_number = value.to_number().max(0.0) as usize
+            }
+            Special::Filename => self.current_filename = self.to_text(&value),
+            Special::Getline => self.set_global(special.name(), value),
         }
     }
 
-    fn set_variable_text(&mut self, identifier: &str, value: String) {
-        if identifier == "NF" {
-            self.set_number_of_fields(&value);
-            return;
-        }
-        self.set_special_variable(identifier, &value);
-        self.variables.insert(identifier.to_string(), value.clone());
-        if let Some(numeric) = parse_full_awk_numeric(&value) {
-            self.numeric_variables
-                .insert(identifier.to_string(), numeric);
+    fn eval_getline(&mut self) -> f64 {
+        if self.read_next_input_record() {
+            1.0
         } else {
-            self.numeric_variables.remove(identifier);
+            self.record = None;
+            0.0
         }
     }
 
-    fn set_variable_numeric(&mut self, identifier: &str, value: f64) {
-        let rendered = format_awk_number(value);
-        if identifier == "NF" {
-            self.set_number_of_fields(&rendered);
-            return;
+    /// Where the variable `name` lives: where the resolver found it to, or
+    /// for a name that is not text of the resolved program, a parameter of
+    /// the running function, a special variable or a global.
+    fn variable_of(&mut self, name: &Name<'_>) -> Variable {
+        if let Some(variable) = name.variable() {
+            return variable;
         }
-        self.set_special_variable(identifier, &rendered);
-        self.variables.insert(identifier.to_string(), rendered);
-        self.numeric_variables.insert(identifier.to_string(), value);
+        if let Some(index) = self.frames.last().and_then(|frame| {
+            frame
+                .iter()
+                .position(|(parameter, _)| *parameter == name.text())
+        }) {
+            return Variable::Local(index);
+        }
+        resolve::resolve(name, None, &mut |name| self.global_slot(name))
     }
 
-    fn eval_getline(&mut self) -> String {
-        if self.read_next_input_record().is_some() {
-            "1".to_string()
-        } else {
-            self.current_line = None;
-            "0".to_string()
+    /// The parameter at `index` of the running function.
+    fn local(&mut self, index: usize) -> &mut Local {
+        let frame = self
+            .frames
+            .last_mut()
+            .expect("parameters are only resolved inside functions");
+        &mut frame[index].1
+    }
+
+    /// The slot of the global `name`, adding one if there is none yet.
+    fn global_slot(&mut self, name: &str) -> usize {
+        if let Some(&slot) = self.global_slots.get(name) {
+            return slot;
         }
+        self.globals.push(Global::default());
+        self.global_slots
+            .insert(name.to_string(), self.globals.len() - 1);
+        self.globals.len() - 1
+    }
+
+    fn global(&self, name: &str) -> Option<&Global> {
+        self.global_slots.get(name).map(|&slot| &self.globals[slot])
+    }
+
+    /// Assign the global scalar `name`, without the side effects of special
+    /// variables.
+    fn set_global(&mut self, name: &str, value: Value) {
+        let slot = self.global_slot(name);
+        self.globals[slot].value = Some(value);
+    }
+
+    /// Resolve the array named `identifier`, creating it on first use. Inside
+    /// a function a parameter that was not bound to an array becomes a fresh
+    /// local array.
+    fn array(&mut self, identifier: &Name<'_>) -> Array {
+        let array = match self.variable_of(identifier) {
+            Variable::Local(index) => &mut self.local(index).array,
+            Variable::Global(slot) => &mut self.globals[slot].array,
+            // A special variable used as an array is an ordinary global array.
+            Variable::Special(_) => {
+                let slot = self.global_slot(identifier);
+                &mut self.globals[slot].array
+            }
+        };
+        array.get_or_insert_with(Array::default).clone()
     }
 
-    fn array_key(&mut self, identifier: &str, index: &Expression<'_>) -> String {
-        let identifier = self.resolve_array_identifier(identifier).to_string();
-        format!("{identifier}\u{1f}{}", self.eval_array_subscript(index))
+    /// Resolve the array named `identifier` without creating it.
+    fn find_array(&self, identifier: &Name<'_>) -> Option<Array> {
+        match identifier.variable() {
+            Some(Variable::Local(index)) => return self.frames.last()?[index].1.array.clone(),
+            Some(Variable::Global(slot)) => return self.globals[slot].array.clone(),
+            _ => {}
+        }
+        if let Some((_, local)) = self
+            .frames
+            .last()
+            .and_then(|locals| locals.iter().find(|(name, _)| *name == identifier.text()))
+        {
+            return local.array.clone();
+        }
+        self.global(identifier)?.array.clone()
     }
 
-    fn resolve_array_identifier<'b>(&'b self, identifier: &'b str) -> &'b str {
-        self.array_aliases
-            .get(identifier)
-            .map(|alias| alias.as_str())
-            .unwrap_or(identifier)
+    fn array_key(&mut self, identifier: &Name<'_>, index: &Expression<'_>) -> (Array, String) {
+        let array = self.array(identifier);
+        (array, self.eval_array_subscript(index))
     }
 
     fn eval_array_subscript(&mut self, index: &Expression<'_>) -> String {
@@ -1246,69 +2478,100 @@ impl<'a> Evaluator<'a> {
                 operator,
                 right,
             } if operator.kind == TokenKind::Comma => {
-                let mut value = self.eval_array_subscript(left);
-                value.push('\u{1c}');
-                value.push_str(&self.eval_array_subscript(right));
-                value
+                self.eval_array_subscript_from_infix(left, right)
+            }
+            _ => {
+                let value = self.eval_expression(index);
+                self.to_text(&value)
             }
-            _ => self.eval_expression(index),
         }
     }
 
-    fn eval_array_access(&mut self, identifier: &str, index: &Expression<'_>) -> String {
-        if identifier == "ARGV" {
-            let idx = self.eval_numeric_expression(index).unwrap_or(0.0) as isize;
-            if idx < 0 {
-                return String::new();
-            }
-            return self.argv.get(idx as usize).cloned().unwrap_or_default();
+    fn eval_array_subscript_from_infix(
+        &mut self,
+        left: &Expression<'_>,
+        right: &Expression<'_>,
+    ) -> String {
+        let mut value = self.eval_array_subscript(left);
+        value.push_str(&self.subscript_separator);
+        value.push_str(&self.eval_array_subscript(right));
+        value
+    }
+
+    fn eval_array_access(&mut self, identifier: &Name<'_>, index: &Expression<'_>) -> Value {
+        let (array, key) = self.array_key(identifier, index);
+        let value = array.borrow().get(&key).cloned();
+        value.unwrap_or_default()
+    }
+
+    fn set_array_element(&mut self, identifier: &Name<'_>, subscript: &str, value: Value) {
+        self.array(identifier)
+            .borrow_mut()
+            .insert(subscript.to_string(), value);
+    }
+
+    fn clear_array(&mut self, identifier: &Name<'_>) {
+        if let Some(array) = self.find_array(identifier) {
+            array.borrow_mut().clear();
         }
+    }
 
-        let key = self.array_key(identifier, index);
-        self.array_variables.get(&key).cloned().unwrap_or_default()
+    fn array_len(&self, identifier: &Name<'_>) -> usize {
+        self.find_array(identifier)
+            .map(|array| array.borrow().len())
+            .unwrap_or(0)
     }
 
-    fn array_keys(&self, identifier: &str) -> Vec<String> {
-        let identifier = self.resolve_array_identifier(identifier);
-        let prefix = format!("{identifier}\u{1f}");
-        self.array_variables
-            .keys()
-            .filter_map(|key| key.strip_prefix(&prefix).map(str::to_string))
-            .collect()
+    fn array_keys(&self, identifier: &Name<'_>) -> Vec<String> {
+        self.find_array(identifier)
+            .map(|array| array.borrow().keys().cloned().collect())
+            .unwrap_or_default()
     }
 
-    fn eval_field_expression(&mut self, expression: &Expression<'_>) -> String {
-        let index = match self.eval_numeric_expression(expression) {
-            Some(value) => value as i64,
-            None => return String::new(),
-        };
+    fn eval_field_expression(&mut self, expression: &Expression<'_>) -> Value {
+        let index = self.field_index(expression);
+        self.field_value(index)
+    }
 
-        let line = match self.current_line.as_deref() {
-            Some(value) => value,
-            None => return String::new(),
-        };
+    fn field_value(&mut self, index: i64) -> Value {
+        if self.record.is_none() {
+            return Value::Uninitialized;
+        }
 
         if index == 0 {
-            return line.to_string();
+            return self
+                .record_text()
+                .map_or(Value::Uninitialized, Value::from_input);
         }
 
         if index < 0 {
             self.runtime_error = Some(format!("attempt to access field {index}"));
-            return String::new();
+            return Value::Uninitialized;
         }
 
-        self.split_line_into_fields(line)
-            .into_iter()
-            .nth((index - 1) as usize)
-            .unwrap_or_default()
+        self.split_record(Some(index as usize));
+        self.record
+            .as_ref()
+            .and_then(|record| record.field(index as usize))
+            .map_or_else(|| Value::String(String::new()), Value::from_input)
     }
 
-    fn eval_length_expression(&mut self, expression: Option<&Expression<'_>>) -> String {
+    fn eval_length_expression(&mut self, expression: Option<&Expression<'_>>) -> Value {
+        if let Some(Expression::Identifier(identifier)) = expression
+            && let Some(array) = self.find_array(identifier)
+            && !array.borrow().is_empty()
+        {
+            return Value::Number(array.borrow().len() as f64);
+        }
+
         let value = match expression {
-            Some(expr) => self.eval_expression(expr),
-            None => self.current_line.clone().unwrap_or_default(),
+            Some(expr) => {
+                let value = self.eval_expression(expr);
+                self.to_text(&value)
+            }
+            None => self.record_text().unwrap_or_default().to_string(),
         };
-        value.chars().count().to_string()
+        Value::Number(self.char_mode.len(&value) as f64)
     }
 
     fn eval_substr_expression(
@@ -1316,35 +2579,32 @@ impl<'a> Evaluator<'a> {
         string: &Expression<'_>,
         start: &Expression<'_>,
         length: Option<&Expression<'_>>,
-    ) -> String {
+    ) -> Value {
         let source = self.eval_expression(string);
-        let chars: Vec<char> = source.chars().collect();
-        if chars.is_empty() {
-            return String::new();
-        }
-
-        let start_index = self.eval_numeric_expression(start).unwrap_or(1.0) as i64;
-        let start_index = if start_index <= 1 {
-            0
-        } else {
-            (start_index - 1) as usize
+        let source = self.to_text(&source);
+        let start = self.eval_expression(start).to_number();
+        let length = length.map(|length| self.eval_expression(length).to_number());
+        self.substring(&source, start, length)
+    }
+
+    fn substring(&self, source: &str, start: f64, length: Option<f64>) -> Value {
+        // Positions are rounded to the nearest integer and the substring spans
+        // characters m through m + n - 1, clipped to the string, as in POSIX.
+        let first = start.round_ties_even();
+        let last = match length {
+            Some(length) => first + length.round_ties_even(),
+            None => f64::INFINITY,
         };
-        if start_index >= chars.len() {
-            return String::new();
+        let first = first.max(1.0);
+        let last = last.min(self.char_mode.len(source) as f64 + 1.0);
+        if first.is_nan() || last.is_nan() || last <= first {
+            return Value::String(String::new());
         }
 
-        let end_index = match length {
-            Some(length) => {
-                let len = self.eval_numeric_expression(length).unwrap_or(0.0) as i64;
-                if len <= 0 {
-                    return String::new();
-                }
-                (start_index + len as usize).min(chars.len())
-            }
-            None => chars.len(),
-        };
-
-        chars[start_index..end_index].iter().collect()
+        Value::String(
+            self.char_mode
+                .slice(source, first as usize - 1, (last - first) as usize),
+        )
     }
 
     fn eval_rand(&self) -> f64 {
@@ -1359,19 +2619,39 @@ impl<'a> Evaluator<'a> {
         (next as f64) / 2_147_483_648.0
     }
 
-    fn eval_function_call(&mut self, name: &str, args: &[Expression<'_>]) -> String {
+    fn eval_numeric_argument(&mut self, args: &[Expression<'_>], index: usize) -> f64 {
+        args.get(index)
+            .map(|arg| self.eval_expression(arg).to_number())
+            .unwrap_or(0.0)
+    }
+
+    fn eval_string_argument(&mut self, args: &[Expression<'_>], index: usize) -> String {
+        args.get(index)
+            .map(|arg| {
+                let value = self.eval_expression(arg);
+                self.to_text(&value)
+            })
+            .unwrap_or_default()
+    }
+
+    fn eval_function_call(&mut self, name: &str, args: &[Expression<'_>]) -> Value {
         match name {
             "sprintf" => {
                 if args.is_empty() {
-                    return String::new();
+                    return Value::String(String::new());
                 }
-                let format = unescape_awk_string(&self.eval_expression(&args[0]));
-                let values: Vec<String> = args
+                let format = unescape_awk_string(&self.eval_string_argument(args, 0));
+                let values: Vec<Value> = args
                     .iter()
                     .skip(1)
-                    .map(|arg| self.eval_printf_argument(arg))
+                    .map(|arg| self.eval_expression(arg))
                     .collect();
-                format_printf(&format, &values)
+                Value::String(format_printf(
+                    &format,
+                    &values,
+                    &self.conversion_format,
+                    self.char_mode,
+                ))
             }
             "split" => {
                 let count = match (args.first(), args.get(1), args.get(2)) {
@@ -1380,161 +2660,129 @@ impl<'a> Evaluator<'a> {
                     }
                     _ => 0,
                 };
-                format_awk_number(count as f64)
+                Value::Number(count as f64)
+            }
+            "patsplit" => {
+                let count = match (args.first(), args.get(1)) {
+                    (Some(string), Some(Expression::Identifier(array))) => {
+                        let separators = match args.get(3) {
+                            Some(Expression::Identifier(separators)) => Some(separators),
+                            _ => None,
+                        };
+                        self.eval_patsplit(string, array, args.get(2), separators)
+                    }
+                    _ => 0,
+                };
+                Value::Number(count as f64)
             }
             "index" => {
-                let string = args
-                    .first()
-                    .map(|arg| self.eval_expression(arg))
-                    .unwrap_or_default();
-                let search = args
-                    .get(1)
-                    .map(|arg| self.eval_expression(arg))
-                    .unwrap_or_default();
-                match string.find(&search) {
-                    Some(index) => format_awk_number((index + 1) as f64),
-                    None => "0".to_string(),
-                }
+                let string = self.eval_string_argument(args, 0);
+                let search = self.eval_string_argument(args, 1);
+                Value::Number(self.index(&string, &search))
             }
-            "match" => format_awk_number(self.eval_match_function(args)),
-            "sqrt" => {
-                let value = args
-                    .first()
-                    .and_then(|arg| self.eval_numeric_expression(arg))
-                    .unwrap_or(0.0);
-                format_awk_number(value.sqrt())
+            "match" => Value::Number(self.eval_match_function(args)),
+            "sqrt" => Value::Number(self.eval_numeric_argument(args, 0).sqrt()),
+            "log" => Value::Number(self.eval_numeric_argument(args, 0).ln()),
+            "exp" => Value::Number(self.eval_numeric_argument(args, 0).exp()),
+            "sin" => Value::Number(self.eval_numeric_argument(args, 0).sin()),
+            "cos" => Value::Number(self.eval_numeric_argument(args, 0).cos()),
+            "atan2" => {
+                let y = self.eval_numeric_argument(args, 0);
+                let x = self.eval_numeric_argument(args, 1);
+                Value::Number(y.atan2(x))
             }
-            "log" => {
-                let value = args
+            "int" => Value::Number(self.eval_numeric_argument(args, 0).trunc()),
+            "srand" => {
+                let seed = args
                     .first()
-                    .and_then(|arg| self.eval_numeric_expression(arg))
-                    .unwrap_or(0.0);
-                format_awk_number(value.ln())
+                    .map(|arg| self.eval_expression(arg).to_number());
+                Value::Number(self.srand(seed))
             }
-            "exp" => {
-                let value = args
-                    .first()
-                    .and_then(|arg| self.eval_numeric_expression(arg))
-                    .unwrap_or(0.0);
-                format_awk_number(value.exp())
+            "tolower" => {
+                let text = self.eval_string_argument(args, 0);
+                Value::String(self.char_mode.to_lowercase(&text))
             }
-            "sin" => {
-                let value = args
-                    .first()
-                    .and_then(|arg| self.eval_numeric_expression(arg))
-                    .unwrap_or(0.0);
-                format_awk_number(value.sin())
+            "toupper" => {
+                let text = self.eval_string_argument(args, 0);
+                Value::String(self.char_mode.to_uppercase(&text))
             }
-            "cos" => {
-                let value = args
-                    .first()
-                    .and_then(|arg| self.eval_numeric_expression(arg))
-                    .unwrap_or(0.0);
-                format_awk_number(value.cos())
+            "csvquote" => {
+                let text = self.eval_string_argument(args, 0);
+                Value::String(csv::quote_csv_field(&text, ","))
             }
-            "int" => {
-                let value = args
-                    .first()
-                    .and_then(|arg| self.eval_numeric_expression(arg))
-                    .unwrap_or(0.0);
-                format_awk_number(value.trunc())
+            "sub" | "gsub" => {
+                let count = match (args.first(), args.get(1)) {
+                    (Some(pattern), Some(replacement)) => {
+                        self.eval_substitution(pattern, replacement, args.get(2), name == "gsub")
+                    }
+                    _ => 0,
+                };
+                Value::Number(count as f64)
             }
-            "srand" => {
-                let seed = args
-                    .first()
-                    .and_then(|arg| self.eval_numeric_expression(arg))
-                    .unwrap_or(1.0) as u64;
-                self.rng_state.set(seed);
-                format_awk_number(seed as f64)
+            "system" => match args.first() {
+                Some(command) => Value::Number(self.eval_system(command)),
+                None => Value::Number(0.0),
+            },
+            "close" => {
+                let target = self.eval_string_argument(args, 0);
+                Value::Number(self.eval_close(&target))
             }
+            "fflush" => Value::Number(0.0),
             _ if self.program.function_definition(name).is_some() => {
                 self.eval_user_defined_function_call(name, args).value
             }
-            _ => "0".to_string(),
+            _ => Value::Number(0.0),
         }
     }
 
-    fn eval_numeric_function_call(&mut self, name: &str, args: &[Expression<'_>]) -> Option<f64> {
-        match name {
-            "split" => match (args.first(), args.get(1), args.get(2)) {
-                (Some(string), Some(Expression::Identifier(array)), separator) => {
-                    Some(self.eval_split(string, array, separator) as f64)
-                }
-                _ => Some(0.0),
-            },
-            "index" => {
-                let string = args
-                    .first()
-                    .map(|arg| self.eval_expression(arg))
-                    .unwrap_or_default();
-                let search = args
-                    .get(1)
-                    .map(|arg| self.eval_expression(arg))
-                    .unwrap_or_default();
-                Some(
-                    string
-                        .find(&search)
-                        .map(|index| (index + 1) as f64)
-                        .unwrap_or(0.0),
-                )
-            }
-            "match" => Some(self.eval_match_function(args)),
-            "sqrt" => args
-                .first()
-                .and_then(|arg| self.eval_numeric_expression(arg))
-                .map(f64::sqrt),
-            "log" => args
-                .first()
-                .and_then(|arg| self.eval_numeric_expression(arg))
-                .map(f64::ln),
-            "exp" => args
-                .first()
-                .and_then(|arg| self.eval_numeric_expression(arg))
-                .map(f64::exp),
-            "sin" => args
-                .first()
-                .and_then(|arg| self.eval_numeric_expression(arg))
-                .map(f64::sin),
-            "cos" => args
-                .first()
-                .and_then(|arg| self.eval_numeric_expression(arg))
-                .map(f64::cos),
-            "int" => args
-                .first()
-                .and_then(|arg| self.eval_numeric_expression(arg))
-                .map(f64::trunc),
-            "srand" => {
-                let seed = args
-                    .first()
-                    .and_then(|arg| self.eval_numeric_expression(arg))
-                    .unwrap_or(1.0) as u64;
-                self.rng_state.set(seed);
-                Some(seed as f64)
-            }
-            _ => None,
-        }
+    /// The position of the first `search` in `string`, in characters from 1,
+    /// or 0 when there is none.
+    fn index(&self, string: &str, search: &str) -> f64 {
+        string
+            .find(search)
+            .map(|index| (self.char_mode.len(&string[..index]) + 1) as f64)
+            .unwrap_or(0.0)
+    }
+
+    /// Seed the random number generator with `seed`, or with the time when
+    /// left out, and return the previous seed.
+    fn srand(&mut self, seed: Option<f64>) -> f64 {
+        let seed = seed.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs() as f64)
+                .unwrap_or(0.0)
+        });
+        self.rng_state.set(seed as u64);
+        std::mem::replace(&mut self.rand_seed, seed)
     }
 
     fn eval_match_function(&mut self, args: &[Expression<'_>]) -> f64 {
-        let text = args
-            .first()
-            .map(|arg| self.eval_expression(arg))
-            .unwrap_or_default();
+        let text = self.eval_string_argument(args, 0);
         let matched = match args.get(1) {
-            Some(Expression::Regex(pattern)) => find_awk_regex_match(&text, pattern),
-            Some(other) => find_awk_regex_match(&text, &self.eval_expression(other)),
+            Some(pattern) => {
+                let pattern = self.eval_regex_pattern(pattern);
+                find_awk_regex_match(&text, &pattern)
+            }
             None => None,
         };
+        self.set_match(&text, matched)
+    }
 
+    /// Set `RSTART` and `RLENGTH` to where `matched`, a byte range of `text`,
+    /// lies in characters, and return `RSTART`.
+    fn set_match(&mut self, text: &str, matched: Option<std::ops::Range<usize>>) -> f64 {
         match matched {
-            Some((start, length)) => {
-                self.set_variable_numeric("RSTART", (start + 1) as f64);
-                self.set_variable_numeric("RLENGTH", length as f64);
+            Some(range) => {
+                let start = self.char_mode.len(&text[..range.start]);
+                let length = self.char_mode.len(&text[range]);
+                self.set_variable(&Name::new("RSTART"), Value::Number((start + 1) as f64));
+                self.set_variable(&Name::new("RLENGTH"), Value::Number(length as f64));
                 (start + 1) as f64
             }
             None => {
-                self.set_variable_numeric("RSTART", 0.0);
-                self.set_variable_numeric("RLENGTH", -1.0);
+                self.set_variable(&Name::new("RSTART"), Value::Number(0.0));
+                self.set_variable(&Name::new("RLENGTH"), Value::Number(-1.0));
                 0.0
             }
         }
@@ -1545,71 +2793,95 @@ impl<'a> Evaluator<'a> {
         name: &str,
         args: &[Expression<'_>],
     ) -> FunctionCallResult {
-        let Some(definition) = self.program.function_definition(name).cloned() else {
+        let program = Rc::clone(&self.program);
+        let Some(definition) = program.function_definition(name) else {
             return FunctionCallResult {
                 value: self.eval_function_call(name, args),
                 output: Vec::new(),
             };
         };
 
-        let argument_values: Vec<String> =
-            args.iter().map(|arg| self.eval_expression(arg)).collect();
+        if self.poll_cancellation() {
+            return FunctionCallResult {
+                value: Value::Uninitialized,
+                output: Vec::new(),
+            };
+        }
 
-        let mut saved_values = Vec::new();
-        let mut saved_array_aliases = Vec::new();
-        for parameter in &definition.parameters {
-            saved_values.push((
-                *parameter,
-                self.variables.get(*parameter).cloned(),
-                self.numeric_variables.get(*parameter).copied(),
+        if self.frames.len() >= self.max_call_depth {
+            self.runtime_error = Some(format!(
+                "function call nesting too deep in {name} (limit {})",
+                self.max_call_depth
             ));
-            saved_array_aliases.push((*parameter, self.array_aliases.get(*parameter).cloned()));
+            return FunctionCallResult {
+                value: Value::Uninitialized,
+                output: Vec::new(),
+            };
         }
 
-        for (index, parameter) in definition.parameters.iter().enumerate() {
-            let value = argument_values.get(index).cloned().unwrap_or_default();
-            self.set_variable_text(parameter, value);
-            if let Some(Expression::Identifier(identifier)) = args.get(index) {
-                self.array_aliases
-                    .insert((*parameter).to_string(), (*identifier).to_string());
-            } else {
-                self.array_aliases.remove(*parameter);
+        // Arguments named by a bare identifier are passed by reference when
+        // used as arrays; parameters without an argument start out as fresh
+        // locals.
+        let mut locals: Frame<'a> = definition
+            .parameters
+            .iter()
+            .map(|parameter| (*parameter, Local::default()))
+            .collect();
+        for (index, arg) in args.iter().enumerate() {
+            let local = match arg {
+                Expression::Identifier(identifier) => Local {
+                    value: self.eval_identifier_expression(identifier),
+                    array: Some(self.array(identifier)),
+                },
+                _ => Local {
+                    value: self.eval_expression(arg),
+                    array: None,
+                },
+            };
+            if let Some((_, parameter)) = locals.get_mut(index) {
+                *parameter = local;
             }
         }
 
+        let profiled = self
+            .profiler
+            .as_ref()
+            .and_then(|profiler| profiler.function(definition))
+            .map(|index| (index, Instant::now()));
+        self.frames.push(locals);
+        if self.debugger.is_some() {
+            self.call_stack.push(Call {
+                function: name.to_string(),
+                offset: self.statement_offset,
+            });
+        }
         let saved_return_value = self.return_value.take();
-        let mut output = Vec::new();
-        for statement in &definition.statements {
-            let statement_output = self.eval_statement(statement, None);
-            self.append_local_output(&mut output, statement_output);
-            if self.should_break_function_body() {
-                break;
+        let output = with_stack(|| {
+            if let Some(compiled) = self.compiled.clone()
+                && let Some(&index) = compiled.function_indices.get(name)
+            {
+                return self.run_code(&compiled, &compiled.functions[index].code);
             }
-        }
-
-        let return_value = self.return_value.take().unwrap_or_default();
-        self.return_value = saved_return_value;
-
-        for (parameter, prior_value, prior_numeric_value) in saved_values {
-            if let Some(value) = prior_value {
-                self.variables.insert(parameter.to_string(), value);
-                if let Some(numeric) = prior_numeric_value {
-                    self.numeric_variables
-                        .insert(parameter.to_string(), numeric);
-                } else {
-                    self.numeric_variables.remove(parameter);
+            let mut output = Vec::new();
+            for statement in &definition.statements {
+                let statement_output = self.eval_statement(statement);
+                self.append_local_output(&mut output, statement_output);
+                if self.should_break_function_body() {
+                    break;
                 }
-            } else {
-                self.variables.remove(parameter);
-                self.numeric_variables.remove(parameter);
             }
+            output
+        });
+
+        self.frames.pop();
+        if let Some(call) = self.call_stack.pop() {
+            // Back in the statement that made the call.
+            self.statement_offset = call.offset;
         }
-        for (parameter, prior_alias) in saved_array_aliases {
-            if let Some(alias) = prior_alias {
-                self.array_aliases.insert(parameter.to_string(), alias);
-            } else {
-                self.array_aliases.remove(parameter);
-            }
+        let return_value = self.return_value.take().unwrap_or_default();
+        self.return_value = saved_return_value;
+        if let (Some(profiler), Some((index, started))) = (&mut self.profiler, profiled) {
+            profiler.add_function(index, started.elapsed());
         }
 
         FunctionCallResult {
@@ -1623,361 +2895,96 @@ impl<'a> Evaluator<'a> {
             return Vec::new();
         }
 
-        if self.field_separator == " " {
-            line.split_whitespace().map(str::to_string).collect()
+        match &self.field_mode {
+            FieldMode::Separator => self.split_with_field_separator(line),
+            FieldMode::Widths(widths) => widths.split(line, self.char_mode),
+            FieldMode::Pattern(pattern) => pattern.split(line).0,
+        }
+    }
+
+    /// Split `source` by `FS`, which `split` does whatever the field mode.
+    fn split_with_field_separator(&self, source: &str) -> Vec<String> {
+        if source.is_empty() {
+            Vec::new()
+        } else if self.csv {
+            csv::split_csv(source)
         } else {
-            split_with_regex(line, &self.field_separator)
+            split_with_separator(source, &self.field_separator)
         }
     }
 
     fn split_source(&mut self, source: &str, separator: Option<&Expression<'_>>) -> Vec<String> {
         match separator {
-            None => self.split_fields(source),
+            None => self.split_with_field_separator(source),
             Some(Expression::Regex(pattern)) => split_with_regex(source, pattern),
             Some(expression) => {
                 let separator = self.eval_expression(expression);
-                if separator == " " {
-                    source.split_whitespace().map(str::to_string).collect()
-                } else {
-                    split_with_regex(source, &separator)
-                }
+                let separator = self.to_text(&separator);
+                self.split_at(source, &separator)
             }
         }
     }
 
-    fn split_line_into_fields(&self, line: &str) -> Vec<String> {
-        if self.variables.contains_key("NF")
-            && !self.output_field_separator.is_empty()
-            && line.contains(&self.output_field_separator)
-        {
-            return line
-                .split(&self.output_field_separator)
-                .map(str::to_string)
-                .collect();
+    /// Split `source` at `separator`, the value of the third argument of
+    /// `split`.
+    fn split_at(&self, source: &str, separator: &str) -> Vec<String> {
+        if self.csv && separator == "," {
+            csv::split_csv(source)
+        } else {
+            split_with_separator(source, separator)
         }
-        self.split_fields(line)
     }
 
-    fn eval_numeric_infix(
+    fn eval_condition(&mut self, expression: &Expression<'_>) -> bool {
+        self.eval_expression(expression).is_true()
+    }
+
+    fn eval_comparison(
         &mut self,
         left: &Expression<'_>,
-        operator: &crate::token::Token<'_>,
+        operator: &TokenKind,
         right: &Expression<'_>,
-    ) -> Option<f64> {
-        if matches!(
-            operator.kind,
-            TokenKind::Assign
-                | TokenKind::AddAssign
-                | TokenKind::SubtractAssign
-                | TokenKind::MultiplyAssign
-                | TokenKind::DivideAssign
-                | TokenKind::ModuloAssign
-                | TokenKind::PowerAssign
-        ) {
-            let identifier = match left {
-                Expression::Identifier(identifier) => *identifier,
-                _ => return None,
-            };
-            let right_value = self.eval_numeric_expression(right).unwrap_or(0.0);
-            let current = parse_awk_numeric(&self.eval_identifier_expression(identifier));
-            let updated = match operator.kind {
-                TokenKind::Assign => right_value,
-                TokenKind::AddAssign => current + right_value,
-                TokenKind::SubtractAssign => current - right_value,
-                TokenKind::MultiplyAssign => current * right_value,
-                TokenKind::DivideAssign => current / right_value,
-                TokenKind::ModuloAssign => current % right_value,
-                TokenKind::PowerAssign => current.powf(right_value),
-                _ => unreachable!(),
-            };
-            self.set_variable_numeric(identifier, updated);
-            return Some(updated);
-        }
-
-        let left_value = self.eval_numeric_expression(left)?;
-        let right_value = self.eval_numeric_expression(right)?;
-
-        match operator.kind {
-            TokenKind::Plus => Some(left_value + right_value),
-            TokenKind::Minus => Some(left_value - right_value),
-            TokenKind::Asterisk => Some(left_value * right_value),
-            TokenKind::Division => Some(left_value / right_value),
-            TokenKind::Percent => Some(left_value % right_value),
-            TokenKind::Caret => Some(left_value.powf(right_value)),
-            TokenKind::GreaterThan => Some(if left_value > right_value { 1.0 } else { 0.0 }),
-            TokenKind::GreaterThanOrEqual => {
-                Some(if left_value >= right_value { 1.0 } else { 0.0 })
-            }
-            TokenKind::LessThan => Some(if left_value < right_value { 1.0 } else { 0.0 }),
-            TokenKind::LessThanOrEqual => Some(if left_value <= right_value { 1.0 } else { 0.0 }),
-            TokenKind::Equal => Some(if left_value == right_value { 1.0 } else { 0.0 }),
-            TokenKind::NotEqual => Some(if left_value != right_value { 1.0 } else { 0.0 }),
-            _ => None,
-        }
-    }
+    ) -> bool {
+        let left_value = self.eval_expression(left);
+        let right_value = self.eval_expression(right);
+        let ordering = left_value.compare(&right_value, &self.conversion_format);
 
-    fn eval_numeric_expression(&mut self, expression: &Expression<'_>) -> Option<f64> {
-        match expression {
-            Expression::Number(value) => Some(*value),
-            Expression::HexNumber { value, .. } => Some(*value),
-            Expression::Identifier(identifier) => Some(
-                self.numeric_variables
-                    .get(*identifier)
-                    .copied()
-                    .unwrap_or_else(|| {
-                        parse_awk_numeric(&self.eval_identifier_expression(identifier))
-                    }),
-            ),
-            Expression::ArrayAccess { identifier, index } => Some(parse_awk_numeric(
-                &self.eval_array_access(identifier, index),
-            )),
-            Expression::Field(inner) => Some(parse_awk_numeric(&self.eval_field_expression(inner))),
-            Expression::Length(expression) => self
-                .eval_length_expression(expression.as_deref())
-                .parse::<f64>()
-                .ok(),
-            Expression::Rand => Some(self.eval_rand()),
-            Expression::FunctionCall { name, args } => self
-                .eval_numeric_function_call(name, args)
-                .or_else(|| self.eval_function_call(name, args).parse().ok()),
-            Expression::Not(expression) => Some(if self.eval_condition(expression) {
-                0.0
-            } else {
-                1.0
-            }),
-            Expression::PreIncrement(_)
-            | Expression::PreDecrement(_)
-            | Expression::PostIncrement(_)
-            | Expression::PostDecrement(_) => self.eval_expression(expression).parse::<f64>().ok(),
-            Expression::Ternary {
-                condition,
-                then_expr,
-                else_expr,
-            } => {
-                if self.eval_condition(condition) {
-                    self.eval_numeric_expression(then_expr)
-                } else {
-                    self.eval_numeric_expression(else_expr)
-                }
-            }
-            Expression::Concatenation { left, right } => {
-                let mut value = self.eval_expression(left);
-                value.push_str(&self.eval_expression(right));
-                value.parse::<f64>().ok()
-            }
-            Expression::Infix {
-                left,
-                operator,
-                right,
-            } => self.eval_numeric_infix(left, operator, right),
-            _ => None,
-        }
-    }
-
-    fn eval_condition(&mut self, expression: &Expression<'_>) -> bool {
-        if let Expression::Not(inner) = expression {
-            return !self.eval_condition(inner);
-        }
-
-        if let Expression::Regex(pattern) = expression {
-            return self
-                .current_line
-                .as_deref()
-                .is_some_and(|line| awk_regex_matches(line, pattern));
-        }
-
-        if let Expression::Infix {
-            left,
-            operator,
-            right,
-        } = expression
-        {
-            if let Some(value) = self.eval_logical(left, operator.kind.clone(), right) {
-                return value;
-            }
-            if let Some(value) = self.eval_regex_match(left, operator.kind.clone(), right) {
-                return value;
-            }
-            if let Some(value) = self.eval_membership(left, operator.kind.clone(), right) {
-                return value;
-            }
-            if let Some(value) = self.eval_comparison(left, operator.kind.clone(), right) {
-                return value;
-            }
-        }
-
-        let value = self.eval_expression(expression);
-        awk_truthy(&value)
-    }
-
-    fn eval_comparison(
-        &mut self,
-        left: &Expression<'_>,
-        operator: TokenKind,
-        right: &Expression<'_>,
-    ) -> Option<bool> {
-        if !matches!(
-            operator,
-            TokenKind::Equal
-                | TokenKind::NotEqual
-                | TokenKind::GreaterThan
-                | TokenKind::GreaterThanOrEqual
-                | TokenKind::LessThan
-                | TokenKind::LessThanOrEqual
-        ) {
-            return None;
-        }
-
-        let left_value = self.comparison_operand(left);
-        let right_value = self.comparison_operand(right);
-
-        let result = match (left_value.numeric, right_value.numeric) {
-            (Some(l), Some(r)) => match operator {
-                TokenKind::Equal => l == r,
-                TokenKind::NotEqual => l != r,
-                TokenKind::GreaterThan => l > r,
-                TokenKind::GreaterThanOrEqual => l >= r,
-                TokenKind::LessThan => l < r,
-                TokenKind::LessThanOrEqual => l <= r,
-                _ => unreachable!(),
-            },
-            _ => match operator {
-                TokenKind::Equal => left_value.text == right_value.text,
-                TokenKind::NotEqual => left_value.text != right_value.text,
-                TokenKind::GreaterThan => left_value.text > right_value.text,
-                TokenKind::GreaterThanOrEqual => left_value.text >= right_value.text,
-                TokenKind::LessThan => left_value.text < right_value.text,
-                TokenKind::LessThanOrEqual => left_value.text <= right_value.text,
-                _ => unreachable!(),
-            },
-        };
-
-        Some(result)
-    }
-
-    fn comparison_operand(&mut self, expression: &Expression<'_>) -> ComparisonOperand {
-        match expression {
-            Expression::Identifier(identifier) => {
-                let text = self.eval_identifier_expression(identifier);
-                let numeric = self
-                    .numeric_variables
-                    .get(*identifier)
-                    .copied()
-                    .or_else(|| parse_full_awk_numeric(&text))
-                    .or_else(|| {
-                        if !self.variables.contains_key(*identifier)
-                            && !is_special_identifier(identifier)
-                        {
-                            Some(0.0)
-                        } else {
-                            None
-                        }
-                    });
-                ComparisonOperand { text, numeric }
-            }
-            Expression::Number(_)
-            | Expression::HexNumber { .. }
-            | Expression::Infix { .. }
-            | Expression::Length(_)
-            | Expression::Rand => {
-                let text = self.eval_expression(expression);
-                let numeric =
-                    parse_full_awk_numeric(&text).or_else(|| Some(parse_awk_numeric(&text)));
-                ComparisonOperand { text, numeric }
-            }
-            Expression::String(_) => {
-                let text = self.eval_expression(expression);
-                ComparisonOperand {
-                    text,
-                    numeric: None,
-                }
-            }
-            Expression::Field(_)
-            | Expression::ArrayAccess { .. }
-            | Expression::Substr { .. }
-            | Expression::FunctionCall { .. }
-            | Expression::Regex(_)
-            | Expression::Concatenation { .. }
-            | Expression::Not(_)
-            | Expression::PreIncrement(_)
-            | Expression::PreDecrement(_)
-            | Expression::PostIncrement(_)
-            | Expression::PostDecrement(_)
-            | Expression::Ternary { .. } => {
-                let text = self.eval_expression(expression);
-                let numeric = if text.is_empty() {
-                    None
-                } else {
-                    parse_full_awk_numeric(&text)
-                };
-                ComparisonOperand { text, numeric }
-            }
+        match operator {
+            TokenKind::Equal => ordering == Ordering::Equal,
+            TokenKind::NotEqual => ordering != Ordering::Equal,
+            TokenKind::GreaterThan => ordering == Ordering::Greater,
+            TokenKind::GreaterThanOrEqual => ordering != Ordering::Less,
+            TokenKind::LessThan => ordering == Ordering::Less,
+            TokenKind::LessThanOrEqual => ordering != Ordering::Greater,
+            _ => unreachable!(),
         }
     }
 
     fn eval_regex_match(
         &mut self,
         left: &Expression<'_>,
-        operator: TokenKind,
+        operator: &TokenKind,
         right: &Expression<'_>,
-    ) -> Option<bool> {
-        if !matches!(operator, TokenKind::Tilde | TokenKind::NoMatch) {
-            return None;
-        }
-
+    ) -> bool {
         let haystack = self.eval_expression(left);
-        let matches = match right {
-            Expression::Regex(pattern) => awk_regex_matches(&haystack, pattern),
-            _ => awk_regex_matches(&haystack, &self.eval_expression(right)),
-        };
-        Some(if operator == TokenKind::NoMatch {
+        let haystack = self.to_text(&haystack);
+        let pattern = self.eval_regex_pattern(right);
+        let matches = awk_regex_matches(&haystack, &pattern);
+        if *operator == TokenKind::NoMatch {
             !matches
         } else {
             matches
-        })
-    }
-
-    fn eval_logical(
-        &mut self,
-        left: &Expression<'_>,
-        operator: TokenKind,
-        right: &Expression<'_>,
-    ) -> Option<bool> {
-        match operator {
-            TokenKind::And => {
-                if !self.eval_condition(left) {
-                    Some(false)
-                } else {
-                    Some(self.eval_condition(right))
-                }
-            }
-            TokenKind::Or => {
-                if self.eval_condition(left) {
-                    Some(true)
-                } else {
-                    Some(self.eval_condition(right))
-                }
-            }
-            _ => None,
         }
     }
 
-    fn eval_membership(
-        &mut self,
-        left: &Expression<'_>,
-        operator: TokenKind,
-        right: &Expression<'_>,
-    ) -> Option<bool> {
-        if operator != TokenKind::In {
-            return None;
-        }
-
+    fn eval_membership(&mut self, left: &Expression<'_>, right: &Expression<'_>) -> bool {
         let identifier = match right {
-            Expression::Identifier(identifier) => *identifier,
-            _ => return Some(false),
+            Expression::Identifier(identifier) => identifier,
+            _ => return false,
         };
-        let key = self.array_key(identifier, left);
-        Some(self.array_variables.contains_key(&key))
+        let key = self.eval_array_subscript(left);
+        self.find_array(identifier)
+            .is_some_and(|array| array.borrow().contains_key(&key))
     }
 }
 
@@ -1989,16 +2996,25 @@ fn awk_regex_matches(text: &str, pattern: &str) -> bool {
     awk_regex_matches_legacy(text, pattern)
 }
 
-fn find_awk_regex_match(text: &str, pattern: &str) -> Option<(usize, usize)> {
+/// Find the leftmost match of `pattern` in `text` as a byte range.
+fn find_awk_regex_match(text: &str, pattern: &str) -> Option<std::ops::Range<usize>> {
     if let Ok(re) = Regex::new(pattern) {
-        return re.find(text).map(|matched| {
-            let start = text[..matched.start()].chars().count();
-            let length = text[matched.start()..matched.end()].chars().count();
-            (start, length)
-        });
+        return re.find(text).map(|matched| matched.range());
     }
 
-    find_awk_regex_match_legacy(text, pattern)
+    find_awk_regex_match_legacy_range(text, pattern)
+}
+
+/// Find the leftmost match of `pattern` in `text` with the legacy matcher,
+/// as a byte range.
+fn find_awk_regex_match_legacy_range(text: &str, pattern: &str) -> Option<std::ops::Range<usize>> {
+    let (start, length) = find_awk_regex_match_legacy(text, pattern)?;
+    let byte_offset = |chars: usize| {
+        text.char_indices()
+            .nth(chars)
+            .map_or(text.len(), |(index, _)| index)
+    };
+    Some(byte_offset(start)..byte_offset(start + length))
 }
 
 fn awk_regex_matches_legacy(text: &str, pattern: &str) -> bool {
@@ -2223,112 +3239,34 @@ fn find_awk_regex_match_legacy(text: &str, pattern: &str) -> Option<(usize, usiz
     Some((text[..byte_start].chars().count(), core.chars().count()))
 }
 
-fn awk_gsub_replace_all(text: &str, pattern: &str, replacement: &str) -> String {
-    if let Ok(re) = Regex::new(pattern) {
-        let mut out = String::new();
-        let mut last = 0usize;
-        for m in re.find_iter(text) {
-            out.push_str(&text[last..m.start()]);
-            out.push_str(&awk_subst_replacement(
-                replacement,
-                &text[m.start()..m.end()],
-            ));
-            last = m.end();
-            if m.start() == m.end() {
-                if let Some((next_idx, ch)) = text[last..].char_indices().next() {
-                    let char_end = last + next_idx + ch.len_utf8();
-                    out.push_str(&text[last..char_end]);
-                    last = char_end;
-                } else {
-                    break;
-                }
-            }
-        }
-        out.push_str(&text[last..]);
-        return out;
-    }
-
-    let anchored_start = pattern.starts_with('^');
-    let anchored_end = pattern.ends_with('$');
-    let mut core = pattern;
-
-    if anchored_start {
-        core = &core[1..];
-    }
-    if anchored_end && !core.is_empty() {
-        core = &core[..core.len() - 1];
-    }
-    if core.is_empty() {
-        return text.to_string();
-    }
-
-    match (anchored_start, anchored_end) {
-        (true, true) => {
-            if text == core {
-                awk_subst_replacement(replacement, core)
-            } else {
-                text.to_string()
-            }
+/// Replace the first match of `regex` in `text` (or every match when
+/// `global`), returning the new text and the number of replacements.
+fn awk_substitute(text: &str, regex: &Regex, replacement: &str, global: bool) -> (String, usize) {
+    let mut out = String::new();
+    let mut last = 0usize;
+    let mut count = 0usize;
+    for m in regex.find_iter(text) {
+        if m.start() < last {
+            continue;
         }
-        (true, false) => {
-            if let Some(suffix) = text.strip_prefix(core) {
-                format!("{}{}", awk_subst_replacement(replacement, core), suffix)
-            } else {
-                text.to_string()
-            }
+        out.push_str(&text[last..m.start()]);
+        out.push_str(&awk_subst_replacement(replacement, m.as_str()));
+        last = m.end();
+        count += 1;
+        if !global {
+            break;
         }
-        (false, true) => {
-            if let Some(prefix) = text.strip_suffix(core) {
-                format!("{prefix}{}", awk_subst_replacement(replacement, core))
+        if m.start() == m.end() {
+            if let Some(ch) = text[last..].chars().next() {
+                out.push(ch);
+                last += ch.len_utf8();
             } else {
-                text.to_string()
-            }
-        }
-        (false, false) => {
-            let mut out = String::new();
-            let mut last = 0usize;
-            while let Some(relative_pos) = text[last..].find(core) {
-                let start = last + relative_pos;
-                out.push_str(&text[last..start]);
-                out.push_str(&awk_subst_replacement(replacement, core));
-                last = start + core.len();
+                break;
             }
-            out.push_str(&text[last..]);
-            out
         }
     }
-}
-
-fn awk_sub_replace_first(text: &str, pattern: &str, replacement: &str) -> String {
-    if let Ok(re) = Regex::new(pattern)
-        && let Some(m) = re.find(text)
-    {
-        let mut out = String::new();
-        out.push_str(&text[..m.start()]);
-        out.push_str(&awk_subst_replacement(
-            replacement,
-            &text[m.start()..m.end()],
-        ));
-        out.push_str(&text[m.end()..]);
-        return out;
-    }
-
-    let replaced = awk_gsub_replace_all(text, pattern, replacement);
-    if replaced == text {
-        return text.to_string();
-    }
-
-    // Legacy fallback replaces all; emulate single replacement by finding first change boundary.
-    // Keep this simple for non-regex fallback patterns used by existing tests.
-    if let Some(pos) = text.find(pattern) {
-        let mut out = String::new();
-        out.push_str(&text[..pos]);
-        out.push_str(replacement);
-        out.push_str(&text[pos + pattern.len()..]);
-        return out;
-    }
-
-    replaced
+    out.push_str(&text[last..]);
+    (out, count)
 }
 
 fn awk_subst_replacement(replacement: &str, matched: &str) -> String {
@@ -2379,6 +3317,22 @@ fn unescape_awk_string(input: &str) -> String {
             Some('r') => output.push('\r'),
             Some('\\') => output.push('\\'),
             Some('"') => output.push('"'),
+            Some('/') => output.push('/'),
+            Some('a') => output.push('\x07'),
+            Some('b') => output.push('\x08'),
+            Some('f') => output.push('\x0c'),
+            Some('v') => output.push('\x0b'),
+            Some(digit @ '0'..='7') => {
+                let mut code = digit.to_digit(8).unwrap_or_default();
+                for _ in 0..2 {
+                    let Some(next) = chars.peek().and_then(|next| next.to_digit(8)) else {
+                        break;
+                    };
+                    code = code * 8 + next;
+                    chars.next();
+                }
+                output.push(char::from_u32(code).unwrap_or_default());
+            }
             Some(other) => {
                 output.push('\\');
                 output.push(other);
@@ -2403,10 +3357,15 @@ fn normalize_output_lines(lines: Vec<String>) -> Vec<String> {
     output.lines().map(str::to_string).collect()
 }
 
-fn format_printf(format: &str, args: &[String]) -> String {
+pub(crate) fn format_printf(
+    format: &str,
+    args: &[Value],
+    convfmt: &str,
+    char_mode: CharMode,
+) -> String {
     let mut result = String::new();
     let mut chars = format.chars().peekable();
-    let mut arg_index = 0usize;
+    let mut args = args.iter();
 
     while let Some(ch) = chars.next() {
         if ch != '%' {
@@ -2420,215 +3379,312 @@ fn format_printf(format: &str, args: &[String]) -> String {
             continue;
         }
 
-        let mut left_justify = false;
-        if chars.peek() == Some(&'-') {
-            left_justify = true;
+        let mut spec = FormatSpec::default();
+        while let Some(flag) = chars.peek() {
+            match flag {
+                '-' => spec.left_justify = true,
+                '+' => spec.plus_sign = true,
+                ' ' => spec.space_sign = true,
+                '#' => spec.alternate = true,
+                '0' => spec.zero_pad = true,
+                _ => break,
+            }
             chars.next();
         }
 
-        let mut width: usize = 0;
-        while let Some(next) = chars.peek() {
-            if next.is_ascii_digit() {
-                width = (width * 10) + (*next as usize - '0' as usize);
-                chars.next();
-            } else {
-                break;
+        if chars.peek() == Some(&'*') {
+            chars.next();
+            let width = args.next().map(Value::to_number).unwrap_or(0.0) as i64;
+            if width < 0 {
+                spec.left_justify = true;
             }
+            spec.width = width.unsigned_abs() as usize;
+        } else {
+            spec.width = read_format_number(&mut chars);
         }
 
-        let mut precision: Option<usize> = None;
         if chars.peek() == Some(&'.') {
             chars.next();
-            let mut value = 0usize;
-            while let Some(next) = chars.peek() {
-                if next.is_ascii_digit() {
-                    value = (value * 10) + (*next as usize - '0' as usize);
-                    chars.next();
-                } else {
-                    break;
-                }
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                let precision = args.next().map(Value::to_number).unwrap_or(0.0) as i64;
+                spec.precision = (precision >= 0).then_some(precision as usize);
+            } else {
+                spec.precision = Some(read_format_number(&mut chars));
             }
-            precision = Some(value);
         }
 
-        let specifier = match chars.next() {
-            Some(value) => value,
-            None => {
-                result.push('%');
-                break;
-            }
+        let Some(conversion) = chars.next() else {
+            result.push('%');
+            break;
         };
 
-        let arg = args.get(arg_index).cloned().unwrap_or_default();
-        arg_index += 1;
-
-        let formatted = match specifier {
-            's' => {
-                if let Some(precision) = precision {
-                    arg.chars().take(precision).collect()
-                } else {
-                    arg
-                }
-            }
-            'd' => (parse_awk_numeric(&arg).trunc() as i64).to_string(),
-            'u' => (parse_awk_numeric(&arg).trunc() as i64 as u64).to_string(),
-            'o' => format!("{:o}", parse_awk_numeric(&arg).trunc() as i64 as u64),
-            'x' => format!("{:x}", parse_awk_numeric(&arg).trunc() as i64 as u64),
-            'X' => format!("{:X}", parse_awk_numeric(&arg).trunc() as i64 as u64),
-            'c' => arg.chars().next().unwrap_or('\0').to_string(),
-            'f' => {
-                let value = parse_awk_numeric(&arg);
-                let precision = precision.unwrap_or(6);
-                format!("{value:.precision$}")
+        let formatted = match conversion {
+            'd' | 'i' | 'o' | 'x' | 'X' | 'u' | 'c' | 's' | 'e' | 'E' | 'f' | 'F' | 'g' | 'G' => {
+                let arg = args.next().cloned().unwrap_or_default();
+                format_conversion(conversion, &spec, arg, convfmt, char_mode)
             }
             _ => {
                 result.push('%');
-                if left_justify {
-                    result.push('-');
-                }
-                if width > 0 {
-                    result.push_str(&width.to_string());
-                }
-                if let Some(precision) = precision {
-                    result.push('.');
-                    result.push_str(&precision.to_string());
-                }
-                result.push(specifier);
+                result.push(conversion);
                 continue;
             }
         };
-
-        if width <= formatted.len() {
-            result.push_str(&formatted);
-            continue;
-        }
-
-        let padding_len = width - formatted.len();
-        let padding = " ".repeat(padding_len);
-        if left_justify {
-            result.push_str(&formatted);
-            result.push_str(&padding);
-        } else {
-            result.push_str(&padding);
-            result.push_str(&formatted);
-        }
+        result.push_str(&formatted);
     }
 
     result
 }
 
-fn parse_awk_numeric(input: &str) -> f64 {
-    let s = input.trim_start();
-    if s.is_empty() {
-        return 0.0;
-    }
+#[derive(Default)]
+struct FormatSpec {
+    left_justify: bool,
+    plus_sign: bool,
+    space_sign: bool,
+    alternate: bool,
+    zero_pad: bool,
+    width: usize,
+    precision: Option<usize>,
+}
 
-    let mut end = 0usize;
-    let mut seen_digit = false;
-    let mut seen_dot = false;
+fn read_format_number(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> usize {
+    let mut value = 0usize;
+    while let Some(digit) = chars.peek().and_then(|ch| ch.to_digit(10)) {
+        value = value.saturating_mul(10).saturating_add(digit as usize);
+        chars.next();
+    }
+    value
+}
 
-    for (idx, ch) in s.char_indices() {
-        if ch.is_ascii_digit() {
-            seen_digit = true;
-            end = idx + ch.len_utf8();
-            continue;
+fn format_conversion(
+    conversion: char,
+    spec: &FormatSpec,
+    arg: Value,
+    convfmt: &str,
+    char_mode: CharMode,
+) -> String {
+    match conversion {
+        's' => {
+            let text = arg.into_string_with(convfmt);
+            let text = match spec.precision {
+                Some(precision) => char_mode.slice(&text, 0, precision),
+                None => text,
+            };
+            pad_text(text, spec, char_mode)
         }
-        if (ch == '+' || ch == '-') && idx == 0 {
-            end = idx + ch.len_utf8();
-            continue;
+        'c' => {
+            let text = match arg {
+                Value::Number(number) => char_mode.char_for_code(number),
+                other => {
+                    let text = char_mode.first(&other.into_string_with(convfmt));
+                    if text.is_empty() {
+                        "\0".to_string()
+                    } else {
+                        text
+                    }
+                }
+            };
+            pad_text(text, spec, char_mode)
         }
-        if ch == '.' && !seen_dot {
-            seen_dot = true;
-            end = idx + ch.len_utf8();
-            continue;
+        'd' | 'i' => {
+            let number = arg.to_number().trunc();
+            let digits = integer_digits(number.abs() as u64, 10, false, spec.precision);
+            let sign = sign_prefix(number < 0.0, spec);
+            pad_number(sign, digits, spec, spec.precision.is_none())
+        }
+        'o' | 'x' | 'X' | 'u' => {
+            let number = arg.to_number().trunc() as i64 as u64;
+            let radix = match conversion {
+                'o' => 8,
+                'u' => 10,
+                _ => 16,
+            };
+            let mut digits = integer_digits(number, radix, conversion == 'X', spec.precision);
+            let mut prefix = "";
+            if spec.alternate && number != 0 {
+                match conversion {
+                    'o' if !digits.starts_with('0') => digits.insert(0, '0'),
+                    'x' => prefix = "0x",
+                    'X' => prefix = "0X",
+                    _ => {}
+                }
+            }
+            pad_number(prefix, digits, spec, spec.precision.is_none())
+        }
+        _ => {
+            let number = arg.to_number();
+            let precision = spec.precision.unwrap_or(6);
+            let body = if !number.is_finite() {
+                let text = if number.is_nan() { "nan" } else { "inf" };
+                if conversion.is_ascii_uppercase() {
+                    text.to_ascii_uppercase()
+                } else {
+                    text.to_string()
+                }
+            } else {
+                match conversion {
+                    'e' | 'E' => format_exponential(number.abs(), precision, conversion == 'E'),
+                    'f' | 'F' => format!("{:.precision$}", number.abs()),
+                    _ => format_general(number.abs(), precision, spec.alternate, conversion == 'G'),
+                }
+            };
+            let sign = sign_prefix(number.is_sign_negative() && number != 0.0, spec);
+            pad_number(sign, body, spec, number.is_finite())
         }
-        break;
     }
+}
 
-    if !seen_digit {
-        return 0.0;
+fn integer_digits(value: u64, radix: u32, uppercase: bool, precision: Option<usize>) -> String {
+    let mut digits = match radix {
+        8 => format!("{value:o}"),
+        16 if uppercase => format!("{value:X}"),
+        16 => format!("{value:x}"),
+        _ => value.to_string(),
+    };
+    match precision {
+        Some(0) if value == 0 => digits.clear(),
+        Some(precision) if digits.len() < precision => {
+            digits.insert_str(0, &"0".repeat(precision - digits.len()));
+        }
+        _ => {}
     }
+    digits
+}
 
-    s[..end].parse::<f64>().unwrap_or(0.0)
+fn sign_prefix(negative: bool, spec: &FormatSpec) -> &'static str {
+    if negative {
+        "-"
+    } else if spec.plus_sign {
+        "+"
+    } else if spec.space_sign {
+        " "
+    } else {
+        ""
+    }
 }
 
-fn parse_full_awk_numeric(input: &str) -> Option<f64> {
-    let s = input.trim();
-    if s.is_empty() {
-        return None;
+fn pad_number(prefix: &str, body: String, spec: &FormatSpec, allow_zero_pad: bool) -> String {
+    let length = prefix.len() + body.len();
+    if spec.zero_pad && allow_zero_pad && !spec.left_justify && spec.width > length {
+        return format!("{prefix}{}{body}", "0".repeat(spec.width - length));
+    }
+    pad_formatted(format!("{prefix}{body}"), spec)
+}
+
+fn pad_formatted(text: String, spec: &FormatSpec) -> String {
+    pad_text(text, spec, CharMode::Bytes)
+}
+
+/// Pad `text` to the field width, measuring it in `char_mode` characters.
+fn pad_text(text: String, spec: &FormatSpec, char_mode: CharMode) -> String {
+    let length = char_mode.len(&text);
+    if spec.width <= length {
+        return text;
+    }
+    let padding = " ".repeat(spec.width - length);
+    if spec.left_justify {
+        format!("{text}{padding}")
+    } else {
+        format!("{padding}{text}")
+    }
+}
+
+fn format_exponential(value: f64, precision: usize, uppercase: bool) -> String {
+    let formatted = format!("{value:.precision$e}");
+    let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    let marker = if uppercase { 'E' } else { 'e' };
+    format!("{mantissa}{marker}{sign}{:02}", exponent.abs())
+}
+
+fn format_general(value: f64, precision: usize, alternate: bool, uppercase: bool) -> String {
+    let precision = precision.max(1);
+    if value == 0.0 {
+        return if alternate {
+            format!("{:.*}", precision - 1, 0.0)
+        } else {
+            "0".to_string()
+        };
+    }
+
+    let exponent = format!("{value:.*e}", precision - 1)
+        .split_once('e')
+        .and_then(|(_, exponent)| exponent.parse::<i32>().ok())
+        .unwrap_or(0);
+    let formatted = if exponent < -4 || exponent >= precision as i32 {
+        format_exponential(value, precision - 1, uppercase)
+    } else {
+        let decimals = (precision as i32 - 1 - exponent).max(0) as usize;
+        format!("{value:.decimals$}")
+    };
+    if alternate {
+        return formatted;
     }
 
-    s.parse::<f64>().ok()
+    match formatted.find(['e', 'E']) {
+        Some(index) => {
+            let (mantissa, exponent) = formatted.split_at(index);
+            format!("{}{exponent}", trim_fraction_zeros(mantissa))
+        }
+        None => trim_fraction_zeros(&formatted).to_string(),
+    }
 }
 
-fn expression_has_precise_numeric_value(expression: &Expression<'_>) -> bool {
-    match expression {
-        Expression::Number(_)
-        | Expression::HexNumber { .. }
-        | Expression::Length(_)
-        | Expression::Rand => true,
-        Expression::Field(_) => false,
-        Expression::Identifier(_) | Expression::ArrayAccess { .. } => false,
-        Expression::FunctionCall { name, .. } => matches!(
-            *name,
-            "index"
-                | "length"
-                | "split"
-                | "sqrt"
-                | "log"
-                | "exp"
-                | "sin"
-                | "cos"
-                | "int"
-                | "srand"
-                | "rand"
-        ),
-        Expression::Not(_)
-        | Expression::PreIncrement(_)
-        | Expression::PreDecrement(_)
-        | Expression::PostIncrement(_)
-        | Expression::PostDecrement(_) => false,
-        Expression::Ternary {
-            then_expr,
-            else_expr,
-            ..
-        } => {
-            expression_has_precise_numeric_value(then_expr)
-                && expression_has_precise_numeric_value(else_expr)
-        }
-        Expression::Concatenation { .. } | Expression::String(_) | Expression::Regex(_) => false,
-        Expression::Substr { .. } => false,
-        Expression::Infix { operator, .. } => matches!(
-            operator.kind,
-            TokenKind::Plus
-                | TokenKind::Minus
-                | TokenKind::Asterisk
-                | TokenKind::Division
-                | TokenKind::Percent
-                | TokenKind::Caret
-                | TokenKind::GreaterThan
-                | TokenKind::GreaterThanOrEqual
-                | TokenKind::LessThan
-                | TokenKind::LessThanOrEqual
-                | TokenKind::Equal
-                | TokenKind::NotEqual
-        ),
+fn trim_fraction_zeros(text: &str) -> &str {
+    if !text.contains('.') {
+        return text;
     }
+    text.trim_end_matches('0').trim_end_matches('.')
 }
 
-fn is_special_identifier(identifier: &str) -> bool {
-    matches!(
-        identifier,
-        "getline" | "FS" | "OFS" | "ORS" | "NF" | "NR" | "FNR" | "FILENAME" | "ARGC"
-    )
+/// Split a command-line operand of the form `name=value` into its parts, as
+/// long as `name` is a valid AWK identifier. Such an operand assigns the
+/// variable when the input loop reaches it, instead of naming an input file.
+///
+/// # Examples
+///
+/// ```
+/// use rawk_core::parse_command_line_assignment;
+///
+/// assert_eq!(parse_command_line_assignment("FS=:"), Some(("FS", ":")));
+/// assert_eq!(parse_command_line_assignment("data/a=b.txt"), None);
+/// ```
+pub fn parse_command_line_assignment(operand: &str) -> Option<(&str, &str)> {
+    let (name, value) = operand.split_once('=')?;
+    let mut chars = name.chars();
+    let first = chars.next()?;
+    if !(first.is_ascii_alphabetic() || first == '_')
+        || !chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+    {
+        return None;
+    }
+    Some((name, value))
 }
 
-fn awk_truthy(value: &str) -> bool {
-    if value.is_empty() {
-        return false;
+/// Split `source` by a field separator string following POSIX: a single space
+/// splits on runs of whitespace, any other single character is a literal
+/// separator, and anything longer is an extended regular expression.
+fn split_with_separator(source: &str, separator: &str) -> Vec<String> {
+    let mut chars = separator.chars();
+    match (chars.next(), chars.next()) {
+        _ if source.is_empty() => Vec::new(),
+        (Some(' '), None) => source.split_whitespace().map(str::to_string).collect(),
+        (Some(ch), None) => source.split(ch).map(str::to_string).collect(),
+        _ => split_with_regex(source, separator),
     }
+}
 
-    parse_full_awk_numeric(value) != Some(0.0)
+/// Replace the elements of `array` with `fields`, numbered from 1, and
+/// return how many there are.
+fn fill_split_array(array: &Array, fields: Vec<String>) -> usize {
+    let mut array = array.borrow_mut();
+    array.clear();
+    let count = fields.len();
+    for (index, field) in fields.into_iter().enumerate() {
+        array.insert((index + 1).to_string(), Value::from_input(field));
+    }
+    count
 }
 
 fn split_with_regex(source: &str, pattern: &str) -> Vec<String> {
@@ -2639,6 +3695,13 @@ fn split_with_regex(source: &str, pattern: &str) -> Vec<String> {
     let Ok(regex) = Regex::new(pattern) else {
         return source.split(pattern).map(str::to_string).collect();
     };
+    split_with_compiled_regex(source, &regex)
+}
+
+fn split_with_compiled_regex(source: &str, regex: &Regex) -> Vec<String> {
+    if source.is_empty() {
+        return Vec::new();
+    }
 
     let mut fields = Vec::new();
     let mut last_end = 0;
@@ -2650,29 +3713,6 @@ fn split_with_regex(source: &str, pattern: &str) -> Vec<String> {
     fields
 }
 
-fn format_awk_number(value: f64) -> String {
-    if !value.is_finite() {
-        return value.to_string();
-    }
-
-    if value == 0.0 {
-        return "0".to_string();
-    }
-
-    if value.fract() == 0.0 {
-        return format!("{value:.0}");
-    }
-
-    let abs = value.abs();
-    let exponent = abs.log10().floor() as i32;
-    let decimals = (6 - exponent - 1).max(0) as usize;
-    let formatted = format!("{value:.decimals$}");
-    formatted
-        .trim_end_matches('0')
-        .trim_end_matches('.')
-        .to_string()
-}
-
 #[cfg(test)]
 mod tests {
     use crate::{Lexer, Parser};
@@ -2690,7 +3730,7 @@ mod tests {
         };
         let mut range_active = false;
 
-        let output = evaluator.eval_rule_for_line(&rule, "input", &mut range_active);
+        let output = evaluator.eval_rule_for_line(0, &rule, &mut range_active);
 
         assert!(output.is_empty());
         assert!(!range_active);
@@ -2704,12 +3744,35 @@ mod tests {
         });
         let mut range_active = false;
 
-        let output = evaluator.eval_rule_for_line(&rule, "input", &mut range_active);
+        let output = evaluator.eval_rule_for_line(0, &rule, &mut range_active);
 
         assert!(output.is_empty());
         assert!(!range_active);
     }
 
+    #[test]
+    fn eval_stray_jumps_end_the_action_they_are_in() {
+        let print = |text| Statement::Print(vec![Expression::String(text)]);
+        let mut program = Program::new();
+        program.add_begin_block(Action {
+            statements: vec![print("a"), Statement::Break, print("unexpected")],
+        });
+        program.add_begin_block(Action {
+            statements: vec![print("b"), Statement::Return(None), print("unexpected")],
+        });
+        program.add_end_block(Action {
+            statements: vec![Statement::Continue, print("unexpected")],
+        });
+        program.add_end_block(Action {
+            statements: vec![print("c")],
+        });
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["a", "b", "c"]);
+    }
+
     #[test]
     fn eval_print_action_outputs_input_line() {
         let lexer = Lexer::new("{ print }");
@@ -2998,6 +4061,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn eval_record_assignment_is_seen_by_later_rules() {
+        let lexer = Lexer::new(r#"{ $0 = "x y z" } $2 == "y" { print NF } { $2 = "B" } 1"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["a".to_string()], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["3".to_string(), "x B z".to_string()]);
+    }
+
+    #[test]
+    fn eval_nf_assignment_rebuilds_record_with_ofs_in_effect() {
+        let lexer = Lexer::new(
+            r#"BEGIN { OFS = "-" } { NF = 4; OFS = ":"; print; print NF, $4 == ""; FS = ","; print $1 }"#,
+        );
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["a b,c".to_string()], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(
+            output,
+            vec!["a-b,c--".to_string(), "4:1".to_string(), "a".to_string()]
+        );
+    }
+
     #[test]
     fn eval_print_line_numbers() {
         let lexer = Lexer::new(r#"{ print NR, $0 }"#);
@@ -3023,6 +4115,18 @@ mod tests {
         assert_eq!(output, vec!["[      USSR] [275             ]".to_string()]);
     }
 
+    #[test]
+    fn eval_printf_joins_partial_lines_across_calls() {
+        let lexer = Lexer::new(r#"BEGIN { printf "a"; printf "b\nc\n\nd"; printf "e\nf" }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["ab", "c", "", "de", "f"]);
+    }
+
     #[test]
     fn eval_gsub_then_print_uses_updated_line() {
         let lexer = Lexer::new(r#"{ gsub(/USA/, "United States"); print }"#);
@@ -3298,117 +4402,863 @@ mod tests {
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["abc".to_string(), "def".to_string()]);
+        assert_eq!(output, vec!["abc".to_string(), "def".to_string()]);
+    }
+
+    #[test]
+    fn eval_exit_stops_processing_and_preserves_nr_for_end() {
+        let lexer = Lexer::new(
+            r#"NR >= 2 { exit } END { if (NR < 2) print FILENAME " has only " NR " lines" }"#,
+        );
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(
+            program,
+            vec!["A".to_string(), "B".to_string(), "C".to_string()],
+            "-",
+        );
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, Vec::<String>::new());
+    }
+
+    #[test]
+    fn eval_user_defined_function_call_can_exit() {
+        let lexer = Lexer::new(
+            r#"BEGIN { print "before"; myabort(1); print "after" } function myabort(n) { print "exit", n; exit n } END { print "end" }"#,
+        );
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["A".to_string()], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(
+            output,
+            vec![
+                "before".to_string(),
+                "exit 1".to_string(),
+                "end".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn eval_array_add_assignment_and_access() {
+        let lexer = Lexer::new(r#"/Asia/ { pop["Asia"] += $3 } END { print pop["Asia"] }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(
+            program,
+            vec![
+                "USSR\t8649\t275\tAsia".to_string(),
+                "China\t3705\t1032\tAsia".to_string(),
+            ],
+            "-",
+        );
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["1307".to_string()]);
+    }
+
+    #[test]
+    fn eval_delete_removes_array_entry_from_for_in() {
+        let lexer =
+            Lexer::new(r#"BEGIN { x[1] = "a"; x[2] = "b"; delete x[1]; for (i in x) print i }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["2".to_string()]);
+    }
+
+    #[test]
+    fn eval_for_in_loop_over_array_keys() {
+        let lexer = Lexer::new(
+            r#"BEGIN { area["Asia"] = 1; area["Europe"] = 2 } END { for (name in area) print name ":" area[name] }"#,
+        );
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["Asia:1".to_string(), "Europe:2".to_string()]);
+    }
+
+    #[test]
+    fn eval_parenthesized_composite_membership_expression() {
+        let lexer =
+            Lexer::new(r#"{ x[$0, $1] = "hit"; if (($0, $1) in x) print "yes"; else print "no" }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["17379\tmel".to_string()], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["yes".to_string()]);
+    }
+
+    #[test]
+    fn eval_print_respects_ors_between_records() {
+        let lexer = Lexer::new(r#"BEGIN { OFS = ":"; ORS = "\n\n" } { print $1, $2 }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(
+            program,
+            vec![
+                "USSR\t8649\t275\tAsia".to_string(),
+                "Canada\t3852\t25\tNorth America".to_string(),
+            ],
+            "-",
+        );
+
+        let output = evaluator.eval();
+
+        assert_eq!(
+            output,
+            vec![
+                "USSR:8649".to_string(),
+                "".to_string(),
+                "Canada:3852".to_string(),
+                "".to_string()
+            ]
+        );
+    }
+
+    /// A path in the temporary directory for a test to write, unique to the
+    /// test and the process.
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("rawk-{name}-{}.txt", std::process::id()))
+    }
+
+    /// Run `script`, with `PATH` standing for the path of a temporary file, and
+    /// return its output and what it wrote to the file.
+    fn eval_writing_file(
+        name: &str,
+        script: &str,
+        input: &[&str],
+        existing: Option<&str>,
+    ) -> (Vec<String>, String) {
+        let path = temp_path(name);
+        match existing {
+            Some(text) => std::fs::write(&path, text).unwrap(),
+            None => {
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+        let script = script.replace("PATH", &path.to_string_lossy());
+        let program = Parser::new(Lexer::new(&script)).parse_program();
+        let input = input.iter().map(|line| line.to_string()).collect();
+        let mut evaluator = Evaluator::new(program, input, "-");
+
+        let output = evaluator.eval();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (output, written)
+    }
+
+    #[test]
+    fn eval_print_redirection_writes_the_file_instead_of_stdout() {
+        let (output, written) = eval_writing_file(
+            "print-redirect",
+            r#"{ print > "PATH" }"#,
+            &["USSR\t8649\t275\tAsia"],
+            None,
+        );
+
+        assert!(output.is_empty());
+        assert_eq!(written, "USSR\t8649\t275\tAsia\n");
+    }
+
+    #[test]
+    fn eval_print_redirection_truncates_once_then_appends() {
+        let (_, written) = eval_writing_file(
+            "truncate",
+            r#"{ print $1, NR > "PATH" }"#,
+            &["a", "b", "c"],
+            Some("old contents\n"),
+        );
+
+        assert_eq!(written, "a 1\nb 2\nc 3\n");
+    }
+
+    #[test]
+    fn eval_print_append_redirection_keeps_existing_contents() {
+        let (_, written) = eval_writing_file(
+            "append",
+            r#"{ print >> "PATH" } END { printf "%s-%s\n", "x", "y" >> "PATH" }"#,
+            &["new"],
+            Some("old\n"),
+        );
+
+        assert_eq!(written, "old\nnew\nx-y\n");
+    }
+
+    #[test]
+    fn eval_close_ends_a_redirected_file_and_reopening_truncates() {
+        let (output, written) = eval_writing_file(
+            "close",
+            r#"BEGIN { print "first" > "PATH"; print close("PATH"), close("PATH"); print "second" > "PATH" }"#,
+            &[],
+            None,
+        );
+
+        assert_eq!(output, vec!["0 -1".to_string()]);
+        assert_eq!(written, "second\n");
+    }
+
+    #[test]
+    fn eval_print_pipe_to_sort_flushes_sorted_output() {
+        let lexer = Lexer::new(r#"BEGIN { print "b" | "sort"; print "a" | "sort" }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn eval_printf_pipe_to_sort_joins_partial_lines() {
+        let lexer = Lexer::new(
+            r#"{ printf "%s", $2 | "sort"; printf " %s\n", $1 | "sort" } END { close("sort"); print "done" }"#,
+        );
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(
+            program,
+            vec!["1 pear".to_string(), "2 apple".to_string()],
+            "-",
+        );
+
+        let output = evaluator.eval();
+
+        assert_eq!(
+            output,
+            vec![
+                "apple 2".to_string(),
+                "pear 1".to_string(),
+                "done".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn eval_print_pipe_runs_the_command_and_close_returns_its_status() {
+        let lexer = Lexer::new(
+            r#"BEGIN { print "quiet" | "tr a-z A-Z"; status = close("tr a-z A-Z"); print "fail" | "cat; exit 3"; print status, close("cat; exit 3"), close("tr a-z A-Z") }"#,
+        );
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, ["QUIET", "fail", "0 3 -1"]);
+    }
+
+    #[test]
+    fn eval_print_pipe_streams_more_than_a_pipe_holds() {
+        let lexer = Lexer::new(
+            r#"BEGIN { for (i = 0; i < 100000; i++) print "line", i | "cat"; close("cat"); print "done" }"#,
+        );
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output.len(), 100_001);
+        assert_eq!(output[99_999], "line 99999");
+        assert_eq!(output[100_000], "done");
+    }
+
+    #[test]
+    fn eval_printf_redirection_writes_the_file_instead_of_stdout() {
+        let (output, written) = eval_writing_file(
+            "printf-redirect",
+            r#"{ printf "%s\n", ($1 > 2) > "PATH"; printf("%s-%s\n", $1, "b") >> "PATH" }"#,
+            &["3"],
+            None,
+        );
+
+        assert!(output.is_empty());
+        assert_eq!(written, "1\n3-b\n");
+    }
+
+    #[test]
+    fn eval_split_function_call_updates_array_and_returns_count() {
+        let lexer = Lexer::new(
+            r#"function f(a) { return split($0, a) } { print f(x); print x[1]; print x[2] }"#,
+        );
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["hello world".to_string()], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(
+            output,
+            vec!["2".to_string(), "hello".to_string(), "world".to_string()]
+        );
+    }
+
+    #[test]
+    fn eval_getline_in_begin_consumes_input_records() {
+        let lexer = Lexer::new(r#"BEGIN { while (getline && NR < 3) print } { print }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(
+            program,
+            vec!["A".to_string(), "B".to_string(), "C".to_string()],
+            "-",
+        );
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["A".to_string(), "B".to_string()]);
+    }
+
+    #[test]
+    fn unescape_awk_string_handles_known_escapes() {
+        let input = r#"line1\nline2\t\"x\"\\done\r"#;
+
+        let output = unescape_awk_string(input);
+
+        assert_eq!(output, "line1\nline2\t\"x\"\\done\r");
+    }
+
+    #[test]
+    fn unescape_awk_string_handles_posix_escapes() {
+        let input = r#"\/\a\b\f\v|\072|\0|\1011|\8"#;
+
+        let output = unescape_awk_string(input);
+
+        assert_eq!(output, "/\x07\x08\x0c\x0b|:|\0|A1|\\8");
+    }
+
+    #[test]
+    fn unescape_awk_string_preserves_unknown_escape_sequences() {
+        let input = r#"a\qb\zc"#;
+
+        let output = unescape_awk_string(input);
+
+        assert_eq!(output, r#"a\qb\zc"#);
+    }
+
+    #[test]
+    fn unescape_awk_string_keeps_trailing_backslash() {
+        let input = "abc\\";
+
+        let output = unescape_awk_string(input);
+
+        assert_eq!(output, "abc\\");
+    }
+
+    #[test]
+    fn unescape_awk_string_mixes_plain_and_escaped_text() {
+        let input = r#"A\tB\nC\\D\"E\q"#;
+
+        let output = unescape_awk_string(input);
+
+        assert_eq!(output, "A\tB\nC\\D\"E\\q");
+    }
+
+    #[test]
+    fn eval_print_string_with_embedded_newline_splits_output_lines() {
+        let lexer = Lexer::new(r#"BEGIN { print "\nUSSR" }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["".to_string(), "USSR".to_string()]);
+    }
+
+    #[test]
+    fn eval_print_respects_custom_ors_without_newlines() {
+        let lexer = Lexer::new(r###"BEGIN { ORS = "##" } { print $1; print $2 }"###);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["alpha beta".to_string()], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["alpha##beta##".to_string()]);
+    }
+
+    #[test]
+    fn eval_match_function_sets_rstart_and_rlength() {
+        let lexer = Lexer::new(r#"BEGIN { print match("abc123", "[0-9]+"), RSTART, RLENGTH }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["4 4 3".to_string()]);
+    }
+
+    #[test]
+    fn eval_match_function_falls_back_to_legacy_regex_matching() {
+        let lexer = Lexer::new(r#"BEGIN { print match("daemon", "(foo|dae)") , RSTART, RLENGTH }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["1 1 3".to_string()]);
+    }
+
+    #[test]
+    fn eval_dynamic_regex_string_uses_full_regex_semantics() {
+        let lexer = Lexer::new(
+            r#"BEGIN { r = "[^aeiou]"; p = "17:"; if ("17abc" ~ r) print "class"; if ("17abc" ~ p) print "prefix"; if ("17:rest" ~ p) print "colon" }"#,
+        );
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["class".to_string(), "colon".to_string()]);
+    }
+
+    #[test]
+    fn eval_comparison_distinguishes_uninitialized_vars_from_empty_fields() {
+        let lexer = Lexer::new(
+            r#"BEGIN { FS = ":" } { if (b == 0) print "b"; if ($1 == 0) print "$1num"; if ($1 == "") print "$1str" }"#,
+        );
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![":x".to_string()], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["b".to_string(), "$1str".to_string()]);
+    }
+
+    #[test]
+    fn eval_field_with_string_literal_index_returns_record() {
+        // A non-numeric string converts to 0, so $"abc" is $0.
+        let lexer = Lexer::new(r#"{ print $"abc" }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["hello world".to_string()], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["hello world".to_string()]);
+    }
+
+    #[test]
+    fn eval_field_in_begin_block_returns_empty() {
+        // No current line exists in a BEGIN block, so all field accesses return "".
+        let lexer = Lexer::new("BEGIN { print $1 }");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["".to_string()]);
+    }
+
+    #[test]
+    fn eval_negative_field_index_sets_runtime_error_and_returns_no_output() {
+        let lexer = Lexer::new("{ print $(-1) }");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["hello world".to_string()], "-");
+
+        let output = evaluator.eval();
+
+        assert!(output.is_empty());
+        assert_eq!(
+            evaluator.runtime_error(),
+            Some("attempt to access field -1")
+        );
+    }
+
+    #[test]
+    fn eval_unary_plus_converts_its_operand_to_a_number() {
+        let lexer = Lexer::new(r#"BEGIN { print +" 12 ", +"3x", +"", -+"4" } { print +$1 + 1 }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["007".to_string()], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["12 3 0 -4".to_string(), "8".to_string()]);
+    }
+
+    #[test]
+    fn eval_sin_of_zero_returns_zero() {
+        let lexer = Lexer::new("BEGIN { print sin(0) }");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["0".to_string()]);
+    }
+
+    #[test]
+    fn eval_sin_of_pi_over_two_returns_one() {
+        let lexer = Lexer::new("BEGIN { print sin(3.14159265358979 / 2) }");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["1".to_string()]);
+    }
+
+    #[test]
+    fn eval_cos_of_zero_returns_one() {
+        let lexer = Lexer::new("BEGIN { print cos(0) }");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["1".to_string()]);
+    }
+
+    #[test]
+    fn eval_cos_of_pi_returns_minus_one() {
+        let lexer = Lexer::new("BEGIN { print cos(3.14159265358979) }");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["-1".to_string()]);
+    }
+
+    #[test]
+    fn eval_srand_returns_previous_seed() {
+        let lexer = Lexer::new("BEGIN { print srand(42); print srand(7); print srand() }");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(
+            output,
+            vec!["0".to_string(), "42".to_string(), "7".to_string()]
+        );
+    }
+
+    #[test]
+    fn eval_srand_affects_subsequent_rand_output() {
+        let lexer =
+            Lexer::new("BEGIN { srand(1); r1 = rand(); srand(1); r2 = rand(); print (r1 == r2) }");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-");
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["1".to_string()]);
+    }
+
+    #[test]
+    fn eval_environ_reads_host_supplied_environment() {
+        let lexer = Lexer::new(r#"BEGIN { print ENVIRON["HOME"]; print ("PATH" in ENVIRON) }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator =
+            Evaluator::new(program, vec![], "-").with_environ([("HOME", "/home/alice")]);
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["/home/alice".to_string(), "0".to_string()]);
+    }
+
+    #[test]
+    fn eval_argv_holds_every_operand() {
+        let lexer = Lexer::new(r#"BEGIN { for (i = 0; i < ARGC; i++) print i, ARGV[i] }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-").with_arguments(vec![
+            "a.txt".to_string(),
+            "x=1".to_string(),
+            "b.txt".to_string(),
+        ]);
+
+        let output = evaluator.eval();
+
+        assert_eq!(
+            output,
+            vec![
+                "0 rawk".to_string(),
+                "1 a.txt".to_string(),
+                "2 x=1".to_string(),
+                "3 b.txt".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn eval_main_loop_honors_argv_edits_made_in_begin() {
+        let lexer = Lexer::new(
+            r#"BEGIN { ARGV[1] = ""; ARGV[ARGC++] = "n=7"; ARGV[ARGC++] = "-" } { print n, $0 }"#,
+        );
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["record".to_string()], "-")
+            .with_arguments(vec!["does-not-exist.txt".to_string()]);
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["7 record".to_string()]);
+        assert_eq!(evaluator.runtime_error(), None);
+    }
+
+    #[test]
+    fn eval_reads_file_operands_in_order_and_resets_fnr() {
+        let path = std::env::temp_dir().join(format!("rawk-argv-{}.txt", std::process::id()));
+        std::fs::write(&path, "first\nsecond\n").unwrap();
+        let lexer = Lexer::new(r#"{ print FILENAME == "-" ? "stdin" : "file", NR, FNR, $0 }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["piped".to_string()], "-")
+            .with_arguments(vec![path.to_string_lossy().into_owned(), "-".to_string()]);
+
+        let output = evaluator.eval();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            output,
+            vec![
+                "file 1 1 first".to_string(),
+                "file 2 2 second".to_string(),
+                "stdin 3 1 piped".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn eval_reads_standard_input_only_when_argv_names_no_file() {
+        let path = std::env::temp_dir().join(format!("rawk-stdin-{}.txt", std::process::id()));
+        std::fs::write(&path, "from file\n").unwrap();
+        let script = format!(
+            r#"BEGIN {{ if (ARGC == 1) {{ ARGV[1] = "{}"; ARGC = 2 }} }} {{ print }}"#,
+            path.display()
+        );
+        let run = |script: &str, arguments: Vec<String>| {
+            let mut parser = Parser::new(Lexer::new(script));
+            let program = parser.parse_program();
+            Evaluator::new(program, vec![], "-")
+                .with_arguments(arguments)
+                .with_standard_input(&b"piped\n"[..])
+                .eval()
+        };
+
+        let from_begin = run(&script, vec![]);
+        let dashed = run("{ print }", vec!["-".to_string()]);
+        let deleted = run(
+            "BEGIN { delete ARGV[1] } { print }",
+            vec![path.to_string_lossy().into_owned()],
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(from_begin, vec!["from file".to_string()]);
+        assert_eq!(dashed, vec!["piped".to_string()]);
+        assert_eq!(deleted, vec!["piped".to_string()]);
+    }
+
+    #[test]
+    fn eval_reads_standard_input_a_record_at_a_time() {
+        let program = Parser::new(Lexer::new("NR == 2 { exit } { print }")).parse_program();
+        // The reader fails past the second line, which is never reached.
+        let input = std::io::Read::chain(&b"a\n\xffb\r\n"[..], FailingReader);
+        let mut evaluator = Evaluator::new(program, vec![], "-")
+            .with_standard_input(std::io::BufReader::new(input));
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["a".to_string()]);
+        assert_eq!(evaluator.runtime_error(), None);
+    }
+
+    #[test]
+    fn eval_replaces_invalid_utf8_in_standard_input() {
+        let program = Parser::new(Lexer::new("{ print NR \": \" $0 }")).parse_program();
+        let mut evaluator =
+            Evaluator::new(program, vec![], "-").with_standard_input(&b"a\n\xff\nb\n"[..]);
+
+        let output = evaluator.eval();
+
+        assert_eq!(output, vec!["1: a", "2: \u{FFFD}", "3: b"]);
     }
 
     #[test]
-    fn eval_exit_stops_processing_and_preserves_nr_for_end() {
-        let lexer = Lexer::new(
-            r#"NR >= 2 { exit } END { if (NR < 2) print FILENAME " has only " NR " lines" }"#,
-        );
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(
-            program,
-            vec!["A".to_string(), "B".to_string(), "C".to_string()],
-            "-",
-        );
+    fn eval_reports_a_failed_read_as_runtime_error() {
+        let program = Parser::new(Lexer::new("{ print }")).parse_program();
+        let mut evaluator = Evaluator::new(program, vec![], "-")
+            .with_standard_input(std::io::BufReader::new(FailingReader));
 
-        let output = evaluator.eval();
+        evaluator.eval();
 
-        assert_eq!(output, Vec::<String>::new());
+        assert_eq!(evaluator.runtime_error(), Some("can't read -: broken"));
+    }
+
+    /// A reader whose every read fails.
+    struct FailingReader;
+
+    impl std::io::Read for FailingReader {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken"))
+        }
     }
 
     #[test]
-    fn eval_user_defined_function_call_can_exit() {
+    fn eval_runs_beginfile_and_endfile_around_each_file() {
+        let path = std::env::temp_dir().join(format!("rawk-beginfile-{}.txt", std::process::id()));
+        std::fs::write(&path, "header\n3\n4\n").unwrap();
         let lexer = Lexer::new(
-            r#"BEGIN { print "before"; myabort(1); print "after" } function myabort(n) { print "exit", n; exit n } END { print "end" }"#,
+            r#"BEGINFILE { print "begin", FILENAME == "-" ? "stdin" : "file", FNR; sum = 0 }
+               FNR == 1 { next }
+               { sum += $1 }
+               ENDFILE { print "end", FNR, sum }
+               END { print NR }"#,
         );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec!["A".to_string()], "-");
+        let mut evaluator =
+            Evaluator::new(program, vec!["header".to_string(), "10".to_string()], "-")
+                .with_arguments(vec![path.to_string_lossy().into_owned(), "-".to_string()]);
 
         let output = evaluator.eval();
+        std::fs::remove_file(&path).unwrap();
 
         assert_eq!(
             output,
             vec![
-                "before".to_string(),
-                "exit 1".to_string(),
-                "end".to_string()
+                "begin file 0".to_string(),
+                "end 3 7".to_string(),
+                "begin stdin 0".to_string(),
+                "end 2 10".to_string(),
+                "5".to_string(),
             ]
         );
     }
 
     #[test]
-    fn eval_array_add_assignment_and_access() {
-        let lexer = Lexer::new(r#"/Asia/ { pop["Asia"] += $3 } END { print pop["Asia"] }"#);
+    fn eval_nextfile_skips_rest_of_file_and_runs_endfile() {
+        let path = std::env::temp_dir().join(format!("rawk-nextfile-{}.txt", std::process::id()));
+        std::fs::write(&path, "a\nb\nc\n").unwrap();
+        let lexer = Lexer::new(
+            r#"FNR == 2 { nextfile; print "unreachable" } { print FILENAME == "-" ? "stdin" : "file", $0 } ENDFILE { print "end", FNR }"#,
+        );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(
-            program,
-            vec![
-                "USSR\t8649\t275\tAsia".to_string(),
-                "China\t3705\t1032\tAsia".to_string(),
-            ],
-            "-",
-        );
+        let mut evaluator = Evaluator::new(program, vec!["x".to_string(), "y".to_string()], "-")
+            .with_arguments(vec![path.to_string_lossy().into_owned(), "-".to_string()]);
 
         let output = evaluator.eval();
+        std::fs::remove_file(&path).unwrap();
 
-        assert_eq!(output, vec!["1307".to_string()]);
+        assert_eq!(
+            output,
+            vec![
+                "file a".to_string(),
+                "end 2".to_string(),
+                "stdin x".to_string(),
+                "end 2".to_string(),
+            ]
+        );
     }
 
     #[test]
-    fn eval_delete_removes_array_entry_from_for_in() {
-        let lexer =
-            Lexer::new(r#"BEGIN { x[1] = "a"; x[2] = "b"; delete x[1]; for (i in x) print i }"#);
+    fn eval_nextfile_in_beginfile_skips_file_without_endfile() {
+        let lexer = Lexer::new(
+            r#"BEGINFILE { nextfile } { print } ENDFILE { print "end" } END { print NR }"#,
+        );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec![], "-");
+        let mut evaluator = Evaluator::new(program, vec!["a".to_string()], "-");
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["2".to_string()]);
+        assert_eq!(output, vec!["0".to_string()]);
     }
 
     #[test]
-    fn eval_for_in_loop_over_array_keys() {
+    fn eval_csv_mode_splits_records_and_split_calls_by_rfc_4180() {
         let lexer = Lexer::new(
-            r#"BEGIN { area["Asia"] = 1; area["Europe"] = 2 } END { for (name in area) print name ":" area[name] }"#,
+            r#"{ n = split($0, all, ","); m = split("a;b", other, ";"); print NR, NF, n, m, $2 "|" all[3] }"#,
         );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec![], "-");
+        let mut evaluator = Evaluator::new(
+            program,
+            vec![
+                r#"1,"two, ""quoted""",three"#.to_string(),
+                r#"x,"multi"#.to_string(),
+                r#"line",z"#.to_string(),
+            ],
+            "-",
+        )
+        .with_csv(true);
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["Asia:1".to_string(), "Europe:2".to_string()]);
+        assert_eq!(
+            output,
+            vec![
+                r#"1 3 3 2 two, "quoted"|three"#.to_string(),
+                "2 3 3 2 multi".to_string(),
+                "line|z".to_string(),
+            ]
+        );
     }
 
     #[test]
-    fn eval_parenthesized_composite_membership_expression() {
-        let lexer =
-            Lexer::new(r#"{ x[$0, $1] = "hit"; if (($0, $1) in x) print "yes"; else print "no" }"#);
+    fn eval_tsv_output_mode_escapes_fields_in_print_and_rebuilt_records() {
+        let lexer = Lexer::new(r#"{ $2 = "tab\there"; print; print $1, "line\nbreak" }"#);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec!["17379\tmel".to_string()], "-");
+        let mut evaluator =
+            Evaluator::new(program, vec!["a b".to_string()], "-").with_output_mode(OutputMode::Tsv);
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["yes".to_string()]);
+        assert_eq!(
+            output,
+            vec!["a\ttab\\there".to_string(), "a\tline\\nbreak".to_string()]
+        );
     }
 
     #[test]
-    fn eval_print_respects_ors_between_records() {
-        let lexer = Lexer::new(r#"BEGIN { OFS = ":"; ORS = "\n\n" } { print $1, $2 }"#);
+    fn eval_fieldwidths_splits_columns_until_fs_is_assigned() {
+        let lexer = Lexer::new(
+            r#"BEGIN { FIELDWIDTHS = "4 1:3 *"; OFS = "," }
+               NR == 1 { print $1 "|" $2 "|" $3 "|" NF; $2 = "X"; print; print $3; FS = " " }
+               NR == 2 { print $2, NF }"#,
+        );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
         let mut evaluator = Evaluator::new(
             program,
             vec![
-                "USSR\t8649\t275\tAsia".to_string(),
-                "Canada\t3852\t25\tNorth America".to_string(),
+                "root 123 /usr/bin/sh -c".to_string(),
+                "user 456 /bin/ls".to_string(),
             ],
             "-",
         );
@@ -3418,159 +5268,144 @@ mod tests {
         assert_eq!(
             output,
             vec![
-                "USSR:8649".to_string(),
-                "".to_string(),
-                "Canada:3852".to_string(),
-                "".to_string()
+                "root|123| /usr/bin/sh -c|3".to_string(),
+                "root,X, /usr/bin/sh -c".to_string(),
+                " /usr/bin/sh -c".to_string(),
+                "456,3".to_string(),
             ]
         );
     }
 
     #[test]
-    fn eval_print_redirection_does_not_write_stdout() {
-        let lexer = Lexer::new(r#"{ print >"tempbig" }"#);
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec!["USSR\t8649\t275\tAsia".to_string()], "-");
-
-        let output = evaluator.eval();
-
-        assert!(output.is_empty());
-    }
-
-    #[test]
-    fn eval_print_pipe_to_sort_flushes_sorted_output() {
-        let lexer = Lexer::new(r#"BEGIN { print "b" | "sort"; print "a" | "sort" }"#);
+    fn eval_reports_invalid_fieldwidths_as_runtime_error() {
+        let lexer = Lexer::new(r#"BEGIN { FIELDWIDTHS = "3 * 2"; print "unreachable" }"#);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
         let mut evaluator = Evaluator::new(program, vec![], "-");
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["a".to_string(), "b".to_string()]);
+        assert!(output.is_empty());
+        assert_eq!(
+            evaluator.runtime_error(),
+            Some(r#"invalid FIELDWIDTHS value "3 * 2""#)
+        );
     }
 
     #[test]
-    fn eval_split_function_call_updates_array_and_returns_count() {
+    fn eval_fpat_collects_matches_as_fields() {
         let lexer = Lexer::new(
-            r#"function f(a) { return split($0, a) } { print f(x); print x[1]; print x[2] }"#,
+            r#"BEGIN { FPAT = "([^ ]+)|(\"[^\"]+\")" }
+               { print NF, $3; $2 = "-"; print $0; n = split("a b", words); print n, NF }"#,
         );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec!["hello world".to_string()], "-");
+        let mut evaluator = Evaluator::new(
+            program,
+            vec![r#"10.0.0.1 GET "Mozilla/5.0 (X11; Linux)" 200"#.to_string()],
+            "-",
+        );
 
         let output = evaluator.eval();
 
         assert_eq!(
             output,
-            vec!["2".to_string(), "hello".to_string(), "world".to_string()]
+            vec![
+                r#"4 "Mozilla/5.0 (X11; Linux)""#.to_string(),
+                r#"10.0.0.1 - "Mozilla/5.0 (X11; Linux)" 200"#.to_string(),
+                "2 4".to_string(),
+            ]
         );
     }
 
     #[test]
-    fn eval_getline_in_begin_consumes_input_records() {
-        let lexer = Lexer::new(r#"BEGIN { while (getline && NR < 3) print } { print }"#);
+    fn eval_reports_missing_input_file_as_runtime_error() {
+        let lexer = Lexer::new("{ print }");
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(
-            program,
-            vec!["A".to_string(), "B".to_string(), "C".to_string()],
-            "-",
-        );
+        let mut evaluator =
+            Evaluator::new(program, vec![], "-").with_arguments(vec!["no/such/file".to_string()]);
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["A".to_string(), "B".to_string()]);
-    }
-
-    #[test]
-    fn unescape_awk_string_handles_known_escapes() {
-        let input = r#"line1\nline2\t\"x\"\\done\r"#;
-
-        let output = unescape_awk_string(input);
-
-        assert_eq!(output, "line1\nline2\t\"x\"\\done\r");
-    }
-
-    #[test]
-    fn unescape_awk_string_preserves_unknown_escape_sequences() {
-        let input = r#"a\qb\zc"#;
-
-        let output = unescape_awk_string(input);
-
-        assert_eq!(output, r#"a\qb\zc"#);
-    }
-
-    #[test]
-    fn unescape_awk_string_keeps_trailing_backslash() {
-        let input = "abc\\";
-
-        let output = unescape_awk_string(input);
-
-        assert_eq!(output, "abc\\");
+        assert!(output.is_empty());
+        assert_eq!(
+            evaluator.runtime_error(),
+            Some("can't open file no/such/file")
+        );
     }
 
     #[test]
-    fn unescape_awk_string_mixes_plain_and_escaped_text() {
-        let input = r#"A\tB\nC\\D\"E\q"#;
+    fn eval_compares_numeric_looking_fields_as_numbers() {
+        let lexer = Lexer::new(r#"{ print ($1 < $2), ($2 < "9"), ($3 == 1) }"#);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        let mut evaluator = Evaluator::new(program, vec!["9 10 1.0".to_string()], "-");
 
-        let output = unescape_awk_string(input);
+        let output = evaluator.eval();
 
-        assert_eq!(output, "A\tB\nC\\D\"E\\q");
+        assert_eq!(output, vec!["1 1 1".to_string()]);
     }
 
     #[test]
-    fn eval_print_string_with_embedded_newline_splits_output_lines() {
-        let lexer = Lexer::new(r#"BEGIN { print "\nUSSR" }"#);
+    fn eval_compares_split_elements_and_environ_values_as_strnums() {
+        let lexer = Lexer::new(
+            r#"BEGIN { split("010 9", parts); print (parts[1] > parts[2]), (ENVIRON["N"] == 100) }"#,
+        );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec![], "-");
+        let mut evaluator = Evaluator::new(program, vec![], "-").with_environ([("N", "1e2")]);
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["".to_string(), "USSR".to_string()]);
+        assert_eq!(output, vec!["1 1".to_string()]);
     }
 
     #[test]
-    fn eval_print_respects_custom_ors_without_newlines() {
-        let lexer = Lexer::new(r###"BEGIN { ORS = "##" } { print $1; print $2 }"###);
+    fn eval_compares_string_constants_lexically() {
+        let lexer =
+            Lexer::new(r#"BEGIN { x = "10"; print (x < "9"), (x < 9), (y == 0), (y == "") }"#);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec!["alpha beta".to_string()], "-");
+        let mut evaluator = Evaluator::new(program, vec![], "-");
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["alpha##beta##".to_string()]);
+        assert_eq!(output, vec!["1 1 1 1".to_string()]);
     }
 
     #[test]
-    fn eval_match_function_sets_rstart_and_rlength() {
-        let lexer = Lexer::new(r#"BEGIN { print match("abc123", "[0-9]+"), RSTART, RLENGTH }"#);
+    fn eval_converts_numbers_with_convfmt_and_prints_with_ofmt() {
+        let lexer = Lexer::new(
+            r#"BEGIN { CONVFMT = "%.2f"; OFMT = "%.1f"; x = 1.23456; y = x ""; print x, y, 7 }"#,
+        );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
         let mut evaluator = Evaluator::new(program, vec![], "-");
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["4 4 3".to_string()]);
+        assert_eq!(output, vec!["1.2 1.23 7".to_string()]);
     }
 
     #[test]
-    fn eval_match_function_falls_back_to_legacy_regex_matching() {
-        let lexer = Lexer::new(r#"BEGIN { print match("daemon", "(foo|dae)") , RSTART, RLENGTH }"#);
+    fn eval_passes_arrays_to_functions_by_reference() {
+        let lexer = Lexer::new(
+            r#"function fill(a, n) { a["k"] = n } BEGIN { fill(arr, 3); fill(fresh, 4); print arr["k"], fresh["k"] }"#,
+        );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
         let mut evaluator = Evaluator::new(program, vec![], "-");
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["1 1 3".to_string()]);
+        assert_eq!(output, vec!["3 4".to_string()]);
     }
 
     #[test]
-    fn eval_dynamic_regex_string_uses_full_regex_semantics() {
+    fn eval_extra_parameters_are_fresh_local_arrays() {
         let lexer = Lexer::new(
-            r#"BEGIN { r = "[^aeiou]"; p = "17:"; if ("17abc" ~ r) print "class"; if ("17abc" ~ p) print "prefix"; if ("17:rest" ~ p) print "colon" }"#,
+            r#"function count(n,    seen, i) { for (i = 0; i < n; i++) seen[i] = 1; i = 0; for (k in seen) i++; return i } BEGIN { print count(2), count(3); for (k in seen) total++; print total + 0, i }"#,
         );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
@@ -3578,132 +5413,186 @@ mod tests {
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["class".to_string(), "colon".to_string()]);
+        assert_eq!(output, vec!["2 3".to_string(), "0 ".to_string()]);
     }
 
     #[test]
-    fn eval_comparison_distinguishes_uninitialized_vars_from_empty_fields() {
+    fn eval_recursion_keeps_locals_in_separate_frames() {
         let lexer = Lexer::new(
-            r#"BEGIN { FS = ":" } { if (b == 0) print "b"; if ($1 == 0) print "$1num"; if ($1 == "") print "$1str" }"#,
+            r#"function walk(depth, out,    mine) { mine[depth] = depth; if (depth < 3) walk(depth + 1, out); out[depth] = (depth in mine) } BEGIN { walk(1, seen); print seen[1], seen[2], seen[3], depth }"#,
         );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec![":x".to_string()], "-");
+        let mut evaluator = Evaluator::new(program, vec![], "-");
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["b".to_string(), "$1str".to_string()]);
+        assert_eq!(output, vec!["1 1 1 ".to_string()]);
     }
 
     #[test]
-    fn eval_field_with_string_literal_index_returns_empty() {
-        // eval_numeric_expression returns None for a bare string literal,
-        // so $"abc" yields "" rather than coercing to $0 as strict AWK would.
-        let lexer = Lexer::new(r#"{ print $"abc" }"#);
+    fn eval_reports_runaway_recursion_as_runtime_error() {
+        let lexer = Lexer::new("function f(n) { return f(n + 1) } BEGIN { print f(1) }");
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec!["hello world".to_string()], "-");
+        let mut evaluator = Evaluator::new(program, vec![], "-").with_max_call_depth(50);
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["".to_string()]);
+        assert!(output.is_empty());
+        assert_eq!(
+            evaluator.runtime_error(),
+            Some("function call nesting too deep in f (limit 50)")
+        );
     }
 
     #[test]
-    fn eval_field_in_begin_block_returns_empty() {
-        // No current line exists in a BEGIN block, so all field accesses return "".
-        let lexer = Lexer::new("BEGIN { print $1 }");
+    fn eval_allows_recursion_within_the_call_depth_limit() {
+        let lexer = Lexer::new(
+            "function depth(n) { if (n == 0) return 0; return depth(n - 1) + 1 } BEGIN { print depth(50) }",
+        );
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec![], "-");
+        let mut evaluator = Evaluator::new(program, vec![], "-").with_max_call_depth(51);
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["".to_string()]);
+        assert_eq!(output, vec!["50".to_string()]);
+        assert_eq!(evaluator.runtime_error(), None);
     }
 
     #[test]
-    fn eval_negative_field_index_sets_runtime_error_and_returns_no_output() {
-        let lexer = Lexer::new("{ print $(-1) }");
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec!["hello world".to_string()], "-");
-
-        let output = evaluator.eval();
+    fn eval_recurses_deeper_than_the_stack_of_the_thread() {
+        let run = |tree_walker: bool| {
+            let lexer = Lexer::new(
+                "function depth(n) { if (n == 0) return 0; return depth(n - 1) + 1 } BEGIN { print depth(20000) }",
+            );
+            let program = Parser::new(lexer).parse_program();
+            let mut evaluator = Evaluator::new(program, vec![], "-");
+            if tree_walker {
+                evaluator = evaluator.with_tree_walker();
+            }
+            evaluator.eval()
+        };
 
-        assert!(output.is_empty());
-        assert_eq!(evaluator.runtime_error(), Some("attempt to access field -1"));
+        for tree_walker in [false, true] {
+            let output = std::thread::Builder::new()
+                .stack_size(512 * 1024)
+                .spawn(move || run(tree_walker))
+                .unwrap()
+                .join()
+                .unwrap();
+            assert_eq!(output, vec!["20000".to_string()]);
+        }
     }
 
     #[test]
-    fn eval_sin_of_zero_returns_zero() {
-        let lexer = Lexer::new("BEGIN { print sin(0) }");
+    fn eval_stops_infinite_loop_at_step_limit() {
+        let lexer = Lexer::new("BEGIN { while (1) x++ }");
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec![], "-");
+        let mut evaluator = Evaluator::new(program, vec![], "-").with_step_limit(10_000);
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["0".to_string()]);
+        assert!(output.is_empty());
+        assert_eq!(evaluator.runtime_error(), Some("step limit exceeded"));
     }
 
     #[test]
-    fn eval_sin_of_pi_over_two_returns_one() {
-        let lexer = Lexer::new("BEGIN { print sin(3.14159265358979 / 2) }");
+    fn eval_stops_infinite_loop_at_deadline() {
+        let lexer = Lexer::new("BEGIN { for (;;) x++ }");
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec![], "-");
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(20);
+        let mut evaluator = Evaluator::new(program, vec![], "-").with_deadline(deadline);
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["1".to_string()]);
+        assert!(output.is_empty());
+        assert_eq!(evaluator.runtime_error(), Some("deadline exceeded"));
     }
 
     #[test]
-    fn eval_cos_of_zero_returns_one() {
-        let lexer = Lexer::new("BEGIN { print cos(0) }");
+    fn eval_stops_when_cancelled_from_another_thread() {
+        let lexer = Lexer::new(r#"BEGIN { print "started"; while (1) x++ } END { print "end" }"#);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec![], "-");
+        let token = crate::CancellationToken::new();
+        let mut evaluator = Evaluator::new(program, vec![], "-").with_cancellation(token.clone());
 
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            token.cancel();
+        });
         let output = evaluator.eval();
+        canceller.join().unwrap();
 
-        assert_eq!(output, vec!["1".to_string()]);
+        assert_eq!(output, vec!["started".to_string()]);
+        assert!(evaluator.was_cancelled());
+        assert_eq!(evaluator.runtime_error(), None);
     }
 
     #[test]
-    fn eval_cos_of_pi_returns_minus_one() {
-        let lexer = Lexer::new("BEGIN { print cos(3.14159265358979) }");
+    fn eval_skips_records_and_end_when_cancelled_before_start() {
+        let lexer = Lexer::new(r#"{ print } END { print "end" }"#);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec![], "-");
+        let token = crate::CancellationToken::new();
+        token.cancel();
+        let mut evaluator = Evaluator::new(program, vec!["a".to_string(), "b".to_string()], "-")
+            .with_cancellation(token);
 
         let output = evaluator.eval();
 
-        assert_eq!(output, vec!["-1".to_string()]);
+        assert!(output.is_empty());
+        assert!(evaluator.was_cancelled());
     }
 
-    #[test]
-    fn eval_srand_sets_seed_and_returns_it() {
-        let lexer = Lexer::new("BEGIN { print srand(42) }");
+    fn eval_with_char_mode(script: &str, char_mode: CharMode) -> Vec<String> {
+        let lexer = Lexer::new(script);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec![], "-");
-
-        let output = evaluator.eval();
+        let mut evaluator = Evaluator::new(program, vec![], "-").with_char_mode(char_mode);
 
-        assert_eq!(output, vec!["42".to_string()]);
+        evaluator.eval()
     }
 
     #[test]
-    fn eval_srand_affects_subsequent_rand_output() {
-        let lexer = Lexer::new("BEGIN { srand(1); r1 = rand(); srand(1); r2 = rand(); print (r1 == r2) }");
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse_program();
-        let mut evaluator = Evaluator::new(program, vec![], "-");
+    fn eval_string_builtins_count_code_points_in_utf8_mode() {
+        let script = r#"BEGIN {
+            s = "Göteborg 東京"
+            print length(s), index(s, "東"), substr(s, index(s, "東"))
+            print match(s, /ö.e/), RSTART, RLENGTH, toupper(s)
+            printf "%-5s|%.2s|%c|%c\n", "Åsa", "東京都", 26481, "Ö!"
+        }"#;
 
-        let output = evaluator.eval();
+        assert_eq!(
+            eval_with_char_mode(script, CharMode::Utf8),
+            vec![
+                "11 10 東京".to_string(),
+                "2 2 3 GÖTEBORG 東京".to_string(),
+                "Åsa  |東京|東|Ö".to_string(),
+            ]
+        );
+    }
 
-        assert_eq!(output, vec!["1".to_string()]);
+    #[test]
+    fn eval_string_builtins_count_bytes_in_byte_mode() {
+        let script = r#"BEGIN {
+            s = "Göteborg 東京"
+            print length(s), index(s, "東"), substr(s, index(s, "東"))
+            print match(s, /ö.e/), RSTART, RLENGTH, toupper(s)
+            printf "%-5s|%.3s|%c\n", "Åsa", "東京都", 321
+        }"#;
+
+        assert_eq!(
+            eval_with_char_mode(script, CharMode::Bytes),
+            vec![
+                "16 11 東京".to_string(),
+                "2 2 4 GöTEBORG 東京".to_string(),
+                "Åsa |東|A".to_string(),
+            ]
+        );
     }
 }
@@ -0,0 +1,1532 @@
+//! Lowering of a program to the bytecode run by the evaluator's virtual
+//! machine. Variables are resolved to global slots, parameter positions or
+//! special variables, and regular expression literals are compiled, once up
+//! front. Every statement and expression is lowered; the tree-walking
+//! evaluator only runs programs when asked to.
+
+use std::{cmp::Ordering, collections::HashMap};
+
+use regex::Regex;
+
+use super::{
+    awk_regex_matches_legacy, find_awk_regex_match_legacy_range,
+    resolve::{Special, Variable, resolve},
+    split_with_compiled_regex, split_with_regex, unescape_awk_string,
+};
+use crate::{
+    Program, Rule,
+    ast::{Expression, Statement},
+    token::TokenKind,
+};
+
+/// An array, by the slot of a global or the position of a parameter.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum ArrayRef {
+    Global(usize),
+    Local(usize),
+}
+
+/// Where a value is loaded from or stored to.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Place {
+    Global(usize),
+    Local(usize),
+    Special(Special),
+    /// The field whose index is on top of the stack.
+    Field,
+    /// The element of an array whose subscript is on top of the stack.
+    Element(ArrayRef),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+}
+
+impl Arithmetic {
+    /// The operation of a binary operator or of a compound assignment.
+    fn of(operator: &TokenKind) -> Option<Self> {
+        Some(match operator {
+            TokenKind::Plus | TokenKind::AddAssign => Self::Add,
+            TokenKind::Minus | TokenKind::SubtractAssign => Self::Subtract,
+            TokenKind::Asterisk | TokenKind::MultiplyAssign => Self::Multiply,
+            TokenKind::Division | TokenKind::DivideAssign => Self::Divide,
+            TokenKind::Percent | TokenKind::ModuloAssign => Self::Modulo,
+            TokenKind::Caret | TokenKind::PowerAssign => Self::Power,
+            _ => return None,
+        })
+    }
+
+    pub(super) fn apply(self, left: f64, right: f64) -> f64 {
+        match self {
+            Self::Add => left + right,
+            Self::Subtract => left - right,
+            Self::Multiply => left * right,
+            Self::Divide => left / right,
+            Self::Modulo => left % right,
+            Self::Power => left.powf(right),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn of(operator: &TokenKind) -> Option<Self> {
+        Some(match operator {
+            TokenKind::Equal => Self::Equal,
+            TokenKind::NotEqual => Self::NotEqual,
+            TokenKind::LessThan => Self::Less,
+            TokenKind::LessThanOrEqual => Self::LessOrEqual,
+            TokenKind::GreaterThan => Self::Greater,
+            TokenKind::GreaterThanOrEqual => Self::GreaterOrEqual,
+            _ => return None,
+        })
+    }
+
+    pub(super) fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Self::Equal => ordering == Ordering::Equal,
+            Self::NotEqual => ordering != Ordering::Equal,
+            Self::Less => ordering == Ordering::Less,
+            Self::LessOrEqual => ordering != Ordering::Greater,
+            Self::Greater => ordering == Ordering::Greater,
+            Self::GreaterOrEqual => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// What an update of a variable leaves on the stack.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Push {
+    Nothing,
+    /// The value before the update.
+    Old,
+    /// The value after the update.
+    New,
+}
+
+/// A regular expression literal. Patterns the `regex` crate rejects are
+/// matched by the legacy matcher instead.
+#[derive(Debug)]
+pub(super) struct Pattern {
+    text: String,
+    regex: Option<Regex>,
+}
+
+impl Pattern {
+    pub(super) fn new(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            regex: Regex::new(text).ok(),
+        }
+    }
+
+    pub(super) fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub(super) fn regex(&self) -> Option<&Regex> {
+        self.regex.as_ref()
+    }
+
+    pub(super) fn is_match(&self, text: &str) -> bool {
+        match &self.regex {
+            Some(regex) => regex.is_match(text),
+            None => awk_regex_matches_legacy(text, &self.text),
+        }
+    }
+
+    /// The leftmost match in `text`, as a byte range.
+    pub(super) fn find(&self, text: &str) -> Option<std::ops::Range<usize>> {
+        match &self.regex {
+            Some(regex) => regex.find(text).map(|matched| matched.range()),
+            None => find_awk_regex_match_legacy_range(text, &self.text),
+        }
+    }
+
+    /// Split `source` at the matches.
+    pub(super) fn split(&self, source: &str) -> Vec<String> {
+        match &self.regex {
+            Some(regex) => split_with_compiled_regex(source, regex),
+            None => split_with_regex(source, &self.text),
+        }
+    }
+}
+
+/// The regular expression argument of `split`, `patsplit`, `match`, `sub` or
+/// `gsub`.
+#[derive(Debug)]
+pub(super) enum RegexArgument {
+    /// Left out, so the builtin's default applies.
+    Default,
+    Literal(Box<Pattern>),
+    /// Computed, with its text on top of the stack.
+    Dynamic,
+}
+
+/// Where `print` or `printf` with a redirection writes.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Redirect {
+    /// A file, truncated by `>` or appended to by `>>` when first opened.
+    File { append: bool },
+    /// The standard input of a command.
+    Pipe,
+}
+
+/// What `sub` or `gsub` replaces in.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum SubstitutionTarget {
+    Record,
+    Place(Place),
+    /// A value that is not assigned back, on top of the stack.
+    Value,
+}
+
+/// A builtin function of fixed arity that only computes its result from its
+/// arguments, plus `close`.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Builtin {
+    Index,
+    Sqrt,
+    Log,
+    Exp,
+    Sin,
+    Cos,
+    Atan2,
+    Int,
+    ToLower,
+    ToUpper,
+    CsvQuote,
+    Close,
+}
+
+impl Builtin {
+    fn of(name: &str) -> Option<Self> {
+        Some(match name {
+            "index" => Self::Index,
+            "sqrt" => Self::Sqrt,
+            "log" => Self::Log,
+            "exp" => Self::Exp,
+            "sin" => Self::Sin,
+            "cos" => Self::Cos,
+            "atan2" => Self::Atan2,
+            "int" => Self::Int,
+            "tolower" => Self::ToLower,
+            "toupper" => Self::ToUpper,
+            "csvquote" => Self::CsvQuote,
+            "close" => Self::Close,
+            _ => return None,
+        })
+    }
+
+    /// The number of arguments evaluated. Missing ones are uninitialized and
+    /// extra ones are not evaluated at all.
+    fn arity(self) -> usize {
+        match self {
+            Self::Index | Self::Atan2 => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// An instruction of the stack machine. Operands are popped in the order they
+/// were pushed, so the last operand is on top of the stack.
+#[derive(Debug)]
+pub(super) enum Instr {
+    /// Start a statement: count a step and stop once the program has exited,
+    /// moved on to the next record or failed. `index` numbers the statement
+    /// as [`visit_statements`](crate::ast::visit_statements) orders the
+    /// statements of the program, for the debugger and the profiler; the
+    /// initialization and update of a `for` loop have none. `depth` is the
+    /// number of blocks the statement is nested in, within its action or
+    /// function.
+    Statement {
+        index: Option<usize>,
+        depth: usize,
+    },
+    /// Start a nested block or loop iteration: count a step and check for
+    /// cancellation.
+    Block,
+    Number(f64),
+    String(String),
+    Uninitialized,
+    Load(Place),
+    /// Load a field whose number is a constant, as in `$1`.
+    LoadField(i64),
+    /// Pop the value and store it.
+    Store(Place),
+    /// Pop the right operand, apply the operation to the current value and
+    /// store the result.
+    Compound {
+        place: Place,
+        arithmetic: Arithmetic,
+        push: Push,
+    },
+    Increment {
+        place: Place,
+        delta: f64,
+        push: Push,
+    },
+    Arithmetic(Arithmetic),
+    Compare(Comparison),
+    Concat,
+    /// Pop a string and add it to the end of a global or parameter, which
+    /// `s = s x` compiles to so the string is not copied.
+    Append(Place),
+    Not,
+    /// Match the string on the stack against a regular expression literal.
+    MatchPattern {
+        pattern: Box<Pattern>,
+        negate: bool,
+    },
+    /// Match the current record against a regular expression literal.
+    MatchRecord(Box<Pattern>),
+    /// Pop a dynamic regular expression and match the string beneath it.
+    Match {
+        negate: bool,
+    },
+    /// Join this many values with `SUBSEP` into one subscript.
+    Subscript(usize),
+    In(ArrayRef),
+    Delete(ArrayRef),
+    DeleteElement(ArrayRef),
+    Length,
+    RecordLength,
+    Substr {
+        length: bool,
+    },
+    Rand,
+    Jump(usize),
+    JumpIfFalse(usize),
+    JumpIfTrue(usize),
+    /// Pop two operands and jump unless the comparison holds.
+    JumpUnless(Comparison, usize),
+    Pop,
+    Dup,
+    Swap,
+    /// Print this many values, or the record when there are none.
+    Print(usize),
+    /// Format this many values, the first being the format.
+    Printf(usize),
+    /// Pop the target, then print this many values, or the record when there
+    /// are none, to it.
+    PrintTo(usize, Redirect),
+    /// Pop the target, then format this many values to it.
+    PrintfTo(usize, Redirect),
+    /// Format this many values, the first being the format, into a string.
+    Sprintf(usize),
+    /// Pop a command, run it and push its exit status.
+    System,
+    /// Pop the arguments of the builtin and push its result.
+    Builtin(Builtin),
+    /// Seed the random number generator with the value on the stack, or with
+    /// the time without one, and push the previous seed.
+    Srand {
+        seed: bool,
+    },
+    /// Push the number of elements of the array, or the length of the
+    /// variable when the array is empty: `length(name)`.
+    LengthOf {
+        place: Place,
+        array: ArrayRef,
+    },
+    /// Pop the separator if dynamic and the string, split the string into
+    /// the array and push the number of elements.
+    Split {
+        array: ArrayRef,
+        separator: RegexArgument,
+    },
+    /// Pop the pattern if dynamic and the string, store the matches in the
+    /// array and the text around them in `separators`, and push the number of
+    /// matches.
+    Patsplit {
+        array: ArrayRef,
+        separators: Option<ArrayRef>,
+        pattern: RegexArgument,
+    },
+    /// `match`: pop the pattern if dynamic and the string, set `RSTART` and
+    /// `RLENGTH`, and push `RSTART`.
+    FindMatch(RegexArgument),
+    /// `sub` or `gsub`: pop the operand of the target, the replacement and the
+    /// pattern if dynamic, replace in the target and push the number of
+    /// replacements.
+    Substitute {
+        global: bool,
+        pattern: RegexArgument,
+        target: SubstitutionTarget,
+    },
+    /// Start a `for (key in array)` loop over the sorted subscripts.
+    Keys(ArrayRef),
+    /// Push the next subscript of the innermost `for`-`in` loop, or jump
+    /// once there are none left.
+    NextKey(usize),
+    /// End the innermost `for`-`in` loop.
+    PopKeys,
+    /// Push a variable passed to a function, along with the array it names
+    /// in case the function uses it as one.
+    Argument {
+        place: Place,
+        array: ArrayRef,
+    },
+    /// Call a compiled function with one argument per flag, each flag telling
+    /// whether the argument came from [`Instr::Argument`].
+    Call {
+        function: usize,
+        references: Box<[bool]>,
+    },
+    Return,
+    Next,
+    NextFile,
+    Exit,
+    /// Stop running the code, for `break` or `continue` outside a loop or
+    /// `return` outside a function, which only a program built without the
+    /// parser has.
+    Leave,
+}
+
+pub(super) struct Function<'a> {
+    pub(super) name: &'a str,
+    pub(super) parameters: Vec<&'a str>,
+    pub(super) code: Vec<Instr>,
+}
+
+/// The code of a rule.
+#[derive(Default)]
+pub(super) struct RuleCode {
+    /// The code testing the pattern, if the rule has one, or the start of a
+    /// range.
+    pub(super) pattern: Option<Vec<Instr>>,
+    /// The code testing the end of a range.
+    pub(super) range_end: Option<Vec<Instr>>,
+    /// The code of the action, if the rule has one.
+    pub(super) action: Option<Vec<Instr>>,
+}
+
+/// A program compiled to bytecode.
+pub(super) struct Compiled<'a> {
+    /// The code of each rule, numbered as by
+    /// [`RuleNumbers`](super::RuleNumbers).
+    pub(super) rules: Vec<RuleCode>,
+    pub(super) functions: Vec<Function<'a>>,
+    /// The index in `functions` of each function.
+    pub(super) function_indices: HashMap<&'a str, usize>,
+}
+
+/// Compile `program`, finding the slot of each global variable with
+/// `global_slot`.
+pub(super) fn compile<'a>(
+    program: &Program<'a>,
+    global_slot: &mut dyn FnMut(&str) -> usize,
+) -> Compiled<'a> {
+    let function_indices: HashMap<&'a str, usize> = program
+        .function_definitions_iter()
+        .enumerate()
+        .map(|(index, definition)| (definition.name, index))
+        .collect();
+
+    let mut next_statement = 0;
+    let mut rules = Vec::new();
+    let actions = program
+        .begin_blocks_iter()
+        .chain(program.begin_file_blocks_iter())
+        .map(|action| (None, Some(action)));
+    let other_rules = program.rules_iter().map(|rule| match rule {
+        Rule::Action(action) => (None, Some(action)),
+        Rule::PatternAction { pattern, action } => (pattern.as_ref(), action.as_ref()),
+        _ => (None, None),
+    });
+    let end_actions = program
+        .end_file_blocks_iter()
+        .chain(program.end_blocks_iter())
+        .map(|action| (None, Some(action)));
+    for (pattern, action) in actions.chain(other_rules).chain(end_actions) {
+        let mut code = RuleCode::default();
+        let mut compile_pattern = |pattern| {
+            let mut compiler =
+                Compiler::new(global_slot, &function_indices, None, &mut next_statement);
+            compiler.expression(pattern);
+            compiler.code
+        };
+        match pattern {
+            Some(Expression::Infix {
+                left,
+                operator,
+                right,
+            }) if operator.kind == TokenKind::Comma => {
+                code.pattern = Some(compile_pattern(left));
+                code.range_end = Some(compile_pattern(right));
+            }
+            Some(pattern) => code.pattern = Some(compile_pattern(pattern)),
+            None => {}
+        }
+        if let Some(action) = action {
+            let mut compiler =
+                Compiler::new(global_slot, &function_indices, None, &mut next_statement);
+            compiler.statements(&action.statements);
+            code.action = Some(compiler.code);
+        }
+        rules.push(code);
+    }
+
+    let mut functions = Vec::new();
+    for definition in program.function_definitions_iter() {
+        let mut compiler = Compiler::new(
+            global_slot,
+            &function_indices,
+            Some(definition.parameters.as_slice()),
+            &mut next_statement,
+        );
+        compiler.statements(&definition.statements);
+        functions.push(Function {
+            name: definition.name,
+            parameters: definition.parameters.clone(),
+            code: compiler.code,
+        });
+    }
+
+    Compiled {
+        rules,
+        functions,
+        function_indices,
+    }
+}
+
+/// The jumps out of a loop being compiled, to be patched once its end is
+/// known.
+#[derive(Default)]
+struct Loop {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+struct Compiler<'c, 'a> {
+    global_slot: &'c mut dyn FnMut(&str) -> usize,
+    functions: &'c HashMap<&'a str, usize>,
+    /// The parameters of the function being compiled, if any.
+    parameters: Option<&'c [&'a str]>,
+    /// The index of the next statement. Actions and functions are compiled
+    /// in the order of [`Program::statement_lists`].
+    next_statement: &'c mut usize,
+    /// The number of blocks the code being compiled is nested in.
+    depth: usize,
+    loops: Vec<Loop>,
+    code: Vec<Instr>,
+    /// The furthest instruction a jump has been pointed at so far.
+    jump_target: usize,
+}
+
+impl<'c, 'a> Compiler<'c, 'a> {
+    fn new(
+        global_slot: &'c mut dyn FnMut(&str) -> usize,
+        functions: &'c HashMap<&'a str, usize>,
+        parameters: Option<&'c [&'a str]>,
+        next_statement: &'c mut usize,
+    ) -> Self {
+        Self {
+            global_slot,
+            functions,
+            parameters,
+            next_statement,
+            depth: 0,
+            loops: Vec::new(),
+            code: Vec::new(),
+            jump_target: 0,
+        }
+    }
+
+    fn emit(&mut self, instr: Instr) -> usize {
+        // A comparison tested by a jump, where no other jump lands between
+        // the two, is fused with it.
+        if let Instr::JumpIfFalse(target) = instr
+            && self.jump_target < self.code.len()
+            && let Some(&Instr::Compare(comparison)) = self.code.last()
+        {
+            *self.code.last_mut().unwrap() = Instr::JumpUnless(comparison, target);
+            return self.code.len() - 1;
+        }
+        self.code.push(instr);
+        self.code.len() - 1
+    }
+
+    /// Point the jump at `from` to `to`.
+    fn patch(&mut self, from: usize, to: usize) {
+        match &mut self.code[from] {
+            Instr::Jump(target)
+            | Instr::JumpIfFalse(target)
+            | Instr::JumpIfTrue(target)
+            | Instr::JumpUnless(_, target)
+            | Instr::NextKey(target) => *target = to,
+            _ => unreachable!("only jumps are patched"),
+        }
+        self.jump_target = self.jump_target.max(to);
+    }
+
+    /// Point the jump at `from` to the next instruction.
+    fn patch_here(&mut self, from: usize) {
+        self.patch(from, self.code.len());
+    }
+
+    fn parameter(&self, name: &str) -> Option<usize> {
+        self.parameters?
+            .iter()
+            .position(|parameter| *parameter == name)
+    }
+
+    fn place(&mut self, name: &str) -> Place {
+        match resolve(name, self.parameters, self.global_slot) {
+            Variable::Local(index) => Place::Local(index),
+            Variable::Global(slot) => Place::Global(slot),
+            Variable::Special(special) => Place::Special(special),
+        }
+    }
+
+    fn array(&mut self, name: &str) -> ArrayRef {
+        match self.parameter(name) {
+            Some(index) => ArrayRef::Local(index),
+            None => ArrayRef::Global((self.global_slot)(name)),
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement<'a>]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn block(&mut self, statements: &[Statement<'a>]) {
+        self.emit(Instr::Block);
+        self.depth += 1;
+        self.statements(statements);
+        self.depth -= 1;
+    }
+
+    /// Compile the body of a loop, then point its `break` statements past the
+    /// end of the loop and its `continue` statements to `next`, or to the
+    /// instruction following the body when `next` is `None`.
+    fn loop_body(&mut self, statements: &[Statement<'a>], next: Option<usize>) -> Loop {
+        self.loops.push(Loop::default());
+        self.block(statements);
+        let jumps = self.loops.pop().unwrap_or_default();
+        let next = next.unwrap_or(self.code.len());
+        for from in &jumps.continues {
+            self.patch(*from, next);
+        }
+        jumps
+    }
+
+    fn patch_breaks(&mut self, jumps: Loop) {
+        for from in jumps.breaks {
+            self.patch_here(from);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement<'a>) {
+        if *statement == Statement::Empty {
+            return;
+        }
+        let index = *self.next_statement;
+        *self.next_statement += 1;
+        self.emit(Instr::Statement {
+            index: Some(index),
+            depth: self.depth,
+        });
+        self.statement_kind(statement)
+    }
+
+    /// Compile the initialization or update of a `for` loop, which runs
+    /// alongside the statements of its body.
+    fn loop_clause(&mut self, statement: &Statement<'a>) {
+        if *statement == Statement::Empty {
+            return;
+        }
+        self.emit(Instr::Statement {
+            index: None,
+            depth: self.depth + 1,
+        });
+        self.statement_kind(statement)
+    }
+
+    fn statement_kind(&mut self, statement: &Statement<'a>) {
+        match statement {
+            Statement::Empty => {}
+            Statement::Expression(expression) => self.effect(expression),
+            Statement::Print(expressions) => {
+                for expression in expressions {
+                    self.expression(expression);
+                }
+                self.emit(Instr::Print(expressions.len()));
+            }
+            Statement::PrintRedirect {
+                expressions,
+                target,
+                append,
+            } => {
+                let redirect = Redirect::File { append: *append };
+                self.print_to(expressions, target, Instr::PrintTo, redirect);
+            }
+            Statement::PrintPipe {
+                expressions,
+                target,
+            } => self.print_to(expressions, target, Instr::PrintTo, Redirect::Pipe),
+            Statement::Printf(expressions) => {
+                let count = self.format_arguments(expressions);
+                self.emit(Instr::Printf(count));
+            }
+            Statement::PrintfRedirect {
+                expressions,
+                target,
+                append,
+            } => {
+                let redirect = Redirect::File { append: *append };
+                self.print_to(expressions, target, Instr::PrintfTo, redirect);
+            }
+            Statement::PrintfPipe {
+                expressions,
+                target,
+            } => self.print_to(expressions, target, Instr::PrintfTo, Redirect::Pipe),
+            Statement::System(command) => {
+                self.expression(command);
+                self.emit(Instr::System);
+                self.emit(Instr::Pop);
+            }
+            Statement::Split {
+                string,
+                array,
+                separator,
+            } => {
+                self.split(string, array, separator.as_ref());
+                self.emit(Instr::Pop);
+            }
+            Statement::SplitAssignment {
+                identifier,
+                string,
+                array,
+                separator,
+            } => {
+                self.split(string, array, separator.as_ref());
+                let place = self.place(identifier);
+                self.emit(Instr::Store(place));
+            }
+            Statement::Sub {
+                pattern,
+                replacement,
+                target,
+            }
+            | Statement::Gsub {
+                pattern,
+                replacement,
+                target,
+            } => {
+                let global = matches!(statement, Statement::Gsub { .. });
+                self.substitution(pattern, replacement, target.as_ref(), global);
+                self.emit(Instr::Pop);
+            }
+            Statement::Assignment { identifier, value } => {
+                let place = self.place(identifier);
+                match appended_operands(identifier, value) {
+                    Some(operands) if matches!(place, Place::Global(_) | Place::Local(_)) => {
+                        for operand in operands {
+                            self.expression(operand);
+                            self.emit(Instr::Append(place));
+                        }
+                    }
+                    _ => {
+                        self.expression(value);
+                        self.emit(Instr::Store(place));
+                    }
+                }
+            }
+            Statement::ArrayAssignment {
+                identifier,
+                index,
+                value,
+            } => {
+                self.subscript(index);
+                self.expression(value);
+                self.emit(Instr::Swap);
+                let array = self.array(identifier);
+                self.emit(Instr::Store(Place::Element(array)));
+            }
+            Statement::FieldAssignment { field, value } => {
+                self.expression(value);
+                self.expression(field);
+                self.emit(Instr::Store(Place::Field));
+            }
+            Statement::AddAssignment { identifier, value } => {
+                self.expression(value);
+                let place = self.place(identifier);
+                self.emit(Instr::Compound {
+                    place,
+                    arithmetic: Arithmetic::Add,
+                    push: Push::Nothing,
+                });
+            }
+            Statement::ArrayAddAssignment {
+                identifier,
+                index,
+                value,
+            } => {
+                self.subscript(index);
+                self.expression(value);
+                self.emit(Instr::Swap);
+                let array = self.array(identifier);
+                self.emit(Instr::Compound {
+                    place: Place::Element(array),
+                    arithmetic: Arithmetic::Add,
+                    push: Push::Nothing,
+                });
+            }
+            Statement::ArrayPostIncrement { identifier, index }
+            | Statement::ArrayPostDecrement { identifier, index } => {
+                let delta = if matches!(statement, Statement::ArrayPostIncrement { .. }) {
+                    1.0
+                } else {
+                    -1.0
+                };
+                self.subscript(index);
+                let array = self.array(identifier);
+                self.emit(Instr::Increment {
+                    place: Place::Element(array),
+                    delta,
+                    push: Push::Nothing,
+                });
+            }
+            Statement::Delete { identifier, index } => {
+                let array = self.array(identifier);
+                match index {
+                    Some(index) => {
+                        self.subscript(index);
+                        self.emit(Instr::DeleteElement(array));
+                    }
+                    None => {
+                        self.emit(Instr::Delete(array));
+                    }
+                }
+            }
+            Statement::PreIncrement { identifier } | Statement::PostIncrement { identifier } => {
+                let place = self.place(identifier);
+                self.emit(Instr::Increment {
+                    place,
+                    delta: 1.0,
+                    push: Push::Nothing,
+                });
+            }
+            Statement::PreDecrement { identifier } | Statement::PostDecrement { identifier } => {
+                let place = self.place(identifier);
+                self.emit(Instr::Increment {
+                    place,
+                    delta: -1.0,
+                    push: Push::Nothing,
+                });
+            }
+            Statement::If {
+                condition,
+                then_statements,
+            } => {
+                self.expression(condition);
+                let skip = self.emit(Instr::JumpIfFalse(0));
+                self.block(then_statements);
+                self.patch_here(skip);
+            }
+            Statement::IfElse {
+                condition,
+                then_statements,
+                else_statements,
+            } => {
+                self.expression(condition);
+                let to_else = self.emit(Instr::JumpIfFalse(0));
+                self.block(then_statements);
+                let to_end = self.emit(Instr::Jump(0));
+                self.patch_here(to_else);
+                self.block(else_statements);
+                self.patch_here(to_end);
+            }
+            Statement::While {
+                condition,
+                statements,
+            } => {
+                let top = self.code.len();
+                self.expression(condition);
+                let exit = self.emit(Instr::JumpIfFalse(0));
+                let jumps = self.loop_body(statements, Some(top));
+                self.emit(Instr::Jump(top));
+                self.patch_here(exit);
+                self.patch_breaks(jumps);
+            }
+            Statement::DoWhile {
+                condition,
+                statements,
+            } => {
+                let top = self.code.len();
+                let jumps = self.loop_body(statements, None);
+                self.expression(condition);
+                self.emit(Instr::JumpIfTrue(top));
+                self.patch_breaks(jumps);
+            }
+            Statement::For {
+                init,
+                condition,
+                update,
+                statements,
+            } => {
+                self.loop_clause(init);
+                let top = self.code.len();
+                self.expression(condition);
+                let exit = self.emit(Instr::JumpIfFalse(0));
+                let jumps = self.loop_body(statements, None);
+                self.loop_clause(update);
+                self.emit(Instr::Jump(top));
+                self.patch_here(exit);
+                self.patch_breaks(jumps);
+            }
+            Statement::ForIn {
+                variable,
+                array,
+                statements,
+            } => {
+                let array = self.array(array);
+                self.emit(Instr::Keys(array));
+                let top = self.emit(Instr::NextKey(0));
+                let place = self.place(variable);
+                self.emit(Instr::Store(place));
+                let jumps = self.loop_body(statements, Some(top));
+                self.emit(Instr::Jump(top));
+                self.patch_here(top);
+                self.patch_breaks(jumps);
+                self.emit(Instr::PopKeys);
+            }
+            Statement::Break | Statement::Continue => {
+                let jump = self.code.len();
+                let Some(jumps) = self.loops.last_mut() else {
+                    self.emit(Instr::Leave);
+                    return;
+                };
+                if *statement == Statement::Break {
+                    jumps.breaks.push(jump);
+                } else {
+                    jumps.continues.push(jump);
+                }
+                self.emit(Instr::Jump(0));
+            }
+            Statement::Return(_) if self.parameters.is_none() => {
+                self.emit(Instr::Leave);
+            }
+            Statement::Return(value) => {
+                match value {
+                    Some(value) => self.expression(value),
+                    None => {
+                        self.emit(Instr::Uninitialized);
+                    }
+                }
+                self.emit(Instr::Return);
+            }
+            Statement::Next => {
+                self.emit(Instr::Next);
+            }
+            Statement::NextFile => {
+                self.emit(Instr::NextFile);
+            }
+            Statement::Exit(status) => {
+                if let Some(status) = status {
+                    self.expression(status);
+                    self.emit(Instr::Pop);
+                }
+                self.emit(Instr::Exit);
+            }
+        }
+    }
+
+    /// Compile `print` or `printf` to `target`, made by `instr` from the
+    /// number of values.
+    fn print_to(
+        &mut self,
+        expressions: &[Expression<'a>],
+        target: &Expression<'a>,
+        instr: fn(usize, Redirect) -> Instr,
+        redirect: Redirect,
+    ) {
+        let count = match instr(0, redirect) {
+            Instr::PrintTo(..) => {
+                for expression in expressions {
+                    self.expression(expression);
+                }
+                expressions.len()
+            }
+            _ => self.format_arguments(expressions),
+        };
+        self.expression(target);
+        self.emit(instr(count, redirect));
+    }
+
+    /// Compile the format and values of `printf` or `sprintf`, returning how
+    /// many there are. A missing format formats nothing.
+    fn format_arguments(&mut self, expressions: &[Expression<'a>]) -> usize {
+        if expressions.is_empty() {
+            self.emit(Instr::String(String::new()));
+            return 1;
+        }
+        for expression in expressions {
+            self.expression(expression);
+        }
+        expressions.len()
+    }
+
+    /// Compile the regular expression argument `pattern` of a builtin.
+    fn regex_argument(&mut self, pattern: Option<&Expression<'a>>) -> RegexArgument {
+        match pattern {
+            None => RegexArgument::Default,
+            Some(Expression::Regex(pattern)) => {
+                RegexArgument::Literal(Box::new(Pattern::new(pattern)))
+            }
+            Some(pattern) => {
+                self.expression(pattern);
+                RegexArgument::Dynamic
+            }
+        }
+    }
+
+    /// Compile `split(string, array, separator)`, which leaves the number of
+    /// elements on the stack.
+    fn split(&mut self, string: &Expression<'a>, array: &str, separator: Option<&Expression<'a>>) {
+        self.expression(string);
+        let separator = self.regex_argument(separator);
+        let array = self.array(array);
+        self.emit(Instr::Split { array, separator });
+    }
+
+    /// Compile `sub` or `gsub`, which leaves the number of replacements on
+    /// the stack.
+    fn substitution(
+        &mut self,
+        pattern: &Expression<'a>,
+        replacement: &Expression<'a>,
+        target: Option<&Expression<'a>>,
+        global: bool,
+    ) {
+        let pattern = self.regex_argument(Some(pattern));
+        self.expression(replacement);
+        let target = match target {
+            None => SubstitutionTarget::Record,
+            Some(Expression::Identifier(identifier)) => {
+                SubstitutionTarget::Place(self.place(identifier))
+            }
+            Some(Expression::Field(index)) => {
+                self.expression(index);
+                SubstitutionTarget::Place(Place::Field)
+            }
+            Some(Expression::ArrayAccess { identifier, index }) => {
+                self.subscript(index);
+                SubstitutionTarget::Place(Place::Element(self.array(identifier)))
+            }
+            Some(target) => {
+                self.expression(target);
+                SubstitutionTarget::Value
+            }
+        };
+        self.emit(Instr::Substitute {
+            global,
+            pattern,
+            target,
+        });
+    }
+
+    /// Compile a call of a builtin function, or of a function that is not
+    /// defined, which is 0 without its arguments being evaluated.
+    fn builtin_call(&mut self, name: &str, args: &[Expression<'a>]) {
+        if let Some(builtin) = Builtin::of(name) {
+            for index in 0..builtin.arity() {
+                match args.get(index) {
+                    Some(arg) => self.expression(arg),
+                    None => {
+                        self.emit(Instr::Uninitialized);
+                    }
+                }
+            }
+            self.emit(Instr::Builtin(builtin));
+            return;
+        }
+        match (name, args) {
+            ("sprintf", _) => {
+                let count = self.format_arguments(args);
+                self.emit(Instr::Sprintf(count));
+            }
+            ("split", [string, Expression::Identifier(array), rest @ ..]) => {
+                self.split(string, array, rest.first());
+            }
+            ("patsplit", [string, Expression::Identifier(array), rest @ ..]) => {
+                self.expression(string);
+                let pattern = self.regex_argument(rest.first());
+                let array = self.array(array);
+                let separators = match rest.get(1) {
+                    Some(Expression::Identifier(separators)) => Some(self.array(separators)),
+                    _ => None,
+                };
+                self.emit(Instr::Patsplit {
+                    array,
+                    separators,
+                    pattern,
+                });
+            }
+            ("match", _) => {
+                match args.first() {
+                    Some(string) => self.expression(string),
+                    None => {
+                        self.emit(Instr::Uninitialized);
+                    }
+                }
+                let pattern = match args.get(1) {
+                    Some(pattern) => self.regex_argument(Some(pattern)),
+                    // Nothing matches a missing pattern.
+                    None => RegexArgument::Default,
+                };
+                self.emit(Instr::FindMatch(pattern));
+            }
+            ("srand", _) => {
+                if let Some(seed) = args.first() {
+                    self.expression(seed);
+                }
+                self.emit(Instr::Srand {
+                    seed: !args.is_empty(),
+                });
+            }
+            ("sub" | "gsub", [pattern, replacement, rest @ ..]) => {
+                self.substitution(pattern, replacement, rest.first(), name == "gsub");
+            }
+            ("system", [command, ..]) => {
+                self.expression(command);
+                self.emit(Instr::System);
+            }
+            _ => {
+                self.emit(Instr::Number(0.0));
+            }
+        }
+    }
+
+    /// Compile an expression evaluated for its side effects only.
+    fn effect(&mut self, expression: &Expression<'a>) {
+        match expression {
+            Expression::Infix {
+                left,
+                operator,
+                right,
+            } if is_assignment(&operator.kind) => {
+                self.assignment(left, &operator.kind, right, false);
+            }
+            Expression::PreIncrement(target) | Expression::PostIncrement(target) => {
+                self.increment(target, 1.0, Push::Nothing);
+            }
+            Expression::PreDecrement(target) | Expression::PostDecrement(target) => {
+                self.increment(target, -1.0, Push::Nothing);
+            }
+            _ => {
+                self.expression(expression);
+                self.emit(Instr::Pop);
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression<'a>) {
+        match expression {
+            Expression::String(value) => {
+                self.emit(Instr::String(unescape_awk_string(value)));
+            }
+            Expression::Number(value) | Expression::HexNumber { value, .. } => {
+                self.emit(Instr::Number(*value));
+            }
+            Expression::Regex(pattern) => {
+                self.emit(Instr::MatchRecord(Box::new(Pattern::new(pattern))));
+            }
+            Expression::Field(index) => match index.as_ref() {
+                Expression::Number(index) => {
+                    self.emit(Instr::LoadField(*index as i64));
+                }
+                index => {
+                    self.expression(index);
+                    self.emit(Instr::Load(Place::Field));
+                }
+            },
+            Expression::Identifier(identifier) => {
+                let place = self.place(identifier);
+                self.emit(Instr::Load(place));
+            }
+            Expression::ArrayAccess { identifier, index } => {
+                self.subscript(index);
+                let array = self.array(identifier);
+                self.emit(Instr::Load(Place::Element(array)));
+            }
+            Expression::Length(None) => {
+                self.emit(Instr::RecordLength);
+            }
+            // `length(name)` counts the elements of an array.
+            Expression::Length(Some(argument)) => match argument.as_ref() {
+                Expression::Identifier(identifier) => {
+                    let place = self.place(identifier);
+                    let array = self.array(identifier);
+                    self.emit(Instr::LengthOf { place, array });
+                }
+                argument => {
+                    self.expression(argument);
+                    self.emit(Instr::Length);
+                }
+            },
+            Expression::Substr {
+                string,
+                start,
+                length,
+            } => {
+                self.expression(string);
+                self.expression(start);
+                if let Some(length) = length {
+                    self.expression(length);
+                }
+                self.emit(Instr::Substr {
+                    length: length.is_some(),
+                });
+            }
+            Expression::Rand => {
+                self.emit(Instr::Rand);
+            }
+            Expression::FunctionCall { name, args } if self.functions.contains_key(name) => {
+                let function = self.functions[name];
+                let references = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Expression::Identifier(identifier) => {
+                            let place = self.place(identifier);
+                            let array = self.array(identifier);
+                            self.emit(Instr::Argument { place, array });
+                            true
+                        }
+                        _ => {
+                            self.expression(arg);
+                            false
+                        }
+                    })
+                    .collect();
+                self.emit(Instr::Call {
+                    function,
+                    references,
+                });
+            }
+            Expression::FunctionCall { name, args } => self.builtin_call(name, args),
+            Expression::Not(operand) => {
+                self.expression(operand);
+                self.emit(Instr::Not);
+            }
+            Expression::PreIncrement(target) => self.increment(target, 1.0, Push::New),
+            Expression::PreDecrement(target) => self.increment(target, -1.0, Push::New),
+            Expression::PostIncrement(target) => self.increment(target, 1.0, Push::Old),
+            Expression::PostDecrement(target) => self.increment(target, -1.0, Push::Old),
+            Expression::Ternary {
+                condition,
+                then_expr,
+                else_expr,
+            } => {
+                self.expression(condition);
+                let to_else = self.emit(Instr::JumpIfFalse(0));
+                self.expression(then_expr);
+                let to_end = self.emit(Instr::Jump(0));
+                self.patch_here(to_else);
+                self.expression(else_expr);
+                self.patch_here(to_end);
+            }
+            Expression::Concatenation { left, right } => {
+                self.expression(left);
+                self.expression(right);
+                self.emit(Instr::Concat);
+            }
+            Expression::Infix {
+                left,
+                operator,
+                right,
+            } => self.infix(expression, left, &operator.kind, right),
+        }
+    }
+
+    fn infix(
+        &mut self,
+        expression: &Expression<'a>,
+        left: &Expression<'a>,
+        operator: &TokenKind,
+        right: &Expression<'a>,
+    ) {
+        if is_assignment(operator) {
+            self.assignment(left, operator, right, true);
+        } else if let Some(arithmetic) = Arithmetic::of(operator) {
+            self.expression(left);
+            self.expression(right);
+            self.emit(Instr::Arithmetic(arithmetic));
+        } else if let Some(comparison) = Comparison::of(operator) {
+            self.expression(left);
+            self.expression(right);
+            self.emit(Instr::Compare(comparison));
+        } else {
+            match operator {
+                TokenKind::And | TokenKind::Or => {
+                    // Jump to the result as soon as one operand decides it.
+                    let decided = |operand| {
+                        if *operator == TokenKind::And {
+                            Instr::JumpIfFalse(operand)
+                        } else {
+                            Instr::JumpIfTrue(operand)
+                        }
+                    };
+                    self.expression(left);
+                    let left_decides = self.emit(decided(0));
+                    self.expression(right);
+                    let right_decides = self.emit(decided(0));
+                    let undecided = if *operator == TokenKind::And {
+                        1.0
+                    } else {
+                        0.0
+                    };
+                    self.emit(Instr::Number(undecided));
+                    let to_end = self.emit(Instr::Jump(0));
+                    self.patch_here(left_decides);
+                    self.patch_here(right_decides);
+                    self.emit(Instr::Number(1.0 - undecided));
+                    self.patch_here(to_end);
+                }
+                TokenKind::Tilde | TokenKind::NoMatch => {
+                    let negate = *operator == TokenKind::NoMatch;
+                    self.expression(left);
+                    match right {
+                        Expression::Regex(pattern) => {
+                            self.emit(Instr::MatchPattern {
+                                pattern: Box::new(Pattern::new(pattern)),
+                                negate,
+                            });
+                        }
+                        _ => {
+                            self.expression(right);
+                            self.emit(Instr::Match { negate });
+                        }
+                    }
+                }
+                TokenKind::In => match right {
+                    Expression::Identifier(identifier) => {
+                        self.subscript(left);
+                        let array = self.array(identifier);
+                        self.emit(Instr::In(array));
+                    }
+                    _ => {
+                        self.emit(Instr::Number(0.0));
+                    }
+                },
+                TokenKind::Comma => self.subscript(expression),
+                _ => {
+                    self.expression(left);
+                    self.expression(right);
+                    self.emit(Instr::Pop);
+                    self.emit(Instr::Pop);
+                    self.emit(Instr::Number(0.0));
+                }
+            }
+        }
+    }
+
+    /// Compile `left operator right`, leaving the assigned value on the stack
+    /// when `keep` is set.
+    fn assignment(
+        &mut self,
+        left: &Expression<'a>,
+        operator: &TokenKind,
+        right: &Expression<'a>,
+        keep: bool,
+    ) {
+        let place = match left {
+            Expression::Identifier(identifier) => self.place(identifier),
+            Expression::Field(_) => Place::Field,
+            Expression::ArrayAccess { identifier, .. } => Place::Element(self.array(identifier)),
+            // Only the value of an assignment to anything else is kept.
+            _ => {
+                self.expression(right);
+                if let Some(arithmetic) = Arithmetic::of(operator) {
+                    self.expression(left);
+                    self.emit(Instr::Swap);
+                    self.emit(Instr::Arithmetic(arithmetic));
+                }
+                if !keep {
+                    self.emit(Instr::Pop);
+                }
+                return;
+            }
+        };
+
+        self.expression(right);
+        let arithmetic = Arithmetic::of(operator);
+        if arithmetic.is_none() && keep {
+            self.emit(Instr::Dup);
+        }
+        match left {
+            Expression::Field(index) => self.expression(index),
+            Expression::ArrayAccess { index, .. } => self.subscript(index),
+            _ => {}
+        }
+        match arithmetic {
+            Some(arithmetic) => {
+                let push = if keep { Push::New } else { Push::Nothing };
+                self.emit(Instr::Compound {
+                    place,
+                    arithmetic,
+                    push,
+                });
+            }
+            None => {
+                self.emit(Instr::Store(place));
+            }
+        }
+    }
+
+    fn increment(&mut self, target: &Expression<'a>, delta: f64, push: Push) {
+        let place = match target {
+            Expression::Identifier(identifier) => self.place(identifier),
+            Expression::Field(index) => {
+                self.expression(index);
+                Place::Field
+            }
+            Expression::ArrayAccess { identifier, index } => {
+                self.subscript(index);
+                Place::Element(self.array(identifier))
+            }
+            _ => {
+                if push != Push::Nothing {
+                    self.emit(Instr::Number(0.0));
+                }
+                return;
+            }
+        };
+        self.emit(Instr::Increment { place, delta, push });
+    }
+
+    /// Compile an array subscript, joining the parts of `(i, j)` with `SUBSEP`.
+    fn subscript(&mut self, index: &Expression<'a>) {
+        let parts = self.subscript_parts(index);
+        if parts > 1 {
+            self.emit(Instr::Subscript(parts));
+        }
+    }
+
+    fn subscript_parts(&mut self, index: &Expression<'a>) -> usize {
+        match index {
+            Expression::Infix {
+                left,
+                operator,
+                right,
+            } if operator.kind == TokenKind::Comma => {
+                self.subscript_parts(left) + self.subscript_parts(right)
+            }
+            _ => {
+                self.expression(index);
+                1
+            }
+        }
+    }
+}
+
+/// The operands `value` concatenates to `name`, when it is `name` followed
+/// by operands that neither read nor change `name`, so adding each to the
+/// end of `name` as it is evaluated gives the same string.
+fn appended_operands<'e, 'a>(
+    name: &str,
+    value: &'e Expression<'a>,
+) -> Option<Vec<&'e Expression<'a>>> {
+    let mut operands = Vec::new();
+    let mut left = value;
+    while let Expression::Concatenation {
+        left: concatenated,
+        right,
+    } = left
+    {
+        if !independent_of(name, right) {
+            return None;
+        }
+        operands.push(right.as_ref());
+        left = concatenated;
+    }
+    let starts_with_name =
+        matches!(left, Expression::Identifier(identifier) if **identifier == *name);
+    if operands.is_empty() || !starts_with_name {
+        return None;
+    }
+    operands.reverse();
+    Some(operands)
+}
+
+/// Whether evaluating `expression` neither reads `name` nor can assign any
+/// variable: it calls no function and has no assignment, increment or
+/// decrement.
+fn independent_of(name: &str, expression: &Expression<'_>) -> bool {
+    let independent = |expression: &Expression<'_>| independent_of(name, expression);
+    match expression {
+        Expression::Number(_)
+        | Expression::HexNumber { .. }
+        | Expression::String(_)
+        | Expression::Regex(_)
+        | Expression::Rand => true,
+        Expression::Identifier(identifier) => **identifier != *name,
+        Expression::Field(operand) | Expression::Not(operand) => independent(operand),
+        Expression::ArrayAccess { index, .. } => independent(index),
+        Expression::Length(operand) => operand.as_deref().is_none_or(independent),
+        Expression::Substr {
+            string,
+            start,
+            length,
+        } => independent(string) && independent(start) && length.as_deref().is_none_or(independent),
+        Expression::Ternary {
+            condition,
+            then_expr,
+            else_expr,
+        } => independent(condition) && independent(then_expr) && independent(else_expr),
+        Expression::Concatenation { left, right } => independent(left) && independent(right),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            let pure = Arithmetic::of(&operator.kind).is_some()
+                || Comparison::of(&operator.kind).is_some()
+                || matches!(
+                    operator.kind,
+                    TokenKind::And
+                        | TokenKind::Or
+                        | TokenKind::Tilde
+                        | TokenKind::NoMatch
+                        | TokenKind::In
+                );
+            pure && independent(left) && independent(right)
+        }
+        Expression::FunctionCall { .. }
+        | Expression::PreIncrement(_)
+        | Expression::PreDecrement(_)
+        | Expression::PostIncrement(_)
+        | Expression::PostDecrement(_) => false,
+    }
+}
+
+fn is_assignment(operator: &TokenKind) -> bool {
+    matches!(
+        operator,
+        TokenKind::Assign
+            | TokenKind::AddAssign
+            | TokenKind::SubtractAssign
+            | TokenKind::MultiplyAssign
+            | TokenKind::DivideAssign
+            | TokenKind::ModuloAssign
+            | TokenKind::PowerAssign
+    )
+}
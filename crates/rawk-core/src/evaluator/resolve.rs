@@ -0,0 +1,486 @@
+//! Resolution of variable names to where their values live: a global slot, a
+//! parameter position or one of the special variables the evaluator keeps in
+//! fields of its own.
+//!
+//! Each occurrence of a name in the program is resolved once, before it runs,
+//! and keeps the variable it refers to in its [`Name`].
+
+use crate::{
+    Program, Rule,
+    ast::{Expression, Name, Statement},
+};
+
+/// A variable whose reads or assignments have side effects, such as `NF`, or
+/// which lives outside the global slots, such as `FS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Special {
+    /// `getline` read as a variable, which reads the next record.
+    Getline,
+    FieldSeparator,
+    OutputFieldSeparator,
+    OutputRecordSeparator,
+    ConversionFormat,
+    OutputFormat,
+    SubscriptSeparator,
+    FieldCount,
+    RecordNumber,
+    FileRecordNumber,
+    Filename,
+    FieldWidths,
+    FieldPattern,
+}
+
+impl Special {
+    pub(super) fn of(name: &str) -> Option<Self> {
+        Some(match name {
+            "getline" => Self::Getline,
+            "FS" => Self::FieldSeparator,
+            "OFS" => Self::OutputFieldSeparator,
+            "ORS" => Self::OutputRecordSeparator,
+            "CONVFMT" => Self::ConversionFormat,
+            "OFMT" => Self::OutputFormat,
+            "SUBSEP" => Self::SubscriptSeparator,
+            "NF" => Self::FieldCount,
+            "NR" => Self::RecordNumber,
+            "FNR" => Self::FileRecordNumber,
+            "FILENAME" => Self::Filename,
+            "FIELDWIDTHS" => Self::FieldWidths,
+            "FPAT" => Self::FieldPattern,
+            _ => return None,
+        })
+    }
+
+    pub(super) fn name(self) -> &'static str {
+        match self {
+            Self::Getline => "getline",
+            Self::FieldSeparator => "FS",
+            Self::OutputFieldSeparator => "OFS",
+            Self::OutputRecordSeparator => "ORS",
+            Self::ConversionFormat => "CONVFMT",
+            Self::OutputFormat => "OFMT",
+            Self::SubscriptSeparator => "SUBSEP",
+            Self::FieldCount => "NF",
+            Self::RecordNumber => "NR",
+            Self::FileRecordNumber => "FNR",
+            Self::Filename => "FILENAME",
+            Self::FieldWidths => "FIELDWIDTHS",
+            Self::FieldPattern => "FPAT",
+        }
+    }
+}
+
+/// Where the value of a variable lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Variable {
+    /// The parameter at this position of the running function.
+    Local(usize),
+    Global(usize),
+    Special(Special),
+}
+
+/// Resolve `name` inside a function with `parameters`, or outside any
+/// function, finding the slot of a global with `global_slot`.
+pub(super) fn resolve(
+    name: &str,
+    parameters: Option<&[&str]>,
+    global_slot: &mut dyn FnMut(&str) -> usize,
+) -> Variable {
+    if let Some(index) =
+        parameters.and_then(|parameters| parameters.iter().position(|parameter| *parameter == name))
+    {
+        Variable::Local(index)
+    } else if let Some(special) = Special::of(name) {
+        Variable::Special(special)
+    } else {
+        Variable::Global(global_slot(name))
+    }
+}
+
+/// Resolve every name in `program`, finding the slot of each global with
+/// `global_slot`.
+pub(super) fn resolve_program(program: &Program<'_>, global_slot: &mut dyn FnMut(&str) -> usize) {
+    let mut resolver = Resolver {
+        global_slot,
+        parameters: None,
+    };
+    for definition in program.function_definitions_iter() {
+        resolver.parameters = Some(&definition.parameters);
+        resolver.statements(&definition.statements);
+    }
+    resolver.parameters = None;
+
+    let actions = program
+        .begin_blocks_iter()
+        .chain(program.begin_file_blocks_iter())
+        .chain(program.end_file_blocks_iter())
+        .chain(program.end_blocks_iter());
+    for action in actions {
+        resolver.statements(&action.statements);
+    }
+    for rule in program.rules_iter() {
+        match rule {
+            Rule::Action(action) => resolver.statements(&action.statements),
+            Rule::PatternAction { pattern, action } => {
+                if let Some(pattern) = pattern {
+                    resolver.expression(pattern);
+                }
+                if let Some(action) = action {
+                    resolver.statements(&action.statements);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Resolver<'r, 'p> {
+    global_slot: &'r mut dyn FnMut(&str) -> usize,
+    /// The parameters of the function being resolved, if any.
+    parameters: Option<&'p [&'p str]>,
+}
+
+impl Resolver<'_, '_> {
+    fn name(&mut self, name: &Name<'_>) {
+        name.resolve_to(resolve(name, self.parameters, self.global_slot));
+    }
+
+    fn statements(&mut self, statements: &[Statement<'_>]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement<'_>) {
+        match statement {
+            Statement::Empty
+            | Statement::Break
+            | Statement::Continue
+            | Statement::Next
+            | Statement::NextFile
+            | Statement::Return(None)
+            | Statement::Exit(None) => {}
+            Statement::Expression(expression)
+            | Statement::System(expression)
+            | Statement::Return(Some(expression))
+            | Statement::Exit(Some(expression)) => self.expression(expression),
+            Statement::Print(expressions) | Statement::Printf(expressions) => {
+                self.expressions(expressions)
+            }
+            Statement::PrintRedirect {
+                expressions,
+                target,
+                ..
+            }
+            | Statement::PrintPipe {
+                expressions,
+                target,
+            }
+            | Statement::PrintfRedirect {
+                expressions,
+                target,
+                ..
+            }
+            | Statement::PrintfPipe {
+                expressions,
+                target,
+            } => {
+                self.expressions(expressions);
+                self.expression(target);
+            }
+            Statement::Split {
+                string,
+                array,
+                separator,
+            } => {
+                self.expression(string);
+                self.name(array);
+                self.optional(separator.as_ref());
+            }
+            Statement::Sub {
+                pattern,
+                replacement,
+                target,
+            }
+            | Statement::Gsub {
+                pattern,
+                replacement,
+                target,
+            } => {
+                self.expression(pattern);
+                self.expression(replacement);
+                self.optional(target.as_ref());
+            }
+            Statement::Assignment { identifier, value }
+            | Statement::AddAssignment { identifier, value } => {
+                self.name(identifier);
+                self.expression(value);
+            }
+            Statement::SplitAssignment {
+                identifier,
+                string,
+                array,
+                separator,
+            } => {
+                self.name(identifier);
+                self.expression(string);
+                self.name(array);
+                self.optional(separator.as_ref());
+            }
+            Statement::ArrayAssignment {
+                identifier,
+                index,
+                value,
+            }
+            | Statement::ArrayAddAssignment {
+                identifier,
+                index,
+                value,
+            } => {
+                self.name(identifier);
+                self.expression(index);
+                self.expression(value);
+            }
+            Statement::FieldAssignment { field, value } => {
+                self.expression(field);
+                self.expression(value);
+            }
+            Statement::ArrayPostIncrement { identifier, index }
+            | Statement::ArrayPostDecrement { identifier, index } => {
+                self.name(identifier);
+                self.expression(index);
+            }
+            Statement::Delete { identifier, index } => {
+                self.name(identifier);
+                self.optional(index.as_ref());
+            }
+            Statement::PreIncrement { identifier }
+            | Statement::PreDecrement { identifier }
+            | Statement::PostIncrement { identifier }
+            | Statement::PostDecrement { identifier } => self.name(identifier),
+            Statement::If {
+                condition,
+                then_statements,
+            } => {
+                self.expression(condition);
+                self.statements(then_statements);
+            }
+            Statement::IfElse {
+                condition,
+                then_statements,
+                else_statements,
+            } => {
+                self.expression(condition);
+                self.statements(then_statements);
+                self.statements(else_statements);
+            }
+            Statement::While {
+                condition,
+                statements,
+            }
+            | Statement::DoWhile {
+                condition,
+                statements,
+            } => {
+                self.expression(condition);
+                self.statements(statements);
+            }
+            Statement::For {
+                init,
+                condition,
+                update,
+                statements,
+            } => {
+                self.statement(init);
+                self.expression(condition);
+                self.statement(update);
+                self.statements(statements);
+            }
+            Statement::ForIn {
+                variable,
+                array,
+                statements,
+            } => {
+                self.name(variable);
+                self.name(array);
+                self.statements(statements);
+            }
+        }
+    }
+
+    fn expressions(&mut self, expressions: &[Expression<'_>]) {
+        for expression in expressions {
+            self.expression(expression);
+        }
+    }
+
+    fn optional(&mut self, expression: Option<&Expression<'_>>) {
+        if let Some(expression) = expression {
+            self.expression(expression);
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression<'_>) {
+        match expression {
+            Expression::Number(_)
+            | Expression::HexNumber { .. }
+            | Expression::String(_)
+            | Expression::Regex(_)
+            | Expression::Rand
+            | Expression::Length(None) => {}
+            Expression::Identifier(name) => self.name(name),
+            Expression::ArrayAccess { identifier, index } => {
+                self.name(identifier);
+                self.expression(index);
+            }
+            Expression::Field(operand)
+            | Expression::Length(Some(operand))
+            | Expression::Not(operand)
+            | Expression::PreIncrement(operand)
+            | Expression::PreDecrement(operand)
+            | Expression::PostIncrement(operand)
+            | Expression::PostDecrement(operand) => self.expression(operand),
+            Expression::Substr {
+                string,
+                start,
+                length,
+            } => {
+                self.expression(string);
+                self.expression(start);
+                self.optional(length.as_deref());
+            }
+            Expression::FunctionCall { args, .. } => self.expressions(args),
+            Expression::Ternary {
+                condition,
+                then_expr,
+                else_expr,
+            } => {
+                self.expression(condition);
+                self.expression(then_expr);
+                self.expression(else_expr);
+            }
+            Expression::Concatenation { left, right } | Expression::Infix { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Lexer, Parser,
+        ast::{Action, FunctionDefinition},
+    };
+
+    fn resolve_source(source: &str) -> (Program<'_>, Vec<String>) {
+        let program = Parser::new(Lexer::new(source)).parse_program();
+        let mut globals = Vec::new();
+        resolve_program(&program, &mut |name| {
+            globals
+                .iter()
+                .position(|global| global == name)
+                .unwrap_or_else(|| {
+                    globals.push(name.to_string());
+                    globals.len() - 1
+                })
+        });
+        (program, globals)
+    }
+
+    fn identifier<'e>(expression: &'e Expression<'_>) -> &'e Name<'e> {
+        match expression {
+            Expression::Identifier(name) => name,
+            other => panic!("expected an identifier, found {other}"),
+        }
+    }
+
+    fn operands<'e>(expression: &'e Expression<'_>) -> (&'e Expression<'e>, &'e Expression<'e>) {
+        match expression {
+            Expression::Infix { left, right, .. } => (left, right),
+            other => panic!("expected an infix expression, found {other}"),
+        }
+    }
+
+    #[test]
+    fn resolves_parameters_specials_and_globals() {
+        let source = "function f(a, NR) { return a + NR + x } { x = NR; a[1] = f(NF) }";
+        let (program, globals) = resolve_source(source);
+
+        let function = program.function_definition("f").unwrap();
+        let Statement::Return(Some(sum)) = &function.statements[0] else {
+            panic!("expected return");
+        };
+        let (a_plus_nr, x_in_f) = operands(sum);
+        let (a, nr) = operands(a_plus_nr);
+        assert_eq!(identifier(a).variable(), Some(Variable::Local(0)));
+        assert_eq!(identifier(nr).variable(), Some(Variable::Local(1)));
+        let x = identifier(x_in_f).variable();
+        assert!(matches!(x, Some(Variable::Global(slot)) if globals[slot] == "x"));
+
+        let Some(Rule::Action(action)) = program.rules_iter().next() else {
+            panic!("expected an action");
+        };
+        let [
+            Statement::Assignment {
+                identifier: x_in_rule,
+                value: nr,
+            },
+            Statement::ArrayAssignment {
+                identifier: a,
+                value: call,
+                ..
+            },
+        ] = action.statements.as_slice()
+        else {
+            panic!("expected two assignments");
+        };
+        assert_eq!(x_in_rule.variable(), x);
+        assert_eq!(
+            identifier(nr).variable(),
+            Some(Variable::Special(Special::RecordNumber))
+        );
+        assert!(matches!(a.variable(), Some(Variable::Global(slot)) if globals[slot] == "a"));
+        let Expression::FunctionCall { args, .. } = call else {
+            panic!("expected a call");
+        };
+        assert_eq!(
+            identifier(&args[0]).variable(),
+            Some(Variable::Special(Special::FieldCount))
+        );
+    }
+
+    #[test]
+    fn resolves_text_shared_by_different_variables_at_each_use() {
+        let name = "n";
+        let mut program = Program::new();
+        program.add_function_definition(FunctionDefinition {
+            name: "f",
+            parameters: vec![name],
+            statements: vec![Statement::PreIncrement {
+                identifier: name.into(),
+            }],
+        });
+        program.add_begin_block(Action {
+            statements: vec![Statement::PreIncrement {
+                identifier: name.into(),
+            }],
+        });
+
+        resolve_program(&program, &mut |_| 7);
+
+        let variable = |statements: &[Statement<'_>]| match &statements[0] {
+            Statement::PreIncrement { identifier } => identifier.variable(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            variable(&program.function_definition("f").unwrap().statements),
+            Some(Variable::Local(0))
+        );
+        assert_eq!(
+            variable(&program.begin_blocks_iter().next().unwrap().statements),
+            Some(Variable::Global(7))
+        );
+    }
+}
@@ -0,0 +1,800 @@
+//! The stack machine that runs the bytecode of [`compile`](super::compile).
+//! It shares the state and the builtins of the tree-walking evaluator.
+
+use std::{rc::Rc, time::Instant};
+
+use super::{
+    Array, Call, Evaluator, Local, awk_substitute,
+    compile::{
+        ArrayRef, Builtin, Compiled, Instr, Pattern, Place, Push, Redirect, RegexArgument,
+        RuleCode, SubstitutionTarget,
+    },
+    fill_split_array, format_printf,
+    resolve::Special,
+    unescape_awk_string, with_stack,
+};
+use crate::{csv, value::Value};
+
+/// How many regular expressions computed at run time are kept compiled
+/// before the cache starts over.
+const DYNAMIC_PATTERN_LIMIT: usize = 256;
+
+/// A [`Place`] with its operand taken off the stack.
+enum Target {
+    Global(usize),
+    Local(usize),
+    Special(Special),
+    Field(i64),
+    Element(Array, String),
+}
+
+/// A statement being timed for the profiler.
+struct Timer {
+    statement: usize,
+    depth: usize,
+    started: Instant,
+}
+
+fn truth(value: bool) -> Value {
+    Value::Number(if value { 1.0 } else { 0.0 })
+}
+
+impl<'a> Evaluator<'a> {
+    /// Run `code`, part of `compiled`, and return its output.
+    pub(super) fn run_code(&mut self, compiled: &Compiled<'a>, code: &[Instr]) -> Vec<String> {
+        let stack_len = self.stack.len();
+        let keys_len = self.key_iterators.len();
+        let arrays_len = self.argument_arrays.len();
+        let mut output = Vec::new();
+        let mut timers = Vec::new();
+        self.execute(compiled, code, &mut output, &mut timers);
+        self.end_timers(&mut timers, 0);
+        self.stack.truncate(stack_len);
+        self.key_iterators.truncate(keys_len);
+        self.argument_arrays.truncate(arrays_len);
+        output.extend(self.take_expression_output());
+        output
+    }
+
+    /// Run the code of a pattern, which leaves its value on the stack.
+    pub(super) fn test_pattern(&mut self, compiled: &Compiled<'a>, code: &[Instr]) -> bool {
+        let mut output = Vec::new();
+        self.execute(compiled, code, &mut output, &mut Vec::new());
+        self.pop().is_true()
+    }
+
+    /// Test the pattern of a rule. A range pattern holds from a record its
+    /// start matches through the next one its end matches, as tracked by
+    /// `range_active`.
+    pub(super) fn test_rule_pattern(
+        &mut self,
+        compiled: &Compiled<'a>,
+        code: &RuleCode,
+        range_active: &mut bool,
+    ) -> bool {
+        let Some(pattern) = &code.pattern else {
+            return true;
+        };
+        let Some(end) = &code.range_end else {
+            return self.test_pattern(compiled, pattern);
+        };
+        if !*range_active {
+            if !self.test_pattern(compiled, pattern) {
+                return false;
+            }
+            *range_active = true;
+        }
+        if self.test_pattern(compiled, end) {
+            *range_active = false;
+        }
+        true
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap_or_default()
+    }
+
+    fn pop_number(&mut self) -> f64 {
+        self.pop().to_number()
+    }
+
+    fn pop_text(&mut self) -> String {
+        self.pop().into_string_with(&self.conversion_format)
+    }
+
+    /// Take a regular expression computed at run time off the stack.
+    fn pop_pattern(&mut self) -> Rc<Pattern> {
+        let text = self.pop_text();
+        self.dynamic_pattern(text)
+    }
+
+    /// The regular expression `text`, compiled only the first time it is
+    /// seen.
+    fn dynamic_pattern(&mut self, text: String) -> Rc<Pattern> {
+        if let Some(pattern) = self.dynamic_patterns.get(&text) {
+            return Rc::clone(pattern);
+        }
+        if self.dynamic_patterns.len() >= DYNAMIC_PATTERN_LIMIT {
+            self.dynamic_patterns.clear();
+        }
+        let pattern = Rc::new(Pattern::new(&text));
+        self.dynamic_patterns.insert(text, Rc::clone(&pattern));
+        pattern
+    }
+
+    /// Stop timing the statements in `timers` nested at least `depth` blocks
+    /// deep, which have finished once a statement at `depth` starts.
+    fn end_timers(&mut self, timers: &mut Vec<Timer>, depth: usize) {
+        let Some(profiler) = &mut self.profiler else {
+            return;
+        };
+        while let Some(timer) = timers.pop_if(|timer| timer.depth >= depth) {
+            profiler.add_statement(timer.statement, timer.started.elapsed());
+        }
+    }
+
+    /// Run `code`, adding its output to `output`. The statements started are
+    /// timed in `timers` while profiling.
+    fn execute(
+        &mut self,
+        compiled: &Compiled<'a>,
+        code: &[Instr],
+        output: &mut Vec<String>,
+        timers: &mut Vec<Timer>,
+    ) {
+        let mut next = 0;
+        while let Some(instr) = code.get(next) {
+            next += 1;
+            match instr {
+                Instr::Statement { index, depth } => {
+                    self.count_step();
+                    if self.exited || self.next_record || self.runtime_error.is_some() {
+                        return;
+                    }
+                    if let Some(index) = *index
+                        && self.debugger.is_some()
+                    {
+                        // Show the output so far at the stop.
+                        let pending = self.take_expression_output();
+                        self.output.extend(pending);
+                        self.output.append(output);
+                        self.enter_statement_at(index);
+                        if self.exited {
+                            return;
+                        }
+                    }
+                    if self.profiler.is_some() {
+                        self.end_timers(timers, *depth);
+                        if let Some(index) = *index {
+                            timers.push(Timer {
+                                statement: index,
+                                depth: *depth,
+                                started: Instant::now(),
+                            });
+                        }
+                    }
+                }
+                Instr::Block => {
+                    self.count_step();
+                    if self.poll_cancellation() {
+                        return;
+                    }
+                }
+                Instr::Number(value) => self.stack.push(Value::Number(*value)),
+                Instr::String(value) => self.stack.push(Value::String(value.clone())),
+                Instr::Uninitialized => self.stack.push(Value::Uninitialized),
+                // Plain variables skip the operands and side effects of other
+                // places.
+                Instr::Load(Place::Global(slot)) => {
+                    let value = self.globals[*slot].value.clone().unwrap_or_default();
+                    self.stack.push(value);
+                }
+                Instr::Load(Place::Local(index)) => {
+                    let value = self.local(*index).value.clone();
+                    self.stack.push(value);
+                }
+                Instr::Load(place) => {
+                    let target = self.target(*place);
+                    let value = self.load(&target);
+                    self.stack.push(value);
+                }
+                Instr::LoadField(index) => {
+                    let value = self.field_value(*index);
+                    self.stack.push(value);
+                }
+                Instr::Store(place @ (Place::Global(_) | Place::Local(_))) => {
+                    let value = self.pop();
+                    *self.variable(*place) = value;
+                }
+                Instr::Store(place) => {
+                    let target = self.target(*place);
+                    let value = self.pop();
+                    self.store(target, value);
+                }
+                Instr::Compound {
+                    place: place @ (Place::Global(_) | Place::Local(_)),
+                    arithmetic,
+                    push,
+                } => {
+                    let right = self.pop_number();
+                    let variable = self.variable(*place);
+                    let current = variable.to_number();
+                    let updated = arithmetic.apply(current, right);
+                    *variable = Value::Number(updated);
+                    self.push_update(*push, current, updated);
+                }
+                Instr::Compound {
+                    place,
+                    arithmetic,
+                    push,
+                } => {
+                    let target = self.target(*place);
+                    let right = self.pop_number();
+                    let current = self.load(&target).to_number();
+                    let updated = arithmetic.apply(current, right);
+                    self.store(target, Value::Number(updated));
+                    self.push_update(*push, current, updated);
+                }
+                Instr::Increment {
+                    place: place @ (Place::Global(_) | Place::Local(_)),
+                    delta,
+                    push,
+                } => {
+                    let variable = self.variable(*place);
+                    let current = variable.to_number();
+                    *variable = Value::Number(current + delta);
+                    self.push_update(*push, current, current + delta);
+                }
+                Instr::Increment { place, delta, push } => {
+                    let target = self.target(*place);
+                    let current = self.load(&target).to_number();
+                    self.store(target, Value::Number(current + delta));
+                    self.push_update(*push, current, current + delta);
+                }
+                Instr::Arithmetic(arithmetic) => {
+                    let right = self.pop_number();
+                    let left = self.pop_number();
+                    self.stack
+                        .push(Value::Number(arithmetic.apply(left, right)));
+                }
+                Instr::Compare(comparison) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let ordering = left.compare(&right, &self.conversion_format);
+                    self.stack.push(truth(comparison.holds(ordering)));
+                }
+                Instr::Concat => {
+                    let right = self.pop();
+                    let mut text = self.pop_text();
+                    match right {
+                        Value::String(right) => text.push_str(&right),
+                        right => text.push_str(&self.to_text(&right)),
+                    }
+                    self.stack.push(Value::String(text));
+                }
+                Instr::Append(place) => {
+                    let right = match self.pop() {
+                        Value::String(right) => right,
+                        right => self.to_text(&right),
+                    };
+                    let value = std::mem::take(self.variable(*place));
+                    let mut text = value.into_string_with(&self.conversion_format);
+                    text.push_str(&right);
+                    *self.variable(*place) = Value::String(text);
+                }
+                Instr::Not => {
+                    let value = self.pop();
+                    self.stack.push(truth(!value.is_true()));
+                }
+                Instr::MatchPattern { pattern, negate } => {
+                    let text = self.pop_text();
+                    self.stack.push(truth(pattern.is_match(&text) != *negate));
+                }
+                Instr::MatchRecord(pattern) => {
+                    let matched = self
+                        .record_text()
+                        .is_some_and(|line| pattern.is_match(line));
+                    self.stack.push(truth(matched));
+                }
+                Instr::Match { negate } => {
+                    let pattern = self.pop_pattern();
+                    let text = self.pop_text();
+                    self.stack.push(truth(pattern.is_match(&text) != *negate));
+                }
+                Instr::Subscript(parts) => {
+                    let parts = self.stack.split_off(self.stack.len() - parts);
+                    let parts: Vec<String> = parts
+                        .into_iter()
+                        .map(|part| part.into_string_with(&self.conversion_format))
+                        .collect();
+                    self.stack
+                        .push(Value::String(parts.join(&self.subscript_separator)));
+                }
+                Instr::In(array) => {
+                    let key = self.pop_text();
+                    let present = self
+                        .find_array_of(*array)
+                        .is_some_and(|array| array.borrow().contains_key(&key));
+                    self.stack.push(truth(present));
+                }
+                Instr::Delete(array) => {
+                    if let Some(array) = self.find_array_of(*array) {
+                        array.borrow_mut().clear();
+                    }
+                }
+                Instr::DeleteElement(array) => {
+                    let key = self.pop_text();
+                    self.array_of(*array).borrow_mut().remove(&key);
+                }
+                Instr::Length => {
+                    let text = self.pop_text();
+                    self.stack
+                        .push(Value::Number(self.char_mode.len(&text) as f64));
+                }
+                Instr::RecordLength => {
+                    let char_mode = self.char_mode;
+                    let length = self.record_text().map_or(0, |line| char_mode.len(line));
+                    self.stack.push(Value::Number(length as f64));
+                }
+                Instr::Substr { length } => {
+                    let length = length.then(|| self.pop_number());
+                    let start = self.pop_number();
+                    let source = self.pop_text();
+                    let substring = self.substring(&source, start, length);
+                    self.stack.push(substring);
+                }
+                Instr::Rand => {
+                    let value = self.eval_rand();
+                    self.stack.push(Value::Number(value));
+                }
+                Instr::Jump(target) => next = *target,
+                Instr::JumpIfFalse(target) => {
+                    if !self.pop().is_true() {
+                        next = *target;
+                    }
+                }
+                Instr::JumpIfTrue(target) => {
+                    if self.pop().is_true() {
+                        next = *target;
+                    }
+                }
+                Instr::JumpUnless(comparison, target) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    if !comparison.holds(left.compare(&right, &self.conversion_format)) {
+                        next = *target;
+                    }
+                }
+                Instr::Pop => {
+                    self.stack.pop();
+                }
+                Instr::Dup => {
+                    let value = self.stack.last().cloned().unwrap_or_default();
+                    self.stack.push(value);
+                }
+                Instr::Swap => {
+                    let len = self.stack.len();
+                    self.stack.swap(len - 1, len - 2);
+                }
+                Instr::Print(count) => {
+                    let line = self.print_text(*count);
+                    let pending_printf = std::mem::take(&mut self.printf_buffer);
+                    output.extend(self.take_expression_output());
+                    output.push(if pending_printf.is_empty() {
+                        line
+                    } else {
+                        pending_printf + &line
+                    });
+                    self.append_output_record_separator(output);
+                }
+                Instr::Printf(count) => {
+                    let rendered = self.format_text(*count);
+                    output.extend(self.take_expression_output());
+                    output.extend(self.buffer_printf(rendered));
+                }
+                Instr::PrintTo(count, redirect) => {
+                    let target = self.pop_text();
+                    let mut text = self.print_text(*count);
+                    text.push_str(&self.output_record_separator);
+                    self.write_redirected(*redirect, target, text);
+                }
+                Instr::PrintfTo(count, redirect) => {
+                    let target = self.pop_text();
+                    let text = self.format_text(*count);
+                    self.write_redirected(*redirect, target, text);
+                }
+                Instr::Sprintf(count) => {
+                    let text = self.format_text(*count);
+                    self.stack.push(Value::String(text));
+                }
+                Instr::System => {
+                    let command = self.pop_text();
+                    let status = self.system(&command);
+                    self.stack.push(Value::Number(status));
+                }
+                Instr::Builtin(builtin) => {
+                    let value = self.builtin(*builtin);
+                    self.stack.push(value);
+                }
+                Instr::Srand { seed } => {
+                    let seed = seed.then(|| self.pop_number());
+                    let previous = self.srand(seed);
+                    self.stack.push(Value::Number(previous));
+                }
+                Instr::LengthOf { place, array } => {
+                    let elements = self
+                        .find_array_of(*array)
+                        .map_or(0, |array| array.borrow().len());
+                    let length = if elements > 0 {
+                        elements
+                    } else {
+                        let target = self.target(*place);
+                        let value = self.load(&target);
+                        self.char_mode.len(&self.to_text(&value))
+                    };
+                    self.stack.push(Value::Number(length as f64));
+                }
+                Instr::Split { array, separator } => {
+                    let fields = match separator {
+                        RegexArgument::Default => {
+                            let source = self.pop_text();
+                            self.split_with_field_separator(&source)
+                        }
+                        RegexArgument::Literal(pattern) => pattern.split(&self.pop_text()),
+                        RegexArgument::Dynamic => {
+                            let separator = self.pop_text();
+                            let source = self.pop_text();
+                            // A separator longer than a character is a regular
+                            // expression.
+                            if separator.chars().nth(1).is_some() && !source.is_empty() {
+                                self.dynamic_pattern(separator).split(&source)
+                            } else {
+                                self.split_at(&source, &separator)
+                            }
+                        }
+                    };
+                    let array = self.array_of(*array);
+                    let count = fill_split_array(&array, fields);
+                    self.stack.push(Value::Number(count as f64));
+                }
+                Instr::Patsplit {
+                    array,
+                    separators,
+                    pattern,
+                } => {
+                    let pattern = match pattern {
+                        RegexArgument::Default => None,
+                        RegexArgument::Literal(pattern) => Some(pattern.text().to_string()),
+                        RegexArgument::Dynamic => Some(self.pop_text()),
+                    };
+                    let source = self.pop_text();
+                    let array = self.array_of(*array);
+                    let separators = separators.map(|separators| self.array_of(separators));
+                    let count = self.patsplit(&source, pattern, &array, separators.as_ref());
+                    self.stack.push(Value::Number(count as f64));
+                }
+                Instr::FindMatch(pattern) => {
+                    let dynamic = match pattern {
+                        RegexArgument::Dynamic => Some(self.pop_pattern()),
+                        _ => None,
+                    };
+                    let text = self.pop_text();
+                    let matched = match (pattern, dynamic) {
+                        (RegexArgument::Literal(pattern), _) => pattern.find(&text),
+                        (_, Some(pattern)) => pattern.find(&text),
+                        _ => None,
+                    };
+                    let start = self.set_match(&text, matched);
+                    self.stack.push(Value::Number(start));
+                }
+                Instr::Substitute {
+                    global,
+                    pattern,
+                    target,
+                } => {
+                    let count = self.substitute(*global, pattern, *target);
+                    self.stack.push(Value::Number(count as f64));
+                }
+                Instr::Keys(array) => {
+                    let mut keys: Vec<String> = self
+                        .find_array_of(*array)
+                        .map(|array| array.borrow().keys().cloned().collect())
+                        .unwrap_or_default();
+                    keys.sort();
+                    self.key_iterators.push(keys.into_iter());
+                }
+                Instr::NextKey(end) => match self.key_iterators.last_mut().and_then(Iterator::next)
+                {
+                    Some(key) => self.stack.push(Value::from_input(key)),
+                    None => next = *end,
+                },
+                Instr::PopKeys => {
+                    self.key_iterators.pop();
+                }
+                Instr::Argument { place, array } => {
+                    let target = self.target(*place);
+                    let value = self.load(&target);
+                    self.stack.push(value);
+                    let array = self.array_of(*array);
+                    self.argument_arrays.push(array);
+                }
+                Instr::Call {
+                    function,
+                    references,
+                } => {
+                    let value = self.call(compiled, *function, references);
+                    self.stack.push(value);
+                }
+                Instr::Return => {
+                    self.return_value = Some(self.pop());
+                    return;
+                }
+                Instr::Next => {
+                    self.next_record = true;
+                    return;
+                }
+                Instr::NextFile => {
+                    self.next_record = true;
+                    self.next_file = true;
+                    return;
+                }
+                Instr::Exit => {
+                    self.exited = true;
+                    return;
+                }
+                Instr::Leave => return,
+            }
+        }
+    }
+
+    /// Pop the values of `print`, joined with `OFS`, or take the record when
+    /// there are none.
+    fn print_text(&mut self, count: usize) -> String {
+        if count == 0 {
+            return self.record_text().unwrap_or_default().to_string();
+        }
+        let values = self.stack.split_off(self.stack.len() - count);
+        let parts: Vec<String> = values
+            .into_iter()
+            .map(|value| self.to_output_text(value))
+            .collect();
+        self.join_fields(&parts)
+    }
+
+    /// Pop the format and values of `printf` or `sprintf` and format them.
+    fn format_text(&mut self, count: usize) -> String {
+        let mut values = self.stack.split_off(self.stack.len() - count);
+        let format = values.remove(0);
+        let format = unescape_awk_string(&self.to_text(&format));
+        format_printf(&format, &values, &self.conversion_format, self.char_mode)
+    }
+
+    fn write_redirected(&mut self, redirect: Redirect, target: String, text: String) {
+        match redirect {
+            Redirect::File { append } => self.write_to_file(target, &text, append),
+            Redirect::Pipe => self.write_to_pipe(target, text),
+        }
+    }
+
+    /// Pop the arguments of `builtin` and compute its result.
+    fn builtin(&mut self, builtin: Builtin) -> Value {
+        let number = |value: f64| Value::Number(value);
+        match builtin {
+            Builtin::Index => {
+                let search = self.pop_text();
+                let string = self.pop_text();
+                number(self.index(&string, &search))
+            }
+            Builtin::Atan2 => {
+                let x = self.pop_number();
+                let y = self.pop_number();
+                number(y.atan2(x))
+            }
+            Builtin::Sqrt => number(self.pop_number().sqrt()),
+            Builtin::Log => number(self.pop_number().ln()),
+            Builtin::Exp => number(self.pop_number().exp()),
+            Builtin::Sin => number(self.pop_number().sin()),
+            Builtin::Cos => number(self.pop_number().cos()),
+            Builtin::Int => number(self.pop_number().trunc()),
+            Builtin::ToLower => {
+                let text = self.pop_text();
+                Value::String(self.char_mode.to_lowercase(&text))
+            }
+            Builtin::ToUpper => {
+                let text = self.pop_text();
+                Value::String(self.char_mode.to_uppercase(&text))
+            }
+            Builtin::CsvQuote => {
+                let text = self.pop_text();
+                Value::String(csv::quote_csv_field(&text, ","))
+            }
+            Builtin::Close => {
+                let target = self.pop_text();
+                number(self.eval_close(&target))
+            }
+        }
+    }
+
+    /// Run `sub` or `gsub` with its operands on the stack and return the
+    /// number of replacements. A pattern that does not compile ends the
+    /// program.
+    fn substitute(
+        &mut self,
+        global: bool,
+        pattern: &RegexArgument,
+        target: SubstitutionTarget,
+    ) -> usize {
+        let (text, place) = match target {
+            SubstitutionTarget::Record => (None, None),
+            SubstitutionTarget::Place(place) => {
+                let target = self.target(place);
+                let value = self.load(&target);
+                (Some(self.to_text(&value)), Some(target))
+            }
+            SubstitutionTarget::Value => (Some(self.pop_text()), None),
+        };
+        let replacement = self.pop_text();
+        let dynamic = match pattern {
+            RegexArgument::Dynamic => Some(self.pop_pattern()),
+            _ => None,
+        };
+        let regex = match pattern {
+            RegexArgument::Literal(pattern) => pattern.regex(),
+            _ => dynamic.as_ref().and_then(|pattern| pattern.regex()),
+        };
+        let Some(regex) = regex else {
+            self.exited = true;
+            return 0;
+        };
+        let replacement = unescape_awk_string(&replacement);
+        let text = match text {
+            Some(text) => text,
+            None => match self.record_text() {
+                Some(line) => line.to_string(),
+                None => return 0,
+            },
+        };
+
+        let (replaced, count) = awk_substitute(&text, regex, &replacement, global);
+        if count > 0 {
+            match (target, place) {
+                (SubstitutionTarget::Record, _) => self.set_record(replaced),
+                (_, Some(place)) => self.store(place, Value::String(replaced)),
+                _ => {}
+            }
+        }
+        count
+    }
+
+    fn push_update(&mut self, push: Push, old: f64, new: f64) {
+        match push {
+            Push::Nothing => {}
+            Push::Old => self.stack.push(Value::Number(old)),
+            Push::New => self.stack.push(Value::Number(new)),
+        }
+    }
+
+    /// Call the compiled function `index` with the arguments on the stack.
+    fn call(&mut self, compiled: &Compiled<'a>, index: usize, references: &[bool]) -> Value {
+        let function = &compiled.functions[index];
+        let mut locals = self.spare_frames.pop().unwrap_or_default();
+        locals.extend(
+            function
+                .parameters
+                .iter()
+                .map(|parameter| (*parameter, Local::default())),
+        );
+        let values = self.stack.len() - references.len();
+        let arrays = self.argument_arrays.len() - references.iter().filter(|r| **r).count();
+        let mut arrays = self.argument_arrays.drain(arrays..);
+        for (index, (value, reference)) in self.stack.drain(values..).zip(references).enumerate() {
+            let array = if *reference { arrays.next() } else { None };
+            if let Some((_, parameter)) = locals.get_mut(index) {
+                *parameter = Local { value, array };
+            }
+        }
+        drop(arrays);
+
+        if self.poll_cancellation() {
+            return Value::Uninitialized;
+        }
+        if self.frames.len() >= self.max_call_depth {
+            self.runtime_error = Some(format!(
+                "function call nesting too deep in {} (limit {})",
+                function.name, self.max_call_depth
+            ));
+            return Value::Uninitialized;
+        }
+
+        let started = self.profiler.is_some().then(Instant::now);
+        self.frames.push(locals);
+        if self.debugger.is_some() {
+            self.call_stack.push(Call {
+                function: function.name.to_string(),
+                offset: self.statement_offset,
+            });
+        }
+        let saved_return_value = self.return_value.take();
+        let output = with_stack(|| self.run_code(compiled, &function.code));
+        if let Some(mut frame) = self.frames.pop() {
+            frame.clear();
+            self.spare_frames.push(frame);
+        }
+        if let Some(call) = self.call_stack.pop() {
+            // Back in the statement that made the call.
+            self.statement_offset = call.offset;
+        }
+        let return_value = self.return_value.take().unwrap_or_default();
+        self.return_value = saved_return_value;
+        self.expression_output.extend(output);
+        if let (Some(profiler), Some(started)) = (&mut self.profiler, started) {
+            profiler.add_function(index, started.elapsed());
+        }
+        return_value
+    }
+
+    /// Take the operand of `place` off the stack.
+    fn target(&mut self, place: Place) -> Target {
+        match place {
+            Place::Global(slot) => Target::Global(slot),
+            Place::Local(index) => Target::Local(index),
+            Place::Special(special) => Target::Special(special),
+            Place::Field => Target::Field(self.pop_number() as i64),
+            Place::Element(array) => {
+                let key = self.pop_text();
+                Target::Element(self.array_of(array), key)
+            }
+        }
+    }
+
+    fn load(&mut self, target: &Target) -> Value {
+        match target {
+            Target::Global(slot) => self.globals[*slot].value.clone().unwrap_or_default(),
+            Target::Local(index) => self.local(*index).value.clone(),
+            Target::Special(special) => self.load_special(*special),
+            Target::Field(index) => self.field_value(*index),
+            Target::Element(array, key) => array.borrow().get(key).cloned().unwrap_or_default(),
+        }
+    }
+
+    fn store(&mut self, target: Target, value: Value) {
+        match target {
+            Target::Global(slot) => self.globals[slot].value = Some(value),
+            Target::Local(index) => self.local(index).value = value,
+            Target::Special(special) => self.store_special(special, value),
+            Target::Field(index) => {
+                let value = value.into_string_with(&self.conversion_format);
+                self.set_field(index, value);
+            }
+            Target::Element(array, key) => {
+                array.borrow_mut().insert(key, value);
+            }
+        }
+    }
+
+    /// The value of a global or parameter, for assigning it.
+    fn variable(&mut self, place: Place) -> &mut Value {
+        match place {
+            Place::Global(slot) => self.globals[slot].value.get_or_insert_default(),
+            Place::Local(index) => &mut self.local(index).value,
+            _ => unreachable!("only variables are assigned in place"),
+        }
+    }
+
+    /// Resolve `array`, creating it on first use.
+    fn array_of(&mut self, array: ArrayRef) -> Array {
+        let array = match array {
+            ArrayRef::Global(slot) => &mut self.globals[slot].array,
+            ArrayRef::Local(index) => &mut self.local(index).array,
+        };
+        array.get_or_insert_with(Array::default).clone()
+    }
+
+    /// Resolve `array` without creating it.
+    fn find_array_of(&mut self, array: ArrayRef) -> Option<Array> {
+        match array {
+            ArrayRef::Global(slot) => self.globals[slot].array.clone(),
+            ArrayRef::Local(index) => self.local(index).array.clone(),
+        }
+    }
+}
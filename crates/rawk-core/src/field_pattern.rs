@@ -0,0 +1,103 @@
+use std::cell::RefCell;
+
+use regex::Regex;
+use regex_automata::{Anchored, Input, MatchKind, hybrid};
+
+/// A compiled `FPAT` pattern, which describes what a field looks like rather
+/// than what separates fields.
+///
+/// Like every AWK regular expression it picks the leftmost-longest match, so
+/// `([^ ]+)|("[^"]+")` takes a whole quoted string rather than its first word.
+pub(crate) struct FieldPattern {
+    /// Finds where the next match starts.
+    start: Regex,
+    /// Finds where the longest match from a given start ends.
+    longest: hybrid::dfa::DFA,
+    cache: RefCell<hybrid::dfa::Cache>,
+}
+
+impl FieldPattern {
+    /// Compile `pattern`, returning `None` when it is not a valid regex.
+    pub(crate) fn new(pattern: &str) -> Option<Self> {
+        let start = Regex::new(pattern).ok()?;
+        let longest = hybrid::dfa::DFA::builder()
+            .configure(hybrid::dfa::DFA::config().match_kind(MatchKind::All))
+            .build(pattern)
+            .ok()?;
+        let cache = RefCell::new(longest.create_cache());
+        Some(Self {
+            start,
+            longest,
+            cache,
+        })
+    }
+
+    /// Collect the matches in `text` as fields, along with the separators
+    /// around them: the text before the first field, then the text after
+    /// each field. An empty match right after a field is not a field.
+    pub(crate) fn split(&self, text: &str) -> (Vec<String>, Vec<String>) {
+        let mut fields = Vec::new();
+        let mut separators = Vec::new();
+        let mut last_end = 0;
+        let mut position = 0;
+        let mut after_field = false;
+
+        while let Some(matched) = self.start.find_at(text, position) {
+            let start = matched.start();
+            let end = self.longest_end(text, start).unwrap_or(matched.end());
+            if start == end && after_field && start == last_end {
+                after_field = false;
+            } else {
+                separators.push(text[last_end..start].to_string());
+                fields.push(text[start..end].to_string());
+                last_end = end;
+                after_field = start != end;
+            }
+
+            if end > start {
+                position = end;
+            } else {
+                match text[start..].chars().next() {
+                    Some(ch) => position = start + ch.len_utf8(),
+                    None => break,
+                }
+            }
+        }
+        separators.push(text[last_end..].to_string());
+        (fields, separators)
+    }
+
+    fn longest_end(&self, text: &str, start: usize) -> Option<usize> {
+        let input = Input::new(text).range(start..).anchored(Anchored::Yes);
+        let mut cache = self.cache.borrow_mut();
+        self.longest
+            .try_search_fwd(&mut cache, &input)
+            .ok()
+            .flatten()
+            .map(|matched| matched.offset())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_prefers_the_longest_alternative() {
+        let pattern = FieldPattern::new(r#"([^ ]+)|("[^"]+")"#).unwrap();
+
+        let (fields, separators) = pattern.split(r#"GET "Mozilla/5.0 (X11)" 200"#);
+
+        assert_eq!(fields, vec!["GET", r#""Mozilla/5.0 (X11)""#, "200"]);
+        assert_eq!(separators, vec!["", " ", " ", ""]);
+    }
+
+    #[test]
+    fn split_keeps_empty_fields_between_separators() {
+        let pattern = FieldPattern::new(r#"([^,]*)|("[^"]+")"#).unwrap();
+
+        let (fields, _) = pattern.split(r#"a,,"b,c",d"#);
+
+        assert_eq!(fields, vec!["a", "", r#""b,c""#, "d"]);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::CharMode;
+
+/// One entry of `FIELDWIDTHS`: characters to skip, then the field width, or
+/// `None` for the rest of the record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Column {
+    skip: usize,
+    width: Option<usize>,
+}
+
+/// A parsed `FIELDWIDTHS` value, which splits records into fixed columns
+/// instead of by `FS`.
+///
+/// The value is a space-separated list of widths. Each width may be prefixed
+/// with `skip:` to ignore that many characters before the field, and the last
+/// entry may be `*` to take the rest of the record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FieldWidths {
+    columns: Vec<Column>,
+}
+
+impl FieldWidths {
+    /// Parse a `FIELDWIDTHS` value, returning `None` when it is malformed.
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let entries: Vec<&str> = value.split_whitespace().collect();
+        let mut columns = Vec::with_capacity(entries.len());
+        for (index, entry) in entries.iter().enumerate() {
+            let (skip, width) = match entry.split_once(':') {
+                Some((skip, width)) => (skip.parse().ok()?, width),
+                None => (0, *entry),
+            };
+            let width = if width == "*" {
+                if index + 1 != entries.len() {
+                    return None;
+                }
+                None
+            } else {
+                Some(width.parse().ok()?)
+            };
+            columns.push(Column { skip, width });
+        }
+        Some(Self { columns })
+    }
+
+    /// Cut `record` into columns, counting characters in `char_mode`. A
+    /// column that starts past the end of the record ends the list, and one
+    /// that runs past it is truncated.
+    pub(crate) fn split(&self, record: &str, char_mode: CharMode) -> Vec<String> {
+        let length = char_mode.len(record);
+        let mut position = 0;
+        let mut fields = Vec::new();
+        for column in &self.columns {
+            position += column.skip;
+            if position >= length {
+                break;
+            }
+            let width = column.width.unwrap_or(length - position);
+            fields.push(char_mode.slice(record, position, width));
+            position += width;
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accepts_skips_and_trailing_star() {
+        let widths = FieldWidths::parse(" 3 2:4 * ").unwrap();
+
+        assert_eq!(
+            widths.split("abcXXdefgrest of line", CharMode::Utf8),
+            vec!["abc", "defg", "rest of line"]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_malformed_values() {
+        assert_eq!(FieldWidths::parse("3 x 4"), None);
+        assert_eq!(FieldWidths::parse("* 3"), None);
+        assert_eq!(FieldWidths::parse("-1"), None);
+    }
+
+    #[test]
+    fn split_truncates_short_records() {
+        let widths = FieldWidths::parse("2 3 4").unwrap();
+
+        assert_eq!(widths.split("ÅÄÖab", CharMode::Utf8), vec!["ÅÄ", "Öab"]);
+        assert_eq!(widths.split("", CharMode::Utf8), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,1022 @@
+//! Canonical formatting of AWK source, used by `--pretty-print`.
+
+use crate::{
+    Lexer, Parser,
+    ast::{Action, Expression, Rule, Statement},
+    lexer::Comment,
+    parse_error::ParseError,
+    parser::{
+        CONCAT_LEFT_PRECEDENCE, CONCAT_RIGHT_PRECEDENCE, Item, ItemKind, infix_operator_precedence,
+    },
+    token::{TokenKind, lookup_functions, lookup_keyword},
+};
+
+const INDENT: &str = "    ";
+
+/// Built-in functions that may start an expression statement.
+const EXPRESSION_STATEMENT_BUILTINS: &[&str] = &[
+    "atan2", "close", "cos", "exp", "index", "int", "length", "log", "match", "rand", "sin",
+    "sprintf", "sqrt", "srand", "substr", "tolower", "toupper",
+];
+
+/// Format an AWK program canonically: one statement per line, bodies
+/// indented by four spaces and always braced, one blank line between
+/// top-level rules and functions, and operators surrounded by spaces.
+///
+/// Comments are kept. A comment at the end of a line stays at the end of the
+/// corresponding output line; one on a line of its own is placed before the
+/// statement or rule that follows it, or before the closing brace of its
+/// block, and one before an `else` stays in the body it follows. Single
+/// blank lines between statements are preserved. Parentheses are added only where needed, so the
+/// output parses back into the same syntax tree as `source`.
+///
+/// # Examples
+///
+/// ```
+/// use rawk_core::format_program;
+///
+/// let formatted = format_program("# count\n{n++}END{print n # total\n}").unwrap();
+/// assert_eq!(
+///     formatted,
+///     "# count\n{\n    n++\n}\n\nEND {\n    print n # total\n}\n"
+/// );
+/// ```
+pub fn format_program(source: &str) -> Result<String, ParseError<'_>> {
+    let mut parser = Parser::new(Lexer::new(source));
+    let items = parser.try_parse_items()?;
+
+    let mut formatter = Formatter {
+        source,
+        comments: parser.comments(),
+        next_comment: 0,
+        statement_starts: parser.statement_starts(),
+        next_statement: 0,
+        body_ends: parser.body_ends(),
+        next_body_end: 0,
+        last_offset: 0,
+        lines: Vec::new(),
+        depth: 0,
+    };
+    for item in &items {
+        formatter.item(item);
+    }
+    formatter.comments_before(usize::MAX);
+
+    let mut output = formatter.lines.join("\n");
+    output.push('\n');
+    Ok(output)
+}
+
+/// Where an expression is printed, mirroring the parser state it will be
+/// read back in.
+#[derive(Clone, Copy)]
+struct Context {
+    /// The minimum binding power the parser accepts at this position.
+    min: u8,
+    /// The left binding power of the operator that follows, if any. An
+    /// expression whose right operand would swallow it needs parentheses.
+    follow: Option<u8>,
+    /// Whether a `/` here starts a regular expression.
+    regex: bool,
+    /// Whether a `>` here would redirect `print` output.
+    output_list: bool,
+}
+
+impl Context {
+    const TOP: Context = Context {
+        min: 0,
+        follow: None,
+        regex: true,
+        output_list: false,
+    };
+
+    fn without_regex(self) -> Self {
+        Context {
+            regex: false,
+            ..self
+        }
+    }
+}
+
+struct Formatter<'a, 's> {
+    source: &'a str,
+    comments: &'s [Comment<'a>],
+    next_comment: usize,
+    statement_starts: &'s [usize],
+    next_statement: usize,
+    body_ends: &'s [usize],
+    next_body_end: usize,
+    /// The source offset of the last statement, rule or comment written.
+    last_offset: usize,
+    lines: Vec<String>,
+    depth: usize,
+}
+
+impl<'a> Formatter<'a, '_> {
+    fn line(&mut self, text: String) {
+        if text.is_empty() {
+            self.lines.push(text);
+        } else {
+            self.lines
+                .push(format!("{}{text}", INDENT.repeat(self.depth)));
+        }
+    }
+
+    /// Write the comments that start before `offset`, either appended to the
+    /// last line or on lines of their own.
+    fn comments_before(&mut self, offset: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment).copied() {
+            if comment.start >= offset {
+                break;
+            }
+            self.next_comment += 1;
+            if self.is_trailing(comment) && !self.lines.is_empty() {
+                let last = self.lines.last_mut().expect("lines is not empty");
+                last.push(' ');
+                last.push_str(comment.text);
+            } else {
+                self.blank_line_before(comment.start);
+                self.line(comment.text.to_string());
+            }
+            self.last_offset = comment.start;
+        }
+    }
+
+    /// Write the comments before `offset` that are on lines of their own,
+    /// up to the first one that ends a line of code.
+    fn own_line_comments_before(&mut self, offset: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment).copied() {
+            if comment.start >= offset || self.is_trailing(comment) {
+                break;
+            }
+            self.comments_before(comment.start + 1);
+        }
+    }
+
+    /// Append the comments before `offset` that end a line of code.
+    fn trailing_comments_before(&mut self, offset: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment).copied() {
+            if comment.start >= offset || !self.is_trailing(comment) || self.lines.is_empty() {
+                break;
+            }
+            self.comments_before(comment.start + 1);
+        }
+    }
+
+    fn is_trailing(&self, comment: Comment<'_>) -> bool {
+        let line_start = self.source[..comment.start]
+            .rfind('\n')
+            .map_or(0, |index| index + 1);
+        !self.source[line_start..comment.start].trim().is_empty()
+    }
+
+    /// Keep a blank line the source has between the last thing written and
+    /// `offset`, except at the start of a block.
+    fn blank_line_before(&mut self, offset: usize) {
+        let Some(last) = self.lines.last() else {
+            return;
+        };
+        if last.is_empty() || last.ends_with('{') {
+            return;
+        }
+        let between = &self.source[self.last_offset.min(offset)..offset];
+        let segments: Vec<&str> = between.split('\n').collect();
+        if segments.len() > 2
+            && segments[1..segments.len() - 1]
+                .iter()
+                .any(|segment| segment.trim().is_empty())
+        {
+            self.lines.push(String::new());
+        }
+    }
+
+    fn item(&mut self, item: &Item<'a>) {
+        self.trailing_comments_before(item.start);
+        if !self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+        self.comments_before(item.start);
+        self.blank_line_before(item.start);
+        self.last_offset = item.start;
+
+        match &item.kind {
+            ItemKind::Function(definition) => {
+                let header = format!(
+                    "function {}({})",
+                    definition.name,
+                    definition.parameters.join(", ")
+                );
+                self.block(header, &definition.statements, item.end);
+            }
+            ItemKind::Rule(rule) => match rule {
+                Rule::Begin(action) => self.action("BEGIN".to_string(), action, item.end),
+                Rule::BeginFile(action) => self.action("BEGINFILE".to_string(), action, item.end),
+                Rule::EndFile(action) => self.action("ENDFILE".to_string(), action, item.end),
+                Rule::End(action) => self.action("END".to_string(), action, item.end),
+                Rule::Action(action) => self.action(String::new(), action, item.end),
+                Rule::PatternAction { pattern, action } => {
+                    let pattern = pattern.as_ref().map(pattern_text).unwrap_or_default();
+                    match action {
+                        Some(action) => self.action(pattern, action, item.end),
+                        None => {
+                            self.line(pattern);
+                            self.comments_before(item.end);
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    /// The start of the next unwritten comment, or the end of the source.
+    fn comment_start(&self) -> usize {
+        self.comments
+            .get(self.next_comment)
+            .map_or(self.source.len(), |comment| comment.start)
+    }
+
+    fn action(&mut self, header: String, action: &Action<'a>, end: usize) {
+        if action.statements.is_empty() && self.comment_start() >= end {
+            self.line(join_header(header, "{}"));
+        } else {
+            self.block(header, &action.statements, end);
+        }
+    }
+
+    /// Write a braced top-level body, placing any comments before its closing
+    /// brace at `end` inside it.
+    fn block(&mut self, header: String, statements: &[Statement<'a>], end: usize) {
+        self.line(join_header(header, "{"));
+        self.depth += 1;
+        self.statements(statements);
+        self.comments_before(end);
+        self.depth -= 1;
+        self.line("}".to_string());
+    }
+
+    fn statements(&mut self, statements: &[Statement<'a>]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    /// Account for a statement the parser recorded, writing the comments
+    /// before it.
+    fn enter_statement(&mut self) {
+        let Some(start) = self.next_statement_start() else {
+            return;
+        };
+        self.comments_before(start);
+        self.blank_line_before(start);
+        self.last_offset = start;
+    }
+
+    fn next_statement_start(&mut self) -> Option<usize> {
+        let start = self.statement_starts.get(self.next_statement).copied()?;
+        self.next_statement += 1;
+        Some(start)
+    }
+
+    /// The offset where the parser saw the next control statement body end,
+    /// or the next `else` start.
+    fn next_body_end(&mut self) -> usize {
+        let Some(end) = self.body_ends.get(self.next_body_end).copied() else {
+            return 0;
+        };
+        self.next_body_end += 1;
+        end
+    }
+
+    /// Account for the end of a control statement body, writing the
+    /// comments before it at the current depth.
+    fn leave_body(&mut self) {
+        let end = self.next_body_end();
+        self.comments_before(end);
+    }
+
+    fn statement(&mut self, statement: &Statement<'a>) {
+        if *statement == Statement::Empty {
+            return;
+        }
+        self.enter_statement();
+        match statement {
+            Statement::If {
+                condition,
+                then_statements,
+            } => self.if_statement(String::new(), condition, then_statements, None),
+            Statement::IfElse {
+                condition,
+                then_statements,
+                else_statements,
+            } => self.if_statement(
+                String::new(),
+                condition,
+                then_statements,
+                Some(else_statements),
+            ),
+            Statement::While {
+                condition,
+                statements,
+            } => {
+                let header = format!("while ({})", expression(condition, Context::TOP));
+                self.body(header, statements);
+            }
+            Statement::DoWhile {
+                condition,
+                statements,
+            } => {
+                let footer = format!("while ({})", expression(condition, Context::TOP));
+                if is_empty_body(statements) {
+                    self.line("do ;".to_string());
+                    self.leave_body();
+                    self.line(footer);
+                } else {
+                    self.line("do {".to_string());
+                    self.indented(statements);
+                    self.line(format!("}} {footer}"));
+                }
+            }
+            Statement::For {
+                init,
+                condition,
+                update,
+                statements,
+            } => {
+                let mut header = format!("for ({};", simple_statement(init));
+                if *condition != Expression::Number(1.0) {
+                    header.push(' ');
+                    header.push_str(&expression(condition, Context::TOP));
+                }
+                header.push(';');
+                if **update != Statement::Empty {
+                    header.push(' ');
+                    header.push_str(&simple_statement(update));
+                }
+                header.push(')');
+                self.body(header, statements);
+            }
+            Statement::ForIn {
+                variable,
+                array,
+                statements,
+            } => self.body(format!("for ({variable} in {array})"), statements),
+            simple => {
+                let text = simple_statement(simple);
+                self.line(text);
+            }
+        }
+    }
+
+    fn if_statement(
+        &mut self,
+        prefix: String,
+        condition: &Expression<'a>,
+        then_statements: &[Statement<'a>],
+        else_statements: Option<&Vec<Statement<'a>>>,
+    ) {
+        let header = format!("{prefix}if ({})", expression(condition, Context::TOP));
+        let Some(else_statements) = else_statements else {
+            self.body(header, then_statements);
+            return;
+        };
+
+        // Comments between the then body and `else` stay with the then body,
+        // except one after its closing brace, which ends the `} else` line.
+        let else_prefix = if is_empty_body(then_statements) {
+            self.line(format!("{header} ;"));
+            self.leave_body();
+            let else_start = self.next_body_end();
+            self.comments_before(else_start);
+            "else "
+        } else {
+            self.line(format!("{header} {{"));
+            self.depth += 1;
+            self.statements(then_statements);
+            self.leave_body();
+            let else_start = self.next_body_end();
+            self.own_line_comments_before(else_start);
+            self.depth -= 1;
+            "} else "
+        };
+
+        match else_statements.as_slice() {
+            [
+                Statement::If {
+                    condition,
+                    then_statements,
+                },
+            ] => {
+                self.next_statement_start();
+                self.if_statement(else_prefix.to_string(), condition, then_statements, None);
+                self.leave_body();
+            }
+            [
+                Statement::IfElse {
+                    condition,
+                    then_statements,
+                    else_statements,
+                },
+            ] => {
+                self.next_statement_start();
+                self.if_statement(
+                    else_prefix.to_string(),
+                    condition,
+                    then_statements,
+                    Some(else_statements),
+                );
+                self.leave_body();
+            }
+            _ => self.body(else_prefix.trim_end().to_string(), else_statements),
+        }
+    }
+
+    /// Write the body of a control statement after `header`: a lone `;` for
+    /// an empty body, otherwise a braced block.
+    fn body(&mut self, header: String, statements: &[Statement<'a>]) {
+        if is_empty_body(statements) {
+            self.line(format!("{header} ;"));
+            self.leave_body();
+        } else {
+            self.line(join_header(header, "{"));
+            self.indented(statements);
+            self.line("}".to_string());
+        }
+    }
+
+    fn indented(&mut self, statements: &[Statement<'a>]) {
+        self.depth += 1;
+        self.statements(statements);
+        self.leave_body();
+        self.depth -= 1;
+    }
+}
+
+fn join_header(header: String, brace: &str) -> String {
+    if header.is_empty() {
+        brace.to_string()
+    } else {
+        format!("{header} {brace}")
+    }
+}
+
+fn is_empty_body(statements: &[Statement<'_>]) -> bool {
+    matches!(statements, [Statement::Empty])
+}
+
+pub(crate) fn pattern_text(pattern: &Expression<'_>) -> String {
+    match pattern {
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } if operator.kind == TokenKind::Comma => {
+            format!(
+                "{}, {}",
+                pattern_start(left),
+                expression(right, Context::TOP)
+            )
+        }
+        pattern => pattern_start(pattern),
+    }
+}
+
+/// A pattern at the start of a rule, where a leading `-` is not accepted.
+fn pattern_start(pattern: &Expression<'_>) -> String {
+    let text = expression(pattern, Context::TOP);
+    if text.starts_with('-') {
+        format!("({text})")
+    } else {
+        text
+    }
+}
+
+/// Format a statement that fits on one line.
+fn simple_statement(statement: &Statement<'_>) -> String {
+    match statement {
+        Statement::Empty => String::new(),
+        Statement::Expression(value) => expression_statement(value),
+        Statement::Print(expressions) => output_statement("print", expressions, None),
+        Statement::PrintRedirect {
+            expressions,
+            target,
+            append,
+        } => output_statement("print", expressions, Some((redirect(*append), target))),
+        Statement::PrintPipe {
+            expressions,
+            target,
+        } => output_statement("print", expressions, Some(("|", target))),
+        Statement::Printf(expressions) => output_statement("printf", expressions, None),
+        Statement::PrintfRedirect {
+            expressions,
+            target,
+            append,
+        } => output_statement("printf", expressions, Some((redirect(*append), target))),
+        Statement::PrintfPipe {
+            expressions,
+            target,
+        } => output_statement("printf", expressions, Some(("|", target))),
+        Statement::System(command) => format!(
+            "system({})",
+            expression(command, Context::TOP.without_regex())
+        ),
+        Statement::Split {
+            string,
+            array,
+            separator,
+        } => split_call(string, array, separator.as_ref()),
+        Statement::Sub {
+            pattern,
+            replacement,
+            target,
+        } => substitution("sub", pattern, replacement, target.as_ref()),
+        Statement::Gsub {
+            pattern,
+            replacement,
+            target,
+        } => substitution("gsub", pattern, replacement, target.as_ref()),
+        Statement::Assignment { identifier, value } => match value {
+            Expression::Infix {
+                left,
+                operator,
+                right,
+            } if matches!(left.as_ref(), Expression::Identifier(name) if name == identifier)
+                && matches!(
+                    operator.kind,
+                    TokenKind::Minus
+                        | TokenKind::Asterisk
+                        | TokenKind::Division
+                        | TokenKind::Percent
+                        | TokenKind::Caret
+                ) =>
+            {
+                format!(
+                    "{identifier} {}= {}",
+                    operator.literal,
+                    expression(right, Context::TOP)
+                )
+            }
+            value => {
+                let mut value_text = expression(value, Context::TOP);
+                if leading_word(&value_text) == "split" {
+                    value_text = format!("({value_text})");
+                }
+                format!("{identifier} = {value_text}")
+            }
+        },
+        Statement::SplitAssignment {
+            identifier,
+            string,
+            array,
+            separator,
+        } => format!(
+            "{identifier} = {}",
+            split_call(string, array, separator.as_ref())
+        ),
+        Statement::ArrayAssignment {
+            identifier,
+            index,
+            value,
+        } => format!(
+            "{identifier}[{}] = {}",
+            list(index),
+            expression(value, Context::TOP)
+        ),
+        Statement::FieldAssignment { field, value } => {
+            let field_text = format!("${}", operand(field, true, false));
+            match value {
+                Expression::Infix {
+                    left,
+                    operator,
+                    right,
+                } if **left == Expression::Field(Box::new(field.clone()))
+                    && matches!(
+                        operator.kind,
+                        TokenKind::Plus
+                            | TokenKind::Minus
+                            | TokenKind::Asterisk
+                            | TokenKind::Division
+                            | TokenKind::Percent
+                            | TokenKind::Caret
+                    ) =>
+                {
+                    format!(
+                        "{field_text} {}= {}",
+                        operator.literal,
+                        expression(right, Context::TOP)
+                    )
+                }
+                value => format!("{field_text} = {}", expression(value, Context::TOP)),
+            }
+        }
+        Statement::AddAssignment { identifier, value } => {
+            format!("{identifier} += {}", expression(value, Context::TOP))
+        }
+        Statement::ArrayAddAssignment {
+            identifier,
+            index,
+            value,
+        } => format!(
+            "{identifier}[{}] += {}",
+            list(index),
+            expression(value, Context::TOP)
+        ),
+        Statement::ArrayPostIncrement { identifier, index } => {
+            format!("{identifier}[{}]++", list(index))
+        }
+        Statement::ArrayPostDecrement { identifier, index } => {
+            format!("{identifier}[{}]--", list(index))
+        }
+        Statement::Delete { identifier, index } => match index {
+            Some(index) => format!("delete {identifier}[{}]", list(index)),
+            None => format!("delete {identifier}"),
+        },
+        Statement::PreIncrement { identifier } => format!("++{identifier}"),
+        Statement::PreDecrement { identifier } => format!("--{identifier}"),
+        Statement::PostIncrement { identifier } => format!("{identifier}++"),
+        Statement::PostDecrement { identifier } => format!("{identifier}--"),
+        Statement::Break => "break".to_string(),
+        Statement::Continue => "continue".to_string(),
+        Statement::Next => "next".to_string(),
+        Statement::NextFile => "nextfile".to_string(),
+        Statement::Return(value) => keyword_with_value("return", value.as_ref()),
+        Statement::Exit(status) => keyword_with_value("exit", status.as_ref()),
+        Statement::If { .. }
+        | Statement::IfElse { .. }
+        | Statement::While { .. }
+        | Statement::DoWhile { .. }
+        | Statement::For { .. }
+        | Statement::ForIn { .. } => {
+            unreachable!("control statements are written by the formatter")
+        }
+    }
+}
+
+/// An expression used as a statement, parenthesized when its first token
+/// would make the parser read a different kind of statement.
+fn expression_statement(value: &Expression<'_>) -> String {
+    let text = expression(value, Context::TOP);
+    let word = leading_word(&text);
+    let accepted = if word.is_empty() {
+        text.starts_with(|ch: char| ch.is_ascii_digit() || matches!(ch, '.' | '"' | '/' | '('))
+    } else if EXPRESSION_STATEMENT_BUILTINS.contains(&word) {
+        true
+    } else {
+        matches!(value, Expression::FunctionCall { .. })
+            && lookup_keyword(word).is_none()
+            && lookup_functions(word).is_none()
+    };
+    if accepted { text } else { format!("({text})") }
+}
+
+fn leading_word(text: &str) -> &str {
+    let end = text
+        .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+        .unwrap_or(text.len());
+    if text.starts_with(|ch: char| ch.is_ascii_digit()) {
+        ""
+    } else {
+        &text[..end]
+    }
+}
+
+fn redirect(append: bool) -> &'static str {
+    if append { ">>" } else { ">" }
+}
+
+fn keyword_with_value(keyword: &str, value: Option<&Expression<'_>>) -> String {
+    match value {
+        Some(value) => format!(
+            "{keyword} {}",
+            expression(value, Context::TOP.without_regex())
+        ),
+        None => keyword.to_string(),
+    }
+}
+
+/// Format `print` or `printf` with its arguments and output target.
+fn output_statement(
+    keyword: &str,
+    expressions: &[Expression<'_>],
+    target: Option<(&str, &Expression<'_>)>,
+) -> String {
+    let context = Context {
+        output_list: true,
+        ..Context::TOP.without_regex()
+    };
+    let mut arguments: Vec<String> = expressions
+        .iter()
+        .map(|argument| expression(argument, context))
+        .collect();
+
+    let mut text = keyword.to_string();
+    // A `printf` whose first argument starts with `(` would be read as the
+    // parenthesized form, so use that form explicitly.
+    if keyword == "printf"
+        && arguments
+            .first()
+            .is_some_and(|first| first.starts_with('('))
+    {
+        arguments = expressions
+            .iter()
+            .enumerate()
+            .map(|(index, argument)| {
+                let context = Context {
+                    regex: index == 0,
+                    ..Context::TOP
+                };
+                expression(argument, context)
+            })
+            .collect();
+        text.push_str(&format!("({})", arguments.join(", ")));
+    } else if !arguments.is_empty() {
+        text.push(' ');
+        text.push_str(&arguments.join(", "));
+    }
+
+    if let Some((operator, target)) = target {
+        text.push_str(&format!(
+            " {operator} {}",
+            expression(target, Context::TOP.without_regex())
+        ));
+    }
+    text
+}
+
+fn split_call(string: &Expression<'_>, array: &str, separator: Option<&Expression<'_>>) -> String {
+    let mut text = format!("split({}, {array}", expression(string, Context::TOP));
+    if let Some(separator) = separator {
+        text.push_str(&format!(", {}", expression(separator, Context::TOP)));
+    }
+    text.push(')');
+    text
+}
+
+fn substitution(
+    name: &str,
+    pattern: &Expression<'_>,
+    replacement: &Expression<'_>,
+    target: Option<&Expression<'_>>,
+) -> String {
+    let mut text = format!(
+        "{name}({}, {}",
+        expression(pattern, Context::TOP),
+        expression(replacement, Context::TOP.without_regex())
+    );
+    if let Some(target) = target {
+        text.push_str(&format!(
+            ", {}",
+            expression(target, Context::TOP.without_regex())
+        ));
+    }
+    text.push(')');
+    text
+}
+
+/// Format a comma-separated list, as in an array subscript.
+fn list(expression_list: &Expression<'_>) -> String {
+    match expression_list {
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } if operator.kind == TokenKind::Comma => {
+            format!("{}, {}", list(left), expression(right, Context::TOP))
+        }
+        single => expression(single, Context::TOP),
+    }
+}
+
+/// The left and right binding powers of an operator expression, or `None`
+/// for a primary expression.
+fn binding(expression: &Expression<'_>) -> Option<(u8, u8)> {
+    match expression {
+        Expression::Infix { operator, .. } if operator.kind == TokenKind::Comma => Some((0, 0)),
+        Expression::Infix { left, operator, .. } if is_unary(left, &operator.kind) => None,
+        Expression::Infix { operator, .. } => infix_operator_precedence(&operator.kind),
+        Expression::Concatenation { .. } => Some((CONCAT_LEFT_PRECEDENCE, CONCAT_RIGHT_PRECEDENCE)),
+        Expression::Ternary { .. } => Some((0, 0)),
+        _ => None,
+    }
+}
+
+/// The parser reads `-x` as `0 - x` and `+x` as `0 + x`, so that is how
+/// they are written back.
+fn is_unary(left: &Expression<'_>, operator: &TokenKind) -> bool {
+    matches!(operator, TokenKind::Minus | TokenKind::Plus) && *left == Expression::Number(0.0)
+}
+
+fn is_atom(expression: &Expression<'_>) -> bool {
+    matches!(
+        expression,
+        Expression::Number(_)
+            | Expression::HexNumber { .. }
+            | Expression::String(_)
+            | Expression::Regex(_)
+            | Expression::Field(_)
+            | Expression::Identifier(_)
+            | Expression::ArrayAccess { .. }
+            | Expression::Length(_)
+            | Expression::Substr { .. }
+            | Expression::Rand
+            | Expression::FunctionCall { .. }
+    )
+}
+
+/// Format the operand of `$`, a unary operator or `++`/`--`: an atom, or any
+/// primary expression when `allow_unary` is set, and otherwise a
+/// parenthesized expression.
+fn operand(value: &Expression<'_>, allow_unary: bool, regex: bool) -> String {
+    let fits = if allow_unary {
+        binding(value).is_none()
+    } else {
+        is_atom(value)
+    };
+    if fits {
+        let text = expression(
+            value,
+            Context {
+                min: u8::MAX,
+                follow: None,
+                regex,
+                output_list: false,
+            },
+        );
+        if text.starts_with('/') && !regex {
+            format!("({text})")
+        } else {
+            text
+        }
+    } else {
+        format!("({})", expression(value, Context::TOP))
+    }
+}
+
+fn expression(value: &Expression<'_>, context: Context) -> String {
+    let needs_parens = match (value, binding(value)) {
+        (Expression::Infix { operator, .. }, Some(_)) if operator.kind == TokenKind::Comma => true,
+        (Expression::Infix { operator, .. }, Some(_))
+            if context.output_list && operator.kind == TokenKind::GreaterThan =>
+        {
+            true
+        }
+        (Expression::Ternary { .. }, _) => context.min > 0 || context.follow.is_some(),
+        (_, Some((left, right))) => {
+            left < context.min || context.follow.is_some_and(|follow| right <= follow)
+        }
+        (_, None) => false,
+    };
+    if needs_parens {
+        let inner = match value {
+            Expression::Infix { operator, .. } if operator.kind == TokenKind::Comma => list(value),
+            _ => expression(value, Context::TOP),
+        };
+        return format!("({inner})");
+    }
+
+    let text = unparenthesized(value, context);
+    if !context.regex && text.starts_with('/') {
+        format!("({text})")
+    } else {
+        text
+    }
+}
+
+fn unparenthesized(value: &Expression<'_>, context: Context) -> String {
+    match value {
+        Expression::Number(number) => number.to_string(),
+        Expression::HexNumber { literal, .. } => literal.to_string(),
+        Expression::String(text) => format!("\"{text}\""),
+        Expression::Regex(pattern) => format!("/{pattern}/"),
+        Expression::Field(index) => format!("${}", operand(index, false, false)),
+        Expression::Identifier(name) => name.to_string(),
+        Expression::ArrayAccess { identifier, index } => format!("{identifier}[{}]", list(index)),
+        Expression::Length(None) => "length()".to_string(),
+        Expression::Length(Some(argument)) => {
+            format!(
+                "length({})",
+                expression(argument, Context::TOP.without_regex())
+            )
+        }
+        Expression::Substr {
+            string,
+            start,
+            length,
+        } => {
+            let mut text = format!(
+                "substr({}, {}",
+                expression(string, Context::TOP.without_regex()),
+                expression(start, Context::TOP.without_regex())
+            );
+            if let Some(length) = length {
+                text.push_str(&format!(
+                    ", {}",
+                    expression(length, Context::TOP.without_regex())
+                ));
+            }
+            text.push(')');
+            text
+        }
+        Expression::Rand => "rand()".to_string(),
+        Expression::FunctionCall { name, args } => format!(
+            "{name}({})",
+            args.iter()
+                .map(|argument| expression(argument, Context::TOP))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        Expression::Not(inner) => format!("!{}", operand(inner, true, true)),
+        Expression::PreIncrement(inner) => format!("++{}", operand(inner, true, false)),
+        Expression::PreDecrement(inner) => format!("--{}", operand(inner, true, false)),
+        Expression::PostIncrement(inner) => format!("{}++", operand(inner, false, false)),
+        Expression::PostDecrement(inner) => format!("{}--", operand(inner, false, false)),
+        Expression::Ternary {
+            condition,
+            then_expr,
+            else_expr,
+        } => {
+            let branch = Context {
+                follow: None,
+                ..context
+            };
+            format!(
+                "{} ? {} : {}",
+                expression(
+                    condition,
+                    Context {
+                        follow: Some(0),
+                        ..context
+                    }
+                ),
+                expression(
+                    then_expr,
+                    Context {
+                        regex: true,
+                        ..branch
+                    }
+                ),
+                expression(
+                    else_expr,
+                    Context {
+                        regex: true,
+                        ..context
+                    }
+                )
+            )
+        }
+        Expression::Concatenation { left, right } => {
+            let left_text = expression(
+                left,
+                Context {
+                    follow: Some(CONCAT_LEFT_PRECEDENCE),
+                    ..context
+                },
+            );
+            let right_context = Context {
+                min: CONCAT_RIGHT_PRECEDENCE,
+                regex: false,
+                ..context
+            };
+            let mut right_text = expression(right, right_context);
+            if right_text.starts_with(['-', '+', '!']) {
+                right_text = format!("({right_text})");
+            }
+            format!("{left_text} {right_text}")
+        }
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            if is_unary(left, &operator.kind) {
+                let sign = operator.literal;
+                let text = operand(right, true, false);
+                return if text.starts_with(sign) {
+                    format!("{sign} {text}")
+                } else {
+                    format!("{sign}{text}")
+                };
+            }
+            let (left_power, right_power) =
+                infix_operator_precedence(&operator.kind).unwrap_or((0, 0));
+            let left_text = expression(
+                left,
+                Context {
+                    follow: Some(left_power),
+                    ..context
+                },
+            );
+            let right_text = expression(
+                right,
+                Context {
+                    min: right_power,
+                    regex: matches!(
+                        operator.kind,
+                        TokenKind::Tilde | TokenKind::NoMatch | TokenKind::And | TokenKind::Or
+                    ),
+                    ..context
+                },
+            );
+            format!("{left_text} {} {right_text}", operator.literal)
+        }
+    }
+}
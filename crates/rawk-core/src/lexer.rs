@@ -17,6 +17,14 @@ pub struct LexError<'a> {
     pub start: usize,
 }
 
+/// A `#` comment skipped by the lexer, kept for the source formatter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Comment<'a> {
+    /// The comment text from `#` up to the end of the line.
+    pub(crate) text: &'a str,
+    pub(crate) start: usize,
+}
+
 #[derive(Debug)]
 pub struct Lexer<'a> {
     input: &'a str,
@@ -24,6 +32,7 @@ pub struct Lexer<'a> {
     read_position: usize,
     ch: Option<u8>,
     errors: Vec<LexError<'a>>,
+    comments: Vec<Comment<'a>>,
 }
 
 impl<'a> Lexer<'a> {
@@ -34,6 +43,7 @@ impl<'a> Lexer<'a> {
             read_position: 0,
             ch: None,
             errors: Vec::new(),
+            comments: Vec::new(),
         };
 
         lexer.read_char();
@@ -56,6 +66,16 @@ impl<'a> Lexer<'a> {
         !self.errors.is_empty()
     }
 
+    /// Where the last token read ends.
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The comments skipped so far, in source order.
+    pub(crate) fn comments(&self) -> &[Comment<'a>] {
+        &self.comments
+    }
+
     fn next_token_impl(&mut self, allow_regex: bool) -> Token<'a> {
         self.skip_whitespace();
         self.skip_comment();
@@ -436,9 +456,12 @@ impl<'a> Lexer<'a> {
 
     fn skip_comment(&mut self) {
         if Some(b'#') == self.ch {
+            let start = self.position;
             while self.ch != Some(b'\n') && self.ch.is_some() {
                 self.read_char();
             }
+            let text = self.input[start..self.position.min(self.input.len())].trim_end();
+            self.comments.push(Comment { text, start });
         }
     }
 
@@ -619,12 +642,14 @@ mod tests {
 
     #[test]
     fn next_identifier_token() {
-        let input = "BEGIN END break continue delete do else exit for function if in next print printf return while";
+        let input = "BEGIN BEGINFILE END ENDFILE break continue delete do else exit for function if in next nextfile print printf return while";
         let mut lexer = Lexer::new(input);
 
         let expected_tokens = vec![
             (TokenKind::Begin, "BEGIN"),
+            (TokenKind::BeginFile, "BEGINFILE"),
             (TokenKind::End, "END"),
+            (TokenKind::EndFile, "ENDFILE"),
             (TokenKind::Break, "break"),
             (TokenKind::Continue, "continue"),
             (TokenKind::Delete, "delete"),
@@ -636,6 +661,7 @@ mod tests {
             (TokenKind::If, "if"),
             (TokenKind::In, "in"),
             (TokenKind::Next, "next"),
+            (TokenKind::NextFile, "nextfile"),
             (TokenKind::Print, "print"),
             (TokenKind::Printf, "printf"),
             (TokenKind::Return, "return"),
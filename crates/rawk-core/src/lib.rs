@@ -1,13 +1,38 @@
-pub use ast::{Action, Expression, Program, Rule};
-pub use evaluator::Evaluator;
+pub use ast::{Action, Expression, Name, Program, Rule};
+pub use cancellation::CancellationToken;
+pub use char_mode::CharMode;
+pub use csv::OutputMode;
+pub use debug::{Call, DebugContext, Debugger};
+pub use dump::{dump_ast, dump_tokens};
+pub use evaluator::{DEFAULT_MAX_CALL_DEPTH, Evaluator, parse_command_line_assignment};
+pub use format::format_program;
 pub use lexer::Lexer;
+pub use lint::{Diagnostic, DiagnosticKind, lint_program};
+pub use outline::{OutlineItem, OutlineKind, outline};
 pub use parse_error::{ParseError, ParseErrorKind};
 pub use parser::Parser;
+pub use profile::{Cost, Profile, annotate_profile};
+pub use session::{Outcome, Session};
+pub use value::Value;
 
 mod ast;
 pub mod awk;
+mod cancellation;
+mod char_mode;
+mod csv;
+mod debug;
+mod dump;
 pub mod evaluator;
+mod field_pattern;
+mod field_widths;
+mod format;
 pub mod lexer;
+mod lint;
+mod outline;
 mod parse_error;
 pub mod parser;
-mod token;
+mod profile;
+mod record;
+mod session;
+pub mod token;
+mod value;
@@ -0,0 +1,1041 @@
+//! Static checks for common mistakes in AWK programs, used by `--lint`.
+
+use std::{collections::HashMap, ops::Range};
+
+use crate::{
+    Lexer, Parser,
+    ast::{Expression, FunctionDefinition, Rule, Statement},
+    parse_error::ParseError,
+    parser::{ItemKind, Node, Spanned},
+    token::TokenKind,
+};
+
+/// Variables the interpreter provides, which need no assignment.
+const SPECIAL_VARIABLES: &[&str] = &[
+    "ARGC",
+    "ARGV",
+    "CONVFMT",
+    "ENVIRON",
+    "FIELDWIDTHS",
+    "FILENAME",
+    "FNR",
+    "FPAT",
+    "FS",
+    "NF",
+    "NR",
+    "OFMT",
+    "OFS",
+    "ORS",
+    "RLENGTH",
+    "RS",
+    "RSTART",
+    "SUBSEP",
+    "getline",
+];
+
+/// Built-in functions called by name, with the fewest and most arguments
+/// they accept. `None` means any number.
+const BUILTIN_ARITIES: &[(&str, usize, Option<usize>)] = &[
+    ("atan2", 2, Some(2)),
+    ("close", 1, Some(1)),
+    ("cos", 1, Some(1)),
+    ("csvquote", 1, Some(1)),
+    ("exp", 1, Some(1)),
+    ("fflush", 0, Some(1)),
+    ("gsub", 2, Some(3)),
+    ("index", 2, Some(2)),
+    ("int", 1, Some(1)),
+    ("log", 1, Some(1)),
+    ("match", 2, Some(2)),
+    ("patsplit", 2, Some(4)),
+    ("sin", 1, Some(1)),
+    ("split", 2, Some(3)),
+    ("sprintf", 1, None),
+    ("sqrt", 1, Some(1)),
+    ("srand", 0, Some(1)),
+    ("sub", 2, Some(3)),
+    ("system", 1, Some(1)),
+    ("tolower", 1, Some(1)),
+    ("toupper", 1, Some(1)),
+];
+
+/// Characters that give a string special meaning when it is used as a
+/// regular expression.
+const REGEX_METACHARACTERS: &[char] = &['\\', '^', '$', '.', '[', '(', '|', '*', '+', '?', '{'];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind<'a> {
+    /// A call to a function that is neither built in nor defined; it
+    /// evaluates to `0`.
+    UndefinedFunction(&'a str),
+    /// A call with more or fewer arguments than the function accepts.
+    ArgumentCount {
+        name: &'a str,
+        min: usize,
+        max: Option<usize>,
+        found: usize,
+    },
+    /// A global variable that is read but assigned nowhere in the program.
+    UnassignedVariable(&'a str),
+    /// A condition whose top-level operator is `=`, probably meant as `==`.
+    AssignmentInCondition,
+    /// A string containing regular expression syntax on the right of `~` or
+    /// `!~`, where its escapes are processed twice.
+    StringUsedAsRegex(&'a str),
+    /// A statement after `next`, `exit`, `return`, `break` or `continue` in
+    /// the same block.
+    UnreachableCode,
+    /// A name used both as a scalar and as an array.
+    ScalarAndArray(&'a str),
+}
+
+/// A warning found by [`lint_program`] about the name, expression or
+/// statement at bytes `start..end` of the source, which starts at `line` and
+/// `column`, both counted from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic<'a> {
+    pub kind: DiagnosticKind<'a>,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl std::fmt::Display for DiagnosticKind<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticKind::UndefinedFunction(name) => {
+                write!(f, "call to undefined function {name}")
+            }
+            DiagnosticKind::ArgumentCount {
+                name,
+                min,
+                max,
+                found,
+            } => {
+                let expected = match max {
+                    Some(max) if max == min => min.to_string(),
+                    Some(max) if *min == 0 => format!("at most {max}"),
+                    Some(max) => format!("{min} to {max}"),
+                    None => format!("at least {min}"),
+                };
+                let plural = if *found == 1 { "" } else { "s" };
+                write!(
+                    f,
+                    "{name} called with {found} argument{plural}, expected {expected}"
+                )
+            }
+            DiagnosticKind::UnassignedVariable(name) => {
+                write!(f, "variable {name} is used but never assigned")
+            }
+            DiagnosticKind::AssignmentInCondition => {
+                write!(f, "assignment used as a condition; did you mean ==?")
+            }
+            DiagnosticKind::StringUsedAsRegex(text) => write!(
+                f,
+                "string \"{text}\" used as a regular expression; use /{text}/ to avoid double escaping"
+            ),
+            DiagnosticKind::UnreachableCode => write!(f, "unreachable statement"),
+            DiagnosticKind::ScalarAndArray(name) => {
+                write!(f, "{name} is used both as a scalar and as an array")
+            }
+        }
+    }
+}
+
+impl Diagnostic<'_> {
+    /// The warning without its position, for editors that show the
+    /// position themselves.
+    pub fn message(&self) -> String {
+        self.kind.to_string()
+    }
+}
+
+impl std::fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+    }
+}
+
+/// Check `source` for common mistakes and return warnings in source order.
+///
+/// Variables assigned only from the command line, with `-v` or an operand
+/// such as `n=1`, are reported as never assigned.
+///
+/// # Examples
+///
+/// ```
+/// use rawk_core::lint_program;
+///
+/// let diagnostics = lint_program("{ if (x = 1) print y }").unwrap();
+/// let messages: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+/// assert_eq!(
+///     messages,
+///     [
+///         "1:7: assignment used as a condition; did you mean ==?",
+///         "1:20: variable y is used but never assigned",
+///     ]
+/// );
+/// ```
+pub fn lint_program(source: &str) -> Result<Vec<Diagnostic<'_>>, ParseError<'_>> {
+    let mut parser = Parser::new(Lexer::new(source)).recording_spans();
+    let items = parser.try_parse_items()?;
+    let source = Source {
+        text: source,
+        spans: parser.spans(),
+    };
+
+    let functions: HashMap<&str, &FunctionDefinition<'_>> = items
+        .iter()
+        .filter_map(|item| match &item.kind {
+            ItemKind::Function(definition) => Some((definition.name, definition)),
+            ItemKind::Rule(_) => None,
+        })
+        .collect();
+
+    // Which parameters each function uses as an array, so that a variable
+    // passed in their place is known to be an array filled by the callee.
+    let unknown = HashMap::new();
+    let mut array_parameters = HashMap::new();
+    for definition in functions.values() {
+        let mut linter = Linter::new(&source, &functions, &[], &unknown);
+        linter.function(definition);
+        let arrays = definition
+            .parameters
+            .iter()
+            .map(|parameter| {
+                linter
+                    .locals
+                    .get(parameter)
+                    .is_some_and(|usage| usage.array.is_some())
+            })
+            .collect();
+        array_parameters.insert(definition.name, arrays);
+    }
+
+    let mut linter = Linter::new(
+        &source,
+        &functions,
+        parser.statement_starts(),
+        &array_parameters,
+    );
+    for item in &items {
+        linter.offset = item.start;
+        match &item.kind {
+            ItemKind::Function(definition) => linter.function(definition),
+            ItemKind::Rule(rule) => linter.rule(rule),
+        }
+    }
+    Ok(linter.finish())
+}
+
+/// The program text with the range of every statement and expression the
+/// parser read from it.
+struct Source<'a, 's> {
+    text: &'a str,
+    spans: &'s [Spanned<'a>],
+}
+
+impl<'a> Source<'a, '_> {
+    /// The range of `text` if it is a name or string the lexer cut from the
+    /// program text, as all those in a parsed program are.
+    fn text_range(&self, text: &'a str) -> Option<Range<usize>> {
+        let start = text
+            .as_ptr()
+            .addr()
+            .checked_sub(self.text.as_ptr().addr())?;
+        let end = start + text.len();
+        (end <= self.text.len()).then_some(start..end)
+    }
+
+    /// The range of the smallest recorded `node` around offset `at`.
+    fn node_range(&self, node: &Node<'a>, at: usize) -> Option<Range<usize>> {
+        self.spans
+            .iter()
+            .filter(|spanned| spanned.start <= at && at < spanned.end && spanned.node == *node)
+            .min_by_key(|spanned| spanned.end - spanned.start)
+            .map(|spanned| spanned.start..spanned.end)
+    }
+
+    /// The line and column of byte `offset`, both counted from 1.
+    fn line_column(&self, offset: usize) -> (usize, usize) {
+        let before = &self.text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+}
+
+/// How a name is used, with where it is first used in each way.
+#[derive(Default)]
+struct Usage {
+    read: Option<Range<usize>>,
+    assigned: bool,
+    scalar: Option<Range<usize>>,
+    array: Option<Range<usize>>,
+}
+
+struct Linter<'a, 's> {
+    source: &'s Source<'a, 's>,
+    functions: &'s HashMap<&'a str, &'s FunctionDefinition<'a>>,
+    array_parameters: &'s HashMap<&'a str, Vec<bool>>,
+    statement_starts: &'s [usize],
+    next_statement: usize,
+    /// The offset of the statement or rule being checked.
+    offset: usize,
+    globals: HashMap<&'a str, Usage>,
+    /// The parameters of the function being checked.
+    parameters: &'s [&'a str],
+    locals: HashMap<&'a str, Usage>,
+    diagnostics: Vec<Diagnostic<'a>>,
+}
+
+impl<'a, 's> Linter<'a, 's> {
+    fn new(
+        source: &'s Source<'a, 's>,
+        functions: &'s HashMap<&'a str, &'s FunctionDefinition<'a>>,
+        statement_starts: &'s [usize],
+        array_parameters: &'s HashMap<&'a str, Vec<bool>>,
+    ) -> Self {
+        Linter {
+            source,
+            functions,
+            array_parameters,
+            statement_starts,
+            next_statement: 0,
+            offset: 0,
+            globals: HashMap::new(),
+            parameters: &[],
+            locals: HashMap::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn report(&mut self, kind: DiagnosticKind<'a>, range: Range<usize>) {
+        let (line, column) = self.source.line_column(range.start);
+        self.diagnostics.push(Diagnostic {
+            kind,
+            start: range.start,
+            end: range.end,
+            line,
+            column,
+        });
+    }
+
+    /// The range of a name or string, or the start of the statement being
+    /// checked for one the parser made up.
+    fn text_range(&self, text: &'a str) -> Range<usize> {
+        self.source
+            .text_range(text)
+            .unwrap_or(self.offset..self.offset)
+    }
+
+    /// The range of `expression`, which contains offset `at`.
+    fn expression_range(&self, expression: &Expression<'a>, at: usize) -> Range<usize> {
+        self.source
+            .node_range(&Node::Expression(expression.clone()), at)
+            .unwrap_or(at..at)
+    }
+
+    fn finish(mut self) -> Vec<Diagnostic<'a>> {
+        let mut globals: Vec<(&'a str, Usage)> = self.globals.drain().collect();
+        globals.sort_by_key(|(name, _)| *name);
+        for (name, usage) in globals {
+            if let (Some(read), false) = (usage.read.clone(), usage.assigned) {
+                self.report(DiagnosticKind::UnassignedVariable(name), read);
+            }
+            self.check_scalar_and_array(name, &usage);
+        }
+        self.diagnostics.sort_by_key(|diagnostic| diagnostic.start);
+        self.diagnostics
+    }
+
+    fn check_scalar_and_array(&mut self, name: &'a str, usage: &Usage) {
+        if let (Some(scalar), Some(array)) = (&usage.scalar, &usage.array) {
+            let later = if scalar.start > array.start {
+                scalar
+            } else {
+                array
+            };
+            self.report(DiagnosticKind::ScalarAndArray(name), later.clone());
+        }
+    }
+
+    /// The usage record for `name`, or `None` for special variables.
+    fn usage(&mut self, name: &'a str) -> Option<&mut Usage> {
+        if self.parameters.contains(&name) {
+            Some(self.locals.entry(name).or_default())
+        } else if SPECIAL_VARIABLES.contains(&name) {
+            None
+        } else {
+            Some(self.globals.entry(name).or_default())
+        }
+    }
+
+    fn scalar(&mut self, name: &'a str, assigned: bool) {
+        let range = self.text_range(name);
+        if let Some(usage) = self.usage(name) {
+            usage.scalar.get_or_insert(range.clone());
+            if assigned {
+                usage.assigned = true;
+            } else {
+                usage.read.get_or_insert(range);
+            }
+        }
+    }
+
+    fn array(&mut self, name: &'a str, assigned: bool) {
+        let range = self.text_range(name);
+        if let Some(usage) = self.usage(name) {
+            usage.array.get_or_insert(range.clone());
+            if assigned {
+                usage.assigned = true;
+            } else {
+                usage.read.get_or_insert(range);
+            }
+        }
+    }
+
+    fn function(&mut self, definition: &'s FunctionDefinition<'a>) {
+        self.parameters = &definition.parameters;
+        self.locals.clear();
+        self.statements(&definition.statements);
+
+        let mut locals: Vec<(&'a str, Usage)> = self.locals.drain().collect();
+        locals.sort_by_key(|(name, _)| *name);
+        for (name, usage) in &locals {
+            self.check_scalar_and_array(name, usage);
+        }
+        self.locals = locals.into_iter().collect();
+        self.parameters = &[];
+    }
+
+    fn rule(&mut self, rule: &Rule<'a>) {
+        match rule {
+            Rule::Begin(action)
+            | Rule::BeginFile(action)
+            | Rule::Action(action)
+            | Rule::EndFile(action)
+            | Rule::End(action) => self.statements(&action.statements),
+            Rule::PatternAction { pattern, action } => {
+                if let Some(pattern) = pattern {
+                    match pattern {
+                        Expression::Infix {
+                            left,
+                            operator,
+                            right,
+                        } if operator.kind == TokenKind::Comma => {
+                            self.condition(left);
+                            self.condition(right);
+                        }
+                        pattern => self.condition(pattern),
+                    }
+                }
+                if let Some(action) = action {
+                    self.statements(&action.statements);
+                }
+            }
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement<'a>]) {
+        let mut jumped = false;
+        let mut reported = false;
+        for statement in statements {
+            if *statement == Statement::Empty {
+                continue;
+            }
+            self.enter_statement();
+            if jumped && !reported {
+                let range = self
+                    .source
+                    .node_range(&Node::Statement(statement.clone()), self.offset)
+                    .unwrap_or(self.offset..self.offset);
+                self.report(DiagnosticKind::UnreachableCode, range);
+                reported = true;
+            }
+            self.statement(statement);
+            jumped |= matches!(
+                statement,
+                Statement::Next
+                    | Statement::NextFile
+                    | Statement::Exit(_)
+                    | Statement::Return(_)
+                    | Statement::Break
+                    | Statement::Continue
+            );
+        }
+    }
+
+    /// Move to the start of the next statement the parser recorded.
+    fn enter_statement(&mut self) {
+        if let Some(start) = self.statement_starts.get(self.next_statement) {
+            self.offset = *start;
+            self.next_statement += 1;
+        }
+    }
+
+    fn condition(&mut self, condition: &Expression<'a>) {
+        if let Expression::Infix { operator, .. } = condition
+            && operator.kind == TokenKind::Assign
+        {
+            let range = self.expression_range(condition, operator.span.start);
+            self.report(DiagnosticKind::AssignmentInCondition, range);
+        }
+        self.expression(condition);
+    }
+
+    fn statement(&mut self, statement: &Statement<'a>) {
+        match statement {
+            Statement::Empty
+            | Statement::Break
+            | Statement::Continue
+            | Statement::Next
+            | Statement::NextFile
+            | Statement::Return(None)
+            | Statement::Exit(None) => {}
+            Statement::Expression(value)
+            | Statement::System(value)
+            | Statement::Return(Some(value))
+            | Statement::Exit(Some(value)) => self.expression(value),
+            Statement::Print(expressions) | Statement::Printf(expressions) => {
+                self.expressions(expressions)
+            }
+            Statement::PrintRedirect {
+                expressions,
+                target,
+                ..
+            }
+            | Statement::PrintPipe {
+                expressions,
+                target,
+            }
+            | Statement::PrintfRedirect {
+                expressions,
+                target,
+                ..
+            }
+            | Statement::PrintfPipe {
+                expressions,
+                target,
+            } => {
+                self.expressions(expressions);
+                self.expression(target);
+            }
+            Statement::Split {
+                string,
+                array,
+                separator,
+            } => {
+                self.expression(string);
+                self.array(array.text(), true);
+                if let Some(separator) = separator {
+                    self.expression(separator);
+                }
+            }
+            Statement::Sub {
+                pattern,
+                replacement,
+                target,
+            }
+            | Statement::Gsub {
+                pattern,
+                replacement,
+                target,
+            } => {
+                self.expression(pattern);
+                self.expression(replacement);
+                if let Some(target) = target {
+                    self.assignment_target(target);
+                }
+            }
+            Statement::Assignment { identifier, value }
+            | Statement::AddAssignment { identifier, value } => {
+                self.expression(value);
+                self.scalar(identifier.text(), true);
+            }
+            Statement::SplitAssignment {
+                identifier,
+                string,
+                array,
+                separator,
+            } => {
+                self.expression(string);
+                self.array(array.text(), true);
+                if let Some(separator) = separator {
+                    self.expression(separator);
+                }
+                self.scalar(identifier.text(), true);
+            }
+            Statement::ArrayAssignment {
+                identifier,
+                index,
+                value,
+            }
+            | Statement::ArrayAddAssignment {
+                identifier,
+                index,
+                value,
+            } => {
+                self.expression(index);
+                self.expression(value);
+                self.array(identifier.text(), true);
+            }
+            Statement::ArrayPostIncrement { identifier, index }
+            | Statement::ArrayPostDecrement { identifier, index } => {
+                self.expression(index);
+                self.array(identifier.text(), true);
+            }
+            Statement::FieldAssignment { field, value } => {
+                self.expression(field);
+                self.expression(value);
+            }
+            Statement::Delete { identifier, index } => {
+                if let Some(index) = index {
+                    self.expression(index);
+                }
+                self.array(identifier.text(), true);
+            }
+            Statement::PreIncrement { identifier }
+            | Statement::PreDecrement { identifier }
+            | Statement::PostIncrement { identifier }
+            | Statement::PostDecrement { identifier } => self.scalar(identifier.text(), true),
+            Statement::If {
+                condition,
+                then_statements,
+            } => {
+                self.condition(condition);
+                self.statements(then_statements);
+            }
+            Statement::IfElse {
+                condition,
+                then_statements,
+                else_statements,
+            } => {
+                self.condition(condition);
+                self.statements(then_statements);
+                self.statements(else_statements);
+            }
+            Statement::While {
+                condition,
+                statements,
+            } => {
+                self.condition(condition);
+                self.statements(statements);
+            }
+            Statement::DoWhile {
+                condition,
+                statements,
+            } => {
+                self.statements(statements);
+                self.condition(condition);
+            }
+            Statement::For {
+                init,
+                condition,
+                update,
+                statements,
+            } => {
+                self.statement(init);
+                self.condition(condition);
+                self.statement(update);
+                self.statements(statements);
+            }
+            Statement::ForIn {
+                variable,
+                array,
+                statements,
+            } => {
+                self.scalar(variable.text(), true);
+                self.array(array.text(), false);
+                self.statements(statements);
+            }
+        }
+    }
+
+    fn expressions(&mut self, expressions: &[Expression<'a>]) {
+        for expression in expressions {
+            self.expression(expression);
+        }
+    }
+
+    /// Record a variable, array element or field that is assigned to.
+    fn assignment_target(&mut self, target: &Expression<'a>) {
+        match target {
+            Expression::Identifier(name) => self.scalar(name.text(), true),
+            Expression::ArrayAccess { identifier, index } => {
+                self.expression(index);
+                self.array(identifier.text(), true);
+            }
+            target => self.expression(target),
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression<'a>) {
+        match expression {
+            Expression::Number(_)
+            | Expression::HexNumber { .. }
+            | Expression::String(_)
+            | Expression::Regex(_)
+            | Expression::Rand
+            | Expression::Length(None) => {}
+            Expression::Identifier(name) => self.scalar(name.text(), false),
+            Expression::Field(index) | Expression::Not(index) => self.expression(index),
+            Expression::Length(Some(argument)) => self.expression(argument),
+            Expression::ArrayAccess { identifier, index } => {
+                self.expression(index);
+                self.array(identifier.text(), false);
+            }
+            Expression::Substr {
+                string,
+                start,
+                length,
+            } => {
+                self.expression(string);
+                self.expression(start);
+                if let Some(length) = length {
+                    self.expression(length);
+                }
+            }
+            Expression::PreIncrement(target)
+            | Expression::PreDecrement(target)
+            | Expression::PostIncrement(target)
+            | Expression::PostDecrement(target) => self.assignment_target(target),
+            Expression::Ternary {
+                condition,
+                then_expr,
+                else_expr,
+            } => {
+                self.expression(condition);
+                self.expression(then_expr);
+                self.expression(else_expr);
+            }
+            // `getline x` parses as `getline` followed by `x`, which it reads
+            // a record into.
+            Expression::Concatenation { left, right } if matches!(left.as_ref(), Expression::Identifier(name) if *name == "getline") =>
+            {
+                self.assignment_target(right);
+            }
+            Expression::Concatenation { left, right } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::Infix {
+                left,
+                operator,
+                right,
+            } => match operator.kind {
+                TokenKind::Assign
+                | TokenKind::AddAssign
+                | TokenKind::SubtractAssign
+                | TokenKind::MultiplyAssign
+                | TokenKind::DivideAssign
+                | TokenKind::ModuloAssign
+                | TokenKind::PowerAssign => {
+                    self.expression(right);
+                    self.assignment_target(left);
+                }
+                TokenKind::In => {
+                    self.expression(left);
+                    match right.as_ref() {
+                        Expression::Identifier(array) => self.array(array.text(), false),
+                        right => self.expression(right),
+                    }
+                }
+                TokenKind::Tilde | TokenKind::NoMatch => {
+                    if let Expression::String(text) = right.as_ref()
+                        && text.contains(REGEX_METACHARACTERS)
+                    {
+                        let range = self.expression_range(right, self.text_range(text).start);
+                        self.report(DiagnosticKind::StringUsedAsRegex(text), range);
+                    }
+                    self.expression(left);
+                    self.expression(right);
+                }
+                _ => {
+                    self.expression(left);
+                    self.expression(right);
+                }
+            },
+            Expression::FunctionCall { name, args } => self.call(expression, name, args),
+        }
+    }
+
+    fn call(&mut self, call: &Expression<'a>, name: &'a str, args: &[Expression<'a>]) {
+        let name_range = self.text_range(name);
+        if let Some(&(_, min, max)) = BUILTIN_ARITIES
+            .iter()
+            .find(|(builtin, _, _)| *builtin == name)
+        {
+            if args.len() < min || max.is_some_and(|max| args.len() > max) {
+                self.report(
+                    DiagnosticKind::ArgumentCount {
+                        name,
+                        min,
+                        max,
+                        found: args.len(),
+                    },
+                    self.expression_range(call, name_range.start),
+                );
+            }
+            for (index, argument) in args.iter().enumerate() {
+                let is_array = match name {
+                    "split" => index == 1,
+                    "patsplit" => index == 1 || index == 3,
+                    _ => false,
+                };
+                match argument {
+                    Expression::Identifier(array) if is_array => self.array(array.text(), true),
+                    argument if (name == "sub" || name == "gsub") && index == 2 => {
+                        self.assignment_target(argument)
+                    }
+                    argument => self.expression(argument),
+                }
+            }
+            return;
+        }
+
+        let Some(definition) = self.functions.get(name) else {
+            self.report(DiagnosticKind::UndefinedFunction(name), name_range);
+            self.expressions(args);
+            return;
+        };
+        let parameters = definition.parameters.len();
+        if args.len() > parameters {
+            self.report(
+                DiagnosticKind::ArgumentCount {
+                    name,
+                    min: 0,
+                    max: Some(parameters),
+                    found: args.len(),
+                },
+                self.expression_range(call, name_range.start),
+            );
+        }
+
+        let array_parameters = self.array_parameters.get(name);
+        for (index, argument) in args.iter().enumerate() {
+            match (argument, array_parameters) {
+                // Arrays are passed by reference and may be filled by the
+                // callee.
+                (Expression::Identifier(array), Some(arrays))
+                    if arrays.get(index).copied().unwrap_or(false) =>
+                {
+                    self.array(array.text(), true)
+                }
+                // Whether a bare name is an array is not known yet while the
+                // functions themselves are checked.
+                (Expression::Identifier(_), None) => {}
+                (argument, _) => self.expression(argument),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<DiagnosticKind<'_>> {
+        lint_program(source)
+            .unwrap()
+            .into_iter()
+            .map(|diagnostic| diagnostic.kind)
+            .collect()
+    }
+
+    /// The text each diagnostic points at.
+    fn ranges(source: &str) -> Vec<&str> {
+        lint_program(source)
+            .unwrap()
+            .iter()
+            .map(|diagnostic| &source[diagnostic.start..diagnostic.end])
+            .collect()
+    }
+
+    #[test]
+    fn reports_undefined_functions_and_argument_counts() {
+        assert_eq!(
+            kinds("function f(a) { return a }\nBEGIN { f(1, 2); g(); print index(\"a\") }"),
+            [
+                DiagnosticKind::ArgumentCount {
+                    name: "f",
+                    min: 0,
+                    max: Some(1),
+                    found: 2
+                },
+                DiagnosticKind::UndefinedFunction("g"),
+                DiagnosticKind::ArgumentCount {
+                    name: "index",
+                    min: 2,
+                    max: Some(2),
+                    found: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_are_reported_at_the_call_or_the_function_name() {
+        assert_eq!(
+            ranges("function f(a) { return a }\nBEGIN { x = f(1, 2) + g(3); print index(\"a\") }"),
+            ["f(1, 2)", "g", "index(\"a\")"]
+        );
+    }
+
+    #[test]
+    fn argument_counts_of_one_are_singular() {
+        let diagnostics = lint_program("BEGIN { print atan2(1) }").unwrap();
+
+        assert_eq!(
+            diagnostics[0].message(),
+            "atan2 called with 1 argument, expected 2"
+        );
+    }
+
+    #[test]
+    fn fewer_arguments_than_parameters_declare_locals() {
+        assert_eq!(
+            kinds("function f(a, tmp) { tmp = a; return tmp }\nBEGIN { print f(1) }"),
+            []
+        );
+    }
+
+    #[test]
+    fn reports_variables_read_but_never_assigned() {
+        let diagnostics = lint_program("{ total += $1 }\nEND { print totl, NR }").unwrap();
+
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                kind: DiagnosticKind::UnassignedVariable("totl"),
+                start: 28,
+                end: 32,
+                line: 2,
+                column: 13,
+            }]
+        );
+    }
+
+    #[test]
+    fn message_leaves_out_the_position() {
+        let diagnostics = lint_program("BEGIN { print x }").unwrap();
+
+        assert_eq!(
+            diagnostics[0].message(),
+            "variable x is used but never assigned"
+        );
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "1:15: variable x is used but never assigned"
+        );
+    }
+
+    #[test]
+    fn arrays_filled_by_split_or_a_function_are_assigned() {
+        assert_eq!(
+            kinds(
+                "function fill(arr) { arr[1] = 1 }\n\
+                 BEGIN { n = split(\"a b\", parts); fill(seen); print parts[n], seen[1] }"
+            ),
+            []
+        );
+    }
+
+    #[test]
+    fn getline_targets_are_assigned() {
+        assert_eq!(kinds("NR == 1 { if (getline l) print l }"), []);
+        assert_eq!(
+            kinds("BEGIN { while ((getline line) > 0) n++; print line, n }"),
+            []
+        );
+        assert_eq!(
+            kinds("BEGIN { while ((getline line < \"f\") > 0) print line }"),
+            []
+        );
+        assert_eq!(
+            kinds("BEGIN { while ((getline row[n++]) > 0) continue; print row[1] }"),
+            []
+        );
+    }
+
+    #[test]
+    fn reports_assignment_in_condition() {
+        let diagnostics = lint_program("$1 = \"x\" { print }\n{ while (n = 0) n++ }").unwrap();
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| (diagnostic.kind.clone(), diagnostic.start))
+                .collect::<Vec<_>>(),
+            [
+                (DiagnosticKind::AssignmentInCondition, 0),
+                (DiagnosticKind::AssignmentInCondition, 28),
+            ]
+        );
+        assert_eq!(
+            ranges("$1 = \"x\" { print }\n{ while (n = 0) n++ }"),
+            ["$1 = \"x\"", "n = 0"]
+        );
+    }
+
+    #[test]
+    fn reports_regex_strings_on_the_right_of_match_operators() {
+        assert_eq!(
+            kinds(r#"$1 ~ "^a\.b" { print } $2 !~ "plain" { print }"#),
+            [DiagnosticKind::StringUsedAsRegex(r"^a\.b")]
+        );
+        assert_eq!(
+            ranges(r#"$1 ~ "^a\.b" { print } $2 !~ "plain" { print }"#),
+            [r#""^a\.b""#]
+        );
+    }
+
+    #[test]
+    fn reports_first_statement_after_a_jump() {
+        let diagnostics =
+            lint_program("{ if ($1) { next; print \"skipped\" } exit 1\n print; print }").unwrap();
+
+        assert_eq!(
+            diagnostics,
+            [
+                Diagnostic {
+                    kind: DiagnosticKind::UnreachableCode,
+                    start: 18,
+                    end: 33,
+                    line: 1,
+                    column: 19,
+                },
+                Diagnostic {
+                    kind: DiagnosticKind::UnreachableCode,
+                    start: 44,
+                    end: 49,
+                    line: 2,
+                    column: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_names_used_as_scalar_and_array() {
+        assert_eq!(
+            kinds("{ count[$1]++ }\nEND { count = 0; print count }"),
+            [DiagnosticKind::ScalarAndArray("count")]
+        );
+        assert_eq!(
+            ranges("{ count[$1]++ }\nEND { count = 0; print count }"),
+            ["count"]
+        );
+        assert_eq!(
+            lint_program("{ count[$1]++ }\nEND { count = 0; print count }").unwrap()[0].start,
+            22
+        );
+        assert_eq!(
+            kinds("function f(list) { list = 1; return list[1] }\nBEGIN { f() }"),
+            [DiagnosticKind::ScalarAndArray("list")]
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! The top-level structure of a program, for editor outlines.
+
+use crate::{
+    Lexer, Parser, ast::Rule, format::pattern_text, parse_error::ParseError, parser::ItemKind,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutlineKind<'a> {
+    Begin,
+    BeginFile,
+    End,
+    EndFile,
+    /// A rule with a pattern, shown as formatted source such as `$1 > 2` or
+    /// `/start/, /end/`.
+    Pattern(String),
+    /// A rule without a pattern, run for every record.
+    Action,
+    Function {
+        name: &'a str,
+        parameters: Vec<&'a str>,
+    },
+}
+
+/// A rule or function definition spanning bytes `start..end` of the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineItem<'a> {
+    pub kind: OutlineKind<'a>,
+    pub start: usize,
+    pub end: usize,
+    /// Where the name of a function starts, or `start` for rules.
+    pub name_start: usize,
+}
+
+/// List the rules and functions of `source` in source order.
+///
+/// # Examples
+///
+/// ```
+/// use rawk_core::{OutlineKind, outline};
+///
+/// let items = outline("BEGIN { n = 0 }\n$1 > 2 { n++ }").unwrap();
+/// assert_eq!(items[1].kind, OutlineKind::Pattern("$1 > 2".to_string()));
+/// assert_eq!((items[1].start, items[1].end), (16, 30));
+/// ```
+pub fn outline(source: &str) -> Result<Vec<OutlineItem<'_>>, ParseError<'_>> {
+    let mut parser = Parser::new(Lexer::new(source));
+    let items = parser.try_parse_items()?;
+
+    Ok(items
+        .into_iter()
+        .map(|item| {
+            // An item ends at its closing brace, which belongs to it.
+            let end = if source[item.end..].starts_with('}') {
+                item.end + 1
+            } else {
+                item.end
+            };
+            let (kind, name_start) = match item.kind {
+                ItemKind::Function(definition) => {
+                    // The name is the first match after the keyword.
+                    let keyword_end = item.start
+                        + source[item.start..]
+                            .find(char::is_whitespace)
+                            .unwrap_or_default();
+                    let name_start = keyword_end
+                        + source[keyword_end..]
+                            .find(definition.name)
+                            .unwrap_or_default();
+                    let kind = OutlineKind::Function {
+                        name: definition.name,
+                        parameters: definition.parameters,
+                    };
+                    (kind, name_start)
+                }
+                ItemKind::Rule(rule) => {
+                    let kind = match rule {
+                        Rule::Begin(_) => OutlineKind::Begin,
+                        Rule::BeginFile(_) => OutlineKind::BeginFile,
+                        Rule::End(_) => OutlineKind::End,
+                        Rule::EndFile(_) => OutlineKind::EndFile,
+                        Rule::Action(_) => OutlineKind::Action,
+                        Rule::PatternAction {
+                            pattern: Some(pattern),
+                            ..
+                        } => OutlineKind::Pattern(pattern_text(&pattern)),
+                        Rule::PatternAction { pattern: None, .. } => OutlineKind::Action,
+                    };
+                    (kind, item.start)
+                }
+            };
+            OutlineItem {
+                kind,
+                start: item.start,
+                end,
+                name_start,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outline_lists_items_in_source_order_with_spans() {
+        let source = "function max(a, b) { return a > b ? a : b }\nEND { print max(1, 2) }\n/x/";
+
+        let items = outline(source).unwrap();
+
+        assert_eq!(
+            items,
+            [
+                OutlineItem {
+                    kind: OutlineKind::Function {
+                        name: "max",
+                        parameters: vec!["a", "b"],
+                    },
+                    start: 0,
+                    end: 43,
+                    name_start: 9,
+                },
+                OutlineItem {
+                    kind: OutlineKind::End,
+                    start: 44,
+                    end: 67,
+                    name_start: 44,
+                },
+                OutlineItem {
+                    kind: OutlineKind::Pattern("/x/".to_string()),
+                    start: 68,
+                    end: 71,
+                    name_start: 68,
+                },
+            ]
+        );
+    }
+}
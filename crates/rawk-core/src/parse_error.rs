@@ -6,7 +6,6 @@ pub enum ParseErrorKind {
     ExpectedStatement,
     ExpectedIdentifier,
     UnsupportedStatement,
-    UnsupportedSubTarget,
     ExpectedLeftParen,
     ExpectedLeftBrace,
     ExpectedRightSquareBracket,
@@ -18,6 +17,8 @@ pub enum ParseErrorKind {
     ExpectedRightParen,
     MissingPrintfFormatString,
     InvalidNumericLiteral,
+    JumpOutsideLoop,
+    ReturnOutsideFunction,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -49,11 +50,6 @@ impl std::fmt::Display for ParseError<'_> {
                 "unexpected token {:?} ({:?}) at byte {}: unsupported statement syntax",
                 self.token.kind, self.token.literal, self.token.span.start
             ),
-            ParseErrorKind::UnsupportedSubTarget => write!(
-                f,
-                "unexpected token {:?} ({:?}) at byte {}: sub target argument is not supported",
-                self.token.kind, self.token.literal, self.token.span.start
-            ),
             ParseErrorKind::ExpectedLeftParen => write!(
                 f,
                 "unexpected token {:?} ({:?}) at byte {}: expected left paren",
@@ -109,6 +105,16 @@ impl std::fmt::Display for ParseError<'_> {
                 "invalid numeric literal {:?} at byte {}",
                 self.token.literal, self.token.span.start
             ),
+            ParseErrorKind::JumpOutsideLoop => write!(
+                f,
+                "{} outside a loop at byte {}",
+                self.token.literal, self.token.span.start
+            ),
+            ParseErrorKind::ReturnOutsideFunction => write!(
+                f,
+                "return outside a function at byte {}",
+                self.token.span.start
+            ),
         }
     }
 }
@@ -176,19 +182,6 @@ mod tests {
         );
     }
 
-    #[test]
-    fn display_unsupported_sub_target_error() {
-        let err = parse_error(
-            ParseErrorKind::UnsupportedSubTarget,
-            Token::new(TokenKind::Comma, ",", 12),
-        );
-
-        assert_eq!(
-            format!("{err}"),
-            "unexpected token Comma (\",\") at byte 12: sub target argument is not supported"
-        );
-    }
-
     #[test]
     fn display_expected_left_paren_error() {
         let err = parse_error(
@@ -331,4 +324,24 @@ mod tests {
             "invalid numeric literal \"0xZZ\" at byte 3"
         );
     }
+
+    #[test]
+    fn display_jump_outside_loop_error() {
+        let err = parse_error(
+            ParseErrorKind::JumpOutsideLoop,
+            Token::new(TokenKind::Continue, "continue", 8),
+        );
+
+        assert_eq!(format!("{err}"), "continue outside a loop at byte 8");
+    }
+
+    #[test]
+    fn display_return_outside_function_error() {
+        let err = parse_error(
+            ParseErrorKind::ReturnOutsideFunction,
+            Token::new(TokenKind::Return, "return", 2),
+        );
+
+        assert_eq!(format!("{err}"), "return outside a function at byte 2");
+    }
 }
@@ -1,33 +1,163 @@
 pub use crate::parse_error::{ParseError, ParseErrorKind};
 use crate::{
     Lexer, Program,
-    ast::{Action, Expression, FunctionDefinition, Rule, Statement},
+    ast::{Action, Expression, FunctionDefinition, Rule, Statement, visit_statements},
+    lexer::Comment,
     token::{Token, TokenKind},
 };
 
+/// Binding powers of implicit concatenation, which sits between the
+/// comparison and additive operators.
+pub(crate) const CONCAT_LEFT_PRECEDENCE: u8 = 6;
+pub(crate) const CONCAT_RIGHT_PRECEDENCE: u8 = 7;
+
 #[derive(Debug)]
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     current_token: Token<'a>,
-    function_definitions: Vec<FunctionDefinition<'a>>,
+    /// Every token read from the lexer so far, with regular expressions
+    /// recognized where the grammar allows them.
+    tokens: Vec<Token<'a>>,
+    /// Where each statement parsed so far starts, in the order the parser
+    /// entered them.
+    statement_starts: Vec<usize>,
+    /// Where each body of a control statement parsed so far ends, in the
+    /// order they end, with where each `else` starts after the body before it.
+    body_ends: Vec<usize>,
+    /// Where each statement of the parsed program starts, in the order of
+    /// `Program::statement_lists`.
+    statement_offsets: Vec<usize>,
+    /// Set while parsing the unparenthesized arguments of `print` or `printf`,
+    /// where a `>` starts an output redirection instead of a comparison.
+    in_output_list: bool,
+    /// How many loops enclose the statement being parsed, so that `break`
+    /// and `continue` outside of one are rejected.
+    loop_depth: usize,
+    /// Set while parsing the body of a function, where `return` is allowed.
+    in_function: bool,
+    /// Where the token before the current one ends.
+    previous_end: usize,
+    /// Where the current token ends.
+    current_end: usize,
+    /// Every statement and expression built so far with its source range,
+    /// when asked for by [`Parser::recording_spans`].
+    spans: Option<Vec<Spanned<'a>>>,
+}
+
+/// A statement or expression the parser built, with the range of the source
+/// it was read from. Nodes the parser adds without source text of their own,
+/// such as the `0` of `-x`, get the range of the text they stand for.
+#[derive(Debug)]
+pub(crate) struct Spanned<'a> {
+    pub(crate) node: Node<'a>,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Node<'a> {
+    Statement(Statement<'a>),
+    Expression(Expression<'a>),
+}
+
+/// A top-level rule or function definition with the source offsets where it
+/// starts and where the token after it starts.
+pub(crate) struct Item<'a> {
+    pub(crate) kind: ItemKind<'a>,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+}
+
+pub(crate) enum ItemKind<'a> {
+    Rule(Rule<'a>),
+    Function(FunctionDefinition<'a>),
+}
+
+/// Where `print` or `printf` sends its output.
+enum OutputTarget<'a> {
+    Stdout,
+    File {
+        target: Expression<'a>,
+        append: bool,
+    },
+    Pipe(Expression<'a>),
 }
 
 impl<'a> Parser<'a> {
     pub fn new(mut lexer: Lexer<'a>) -> Self {
         let current_token = lexer.next_token_regex_aware();
+        let current_end = lexer.position();
         Parser {
             lexer,
+            tokens: vec![current_token.clone()],
             current_token,
-            function_definitions: Vec::new(),
+            statement_starts: Vec::new(),
+            body_ends: Vec::new(),
+            statement_offsets: Vec::new(),
+            in_output_list: false,
+            loop_depth: 0,
+            in_function: false,
+            previous_end: 0,
+            current_end,
+            spans: None,
         }
     }
 
+    /// Record the source range of every statement and expression parsed,
+    /// for [`Parser::spans`].
+    pub(crate) fn recording_spans(mut self) -> Self {
+        self.spans = Some(Vec::new());
+        self
+    }
+
     fn next_token(&mut self) {
         self.current_token = self.lexer.next_token();
+        self.token_read();
     }
 
     fn next_token_in_regex_context(&mut self) {
         self.current_token = self.lexer.next_token_regex_aware();
+        self.token_read();
+    }
+
+    fn token_read(&mut self) {
+        self.tokens.push(self.current_token.clone());
+        self.previous_end = self.current_end;
+        self.current_end = self.lexer.position();
+    }
+
+    /// Where the current token starts, counting the opening quote or slash
+    /// of a string or regular expression.
+    fn current_start(&self) -> usize {
+        match self.current_token.kind {
+            TokenKind::String | TokenKind::Regex => self.current_token.span.start - 1,
+            _ => self.current_token.span.start,
+        }
+    }
+
+    /// Record `node` as read from `start` to `end`, if spans are recorded.
+    fn record(&mut self, start: usize, end: usize, node: impl FnOnce() -> Node<'a>) {
+        if let Some(spans) = &mut self.spans {
+            spans.push(Spanned {
+                node: node(),
+                start,
+                end,
+            });
+        }
+    }
+
+    /// Record `expression` as read from `start` to the end of the last token.
+    fn record_expression(&mut self, start: usize, expression: &Expression<'a>) {
+        self.record(start, self.previous_end, || {
+            Node::Expression(expression.clone())
+        });
+    }
+
+    /// Record `statement` as read from `start` to the end of the last token.
+    fn record_statement(&mut self, start: usize, statement: &Statement<'a>) {
+        self.record(start, self.previous_end, || {
+            Node::Statement(statement.clone())
+        });
     }
 
     fn skip_newlines(&mut self) {
@@ -80,6 +210,7 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_array_index_expression(&mut self) -> Result<Expression<'a>, ParseError<'a>> {
+        let start = self.current_start();
         let mut index = self.parse_expression()?;
         while self.current_token.kind == TokenKind::Comma {
             let operator = self.current_token.clone();
@@ -90,6 +221,7 @@ impl<'a> Parser<'a> {
                 operator,
                 right: Box::new(right),
             };
+            self.record_expression(start, &index);
         }
         Ok(index)
     }
@@ -117,10 +249,6 @@ impl<'a> Parser<'a> {
         self.parse_error(ParseErrorKind::UnsupportedStatement)
     }
 
-    fn unsupported_sub_target(&self) -> ParseError<'a> {
-        self.parse_error(ParseErrorKind::UnsupportedSubTarget)
-    }
-
     fn expected_left_paren(&self) -> ParseError<'a> {
         self.parse_error(ParseErrorKind::ExpectedLeftParen)
     }
@@ -165,6 +293,14 @@ impl<'a> Parser<'a> {
         self.parse_error(ParseErrorKind::InvalidNumericLiteral)
     }
 
+    fn jump_outside_loop(&self) -> ParseError<'a> {
+        self.parse_error(ParseErrorKind::JumpOutsideLoop)
+    }
+
+    fn return_outside_function(&self) -> ParseError<'a> {
+        self.parse_error(ParseErrorKind::ReturnOutsideFunction)
+    }
+
     fn split_print_parenthesized_list(expression: Expression<'a>) -> Option<Vec<Expression<'a>>> {
         fn flatten<'a>(expression: Expression<'a>, expressions: &mut Vec<Expression<'a>>) -> bool {
             match expression {
@@ -200,6 +336,22 @@ impl<'a> Parser<'a> {
                 let action = self.parse_action()?;
                 Ok(Some(Rule::Begin(action)))
             }
+            TokenKind::BeginFile => {
+                self.next_token();
+                if self.current_token.kind != TokenKind::LeftCurlyBrace {
+                    return Err(self.expected_left_brace());
+                }
+                let action = self.parse_action()?;
+                Ok(Some(Rule::BeginFile(action)))
+            }
+            TokenKind::EndFile => {
+                self.next_token();
+                if self.current_token.kind != TokenKind::LeftCurlyBrace {
+                    return Err(self.expected_left_brace());
+                }
+                let action = self.parse_action()?;
+                Ok(Some(Rule::EndFile(action)))
+            }
             TokenKind::NewLine => {
                 self.next_token_in_regex_context();
                 self.parse_next_rule()
@@ -208,10 +360,6 @@ impl<'a> Parser<'a> {
             TokenKind::LeftCurlyBrace => {
                 self.parse_action().map(|action| Some(Rule::Action(action)))
             }
-            TokenKind::Function => {
-                self.parse_function_definition()?;
-                Ok(None)
-            }
             TokenKind::End => {
                 self.next_token();
                 if self.current_token.kind != TokenKind::LeftCurlyBrace {
@@ -226,8 +374,11 @@ impl<'a> Parser<'a> {
             | TokenKind::DollarSign
             | TokenKind::LeftParen
             | TokenKind::Identifier
+            | TokenKind::Atan2
+            | TokenKind::Close
             | TokenKind::Cos
             | TokenKind::Exp
+            | TokenKind::Gsub
             | TokenKind::Index
             | TokenKind::Int
             | TokenKind::Length
@@ -239,7 +390,11 @@ impl<'a> Parser<'a> {
             | TokenKind::Split
             | TokenKind::Sqrt
             | TokenKind::Srand
+            | TokenKind::Sub
             | TokenKind::Substr
+            | TokenKind::System
+            | TokenKind::ToLower
+            | TokenKind::ToUpper
             | TokenKind::ExclamationMark
             | TokenKind::Increment
             | TokenKind::Decrement => self.parse_pattern_rule(),
@@ -248,6 +403,7 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_pattern_rule(&mut self) -> Result<Option<Rule<'a>>, ParseError<'a>> {
+        let start = self.current_start();
         let mut pattern = self.parse_expression()?;
         if self.current_token.kind == TokenKind::Comma {
             let operator = self.current_token.clone();
@@ -258,6 +414,7 @@ impl<'a> Parser<'a> {
                 operator,
                 right: Box::new(right),
             };
+            self.record_expression(start, &pattern);
         }
         let pattern = Some(pattern);
 
@@ -301,6 +458,14 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_statement(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
+        let start = self.current_start();
+        self.statement_starts.push(start);
+        let statement = self.parse_statement_kind()?;
+        self.record_statement(start, &statement);
+        Ok(statement)
+    }
+
+    fn parse_statement_kind(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
         match self.current_token.kind {
             TokenKind::Print => self.parse_print_function(),
             TokenKind::Printf => self.parse_printf_function(),
@@ -308,8 +473,8 @@ impl<'a> Parser<'a> {
             TokenKind::Split => self.parse_split_statement(),
             TokenKind::Sub => self.parse_sub_function(),
             TokenKind::Gsub => self.parse_gsub_function(),
-            TokenKind::Break => Ok(self.parse_break_statement()),
-            TokenKind::Continue => Ok(self.parse_continue_statement()),
+            TokenKind::Break => self.parse_break_statement(),
+            TokenKind::Continue => self.parse_continue_statement(),
             TokenKind::Delete => self.parse_delete_statement(),
             TokenKind::If => self.parse_if_statement(),
             TokenKind::Do => self.parse_do_statement(),
@@ -317,6 +482,7 @@ impl<'a> Parser<'a> {
             TokenKind::For => self.parse_for_statement(),
             TokenKind::Return => self.parse_return_statement(),
             TokenKind::Next => Ok(self.parse_next_statement()),
+            TokenKind::NextFile => Ok(self.parse_nextfile_statement()),
             TokenKind::Exit => self.parse_exit_statement(),
             TokenKind::Identifier => self.parse_assignment_statement(),
             TokenKind::DollarSign => self.parse_field_assignment_statement(),
@@ -326,6 +492,7 @@ impl<'a> Parser<'a> {
             | TokenKind::String
             | TokenKind::Regex
             | TokenKind::LeftParen
+            | TokenKind::Atan2
             | TokenKind::Close
             | TokenKind::Cos
             | TokenKind::Exp
@@ -346,7 +513,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_function_definition(&mut self) -> Result<(), ParseError<'a>> {
+    fn parse_function_definition(&mut self) -> Result<FunctionDefinition<'a>, ParseError<'a>> {
         self.next_token();
         if self.current_token.kind != TokenKind::Identifier {
             return Err(self.expected_identifier());
@@ -378,6 +545,23 @@ impl<'a> Parser<'a> {
             return Err(self.expected_left_brace());
         }
 
+        self.in_function = true;
+        let statements = self.parse_function_body();
+        self.in_function = false;
+        let statements = statements?;
+        if self.current_token.kind != TokenKind::RightCurlyBrace {
+            return Err(self.expected_right_brace());
+        }
+        Ok(FunctionDefinition {
+            name,
+            parameters,
+            statements,
+        })
+    }
+
+    /// Parse the statements of a function body up to its closing brace or
+    /// the end of input.
+    fn parse_function_body(&mut self) -> Result<Vec<Statement<'a>>, ParseError<'a>> {
         let mut statements = Vec::new();
         self.next_token(); // consume '{'
         while self.current_token.kind != TokenKind::RightCurlyBrace
@@ -393,16 +577,7 @@ impl<'a> Parser<'a> {
 
             statements.push(self.parse_statement()?);
         }
-        if self.current_token.kind != TokenKind::RightCurlyBrace {
-            return Err(self.expected_right_brace());
-        }
-        self.function_definitions.push(FunctionDefinition {
-            name,
-            parameters,
-            statements,
-        });
-
-        Ok(())
+        Ok(statements)
     }
 
     fn parse_simple_statement(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
@@ -415,6 +590,7 @@ impl<'a> Parser<'a> {
             | TokenKind::String
             | TokenKind::Regex
             | TokenKind::LeftParen
+            | TokenKind::Atan2
             | TokenKind::Close
             | TokenKind::Cos
             | TokenKind::Exp
@@ -449,10 +625,12 @@ impl<'a> Parser<'a> {
             && self.token_is_immediately_after(&identifier)
         {
             let args = self.parse_call_arguments()?;
-            return Ok(Statement::Expression(Expression::FunctionCall {
+            let call = Expression::FunctionCall {
                 name: identifier.literal,
                 args,
-            }));
+            };
+            self.record_expression(identifier.span.start, &call);
+            return Ok(Statement::Expression(call));
         }
         if self.current_token.kind == TokenKind::LeftSquareBracket {
             self.next_token_in_regex_context();
@@ -466,7 +644,7 @@ impl<'a> Parser<'a> {
                     self.next_token_in_regex_context();
                     let value = self.parse_expression()?;
                     Ok(Statement::ArrayAssignment {
-                        identifier: identifier.literal,
+                        identifier: identifier.literal.into(),
                         index,
                         value,
                     })
@@ -475,7 +653,7 @@ impl<'a> Parser<'a> {
                     self.next_token_in_regex_context();
                     let value = self.parse_expression()?;
                     Ok(Statement::ArrayAddAssignment {
-                        identifier: identifier.literal,
+                        identifier: identifier.literal.into(),
                         index,
                         value,
                     })
@@ -483,14 +661,14 @@ impl<'a> Parser<'a> {
                 TokenKind::Increment => {
                     self.next_token();
                     Ok(Statement::ArrayPostIncrement {
-                        identifier: identifier.literal,
+                        identifier: identifier.literal.into(),
                         index,
                     })
                 }
                 TokenKind::Decrement => {
                     self.next_token();
                     Ok(Statement::ArrayPostDecrement {
-                        identifier: identifier.literal,
+                        identifier: identifier.literal.into(),
                         index,
                     })
                 }
@@ -505,27 +683,27 @@ impl<'a> Parser<'a> {
                 }
                 let value = self.parse_expression()?;
                 Ok(Statement::Assignment {
-                    identifier: identifier.literal,
+                    identifier: identifier.literal.into(),
                     value,
                 })
             }
             TokenKind::Increment => {
                 self.next_token();
                 Ok(Statement::PostIncrement {
-                    identifier: identifier.literal,
+                    identifier: identifier.literal.into(),
                 })
             }
             TokenKind::Decrement => {
                 self.next_token();
                 Ok(Statement::PostDecrement {
-                    identifier: identifier.literal,
+                    identifier: identifier.literal.into(),
                 })
             }
             TokenKind::AddAssign => {
                 self.next_token_in_regex_context();
                 let value = self.parse_expression()?;
                 Ok(Statement::AddAssignment {
-                    identifier: identifier.literal,
+                    identifier: identifier.literal.into(),
                     value,
                 })
             }
@@ -537,13 +715,20 @@ impl<'a> Parser<'a> {
                 let assign_token = self.current_token.clone();
                 self.next_token_in_regex_context();
                 let right_value = self.parse_expression()?;
+                let start = identifier.span.start;
+                let left = Expression::Identifier(identifier.literal.into());
+                self.record(start, start + identifier.literal.len(), || {
+                    Node::Expression(left.clone())
+                });
+                let value = Expression::Infix {
+                    left: Box::new(left),
+                    operator: compound_assign_operator(&assign_token),
+                    right: Box::new(right_value),
+                };
+                self.record_expression(start, &value);
                 Ok(Statement::Assignment {
-                    identifier: identifier.literal,
-                    value: Expression::Infix {
-                        left: Box::new(Expression::Identifier(identifier.literal)),
-                        operator: compound_assign_operator(&assign_token),
-                        right: Box::new(right_value),
-                    },
+                    identifier: identifier.literal.into(),
+                    value,
                 })
             }
             _ => Err(self.unsupported_statement()),
@@ -559,7 +744,7 @@ impl<'a> Parser<'a> {
         self.next_token();
         if self.current_token.kind != TokenKind::LeftSquareBracket {
             return Ok(Statement::Delete {
-                identifier,
+                identifier: identifier.into(),
                 index: None,
             });
         }
@@ -571,19 +756,25 @@ impl<'a> Parser<'a> {
         }
         self.next_token();
         Ok(Statement::Delete {
-            identifier,
+            identifier: identifier.into(),
             index: Some(index),
         })
     }
 
-    fn parse_break_statement(&mut self) -> Statement<'a> {
+    fn parse_break_statement(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
+        if self.loop_depth == 0 {
+            return Err(self.jump_outside_loop());
+        }
         self.next_token();
-        Statement::Break
+        Ok(Statement::Break)
     }
 
-    fn parse_continue_statement(&mut self) -> Statement<'a> {
+    fn parse_continue_statement(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
+        if self.loop_depth == 0 {
+            return Err(self.jump_outside_loop());
+        }
         self.next_token();
-        Statement::Continue
+        Ok(Statement::Continue)
     }
 
     fn parse_pre_increment_statement(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
@@ -593,7 +784,9 @@ impl<'a> Parser<'a> {
         }
         let identifier = self.current_token.literal;
         self.next_token();
-        Ok(Statement::PreIncrement { identifier })
+        Ok(Statement::PreIncrement {
+            identifier: identifier.into(),
+        })
     }
 
     fn parse_pre_decrement_statement(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
@@ -603,7 +796,9 @@ impl<'a> Parser<'a> {
         }
         let identifier = self.current_token.literal;
         self.next_token();
-        Ok(Statement::PreDecrement { identifier })
+        Ok(Statement::PreDecrement {
+            identifier: identifier.into(),
+        })
     }
 
     fn parse_split_assignment_statement(
@@ -636,9 +831,9 @@ impl<'a> Parser<'a> {
         }
         self.next_token();
         Ok(Statement::SplitAssignment {
-            identifier,
+            identifier: identifier.into(),
             string,
-            array,
+            array: array.into(),
             separator,
         })
     }
@@ -671,14 +866,28 @@ impl<'a> Parser<'a> {
         self.next_token();
         Ok(Statement::Split {
             string,
-            array,
+            array: array.into(),
             separator,
         })
     }
 
     fn parse_field_assignment_statement(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
+        let start = self.current_start();
         self.next_token();
         let field = self.parse_primary_expression()?;
+        let field_end = self.previous_end;
+        if !matches!(
+            self.current_token.kind,
+            TokenKind::Assign
+                | TokenKind::AddAssign
+                | TokenKind::SubtractAssign
+                | TokenKind::MultiplyAssign
+                | TokenKind::DivideAssign
+                | TokenKind::ModuloAssign
+                | TokenKind::PowerAssign
+        ) {
+            return Err(self.unsupported_statement());
+        }
         let assign_token = self.current_token.clone();
         self.next_token_in_regex_context();
         let right_value = self.parse_expression()?;
@@ -687,11 +896,15 @@ impl<'a> Parser<'a> {
             right_value
         } else {
             let operator = compound_assign_operator(&assign_token);
-            Expression::Infix {
-                left: Box::new(Expression::Field(Box::new(field.clone()))),
+            let left = Expression::Field(Box::new(field.clone()));
+            self.record(start, field_end, || Node::Expression(left.clone()));
+            let value = Expression::Infix {
+                left: Box::new(left),
                 operator,
                 right: Box::new(right_value),
-            }
+            };
+            self.record_expression(start, &value);
+            value
         };
         Ok(Statement::FieldAssignment { field, value })
     }
@@ -712,6 +925,7 @@ impl<'a> Parser<'a> {
         self.skip_terminators();
 
         if self.current_token.kind == TokenKind::Else {
+            self.body_ends.push(self.current_token.span.start);
             self.next_token();
             let else_statements = self.parse_control_statement_body()?;
             return Ok(Statement::IfElse {
@@ -738,6 +952,9 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_return_statement(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
+        if !self.in_function {
+            return Err(self.return_outside_function());
+        }
         self.next_token();
         let value = if self.is_statement_terminator() {
             None
@@ -752,6 +969,13 @@ impl<'a> Parser<'a> {
         Statement::Next
     }
 
+    fn parse_nextfile_statement(&mut self) -> Statement<'a> {
+        self.next_token();
+        Statement::NextFile
+    }
+
+    /// Parse the statements of a block up to its closing brace, which is
+    /// left as the current token.
     fn parse_statement_block(&mut self) -> Result<Vec<Statement<'a>>, ParseError<'a>> {
         self.next_token(); // consume '{'
         let mut statements = Vec::new();
@@ -770,23 +994,43 @@ impl<'a> Parser<'a> {
         if self.current_token.kind != TokenKind::RightCurlyBrace {
             return Err(self.expected_right_brace());
         }
-        self.next_token();
         Ok(statements)
     }
 
+    /// Parse the body of a control statement and record where it ends: at
+    /// its closing brace, or at the token after a body without braces.
     fn parse_control_statement_body(&mut self) -> Result<Vec<Statement<'a>>, ParseError<'a>> {
         self.skip_newlines();
 
-        if self.current_token.kind == TokenKind::LeftCurlyBrace {
-            return self.parse_statement_block();
-        }
-
-        if self.current_token.kind == TokenKind::Semicolon {
-            self.next_token();
-            return Ok(vec![Statement::Empty]);
-        }
+        let statements = match self.current_token.kind {
+            TokenKind::LeftCurlyBrace => {
+                let statements = self.parse_statement_block()?;
+                self.body_ends.push(self.current_token.span.start);
+                self.next_token();
+                statements
+            }
+            TokenKind::Semicolon => {
+                let start = self.current_start();
+                self.next_token();
+                self.body_ends.push(self.current_token.span.start);
+                self.record_statement(start, &Statement::Empty);
+                vec![Statement::Empty]
+            }
+            _ => {
+                let statement = self.parse_statement()?;
+                self.body_ends.push(self.current_token.span.start);
+                vec![statement]
+            }
+        };
+        Ok(statements)
+    }
 
-        Ok(vec![self.parse_statement()?])
+    /// Parse the body of a loop, where `break` and `continue` are allowed.
+    fn parse_loop_body(&mut self) -> Result<Vec<Statement<'a>>, ParseError<'a>> {
+        self.loop_depth += 1;
+        let statements = self.parse_control_statement_body();
+        self.loop_depth -= 1;
+        statements
     }
 
     fn parse_while_statement(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
@@ -800,7 +1044,7 @@ impl<'a> Parser<'a> {
             return Err(self.expected_right_paren());
         }
         self.next_token();
-        let statements = self.parse_control_statement_body()?;
+        let statements = self.parse_loop_body()?;
         Ok(Statement::While {
             condition,
             statements,
@@ -809,7 +1053,7 @@ impl<'a> Parser<'a> {
 
     fn parse_do_statement(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
         self.next_token();
-        let statements = self.parse_control_statement_body()?;
+        let statements = self.parse_loop_body()?;
 
         self.skip_terminators();
 
@@ -840,6 +1084,7 @@ impl<'a> Parser<'a> {
         self.next_token();
         self.skip_newlines();
 
+        let init_start = self.current_start();
         let init = if self.current_token.kind == TokenKind::Semicolon {
             Statement::Empty
         } else if self.current_token.kind == TokenKind::Identifier {
@@ -856,10 +1101,10 @@ impl<'a> Parser<'a> {
                     return Err(self.expected_right_paren());
                 }
                 self.next_token();
-                let statements = self.parse_control_statement_body()?;
+                let statements = self.parse_loop_body()?;
                 return Ok(Statement::ForIn {
-                    variable: variable.literal,
-                    array,
+                    variable: variable.literal.into(),
+                    array: array.into(),
                     statements,
                 });
             }
@@ -867,6 +1112,7 @@ impl<'a> Parser<'a> {
         } else {
             self.parse_simple_statement()?
         };
+        self.record_header_statement(init_start, &init);
         self.skip_newlines();
         if self.current_token.kind != TokenKind::Semicolon {
             return Err(self.expected_semicolon());
@@ -875,6 +1121,8 @@ impl<'a> Parser<'a> {
         self.skip_newlines_in_regex_context();
 
         let condition = if self.current_token.kind == TokenKind::Semicolon {
+            let start = self.current_start();
+            self.record(start, start, || Node::Expression(Expression::Number(1.0)));
             Expression::Number(1.0)
         } else {
             self.parse_expression()?
@@ -886,17 +1134,19 @@ impl<'a> Parser<'a> {
         self.next_token_in_regex_context();
         self.skip_newlines_in_regex_context();
 
+        let update_start = self.current_start();
         let update = if self.current_token.kind == TokenKind::RightParen {
             Statement::Empty
         } else {
             self.parse_simple_statement()?
         };
+        self.record_header_statement(update_start, &update);
         self.skip_newlines();
         if self.current_token.kind != TokenKind::RightParen {
             return Err(self.expected_right_paren());
         }
         self.next_token();
-        let statements = self.parse_control_statement_body()?;
+        let statements = self.parse_loop_body()?;
 
         Ok(Statement::For {
             init: Box::new(init),
@@ -906,10 +1156,80 @@ impl<'a> Parser<'a> {
         })
     }
 
+    /// Record the initialization or update of a `for` loop, which is empty
+    /// at `start` when left out.
+    fn record_header_statement(&mut self, start: usize, statement: &Statement<'a>) {
+        let end = if *statement == Statement::Empty {
+            start
+        } else {
+            self.previous_end
+        };
+        self.record(start, end, || Node::Statement(statement.clone()));
+    }
+
     fn parse_print_function(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
+        self.next_token();
+        let expressions = self.parse_output_expression_list()?;
+
+        Ok(match self.parse_output_target()? {
+            OutputTarget::Stdout => Statement::Print(expressions),
+            OutputTarget::File { target, append } => Statement::PrintRedirect {
+                expressions,
+                target,
+                append,
+            },
+            OutputTarget::Pipe(target) => Statement::PrintPipe {
+                expressions,
+                target,
+            },
+        })
+    }
+
+    fn parse_printf_function(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
+        self.next_token();
+        let expressions = if self.current_token.kind == TokenKind::LeftParen {
+            self.next_token_in_regex_context();
+            let mut expressions = Vec::new();
+            while self.current_token.kind != TokenKind::RightParen
+                && self.current_token.kind != TokenKind::Eof
+            {
+                if self.current_token.kind == TokenKind::Comma {
+                    self.next_token();
+                    continue;
+                }
+                expressions.push(self.parse_expression()?);
+            }
+            if self.current_token.kind == TokenKind::RightParen {
+                self.next_token();
+            }
+            expressions
+        } else {
+            self.parse_output_expression_list()?
+        };
+
+        if expressions.is_empty() {
+            return Err(self.missing_printf_format_string());
+        }
+
+        Ok(match self.parse_output_target()? {
+            OutputTarget::Stdout => Statement::Printf(expressions),
+            OutputTarget::File { target, append } => Statement::PrintfRedirect {
+                expressions,
+                target,
+                append,
+            },
+            OutputTarget::Pipe(target) => Statement::PrintfPipe {
+                expressions,
+                target,
+            },
+        })
+    }
+
+    /// Parse the comma-separated arguments of `print` or `printf`, stopping
+    /// before any `>`, `>>` or `|` that redirects the output.
+    fn parse_output_expression_list(&mut self) -> Result<Vec<Expression<'a>>, ParseError<'a>> {
         let mut expressions = Vec::new();
         let mut expect_more = false;
-        self.next_token();
 
         loop {
             if self.current_token.kind == TokenKind::RightCurlyBrace
@@ -939,7 +1259,10 @@ impl<'a> Parser<'a> {
             }
 
             let started_with_left_paren = self.current_token.kind == TokenKind::LeftParen;
-            let expression = self.parse_expression()?;
+            let in_output_list = std::mem::replace(&mut self.in_output_list, true);
+            let expression = self.parse_expression_with_min_precedence(0);
+            self.in_output_list = in_output_list;
+            let expression = expression?;
             if started_with_left_paren {
                 if let Some(grouped_expressions) =
                     Self::split_print_parenthesized_list(expression.clone())
@@ -953,57 +1276,25 @@ impl<'a> Parser<'a> {
             }
             expect_more = false;
         }
-        if self.current_token.kind == TokenKind::GreaterThan
-            || self.current_token.kind == TokenKind::Append
-        {
-            let append = self.current_token.kind == TokenKind::Append;
-            self.next_token();
-            let target = self.parse_expression()?;
-            return Ok(Statement::PrintRedirect {
-                expressions,
-                target,
-                append,
-            });
-        }
-        if self.current_token.kind == TokenKind::Pipe {
-            self.next_token();
-            let target = self.parse_expression()?;
-            return Ok(Statement::PrintPipe {
-                expressions,
-                target,
-            });
-        }
 
-        Ok(Statement::Print(expressions))
+        Ok(expressions)
     }
 
-    fn parse_printf_function(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
-        self.next_token();
-        let expressions = if self.current_token.kind == TokenKind::LeftParen {
-            self.next_token_in_regex_context();
-            let mut expressions = Vec::new();
-            while self.current_token.kind != TokenKind::RightParen
-                && self.current_token.kind != TokenKind::Eof
-            {
-                if self.current_token.kind == TokenKind::Comma {
-                    self.next_token();
-                    continue;
-                }
-                expressions.push(self.parse_expression()?);
+    fn parse_output_target(&mut self) -> Result<OutputTarget<'a>, ParseError<'a>> {
+        match self.current_token.kind {
+            TokenKind::GreaterThan | TokenKind::Append => {
+                let append = self.current_token.kind == TokenKind::Append;
+                self.next_token();
+                let target = self.parse_expression()?;
+                Ok(OutputTarget::File { target, append })
             }
-            if self.current_token.kind == TokenKind::RightParen {
+            TokenKind::Pipe => {
                 self.next_token();
+                let target = self.parse_expression()?;
+                Ok(OutputTarget::Pipe(target))
             }
-            expressions
-        } else {
-            self.parse_expression_list_until_action_end_from_current()?
-        };
-
-        if expressions.is_empty() {
-            return Err(self.missing_printf_format_string());
+            _ => Ok(OutputTarget::Stdout),
         }
-
-        Ok(Statement::Printf(expressions))
     }
 
     fn parse_gsub_function(&mut self) -> Result<Statement<'a>, ParseError<'a>> {
@@ -1055,9 +1346,12 @@ impl<'a> Parser<'a> {
         self.next_token();
         let replacement = self.parse_expression()?;
 
-        if self.current_token.kind == TokenKind::Comma {
-            return Err(self.unsupported_sub_target());
-        }
+        let target = if self.current_token.kind == TokenKind::Comma {
+            self.next_token();
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
 
         if self.current_token.kind != TokenKind::RightParen {
             return Err(self.expected_right_paren());
@@ -1067,6 +1361,7 @@ impl<'a> Parser<'a> {
         Ok(Statement::Sub {
             pattern,
             replacement,
+            target,
         })
     }
 
@@ -1084,75 +1379,29 @@ impl<'a> Parser<'a> {
         Ok(Statement::System(command))
     }
 
-    fn parse_expression_list_until_action_end_from_current(
-        &mut self,
-    ) -> Result<Vec<Expression<'a>>, ParseError<'a>> {
-        let mut expressions = Vec::new();
-        let mut expect_more = false;
-
-        loop {
-            if self.current_token.kind == TokenKind::RightCurlyBrace
-                || self.current_token.kind == TokenKind::RightParen
-                || self.current_token.kind == TokenKind::Eof
-            {
-                break;
-            }
-
-            if self.current_token.kind == TokenKind::NewLine
-                || self.current_token.kind == TokenKind::Semicolon
-            {
-                if expect_more {
-                    self.next_token();
-                    continue;
-                }
-                break;
-            }
-
-            if self.current_token.kind == TokenKind::Comma {
-                self.next_token();
-                expect_more = true;
-                continue;
-            }
-
-            let started_with_left_paren = self.current_token.kind == TokenKind::LeftParen;
-            let expression = self.parse_expression()?;
-            expressions.push(expression);
-            if started_with_left_paren && self.current_token.kind == TokenKind::Comma {
-                while self.current_token.kind == TokenKind::Comma {
-                    self.next_token();
-                    expressions.push(self.parse_expression()?);
-                }
-                if self.current_token.kind != TokenKind::RightParen {
-                    return Err(self.expected_right_paren());
-                }
-                self.next_token();
-            }
-            expect_more = false;
-        }
-
-        Ok(expressions)
-    }
-
     fn parse_expression(&mut self) -> Result<Expression<'a>, ParseError<'a>> {
-        self.parse_expression_with_min_precedence(0)
+        let in_output_list = std::mem::replace(&mut self.in_output_list, false);
+        let expression = self.parse_expression_with_min_precedence(0);
+        self.in_output_list = in_output_list;
+        expression
     }
 
     fn parse_expression_with_min_precedence(
         &mut self,
         min_precedence: u8,
     ) -> Result<Expression<'a>, ParseError<'a>> {
+        let start = self.current_start();
         let left = self.parse_primary_expression()?;
-        self.parse_expression_suffix(left, min_precedence)
+        self.parse_expression_suffix(start, left, min_precedence)
     }
 
+    /// Parse the operators that follow `left`, which starts at `start`.
     fn parse_expression_suffix(
         &mut self,
+        start: usize,
         mut left: Expression<'a>,
         min_precedence: u8,
     ) -> Result<Expression<'a>, ParseError<'a>> {
-        const CONCAT_LEFT_PRECEDENCE: u8 = 6;
-        const CONCAT_RIGHT_PRECEDENCE: u8 = 7;
-
         loop {
             if self.current_token.kind == TokenKind::QuestionMark {
                 if min_precedence > 0 {
@@ -1170,6 +1419,7 @@ impl<'a> Parser<'a> {
                     then_expr: Box::new(then_expr),
                     else_expr: Box::new(else_expr),
                 };
+                self.record_expression(start, &left);
                 continue;
             }
 
@@ -1185,9 +1435,14 @@ impl<'a> Parser<'a> {
                     left: Box::new(left),
                     right: Box::new(right),
                 };
+                self.record_expression(start, &left);
                 continue;
             }
 
+            if self.in_output_list && self.current_token.kind == TokenKind::GreaterThan {
+                break;
+            }
+
             let (left_precedence, right_precedence) =
                 match infix_operator_precedence(&self.current_token.kind) {
                     Some(value) => value,
@@ -1214,12 +1469,14 @@ impl<'a> Parser<'a> {
                 operator,
                 right: Box::new(right),
             };
+            self.record_expression(start, &left);
         }
 
         Ok(left)
     }
 
     fn parse_condition_in_parens(&mut self) -> Result<Expression<'a>, ParseError<'a>> {
+        let start = self.current_start();
         let mut condition = self.parse_expression()?;
         if self.current_token.kind == TokenKind::Comma {
             while self.current_token.kind == TokenKind::Comma {
@@ -1231,19 +1488,33 @@ impl<'a> Parser<'a> {
                     operator,
                     right: Box::new(right),
                 };
+                self.record_expression(start, &condition);
             }
             if self.current_token.kind != TokenKind::RightParen {
                 return Err(self.expected_right_paren());
             }
             self.next_token();
-            condition = self.parse_expression_suffix(condition, 0)?;
+            condition = self.parse_expression_suffix(start, condition, 0)?;
         }
         Ok(condition)
     }
 
     fn parse_primary_expression(&mut self) -> Result<Expression<'a>, ParseError<'a>> {
-        if self.current_token.kind == TokenKind::Minus {
+        let start = self.current_start();
+        let expression = self.parse_unary_expression()?;
+        self.record_expression(start, &expression);
+        Ok(expression)
+    }
+
+    fn parse_unary_expression(&mut self) -> Result<Expression<'a>, ParseError<'a>> {
+        if matches!(self.current_token.kind, TokenKind::Minus | TokenKind::Plus) {
+            // Read `-x` as `0 - x` and `+x` as `0 + x`, which both convert the
+            // operand to a number.
             let operator = self.current_token.clone();
+            let start = operator.span.start;
+            self.record(start, start + 1, || {
+                Node::Expression(Expression::Number(0.0))
+            });
             self.next_token();
             let right = self.parse_primary_expression()?;
             return Ok(Expression::Infix {
@@ -1252,10 +1523,6 @@ impl<'a> Parser<'a> {
                 right: Box::new(right),
             });
         }
-        if self.current_token.kind == TokenKind::Plus {
-            self.next_token();
-            return self.parse_primary_expression();
-        }
         if self.current_token.kind == TokenKind::ExclamationMark {
             self.next_token_in_regex_context();
             let expression = self.parse_primary_expression()?;
@@ -1288,6 +1555,13 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_primary_atom(&mut self) -> Result<Expression<'a>, ParseError<'a>> {
+        let start = self.current_start();
+        let expression = self.parse_atom()?;
+        self.record_expression(start, &expression);
+        Ok(expression)
+    }
+
+    fn parse_atom(&mut self) -> Result<Expression<'a>, ParseError<'a>> {
         match self.current_token.kind {
             TokenKind::String => {
                 let expression = Expression::String(self.current_token.literal);
@@ -1313,6 +1587,7 @@ impl<'a> Parser<'a> {
             }
             TokenKind::LeftParen => {
                 self.next_token_in_regex_context();
+                let start = self.current_start();
                 let mut expression = self.parse_expression()?;
                 while self.current_token.kind == TokenKind::Comma {
                     let operator = self.current_token.clone();
@@ -1323,6 +1598,7 @@ impl<'a> Parser<'a> {
                         operator,
                         right: Box::new(right),
                     };
+                    self.record_expression(start, &expression);
                 }
                 if self.current_token.kind != TokenKind::RightParen {
                     return Err(self.expected_right_paren());
@@ -1350,11 +1626,11 @@ impl<'a> Parser<'a> {
                     }
                     self.next_token();
                     Ok(Expression::ArrayAccess {
-                        identifier: identifier.literal,
+                        identifier: identifier.literal.into(),
                         index: Box::new(index),
                     })
                 } else {
-                    Ok(Expression::Identifier(identifier.literal))
+                    Ok(Expression::Identifier(identifier.literal.into()))
                 }
             }
             TokenKind::Length => {
@@ -1414,9 +1690,11 @@ impl<'a> Parser<'a> {
                 }
                 Ok(Expression::Rand)
             }
-            TokenKind::Close
+            TokenKind::Atan2
+            | TokenKind::Close
             | TokenKind::Cos
             | TokenKind::Exp
+            | TokenKind::Gsub
             | TokenKind::Index
             | TokenKind::Int
             | TokenKind::Log
@@ -1425,7 +1703,11 @@ impl<'a> Parser<'a> {
             | TokenKind::Sprintf
             | TokenKind::Split
             | TokenKind::Sqrt
-            | TokenKind::Srand => {
+            | TokenKind::Srand
+            | TokenKind::Sub
+            | TokenKind::System
+            | TokenKind::ToLower
+            | TokenKind::ToUpper => {
                 let name = self.current_token.literal;
                 self.next_token();
                 if self.current_token.kind == TokenKind::LeftParen {
@@ -1438,27 +1720,129 @@ impl<'a> Parser<'a> {
         }
     }
 
-    pub fn try_parse_program(&mut self) -> Result<Program<'_>, ParseError<'a>> {
+    pub fn try_parse_program(&mut self) -> Result<Program<'a>, ParseError<'a>> {
         let mut program = Program::new();
-
-        while !self.is_eof() {
-            match self.parse_next_rule()? {
-                Some(Rule::Begin(action)) => program.add_begin_block(action),
-                Some(Rule::End(action)) => program.add_end_block(action),
-                Some(rule) => program.add_rule(rule),
-                None => {}
+        let mut function_definitions = Vec::new();
+
+        let items = self.try_parse_items()?;
+        self.statement_offsets = self.order_statement_starts(&items);
+        for item in items {
+            match item.kind {
+                ItemKind::Rule(Rule::Begin(action)) => program.add_begin_block(action),
+                ItemKind::Rule(Rule::End(action)) => program.add_end_block(action),
+                ItemKind::Rule(Rule::BeginFile(action)) => program.add_begin_file_block(action),
+                ItemKind::Rule(Rule::EndFile(action)) => program.add_end_file_block(action),
+                ItemKind::Rule(rule) => program.add_rule(rule),
+                ItemKind::Function(definition) => function_definitions.push(definition),
             }
-            self.next_token_in_regex_context();
         }
 
-        for definition in self.function_definitions.drain(..) {
+        for definition in function_definitions {
             program.add_function_definition(definition);
         }
 
         Ok(program)
     }
 
-    pub fn parse_program(&mut self) -> Program<'_> {
+    /// Rearrange the statement starts of `items`, which come in source order,
+    /// into the order of `Program::statement_lists`.
+    pub(crate) fn order_statement_starts(&self, items: &[Item<'a>]) -> Vec<usize> {
+        // BEGIN, BEGINFILE, other rules, ENDFILE, END, functions.
+        let mut groups: [Vec<usize>; 6] = Default::default();
+        let mut starts = self.statement_starts.iter().copied();
+        for item in items {
+            let (group, statements): (usize, &[Statement<'a>]) = match &item.kind {
+                ItemKind::Rule(Rule::Begin(action)) => (0, &action.statements),
+                ItemKind::Rule(Rule::BeginFile(action)) => (1, &action.statements),
+                ItemKind::Rule(
+                    Rule::Action(action)
+                    | Rule::PatternAction {
+                        action: Some(action),
+                        ..
+                    },
+                ) => (2, &action.statements),
+                ItemKind::Rule(Rule::PatternAction { action: None, .. }) => (2, &[]),
+                ItemKind::Rule(Rule::EndFile(action)) => (3, &action.statements),
+                ItemKind::Rule(Rule::End(action)) => (4, &action.statements),
+                ItemKind::Function(definition) => (5, &definition.statements),
+            };
+            visit_statements(statements, &mut |_| {
+                groups[group].extend(starts.next());
+            });
+        }
+        groups.concat()
+    }
+
+    /// Parse the rules and function definitions of a program in source order.
+    pub(crate) fn try_parse_items(&mut self) -> Result<Vec<Item<'a>>, ParseError<'a>> {
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_newlines_in_regex_context();
+            if self.is_eof() {
+                break;
+            }
+            let start = self.current_start();
+            let kind = if self.current_token.kind == TokenKind::Function {
+                ItemKind::Function(self.parse_function_definition()?)
+            } else {
+                match self.parse_next_rule()? {
+                    Some(rule) => ItemKind::Rule(rule),
+                    None => break,
+                }
+            };
+            let end = self.current_token.span.start;
+            items.push(Item { kind, start, end });
+            self.next_token_in_regex_context();
+        }
+
+        Ok(items)
+    }
+
+    /// The statements and expressions built so far with their source ranges,
+    /// inner ones before the ones containing them, if the parser is
+    /// [recording them](Parser::recording_spans). A node may be listed more
+    /// than once, and nodes the parser dropped are listed too.
+    pub(crate) fn spans(&self) -> &[Spanned<'a>] {
+        self.spans.as_deref().unwrap_or_default()
+    }
+
+    /// The comments skipped while parsing, in source order.
+    pub(crate) fn comments(&self) -> &[Comment<'a>] {
+        self.lexer.comments()
+    }
+
+    /// The source offset where each statement of the last program parsed by
+    /// [`Parser::try_parse_program`] starts. Statements are listed action by
+    /// action and function by function, in the order the evaluator keeps
+    /// them: `BEGIN` actions, `BEGINFILE` actions, the other rules, `ENDFILE`
+    /// actions, `END` actions and functions, each kind in source order. Within
+    /// one action or function, statements come in source order.
+    pub fn statement_offsets(&self) -> &[usize] {
+        &self.statement_offsets
+    }
+
+    /// The tokens read so far, in source order. After a parse error the
+    /// stream ends at the token that caused it.
+    pub fn tokens(&self) -> &[Token<'a>] {
+        &self.tokens
+    }
+
+    /// The start offset of every statement parsed, in the order a pre-order
+    /// walk of the syntax tree visits them. Statements in a `for` header are
+    /// not included.
+    pub(crate) fn statement_starts(&self) -> &[usize] {
+        &self.statement_starts
+    }
+
+    /// Where each body of a control statement ends, at its closing brace or
+    /// at the token after it, in the order the bodies end. The start of an
+    /// `else` follows the end of the body before it.
+    pub(crate) fn body_ends(&self) -> &[usize] {
+        &self.body_ends
+    }
+
+    pub fn parse_program(&mut self) -> Program<'a> {
         self.try_parse_program()
             .unwrap_or_else(|err| panic!("{err}"))
     }
@@ -1473,7 +1857,7 @@ impl<'a> Parser<'a> {
             && self.current_token.kind != TokenKind::Eof
         {
             if self.current_token.kind == TokenKind::Comma {
-                self.next_token();
+                self.next_token_in_regex_context();
                 continue;
             }
             args.push(self.parse_expression()?);
@@ -1485,7 +1869,7 @@ impl<'a> Parser<'a> {
     }
 }
 
-fn infix_operator_precedence(kind: &TokenKind) -> Option<(u8, u8)> {
+pub(crate) fn infix_operator_precedence(kind: &TokenKind) -> Option<(u8, u8)> {
     match kind {
         TokenKind::Assign
         | TokenKind::AddAssign
@@ -1521,8 +1905,11 @@ fn is_expression_start(kind: &TokenKind) -> bool {
             | TokenKind::DollarSign
             | TokenKind::LeftParen
             | TokenKind::Identifier
+            | TokenKind::Atan2
+            | TokenKind::Close
             | TokenKind::Cos
             | TokenKind::Exp
+            | TokenKind::Gsub
             | TokenKind::Index
             | TokenKind::Int
             | TokenKind::Length
@@ -1534,7 +1921,11 @@ fn is_expression_start(kind: &TokenKind) -> bool {
             | TokenKind::Split
             | TokenKind::Sqrt
             | TokenKind::Srand
+            | TokenKind::Sub
             | TokenKind::Substr
+            | TokenKind::System
+            | TokenKind::ToLower
+            | TokenKind::ToUpper
             | TokenKind::Increment
             | TokenKind::Decrement
     )
@@ -1700,15 +2091,26 @@ mod tests {
     }
 
     #[test]
-    fn parse_sub_with_target_returns_parse_error() {
+    fn parse_sub_with_target() {
         let mut parser = Parser::new(Lexer::new(r#"BEGIN { sub(/a/, "b", t) }"#));
 
-        let err = parser
-            .try_parse_program()
-            .expect_err("expected parse error for unsupported sub target argument");
+        let program = parser.parse_program();
 
-        assert_eq!(err.kind, ParseErrorKind::UnsupportedSubTarget);
-        assert_eq!(err.token.kind, TokenKind::Comma);
+        assert_eq!(program.to_string(), r#"BEGIN { sub(/a/, "b", t) }"#);
+    }
+
+    #[test]
+    fn parse_string_builtins_as_expressions() {
+        let mut parser = Parser::new(Lexer::new(
+            r#"BEGIN { n = gsub(/a/, "b", s) + sub(/c/, "d"); print toupper(x), tolower(y), atan2(0, -1), system("true") }"#,
+        ));
+
+        let program = parser.parse_program();
+
+        assert_eq!(
+            program.to_string(),
+            r#"BEGIN { n = gsub(/a/, "b", s) + sub(/c/, "d"); print toupper(x), tolower(y), atan2(0, 0 - 1), system("true") }"#
+        );
     }
 
     #[test]
@@ -1766,6 +2168,18 @@ mod tests {
         let _ = compound_assign_operator(&token);
     }
 
+    #[test]
+    fn parse_field_statement_without_assignment_returns_parse_error() {
+        let mut parser = Parser::new(Lexer::new("BEGIN { $1 > 0 }"));
+
+        let err = parser
+            .try_parse_program()
+            .expect_err("expected parse error for a field that is not assigned");
+
+        assert_eq!(err.kind, ParseErrorKind::UnsupportedStatement);
+        assert_eq!(err.token.kind, TokenKind::GreaterThan);
+    }
+
     #[test]
     fn parse_printf_expression_list_with_extra_right_paren_returns_parse_error() {
         let mut parser = Parser::new(Lexer::new(r#"BEGIN { printf "%s", 1) }"#));
@@ -2002,6 +2416,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_unary_plus_as_addition_to_zero() {
+        let mut parser = Parser::new(Lexer::new("BEGIN { print +x }"));
+
+        let program = parser.parse_program();
+        let mut begin_blocks = program.begin_blocks_iter();
+        let Action { statements } = begin_blocks.next().expect("expected begin block");
+
+        let exprs = match &statements[0] {
+            Statement::Print(expressions) => expressions,
+            _ => panic!("expected print statement"),
+        };
+
+        match &exprs[0] {
+            Expression::Infix {
+                left,
+                operator,
+                right,
+            } => {
+                assert_eq!(operator.kind, TokenKind::Plus);
+                assert!(matches!(**left, Expression::Number(0.0)));
+                assert_eq!(**right, Expression::Identifier("x".into()));
+            }
+            _ => panic!("expected infix expression"),
+        }
+    }
+
     #[test]
     fn parse_print_concatenation() {
         let mut parser = Parser::new(Lexer::new(r#"BEGIN { print "Value:" 42 }"#));
@@ -2027,7 +2468,7 @@ mod tests {
 
     #[test]
     fn parse_continue_statement() {
-        let mut parser = Parser::new(Lexer::new(r#"{ continue }"#));
+        let mut parser = Parser::new(Lexer::new(r#"{ while (1) continue }"#));
 
         let program = parser.parse_program();
         let mut rules = program.rules_iter();
@@ -2038,9 +2479,42 @@ mod tests {
             _ => panic!("expected action rule"),
         };
 
+        let Statement::While { statements, .. } = &statements[0] else {
+            panic!("expected while statement");
+        };
         assert!(matches!(statements[0], Statement::Continue));
     }
 
+    #[test]
+    fn parse_jumps_outside_loops_return_parse_errors() {
+        for source in [
+            "{ break }",
+            "{ if (1) continue }",
+            "{ while (1) x++; break }",
+            "function f() { break } { f() }",
+        ] {
+            let mut parser = Parser::new(Lexer::new(source));
+
+            let err = parser
+                .try_parse_program()
+                .expect_err("expected parse error for jump outside a loop");
+
+            assert_eq!(err.kind, ParseErrorKind::JumpOutsideLoop, "{source}");
+        }
+    }
+
+    #[test]
+    fn parse_return_outside_function_returns_parse_error() {
+        let mut parser = Parser::new(Lexer::new("function f() { return 1 } { return }"));
+
+        let err = parser
+            .try_parse_program()
+            .expect_err("expected parse error for return outside a function");
+
+        assert_eq!(err.kind, ParseErrorKind::ReturnOutsideFunction);
+        assert_eq!(err.token.span.start, 28);
+    }
+
     #[test]
     fn parse_identifier_followed_by_spaced_parentheses_as_concatenation() {
         let mut parser = Parser::new(Lexer::new(r#"{ x = $1; print x (++i) }"#));
@@ -2062,7 +2536,7 @@ mod tests {
         assert_eq!(exprs.len(), 1);
         match &exprs[0] {
             Expression::Concatenation { left, right } => {
-                assert!(matches!(**left, Expression::Identifier("x")));
+                assert_eq!(**left, Expression::Identifier("x".into()));
                 assert!(matches!(**right, Expression::PreIncrement(_)));
             }
             _ => panic!("expected concatenation expression"),
@@ -2143,9 +2617,9 @@ mod tests {
                 then_expr,
                 else_expr,
             } => {
-                assert!(matches!(**condition, Expression::Identifier("x")));
-                assert!(matches!(**then_expr, Expression::Identifier("y")));
-                assert!(matches!(**else_expr, Expression::Identifier("z")));
+                assert_eq!(**condition, Expression::Identifier("x".into()));
+                assert_eq!(**then_expr, Expression::Identifier("y".into()));
+                assert_eq!(**else_expr, Expression::Identifier("z".into()));
             }
             _ => panic!("expected ternary expression"),
         }
@@ -2567,6 +3041,73 @@ mod tests {
         assert_eq!(r#"{ print c ":" pop[c] | "sort" }"#, program.to_string());
     }
 
+    #[test]
+    fn parse_print_redirection_after_arguments() {
+        let mut parser = Parser::new(Lexer::new(r#"{ print $1, $2 > $3 ".txt" }"#));
+
+        let program = parser.parse_program();
+
+        assert_eq!(r#"{ print $1, $2 > $3 ".txt" }"#, program.to_string());
+    }
+
+    #[test]
+    fn parse_printf_redirection_and_pipe() {
+        let mut parser = Parser::new(Lexer::new(
+            r#"{ printf "%s\n", $1 > "out"; printf("%d\n", $2) >> "log"; printf "%s\n", $1 | "sort" }"#,
+        ));
+
+        let program = parser.parse_program();
+
+        assert_eq!(
+            r#"{ printf "%s\n", $1 > "out"; printf "%d\n", $2 >> "log"; printf "%s\n", $1 | "sort" }"#,
+            program.to_string()
+        );
+    }
+
+    #[test]
+    fn parse_printf_comparison_inside_parentheses_is_not_redirection() {
+        let mut parser = Parser::new(Lexer::new(
+            r#"{ printf "%d %d\n", ($1 > 2), f($2 > 3) > "out" }"#,
+        ));
+
+        let program = parser.parse_program();
+
+        let Some(Rule::Action(action)) = program.rules_iter().next() else {
+            panic!("expected an action rule");
+        };
+        let Statement::PrintfRedirect {
+            expressions,
+            target,
+            append: false,
+        } = &action.statements[0]
+        else {
+            panic!(
+                "expected printf redirection, got {:?}",
+                action.statements[0]
+            );
+        };
+        assert_eq!(expressions.len(), 3);
+        assert_eq!(expressions[1].to_string(), "$1 > 2");
+        assert_eq!(expressions[2].to_string(), "f($2 > 3)");
+        assert_eq!(target, &Expression::String("out"));
+    }
+
+    #[test]
+    fn parse_beginfile_endfile_and_nextfile() {
+        let mut parser = Parser::new(Lexer::new(
+            "BEGIN { n = 0 }\nBEGINFILE { print FILENAME }\nFNR == 2 { nextfile }\nENDFILE { print FNR }\nEND { print NR }",
+        ));
+
+        let program = parser.parse_program();
+
+        assert_eq!(program.begin_file_blocks_iter().count(), 1);
+        assert_eq!(program.end_file_blocks_iter().count(), 1);
+        assert_eq!(
+            "BEGIN { n = 0 } BEGINFILE { print FILENAME } FNR == 2 { nextfile } ENDFILE { print FNR } END { print NR }",
+            program.to_string()
+        );
+    }
+
     #[test]
     fn parse_hexadecimal_number() {
         let mut parser = Parser::new(Lexer::new(r#"BEGIN { print 0xAA }"#));
@@ -0,0 +1,285 @@
+//! Execution counts and times of the parts of a program.
+
+use std::{collections::HashMap, fmt::Write, time::Duration};
+
+use crate::{
+    Lexer, Parser,
+    ast::{FunctionDefinition, Program, Rule, Statement, visit_statements},
+    parse_error::ParseError,
+    parser::ItemKind,
+};
+
+/// How many times part of a program ran, and the time spent in it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cost {
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl Cost {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+/// The costs gathered by an [`Evaluator`](crate::Evaluator) run with
+/// [`Evaluator::with_profiling`](crate::Evaluator::with_profiling).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// One entry per rule: the `BEGIN` actions, the `BEGINFILE` actions, the
+    /// other rules, the `ENDFILE` actions and the `END` actions, each kind in
+    /// source order. A rule with a pattern counts every record it is tried on,
+    /// and its time includes testing the pattern.
+    pub rules: Vec<Cost>,
+    /// One entry per statement, in the order of
+    /// [`Parser::statement_offsets`]. The time of a statement includes the
+    /// statements nested in it and the functions it calls.
+    pub statements: Vec<Cost>,
+    /// One entry per function in source order, counting its calls.
+    pub functions: Vec<(String, Cost)>,
+}
+
+/// Gathers a [`Profile`]. Rules are given by their number, and the statements
+/// and functions the tree walker runs are found by their address.
+pub(crate) struct Profiler {
+    profile: Profile,
+    statements: HashMap<usize, usize>,
+    functions: HashMap<usize, usize>,
+}
+
+impl Profiler {
+    pub(crate) fn new(program: &Program<'_>) -> Self {
+        let rules = program.begin_blocks_iter().len()
+            + program.begin_file_blocks_iter().len()
+            + program.rules_iter().len()
+            + program.end_file_blocks_iter().len()
+            + program.end_blocks_iter().len();
+
+        let mut statements = HashMap::new();
+        for list in program.statement_lists() {
+            visit_statements(list, &mut |statement| {
+                statements.insert(address(statement), statements.len());
+            });
+        }
+
+        let mut functions = HashMap::new();
+        let mut function_costs = Vec::new();
+        for definition in program.function_definitions_iter() {
+            functions.insert(address(definition), functions.len());
+            function_costs.push((definition.name.to_string(), Cost::default()));
+        }
+
+        Self {
+            profile: Profile {
+                rules: vec![Cost::default(); rules],
+                statements: vec![Cost::default(); statements.len()],
+                functions: function_costs,
+            },
+            statements,
+            functions,
+        }
+    }
+
+    pub(crate) fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub(crate) fn statement(&self, statement: &Statement<'_>) -> Option<usize> {
+        self.statements.get(&address(statement)).copied()
+    }
+
+    pub(crate) fn function(&self, definition: &FunctionDefinition<'_>) -> Option<usize> {
+        self.functions.get(&address(definition)).copied()
+    }
+
+    pub(crate) fn add_rule(&mut self, index: usize, time: Duration) {
+        self.profile.rules[index].add(time);
+    }
+
+    pub(crate) fn add_statement(&mut self, index: usize, time: Duration) {
+        self.profile.statements[index].add(time);
+    }
+
+    pub(crate) fn add_function(&mut self, index: usize, time: Duration) {
+        self.profile.functions[index].1.add(time);
+    }
+}
+
+/// Print `source` with the count and time in milliseconds of `profile`, taken
+/// from a run of the same program, beside each line. A line shows the cost of
+/// the rule or function that starts on it, or else of its first statement.
+///
+/// # Examples
+///
+/// ```
+/// use rawk_core::{Evaluator, Lexer, Parser, annotate_profile};
+///
+/// let source = "{ n++ }\nEND { print n }";
+/// let program = Parser::new(Lexer::new(source)).parse_program();
+/// let mut evaluator = Evaluator::new(program, vec!["a".into(), "b".into()], "-")
+///     .with_profiling();
+/// evaluator.eval();
+///
+/// let annotated = annotate_profile(source, evaluator.profile().unwrap()).unwrap();
+/// let counts: Vec<&str> = annotated
+///     .lines()
+///     .map(|line| line.split_whitespace().next().unwrap())
+///     .collect();
+/// assert_eq!(counts, ["count", "2", "1"]);
+/// ```
+pub fn annotate_profile<'a>(source: &'a str, profile: &Profile) -> Result<String, ParseError<'a>> {
+    let mut parser = Parser::new(Lexer::new(source));
+    let items = parser.try_parse_items()?;
+    let line_of = |offset: usize| source[..offset].matches('\n').count();
+
+    // Rules and functions in the order of `Profile`: BEGIN, BEGINFILE, other
+    // rules, ENDFILE, END, functions.
+    let mut groups: [Vec<usize>; 6] = Default::default();
+    for item in &items {
+        let group = match &item.kind {
+            ItemKind::Rule(Rule::Begin(_)) => 0,
+            ItemKind::Rule(Rule::BeginFile(_)) => 1,
+            ItemKind::Rule(Rule::EndFile(_)) => 3,
+            ItemKind::Rule(Rule::End(_)) => 4,
+            ItemKind::Rule(_) => 2,
+            ItemKind::Function(_) => 5,
+        };
+        groups[group].push(item.start);
+    }
+    let [begin, begin_file, rules, end_file, end, functions] = groups;
+    let rule_starts = [begin, begin_file, rules, end_file, end].concat();
+    let item_costs = rule_starts.into_iter().zip(profile.rules.iter()).chain(
+        functions
+            .into_iter()
+            .zip(profile.functions.iter().map(|(_, cost)| cost)),
+    );
+    let statement_costs = parser
+        .order_statement_starts(&items)
+        .into_iter()
+        .zip(profile.statements.iter());
+
+    let mut line_costs: HashMap<usize, &Cost> = HashMap::new();
+    for (offset, cost) in item_costs {
+        line_costs.entry(line_of(offset)).or_insert(cost);
+    }
+    for (offset, cost) in statement_costs {
+        line_costs.entry(line_of(offset)).or_insert(cost);
+    }
+
+    let mut annotated = format!("{:>8} {:>10}\n", "count", "time (ms)");
+    for (number, line) in source.lines().enumerate() {
+        let columns = match line_costs.get(&number) {
+            Some(cost) => format!(
+                "{:>8} {:>10.3}",
+                cost.count,
+                cost.time.as_secs_f64() * 1000.0
+            ),
+            None => String::new(),
+        };
+        let _ = writeln!(annotated, "{}", format!("{columns:19}  {line}").trim_end());
+    }
+    Ok(annotated)
+}
+
+/// The address of `value`, which identifies a part of a program that stays in
+/// place while it runs.
+pub(crate) fn address<T>(value: &T) -> usize {
+    value as *const T as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Evaluator;
+
+    fn profile(source: &str, input: &[&str]) -> Profile {
+        profile_on(source, input, false)
+    }
+
+    fn profile_on(source: &str, input: &[&str], tree_walker: bool) -> Profile {
+        let program = Parser::new(Lexer::new(source)).parse_program();
+        let input = input.iter().map(|line| line.to_string()).collect();
+        let mut evaluator = Evaluator::new(program, input, "-").with_profiling();
+        if tree_walker {
+            evaluator = evaluator.with_tree_walker();
+        }
+        evaluator.eval();
+        evaluator.profile().unwrap().clone()
+    }
+
+    fn counts(costs: &[Cost]) -> Vec<u64> {
+        costs.iter().map(|cost| cost.count).collect()
+    }
+
+    #[test]
+    fn counts_rules_statements_and_function_calls() {
+        let source = "END { print total }\nfunction double(n) { return n * 2 }\n$1 > 1 { total += double($1) }\n/x/\nBEGIN { total = 0 }";
+        let profile = profile(source, &["1", "2", "3"]);
+
+        // BEGIN, the two rules, END.
+        assert_eq!(counts(&profile.rules), [1, 3, 3, 1]);
+        // total = 0, total += ..., print total, return.
+        assert_eq!(counts(&profile.statements), [1, 2, 1, 2]);
+        assert_eq!(profile.functions.len(), 1);
+        assert_eq!(profile.functions[0].0, "double");
+        assert_eq!(profile.functions[0].1.count, 2);
+    }
+
+    #[test]
+    fn nested_statements_are_counted_on_their_own() {
+        let profile = profile(
+            "BEGIN { for (i = 0; i < 3; i++) if (i % 2) n++; else m++ }",
+            &[],
+        );
+
+        // for, if, n++, m++.
+        assert_eq!(counts(&profile.statements), [1, 3, 1, 2]);
+        assert!(profile.statements[0].time >= profile.statements[1].time);
+    }
+
+    #[test]
+    fn bytecode_counts_match_the_tree_walker() {
+        let source = "function f(n) { while (n > 0) { if (n % 3) n--; else n -= 2 } return n }\n{ for (i = 0; i < 10; i++) { total += f($1 + i); if (i > 5) break } }\n/2/ { next }\nEND { print total }";
+        let input = ["1", "2", "3"];
+        let bytecode = profile_on(source, &input, false);
+        let tree = profile_on(source, &input, true);
+
+        assert_eq!(counts(&bytecode.rules), counts(&tree.rules));
+        assert_eq!(counts(&bytecode.statements), counts(&tree.statements));
+        assert_eq!(bytecode.functions[0].1.count, tree.functions[0].1.count);
+        assert_eq!(bytecode.functions[0].1.count, 21);
+    }
+
+    #[test]
+    fn annotates_each_line_with_its_rule_or_first_statement() {
+        let source = "function f(x) {\n    return x\n}\n\n{ n++; f($0) }\nEND {\n    print n\n}";
+        let profile = profile(source, &["a", "b"]);
+        let annotated = annotate_profile(source, &profile).unwrap();
+
+        let columns: Vec<(&str, &str)> = annotated
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let (count, source) = line.split_at(line.len().min(19));
+                (
+                    count.split_whitespace().next().unwrap_or_default(),
+                    source.trim(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            columns,
+            [
+                ("2", "function f(x) {"),
+                ("2", "return x"),
+                ("", "}"),
+                ("", ""),
+                ("2", "{ n++; f($0) }"),
+                ("1", "END {"),
+                ("1", "print n"),
+                ("", "}"),
+            ]
+        );
+    }
+}
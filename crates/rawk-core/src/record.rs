@@ -0,0 +1,269 @@
+use crate::csv;
+
+/// The current input record: the text of `$0` and its fields.
+///
+/// The fields are split lazily, at most once per record and only as far as
+/// the highest field asked for, with the field splitting in effect when the
+/// record was read. The strings holding them are kept for the fields of the
+/// next record. Assigning a field or `NF` changes the fields and marks the
+/// record dirty; the evaluator then rebuilds `$0` by joining the fields with
+/// `OFS` the next time the text is needed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Record {
+    text: String,
+    /// The fields split so far come first; the strings after them are left
+    /// from earlier records.
+    fields: Vec<String>,
+    len: usize,
+    /// Where in `text` splitting resumes, or `None` once every field is split.
+    rest: Option<usize>,
+    dirty: bool,
+}
+
+impl Record {
+    /// A record of `text`, whose fields are not split yet.
+    pub(crate) fn new(text: String) -> Self {
+        let mut record = Self::default();
+        record.reset(text);
+        record
+    }
+
+    /// Make `text` the record, dropping the fields of the last one but
+    /// keeping their strings.
+    pub(crate) fn reset(&mut self, text: String) {
+        self.rest = (!text.is_empty()).then_some(0);
+        self.text = text;
+        self.len = 0;
+        self.dirty = false;
+    }
+
+    /// The text of `$0`, which is stale while the record is dirty.
+    pub(crate) fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The fields split so far, which are all of them once
+    /// [`Record::is_split`] up to `None`.
+    pub(crate) fn fields(&self) -> &[String] {
+        &self.fields[..self.len]
+    }
+
+    /// Field `index`, counting from 1, or `None` past `NF` or the fields split
+    /// so far.
+    pub(crate) fn field(&self, index: usize) -> Option<&str> {
+        self.fields().get(index.checked_sub(1)?).map(String::as_str)
+    }
+
+    /// Whether the fields are split as far as field `index`, or all of them
+    /// with `None`.
+    pub(crate) fn is_split(&self, index: Option<usize>) -> bool {
+        self.rest.is_none() || index.is_some_and(|index| index <= self.len)
+    }
+
+    /// Whether fields changed since `$0` was last rebuilt.
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Split fields separated by runs of whitespace, ignoring whitespace at
+    /// either end, until field `index` or the end of the record.
+    pub(crate) fn split_whitespace(&mut self, index: Option<usize>) {
+        while let Some(start) = self.rest
+            && !self.is_split(index)
+        {
+            let remaining = &self.text[start..];
+            let Some(offset) = remaining.find(|c: char| !c.is_whitespace()) else {
+                self.rest = None;
+                break;
+            };
+            let start = start + offset;
+            let end = self.text[start..]
+                .find(char::is_whitespace)
+                .map_or(self.text.len(), |end| start + end);
+            self.push_field(start, end);
+            self.rest = (end < self.text.len()).then_some(end);
+        }
+    }
+
+    /// Split fields separated by `separator` until field `index` or the end
+    /// of the record.
+    pub(crate) fn split_on(&mut self, separator: char, index: Option<usize>) {
+        while let Some(start) = self.rest
+            && !self.is_split(index)
+        {
+            match self.text[start..].find(separator) {
+                Some(end) => {
+                    let end = start + end;
+                    self.push_field(start, end);
+                    self.rest = Some(end + separator.len_utf8());
+                }
+                None => {
+                    self.push_field(start, self.text.len());
+                    self.rest = None;
+                }
+            }
+        }
+    }
+
+    /// Split CSV fields, as [`csv::split_csv`] does, until field `index` or
+    /// the end of the record.
+    pub(crate) fn split_csv(&mut self, index: Option<usize>) {
+        while let Some(start) = self.rest
+            && !self.is_split(index)
+        {
+            let field = match self.fields.get_mut(self.len) {
+                Some(field) => {
+                    field.clear();
+                    field
+                }
+                None => {
+                    self.fields.push(String::new());
+                    &mut self.fields[self.len]
+                }
+            };
+            self.rest = csv::read_csv_field(&self.text[start..], field).map(|next| start + next);
+            self.len += 1;
+        }
+    }
+
+    /// Take `fields`, split some other way, as all the fields of the record.
+    pub(crate) fn set_fields(&mut self, fields: Vec<String>) {
+        self.len = fields.len();
+        self.fields = fields;
+        self.rest = None;
+    }
+
+    /// Store field `index`, counting from 1, adding empty fields up to it.
+    /// The record must be split.
+    pub(crate) fn set_field(&mut self, index: usize, value: String) {
+        while self.len < index {
+            self.push_empty_field();
+        }
+        self.fields[index - 1] = value;
+        self.dirty = true;
+    }
+
+    /// Truncate the fields to `count` or pad them with empty fields. The
+    /// record must be split.
+    pub(crate) fn set_field_count(&mut self, count: usize) {
+        self.len = self.len.min(count);
+        while self.len < count {
+            self.push_empty_field();
+        }
+        self.dirty = true;
+    }
+
+    /// Replace the stale text of `$0` with `text` rebuilt from the fields.
+    pub(crate) fn rebuild(&mut self, text: String) {
+        self.text = text;
+        self.dirty = false;
+    }
+
+    /// Add the text between `start` and `end` as the next field, in a string
+    /// left from an earlier record if there is one.
+    fn push_field(&mut self, start: usize, end: usize) {
+        let text = &self.text[start..end];
+        match self.fields.get_mut(self.len) {
+            Some(field) => {
+                field.clear();
+                field.push_str(text);
+            }
+            None => self.fields.push(text.to_string()),
+        }
+        self.len += 1;
+    }
+
+    fn push_empty_field(&mut self) {
+        match self.fields.get_mut(self.len) {
+            Some(field) => field.clear(),
+            None => self.fields.push(String::new()),
+        }
+        self.len += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(text: &str) -> Record {
+        let mut record = Record::new(text.to_string());
+        record.split_whitespace(None);
+        record
+    }
+
+    #[test]
+    fn set_field_pads_missing_fields_and_marks_record_dirty() {
+        let mut record = record("a b");
+
+        record.set_field(4, "d".to_string());
+
+        assert!(record.is_dirty());
+        assert_eq!(record.fields(), ["a", "b", "", "d"]);
+        assert_eq!(record.field(3), Some(""));
+        assert_eq!(record.field(5), None);
+        assert_eq!(record.field(0), None);
+    }
+
+    #[test]
+    fn rebuild_clears_dirty_flag() {
+        let mut record = record("a b c");
+
+        record.set_field_count(2);
+        record.rebuild(record.fields().join("-"));
+
+        assert!(!record.is_dirty());
+        assert_eq!(record.text(), "a-b");
+    }
+
+    #[test]
+    fn splits_only_as_far_as_asked() {
+        let mut record = Record::new("a,b,,d,".to_string());
+
+        record.split_on(',', Some(2));
+        assert_eq!(record.fields(), ["a", "b"]);
+        assert!(record.is_split(Some(2)));
+        assert!(!record.is_split(None));
+
+        record.split_on(',', None);
+        assert_eq!(record.fields(), ["a", "b", "", "d", ""]);
+        assert!(record.is_split(Some(9)));
+    }
+
+    #[test]
+    fn splits_csv_only_as_far_as_asked() {
+        let mut record = Record::new(r#""a,b",c,"say ""hi""","#.to_string());
+
+        record.split_csv(Some(2));
+        assert_eq!(record.fields(), ["a,b", "c"]);
+        assert!(!record.is_split(None));
+
+        record.split_csv(None);
+        assert_eq!(record.fields(), ["a,b", "c", r#"say "hi""#, ""]);
+        assert_eq!(record.fields(), csv::split_csv(record.text()));
+    }
+
+    #[test]
+    fn splits_whitespace_like_str_split_whitespace() {
+        for text in ["", "   ", " a  b\tc ", "a", "a b  ", "\ta\u{a0}b"] {
+            let mut record = Record::new(text.to_string());
+            record.split_whitespace(Some(1));
+            record.split_whitespace(None);
+            let expected: Vec<&str> = text.split_whitespace().collect();
+            assert_eq!(record.fields(), expected, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn reset_keeps_field_strings_for_the_next_record() {
+        let mut record = record("first second third");
+        let buffer = record.fields()[0].as_ptr();
+
+        record.reset("x".to_string());
+        assert!(record.fields().is_empty());
+        record.split_whitespace(None);
+
+        assert_eq!(record.fields(), ["x"]);
+        assert_eq!(record.fields()[0].as_ptr(), buffer);
+    }
+}
@@ -0,0 +1,396 @@
+use std::cmp::Ordering;
+
+use crate::{
+    Action, Evaluator, Lexer, ParseError, Parser, Program, Rule,
+    ast::Statement,
+    token::TokenKind,
+    value::{Value, format_awk_number, parse_numeric_string},
+};
+
+/// Where statements typed into a session are placed to be parsed.
+const STATEMENTS_PREFIX: &str = "BEGIN {\n";
+const STATEMENTS_SUFFIX: &str = "\n}";
+
+/// Where a lone expression is placed to be parsed, so that its value gets
+/// printed.
+const EXPRESSION_PREFIX: &str = "BEGIN { print (";
+const EXPRESSION_SUFFIX: &str = ") }";
+
+/// Variables and arrays the evaluator sets up itself, left out of
+/// [`Session::variables`] and [`Session::arrays`].
+const PREDEFINED: &[&str] = &["ARGC", "ARGV", "ENVIRON"];
+
+/// What came of one input to [`Session::execute`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The output of the input, which may be empty.
+    Output(Vec<String>),
+    /// The input stops in the middle of a statement or rule; more lines are
+    /// needed to run it.
+    Incomplete,
+    /// A parse or runtime error.
+    Error(String),
+}
+
+/// A program built up one input at a time, for an interactive session.
+///
+/// Each input to [`Session::execute`] is one of:
+///
+/// - rules and function definitions. `BEGIN` actions run at once, other rules
+///   are added to the program and run over the records read with
+///   [`Session::next_record`], and `END` actions run in [`Session::finish`].
+///   A function replaces any earlier function of the same name. Rules need an
+///   action, since a bare pattern reads as an expression.
+/// - statements, which run at once.
+/// - an expression, whose value is printed.
+///
+/// Variables, arrays, functions and the input position carry over from one
+/// input to the next.
+///
+/// ```
+/// use rawk_core::{Outcome, Session};
+///
+/// let mut session = Session::default();
+/// session.execute("function double(n) { return 2 * n }");
+/// session.execute("x = double(21)");
+///
+/// assert_eq!(session.execute("x"), Outcome::Output(vec!["42".to_string()]));
+/// assert_eq!(session.execute("if (x) {"), Outcome::Incomplete);
+/// ```
+pub struct Session {
+    evaluator: Evaluator<'static>,
+    /// The state of each range pattern among the rules, in order.
+    range_state: Vec<bool>,
+}
+
+impl Session {
+    /// Start a session running with `evaluator`, whose options and program
+    /// carry over into the session.
+    pub fn new(evaluator: Evaluator<'static>) -> Self {
+        Self {
+            evaluator,
+            range_state: Vec::new(),
+        }
+    }
+
+    /// Parse and run `source`. See [`Session`] for how it is read.
+    pub fn execute(&mut self, source: &str) -> Outcome {
+        if source.trim().is_empty() {
+            return Outcome::Output(Vec::new());
+        }
+
+        let program_error = match Parser::new(Lexer::new(source)).try_parse_program() {
+            Ok(program) if !has_bare_pattern(&program) => {
+                return self.add_program(source);
+            }
+            Ok(_) => None,
+            Err(err) => Some(err),
+        };
+
+        let wrapped = format!("{STATEMENTS_PREFIX}{source}{STATEMENTS_SUFFIX}");
+        let mut parser = Parser::new(Lexer::new(&wrapped));
+        let statement_error = match parser.try_parse_program() {
+            Ok(program) => {
+                let mut statements: Vec<Statement<'_>> = program
+                    .begin_blocks_iter()
+                    .flat_map(|action| action.statements.clone())
+                    .collect();
+                // A lone function call prints its result.
+                if let [Statement::Expression(expression)] = statements.as_slice() {
+                    statements = vec![Statement::Print(vec![expression.clone()])];
+                }
+                return self.run(&Action { statements });
+            }
+            Err(err) => err,
+        };
+
+        let wrapped = format!("{EXPRESSION_PREFIX}{source}{EXPRESSION_SUFFIX}");
+        if let Ok(program) = Parser::new(Lexer::new(&wrapped)).try_parse_program()
+            && let Some(action) = program.begin_blocks_iter().next()
+        {
+            return self.run(action);
+        }
+
+        let statement_error = shift_error(statement_error, STATEMENTS_PREFIX.len());
+        let end = source.trim_end().len();
+        let program_error_at_end = program_error
+            .as_ref()
+            .is_some_and(|err| err.token.kind == TokenKind::Eof);
+        if program_error_at_end || statement_error.token.span.start >= end {
+            return Outcome::Incomplete;
+        }
+
+        let error = match program_error {
+            Some(err) if err.token.span.start > statement_error.token.span.start => err,
+            _ => statement_error,
+        };
+        Outcome::Error(error.to_string())
+    }
+
+    /// Read records from `filename`, replacing any input opened before.
+    pub fn open_input(&mut self, filename: impl Into<String>) -> Result<Vec<String>, String> {
+        self.range_state.clear();
+        self.evaluator.open_input(filename.into())
+    }
+
+    /// Whether the input may have more records. This stays `true` until a
+    /// call to [`Session::next_record`] finds the input exhausted.
+    pub fn has_input(&self) -> bool {
+        self.evaluator.has_input()
+    }
+
+    /// Read the next record and run the rules over it.
+    pub fn next_record(&mut self) -> Result<Vec<String>, String> {
+        self.evaluator.run_next_record(&mut self.range_state)
+    }
+
+    /// Run the rules over the rest of the input, then the `END` actions.
+    pub fn finish(&mut self) -> Result<Vec<String>, String> {
+        let mut output = Vec::new();
+        while self.has_input() && !self.has_exited() {
+            output.extend(self.next_record()?);
+        }
+        output.extend(self.evaluator.run_end_actions()?);
+        Ok(output)
+    }
+
+    /// Whether an `exit` statement has run, which ends the session.
+    pub fn has_exited(&self) -> bool {
+        self.evaluator.has_exited()
+    }
+
+    /// The assigned global variables and their values, sorted by name.
+    /// Strings are quoted.
+    pub fn variables(&self) -> Vec<(String, String)> {
+        self.evaluator
+            .global_variables()
+            .into_iter()
+            .filter(|(name, _)| !PREDEFINED.contains(name))
+            .map(|(name, value)| (name.to_string(), describe(value)))
+            .collect()
+    }
+
+    /// The global arrays and their number of elements, sorted by name.
+    pub fn arrays(&self) -> Vec<(String, usize)> {
+        self.evaluator
+            .global_arrays()
+            .into_iter()
+            .filter(|(name, _)| !PREDEFINED.contains(name))
+            .map(|(name, len)| (name.to_string(), len))
+            .collect()
+    }
+
+    /// The elements of the array `name` as subscript and value, in numeric
+    /// order of the subscripts that are numbers followed by the others in
+    /// string order. Values are quoted as in [`Session::variables`].
+    pub fn array(&self, name: &str) -> Option<Vec<(String, String)>> {
+        let mut elements = self.evaluator.array_elements(name)?;
+        elements.sort_by(|(a, _), (b, _)| compare_subscripts(a, b));
+        Some(
+            elements
+                .into_iter()
+                .map(|(subscript, value)| (subscript, describe(&value)))
+                .collect(),
+        )
+    }
+
+    fn run(&mut self, action: &Action<'_>) -> Outcome {
+        match self.evaluator.run_action(action) {
+            Ok(output) => Outcome::Output(output),
+            Err(err) => Outcome::Error(err),
+        }
+    }
+
+    /// Add the rules and functions of `source` to the program and run its
+    /// `BEGIN` actions.
+    fn add_program(&mut self, source: &str) -> Outcome {
+        // The program keeps borrowing the source and the parser for as long
+        // as the session runs.
+        let source: &'static str = Box::leak(source.to_string().into_boxed_str());
+        let parser: &'static mut Parser<'static> =
+            Box::leak(Box::new(Parser::new(Lexer::new(source))));
+        let program = match parser.try_parse_program() {
+            Ok(program) => program,
+            Err(err) => return Outcome::Error(err.to_string()),
+        };
+
+        let target = self.evaluator.program_mut();
+        for definition in program.function_definitions_iter() {
+            target.define_function(definition.clone());
+        }
+        for rule in program.rules_iter() {
+            target.add_rule(rule.clone());
+        }
+        for action in program.begin_file_blocks_iter() {
+            target.add_begin_file_block(action.clone());
+        }
+        for action in program.end_file_blocks_iter() {
+            target.add_end_file_block(action.clone());
+        }
+        for action in program.end_blocks_iter() {
+            target.add_end_block(action.clone());
+        }
+
+        let mut output = Vec::new();
+        for action in program.begin_blocks_iter() {
+            match self.run(action) {
+                Outcome::Output(lines) => output.extend(lines),
+                outcome => return outcome,
+            }
+            if self.has_exited() {
+                break;
+            }
+        }
+        Outcome::Output(output)
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new(Evaluator::new(Program::new(), Vec::new(), "-").with_arguments(Vec::new()))
+    }
+}
+
+/// Whether `program` has a rule without an action, which is how a lone
+/// expression such as `x` or `$1 > 2` parses as a program.
+fn has_bare_pattern(program: &Program<'_>) -> bool {
+    program
+        .rules_iter()
+        .any(|rule| matches!(rule, Rule::PatternAction { action: None, .. }))
+}
+
+/// Report `err` at its offset in the text placed after `prefix`.
+fn shift_error(mut err: ParseError<'_>, prefix: usize) -> ParseError<'_> {
+    err.token.span.start = err.token.span.start.saturating_sub(prefix);
+    err
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Uninitialized => "(uninitialized)".to_string(),
+        Value::Number(number) => format_awk_number(*number),
+        Value::String(text) => format!("{text:?}"),
+        Value::StrNum(text, _) => text.clone(),
+    }
+}
+
+fn compare_subscripts(a: &str, b: &str) -> Ordering {
+    match (parse_numeric_string(a), parse_numeric_string(b)) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(lines: &[&str]) -> Outcome {
+        Outcome::Output(lines.iter().map(|line| line.to_string()).collect())
+    }
+
+    #[test]
+    fn state_carries_over_between_inputs() {
+        let mut session = Session::default();
+
+        assert_eq!(session.execute("x = 6; count[\"a\"]++"), output(&[]));
+        assert_eq!(
+            session.execute("function triple(n) { return 3 * n }"),
+            output(&[])
+        );
+        assert_eq!(
+            session.execute("print triple(x), count[\"a\"]"),
+            output(&["18 1"])
+        );
+        assert_eq!(session.execute("triple(x) + 1"), output(&["19"]));
+    }
+
+    #[test]
+    fn functions_can_be_redefined() {
+        let mut session = Session::default();
+
+        session.execute("function f() { return 1 }");
+        session.execute("function f() { return 2 }");
+
+        assert_eq!(session.execute("f()"), output(&["2"]));
+    }
+
+    #[test]
+    fn unfinished_input_is_incomplete() {
+        let mut session = Session::default();
+
+        assert_eq!(session.execute("if (x > 1) {"), Outcome::Incomplete);
+        assert_eq!(session.execute("function f(a,"), Outcome::Incomplete);
+        assert_eq!(session.execute("print 1 +"), Outcome::Incomplete);
+        assert_eq!(session.execute("$1 > 0 {\n  n++"), Outcome::Incomplete);
+        assert_eq!(
+            session.execute("for (i = 0; i < 3; i++) {\n  s = s i\n}\nprint s"),
+            output(&["012"])
+        );
+    }
+
+    #[test]
+    fn errors_are_reported_at_their_offset_in_the_input() {
+        let mut session = Session::default();
+
+        let Outcome::Error(message) = session.execute("print )") else {
+            panic!("expected an error");
+        };
+        assert!(
+            message.ends_with("at byte 6: expected statement"),
+            "{message}"
+        );
+
+        assert_eq!(
+            session.execute("FIELDWIDTHS = \"x\""),
+            Outcome::Error("invalid FIELDWIDTHS value \"x\"".to_string())
+        );
+        assert_eq!(session.execute("1 + 1"), output(&["2"]));
+    }
+
+    #[test]
+    fn records_are_stepped_through_the_rules() {
+        let path = std::env::temp_dir().join(format!("rawk-session-{}.txt", std::process::id()));
+        std::fs::write(&path, "a 1\nb 2\nc 3\n").unwrap();
+        let mut session = Session::default();
+        session.execute("$2 > 1 { total += $2; print $1 }");
+        session.execute("END { print \"total\", total }");
+
+        assert_eq!(session.open_input(path.to_string_lossy()), Ok(Vec::new()));
+        assert_eq!(session.next_record(), Ok(Vec::new()));
+        assert_eq!(session.next_record(), Ok(vec!["b".to_string()]));
+        assert_eq!(session.execute("$0"), output(&["b 2"]));
+        assert_eq!(
+            session.finish(),
+            Ok(vec!["c".to_string(), "total 5".to_string()])
+        );
+        assert!(!session.has_input());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn variables_and_arrays_can_be_inspected() {
+        let mut session = Session::default();
+        session.execute("n = 2; s = \"two\"; a[10] = 1; a[9] = \"x\"; a[\"k\"] = 3");
+
+        assert_eq!(
+            session.variables(),
+            [
+                ("n".to_string(), "2".to_string()),
+                ("s".to_string(), "\"two\"".to_string())
+            ]
+        );
+        assert_eq!(session.arrays(), [("a".to_string(), 3)]);
+        assert_eq!(
+            session.array("a").unwrap(),
+            [
+                ("9".to_string(), "\"x\"".to_string()),
+                ("10".to_string(), "1".to_string()),
+                ("k".to_string(), "3".to_string())
+            ]
+        );
+        assert_eq!(session.array("missing"), None);
+    }
+}
@@ -9,7 +9,9 @@ pub enum TokenKind {
 
     // Keywords.
     Begin,
+    BeginFile,
     End,
+    EndFile,
     Break,
     Continue,
     Delete,
@@ -21,6 +23,7 @@ pub enum TokenKind {
     If,
     In,
     Next,
+    NextFile,
     Print,
     Printf,
     Return,
@@ -119,7 +122,9 @@ impl Token<'_> {
 pub fn lookup_keyword(ident: &str) -> Option<TokenKind> {
     match ident {
         "BEGIN" => Some(TokenKind::Begin),
+        "BEGINFILE" => Some(TokenKind::BeginFile),
         "END" => Some(TokenKind::End),
+        "ENDFILE" => Some(TokenKind::EndFile),
         "break" => Some(TokenKind::Break),
         "continue" => Some(TokenKind::Continue),
         "delete" => Some(TokenKind::Delete),
@@ -131,6 +136,7 @@ pub fn lookup_keyword(ident: &str) -> Option<TokenKind> {
         "if" => Some(TokenKind::If),
         "in" => Some(TokenKind::In),
         "next" => Some(TokenKind::Next),
+        "nextfile" => Some(TokenKind::NextFile),
         "print" => Some(TokenKind::Print),
         "printf" => Some(TokenKind::Printf),
         "return" => Some(TokenKind::Return),
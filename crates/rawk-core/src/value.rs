@@ -0,0 +1,256 @@
+use std::cmp::Ordering;
+
+use crate::{CharMode, evaluator::format_printf};
+
+/// A runtime AWK value.
+///
+/// Strings that come from input data — fields, records read by `getline`,
+/// elements created by `split`, `ENVIRON` and `ARGV` — are *strnums* when they
+/// look like numbers: they keep their original text for printing but compare
+/// numerically, as POSIX requires.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+    /// A variable or array element that has never been assigned. It behaves as
+    /// both `0` and `""`.
+    #[default]
+    Uninitialized,
+    Number(f64),
+    String(String),
+    StrNum(String, f64),
+}
+
+impl Value {
+    /// Classify text read from input, producing a strnum when it looks numeric.
+    pub fn from_input(text: impl Into<String>) -> Self {
+        let text = text.into();
+        match parse_numeric_string(&text) {
+            Some(number) => Value::StrNum(text, number),
+            None => Value::String(text),
+        }
+    }
+
+    pub fn to_number(&self) -> f64 {
+        match self {
+            Value::Uninitialized => 0.0,
+            Value::Number(number) | Value::StrNum(_, number) => *number,
+            Value::String(text) => parse_awk_numeric(text),
+        }
+    }
+
+    /// Convert to a string, formatting non-integral numbers with `convfmt`.
+    pub fn to_string_with(&self, convfmt: &str) -> String {
+        match self {
+            Value::Uninitialized => String::new(),
+            Value::Number(number) => format_number(*number, convfmt),
+            Value::String(text) | Value::StrNum(text, _) => text.clone(),
+        }
+    }
+
+    pub fn into_string_with(self, convfmt: &str) -> String {
+        match self {
+            Value::Uninitialized => String::new(),
+            Value::Number(number) => format_number(number, convfmt),
+            Value::String(text) | Value::StrNum(text, _) => text,
+        }
+    }
+
+    pub fn is_true(&self) -> bool {
+        match self {
+            Value::Uninitialized => false,
+            Value::Number(number) | Value::StrNum(_, number) => *number != 0.0,
+            Value::String(text) => !text.is_empty(),
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        !matches!(self, Value::String(_))
+    }
+
+    /// Compare two values: numerically when both are numbers, strnums or
+    /// uninitialized, and as strings otherwise.
+    pub fn compare(&self, other: &Value, convfmt: &str) -> Ordering {
+        if self.is_numeric() && other.is_numeric() {
+            return self
+                .to_number()
+                .partial_cmp(&other.to_number())
+                .unwrap_or(Ordering::Equal);
+        }
+
+        self.to_string_with(convfmt)
+            .cmp(&other.to_string_with(convfmt))
+    }
+}
+
+/// Render a number the way AWK converts numbers to strings: integral values
+/// print as integers and everything else goes through `format` (`CONVFMT` or
+/// `OFMT`).
+pub fn format_number(value: f64, format: &str) -> String {
+    if format == "%.6g" || (value.is_finite() && value.fract() == 0.0) {
+        return format_awk_number(value);
+    }
+
+    format_printf(format, &[Value::Number(value)], "%.6g", CharMode::Utf8)
+}
+
+pub fn format_awk_number(value: f64) -> String {
+    if !value.is_finite() {
+        return value.to_string();
+    }
+
+    if value == 0.0 {
+        return "0".to_string();
+    }
+
+    if value.fract() == 0.0 {
+        return format!("{value:.0}");
+    }
+
+    let abs = value.abs();
+    let exponent = abs.log10().floor() as i32;
+    let decimals = (6 - exponent - 1).max(0) as usize;
+    let formatted = format!("{value:.decimals$}");
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Convert the longest numeric prefix of `input` to a number, as AWK does for
+/// string-to-number conversions. Text without a numeric prefix is `0`.
+pub fn parse_awk_numeric(input: &str) -> f64 {
+    let s = input.trim_start();
+    let end = numeric_prefix_len(s);
+    if end == 0 {
+        return 0.0;
+    }
+
+    s[..end].parse::<f64>().unwrap_or(0.0)
+}
+
+/// Parse `input` as a number only if the whole string (ignoring surrounding
+/// blanks) is a decimal floating-point constant.
+pub fn parse_numeric_string(input: &str) -> Option<f64> {
+    let s = input.trim_matches(|ch| matches!(ch, ' ' | '\t' | '\n'));
+    let end = numeric_prefix_len(s);
+    if end == 0 || end != s.len() {
+        return None;
+    }
+
+    s.parse::<f64>().ok()
+}
+
+fn numeric_prefix_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut idx = 0usize;
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        idx += 1;
+    }
+
+    let mut seen_digit = false;
+    while idx < bytes.len() && bytes[idx].is_ascii_digit() {
+        idx += 1;
+        seen_digit = true;
+    }
+    if idx < bytes.len() && bytes[idx] == b'.' {
+        idx += 1;
+        while idx < bytes.len() && bytes[idx].is_ascii_digit() {
+            idx += 1;
+            seen_digit = true;
+        }
+    }
+    if !seen_digit {
+        return 0;
+    }
+
+    if idx < bytes.len() && matches!(bytes[idx], b'e' | b'E') {
+        let mut exponent_end = idx + 1;
+        if matches!(bytes.get(exponent_end), Some(b'+' | b'-')) {
+            exponent_end += 1;
+        }
+        if bytes.get(exponent_end).is_some_and(u8::is_ascii_digit) {
+            while bytes.get(exponent_end).is_some_and(u8::is_ascii_digit) {
+                exponent_end += 1;
+            }
+            idx = exponent_end;
+        }
+    }
+
+    idx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_input_classifies_numeric_looking_text_as_strnum() {
+        assert_eq!(
+            Value::from_input(" 12.5e1 "),
+            Value::StrNum(" 12.5e1 ".to_string(), 125.0)
+        );
+        assert_eq!(
+            Value::from_input("12abc"),
+            Value::String("12abc".to_string())
+        );
+        assert_eq!(Value::from_input(""), Value::String(String::new()));
+        assert_eq!(Value::from_input("inf"), Value::String("inf".to_string()));
+        assert_eq!(Value::from_input("0x1A"), Value::String("0x1A".to_string()));
+    }
+
+    #[test]
+    fn strnums_compare_numerically_and_strings_compare_lexically() {
+        let ten = Value::from_input("10");
+        let nine = Value::from_input("9");
+        let ten_string = Value::String("10".to_string());
+
+        assert_eq!(ten.compare(&nine, "%.6g"), Ordering::Greater);
+        assert_eq!(ten_string.compare(&nine, "%.6g"), Ordering::Less);
+        assert_eq!(
+            Value::from_input("1.0").compare(&Value::Number(1.0), "%.6g"),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn uninitialized_compares_as_zero_and_as_empty_string() {
+        let uninitialized = Value::Uninitialized;
+
+        assert_eq!(
+            uninitialized.compare(&Value::Number(0.0), "%.6g"),
+            Ordering::Equal
+        );
+        assert_eq!(
+            uninitialized.compare(&Value::String(String::new()), "%.6g"),
+            Ordering::Equal
+        );
+        assert_eq!(
+            uninitialized.compare(&Value::String("0".to_string()), "%.6g"),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn truthiness_depends_on_value_kind() {
+        assert!(!Value::Uninitialized.is_true());
+        assert!(Value::String("0".to_string()).is_true());
+        assert!(!Value::from_input("0.0").is_true());
+        assert!(!Value::Number(0.0).is_true());
+        assert!(!Value::String(String::new()).is_true());
+    }
+
+    #[test]
+    fn parse_awk_numeric_reads_longest_numeric_prefix() {
+        assert_eq!(parse_awk_numeric("  3.5kg"), 3.5);
+        assert_eq!(parse_awk_numeric("1e3x"), 1000.0);
+        assert_eq!(parse_awk_numeric("1e"), 1.0);
+        assert_eq!(parse_awk_numeric("-.5"), -0.5);
+        assert_eq!(parse_awk_numeric("abc"), 0.0);
+    }
+
+    #[test]
+    fn numbers_convert_with_convfmt_unless_integral() {
+        assert_eq!(Value::Number(3.0).to_string_with("%.2f"), "3");
+        assert_eq!(Value::Number(1.23456).to_string_with("%.2f"), "1.23");
+        assert_eq!(Value::Number(1.23456).to_string_with("%.6g"), "1.23456");
+    }
+}
@@ -0,0 +1,225 @@
+use rawk_core::awk::Awk;
+
+fn run(script: &str, input: &[&str]) -> Vec<String> {
+    let input: Vec<String> = input.iter().map(|line| line.to_string()).collect();
+
+    let awk = Awk::new(script).unwrap_or_else(|err| panic!("failed to parse AWK script: {err}"));
+    awk.run(input, None, None)
+}
+
+fn assert_output(script: &str, input: &[&str], expected: &[&str]) {
+    assert_eq!(run(script, input), expected);
+}
+
+#[test]
+fn atan2() {
+    assert_output(
+        r#"BEGIN { printf "%.5f %.5f %.5f\n", atan2(0, -1), atan2(1, 0), atan2(-1, -1) }"#,
+        &[],
+        &["3.14159 1.57080 -2.35619"],
+    );
+}
+
+#[test]
+fn cos() {
+    assert_output(
+        r#"BEGIN { printf "%.5f %.5f\n", cos(0), cos(atan2(0, -1)) }"#,
+        &[],
+        &["1.00000 -1.00000"],
+    );
+}
+
+#[test]
+fn sin() {
+    assert_output(
+        r#"BEGIN { printf "%.5f %.5f\n", sin(0), sin(atan2(1, 0)) }"#,
+        &[],
+        &["0.00000 1.00000"],
+    );
+}
+
+#[test]
+fn exp() {
+    assert_output(
+        r#"BEGIN { printf "%.5f %g\n", exp(1), exp(0) }"#,
+        &[],
+        &["2.71828 1"],
+    );
+}
+
+#[test]
+fn log() {
+    assert_output(
+        r#"BEGIN { printf "%g %.5f\n", log(1), log(10) }"#,
+        &[],
+        &["0 2.30259"],
+    );
+}
+
+#[test]
+fn sqrt() {
+    assert_output(r#"BEGIN { print sqrt(16), sqrt(2) }"#, &[], &["4 1.41421"]);
+}
+
+#[test]
+fn int() {
+    assert_output(
+        r#"{ print int($1), int($2), int("12abc"), int(-0.5) }"#,
+        &["3.9 -3.9"],
+        &["3 -3 12 0"],
+    );
+}
+
+#[test]
+fn rand() {
+    assert_output(
+        r#"BEGIN { for (i = 0; i < 100; i++) { r = rand(); if (r < 0 || r >= 1) bad++ } print bad + 0 }"#,
+        &[],
+        &["0"],
+    );
+}
+
+#[test]
+fn srand() {
+    assert_output(
+        r#"BEGIN { first = srand(5); a = rand(); previous = srand(5); b = rand(); print first, previous, (a == b) }"#,
+        &[],
+        &["0 5 1"],
+    );
+    assert_output(
+        r#"BEGIN { srand(); print (srand() > 1000000000) }"#,
+        &[],
+        &["1"],
+    );
+}
+
+#[test]
+fn gsub() {
+    assert_output(
+        r#"{ n = gsub(/o/, "[&]"); m = gsub(/x/, "y"); print n, m, $0 }"#,
+        &["foo bor"],
+        &["3 0 f[o][o] b[o]r"],
+    );
+}
+
+#[test]
+fn index() {
+    assert_output(
+        r#"BEGIN { print index("banana", "nan"), index("banana", "x"), index("abc", "") }"#,
+        &[],
+        &["3 0 1"],
+    );
+}
+
+#[test]
+fn length() {
+    assert_output(
+        r#"{ print length(), length, length($1), length(12345), length("") }"#,
+        &["hello world"],
+        &["11 11 5 5 0"],
+    );
+}
+
+#[test]
+fn match_sets_rstart_and_rlength() {
+    assert_output(
+        r#"BEGIN { print match("foobar", /o+/), RSTART, RLENGTH; print match("foobar", /z/), RSTART, RLENGTH }"#,
+        &[],
+        &["2 2 2", "0 0 -1"],
+    );
+}
+
+#[test]
+fn split() {
+    assert_output(
+        r#"BEGIN { n = split("a:b:c", parts, ":"); m = split("  x  y ", words); print n, parts[1] parts[3], m, words[2] }"#,
+        &[],
+        &["3 ac 2 y"],
+    );
+}
+
+#[test]
+fn sprintf() {
+    assert_output(
+        r#"BEGIN { s = sprintf("%-4s|%5.2f|%03d|%x|%c|%e", "ab", 3.14159, 7, 255, 65, 1234.5); print s }"#,
+        &[],
+        &["ab  | 3.14|007|ff|A|1.234500e+03"],
+    );
+}
+
+#[test]
+fn sub() {
+    assert_output(
+        r#"{ n = sub(/o/, "0"); t = "aaa"; m = sub(/a/, "b", t); sub(/r$/, "R", $2); print n, m, t, $0 }"#,
+        &["foo bar"],
+        &["1 1 baa f0o baR"],
+    );
+}
+
+#[test]
+fn substr() {
+    assert_output(
+        r#"BEGIN { s = "hello"; print substr(s, 2), substr(s, 2, 3), substr(s, 0, 2), substr(s, 1.5, 2.5), substr(s, -1), substr(s, 4, 100) "|" substr(s, 9) "|" }"#,
+        &[],
+        &["ello ell h el hello lo||"],
+    );
+}
+
+#[test]
+fn tolower() {
+    assert_output(
+        r#"{ print tolower($0) }"#,
+        &["Hello, WORLD 42"],
+        &["hello, world 42"],
+    );
+}
+
+#[test]
+fn toupper() {
+    assert_output(
+        r#"{ print toupper($0) }"#,
+        &["Hello, world 42"],
+        &["HELLO, WORLD 42"],
+    );
+}
+
+#[test]
+fn close() {
+    assert_output(
+        r#"BEGIN { print "b" | "sort"; print "a" | "sort"; r = close("sort"); print "done", r, close("sort") }"#,
+        &[],
+        &["a", "b", "done 0 -1"],
+    );
+}
+
+#[test]
+fn system() {
+    assert_output(
+        r#"BEGIN { printf "before "; status = system("echo inside; exit 3"); print "after", status }"#,
+        &[],
+        &["before inside", "after 3"],
+    );
+}
+
+#[test]
+fn csvquote() {
+    assert_output(
+        r#"{ print csvquote($1) "," csvquote($2) "," csvquote($3) }"#,
+        &[r#"plain a,b x"y"#],
+        &[r#"plain,"a,b","x""y""#],
+    );
+}
+
+#[test]
+fn patsplit() {
+    assert_output(
+        r#"BEGIN { n = patsplit("ab12cd345", parts, /[0-9]+/, seps); print n, parts[1], parts[2], seps[0] "|" seps[1] "|" seps[2] "|" }"#,
+        &[],
+        &["2 12 345 ab|cd||"],
+    );
+    assert_output(
+        r#"BEGIN { FPAT = "[a-z]+" } { print patsplit($0, words), words[3] }"#,
+        &["one, two; three"],
+        &["3 three"],
+    );
+}
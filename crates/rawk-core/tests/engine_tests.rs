@@ -0,0 +1,144 @@
+//! Run programs on both the bytecode evaluator and the tree walker it is
+//! checked against, and compare their output.
+
+use std::fs;
+
+use rawk_core::{Evaluator, Lexer, Parser};
+
+const TEST_DATA: &str = "tests/onetrueawk-testdata";
+
+/// Steps allowed per run, so that a program waiting for input that never
+/// comes fails instead of hanging.
+const STEP_LIMIT: u64 = 5_000_000;
+
+fn run(
+    script: &str,
+    input: &[String],
+    filename: &str,
+    tree_walker: bool,
+) -> Option<(Vec<String>, Option<String>)> {
+    let program = Parser::new(Lexer::new(script)).try_parse_program().ok()?;
+    let mut evaluator = Evaluator::new(program, input.to_vec(), filename)
+        .with_environ([("HOME", "/home/awk")])
+        .with_step_limit(STEP_LIMIT);
+    if tree_walker {
+        evaluator = evaluator.with_tree_walker();
+    }
+    let output = evaluator.eval();
+    Some((output, evaluator.runtime_error().map(str::to_string)))
+}
+
+/// Compare both evaluators on `script`, returning a description of how they
+/// differ, if they do.
+fn difference(script: &str, input: &[String], filename: &str) -> Option<String> {
+    let bytecode = run(script, input, filename, false)?;
+    let tree = run(script, input, filename, true)?;
+    (bytecode != tree).then(|| format!("bytecode: {bytecode:?}\ntree walker: {tree:?}"))
+}
+
+fn assert_engines_agree_on_corpus(prefix: &str, data: &str) {
+    let filename = format!("{TEST_DATA}/{data}");
+    let input: Vec<String> = fs::read_to_string(&filename)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    let mut scripts: Vec<_> = fs::read_dir(TEST_DATA)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            name.starts_with(prefix) && name.ends_with(".awk")
+        })
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    let failures: Vec<String> = scripts
+        .iter()
+        .filter_map(|path| {
+            let script = fs::read_to_string(path).unwrap();
+            let difference = difference(&script, &input, &filename)?;
+            Some(format!("{}:\n{difference}", path.display()))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
+
+#[test]
+fn engines_agree_on_onetrueawk_p_programs() {
+    assert_engines_agree_on_corpus("p.", "countries");
+}
+
+#[test]
+fn engines_agree_on_onetrueawk_t_programs() {
+    assert_engines_agree_on_corpus("t.", "data");
+}
+
+#[test]
+fn engines_agree_on_control_flow_and_scoping() {
+    let input: Vec<String> = ["a b c", "1 2 3", "x", "", "4 5 6 7"]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+    let scripts = [
+        // Comparisons inside a ternary used as a condition.
+        "{ if (NF > 2 ? $1 < $2 : $1 > \"m\") print \"yes\", NR; else print \"no\", NR }",
+        "{ while (i < NF && $i != \"c\") i++; print i; i = 0 }",
+        "{ for (i = NF; i > 0; i--) { if ($i == 2) continue; if ($i == 6) break; printf \"%s.\", $i } print \"\" }",
+        "{ do { n++ } while (n % 3); print n }",
+        "NR == 2, NR == 4 { print \"range\", $0 }",
+        "/^[0-9]/ { s += $1 } !/x/ { c++ } END { print s, c }",
+        "$2 ~ \"^[0-9]+$\" { print $2 } $1 !~ /a/ { print \"!a\" }",
+        // Assignments, increments and compound operators on every kind of place.
+        "{ $2 = NR; x += $2; a[$1] += NR; a[$1]++; ++a[NR]; $3 *= 2; print; print x, a[$1], a[NR], NF }",
+        "{ NF = 2; print; $5 = \"e\"; print; print NF }",
+        "{ y = z = NR; print y z, (y += 2) * 2, y-- + --y, y }",
+        "{ OFS = \"-\"; $1 = $1; print; FS = \",\" } END { print FS, OFS }",
+        "BEGIN { CONVFMT = \"%.2f\"; x = 3.14159; a[x] = 1; for (k in a) print k; print (x \"\") }",
+        "{ k[$1, NR] = NR } END { for (i in k) { split(i, p, SUBSEP); print p[1], p[2] } print ((\"x\", 3) in k) }",
+        "{ delete seen; for (i = 1; i <= NF; i++) seen[$i]; print length(seen) }",
+        "END { print length(), length(\"abc\"), substr(\"hello\", 2, 3), substr(\"hello\", 3) }",
+        // Functions: recursion, array parameters, locals and early exits.
+        "function fact(n) { return n < 2 ? 1 : n * fact(n - 1) } { print fact(NR) }",
+        "function fill(arr, n,   i) { for (i = 1; i <= n; i++) arr[i] = i * i } END { fill(sq, 4); for (k in sq) print k, sq[k]; print i }",
+        "function f(x) { x[1] = 5 } END { f(a); print a[1]; f(b); print length(b) }",
+        "function g(n) { if (n > 2) next; return n } { print g(NR) } END { print \"end\" }",
+        "function h() { while (1) return 7 } END { print h(), h() + 1 }",
+        "function noreturn(s) { s = s \"!\" } END { print \"[\" noreturn(\"a\") \"]\" }",
+        "function out(s) { print \"in\", s; return s } END { print out(1), out(2) }",
+        "function shadow(NR, FS) { NR = 5; FS = \":\"; return NR FS } { print shadow(1), NR, FS }",
+        "{ if (NR == 3) exit; print } END { print \"done\", NR }",
+        "NR == 2 { next } { print NR }",
+        "{ printf \"%s|\", $1 } NR == 3 { print \"\" } END { printf \"%d%%\\n\", 50 }",
+        "BEGIN { while ((getline line) > 0) n++; print n, line }",
+        "{ getline; print \"got\", $0 }",
+        "{ sub(/[0-9]/, \"#\"); gsub(\"b\", \"B\"); print; n = split($0, parts); print n, parts[1] }",
+        "{ print > \"/dev/null\"; print | \"sort\" }",
+        // Builtins, redirections and range patterns.
+        "{ t = $0; n = gsub(/[a-z]/, \"<&>\", t); a[NR] = $1; sub(\"[0-9]\", \"#\", a[NR]); sub(/./, \"_\", $2); print n, t, a[NR], $0, sub(/z/, \"\", \"lit\") }",
+        "{ print match($0, /[0-9]+/), RSTART, RLENGTH, match($0, \"b\"), RSTART, match($0) }",
+        "{ n = split($0, p, /[ ]+/); m = split($0, q, \"\"); k = patsplit($0, w, /[a-z0-9]/, sep); print n, m, k, p[1], w[k], length(sep), length(w), length(x), length($1) }",
+        "BEGIN { x = \"abc\"; print length(x), index(\"hello\", \"ll\"), index(\"a\"), (atan2(0, -1) > 3), int(-2.5), sqrt(16), exp(0), log(1), sin(0), cos(0), tolower(\"AbC\") toupper(\"aBc\"), csvquote(\"a,b\"), sprintf(\"%d-%s\", 7, \"x\"), sprintf(), undefined(1, y++), y }",
+        "{ print NR > \"/dev/null\"; printf \"%s\", $1 >> \"/dev/null\"; print \"x\" | \"cat >/dev/null\"; printf \"y\\n\" | \"cat >/dev/null\" } END { print close(\"cat >/dev/null\"), close(\"nope\"), system(\"exit 3\"), fflush() }",
+        "NR == 2, /x/ { print \"in\", NR } /^[0-9]/, /^[0-9]/ { print \"one\", NR }",
+        "BEGIN { print (3 += 4), srand(5); $0 = \"a b\"; print length(), split(\"a:b\", parts, \":\"), parts[2] }",
+        "BEGIN { x[1]; if (1 in x) print \"in\"; if (!(2 in x)) print \"not in\"; print !0, !\"\", !\"a\" }",
+        "BEGIN { print 1 == 1.0, \"10\" < \"9\", 10 < 9, \"a\" < \"b\", -3 % 2, 2 ^ 10, 7 / 2 }",
+        "{ print ($1 < $2), ($1 == 1) }",
+        "BEGIN { srand(1); print rand() < 1, int(3.9), sprintf(\"%05.1f\", 2.25) }",
+        // Strings built up in place and regular expressions computed at run time.
+        "function g() { w = \"reset\"; return \"!\" } BEGIN { CONVFMT = \"%.2f\"; s = 0.125; s = s \"x\" 1.5; t = t \"a\" (t = \"b\"); u = 1; u = u u; w = \"w\"; w = w g(); print s, t, u, w } { line = line $1 (NR % 2 ? \",\" : \";\") $NF; n = n (n ~ /1/) } END { print line, n }",
+        "function f(p) { p = p \"<\" $1 \">\" substr(p, 1, 1); return p } { k = k (k == \"\" ? $2 : length(k)); print f($3), k }",
+        "{ re = NR % 2 ? \"^[0-9]\" : \"[a-z]+$\"; print ($0 ~ re), ($0 !~ re \"|,\"), match($0, re), RSTART, split($0, parts, re \"| \"), parts[1], gsub(re, \"<&>\"), sub(re, \"#\", parts[2]), parts[2], $0 }",
+    ];
+
+    let failures: Vec<String> = scripts
+        .iter()
+        .filter_map(|script| {
+            let difference = difference(script, &input, "-")?;
+            Some(format!("{script}\n{difference}"))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
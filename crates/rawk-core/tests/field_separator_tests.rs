@@ -0,0 +1,96 @@
+use rawk_core::awk::Awk;
+
+fn run(script: &str, input: &[&str], field_separator: Option<&str>) -> Vec<String> {
+    let input: Vec<String> = input.iter().map(|line| line.to_string()).collect();
+
+    let awk = Awk::new(script).unwrap_or_else(|err| panic!("failed to parse AWK script: {err}"));
+    awk.run(input, None, field_separator.map(str::to_string))
+}
+
+fn assert_fields(field_separator: &str, input: &str, expected: &[&str]) {
+    assert_eq!(
+        run(
+            r#"{ for (i = 1; i <= NF; i++) print i ":" $i }"#,
+            &[input],
+            Some(field_separator),
+        ),
+        expected
+    );
+}
+
+#[test]
+fn default_separator_splits_on_runs_of_blanks() {
+    assert_fields(" ", "  a \t b  c ", &["1:a", "2:b", "3:c"]);
+}
+
+#[test]
+fn single_character_is_a_literal_separator() {
+    assert_fields("|", "a|b||c", &["1:a", "2:b", "3:", "4:c"]);
+    assert_fields(".", "192.168.0.1", &["1:192", "2:168", "3:0", "4:1"]);
+    assert_fields("*", "x*y", &["1:x", "2:y"]);
+}
+
+#[test]
+fn single_tab_is_not_whitespace_splitting() {
+    assert_fields("\t", "a\t\tb c", &["1:a", "2:", "3:b c"]);
+}
+
+#[test]
+fn longer_separator_is_an_extended_regex() {
+    assert_fields("[,;]+", "a,;b;c", &["1:a", "2:b", "3:c"]);
+    assert_fields("a|b", "1a2b3", &["1:1", "2:2", "3:3"]);
+}
+
+#[test]
+fn t_option_means_tab() {
+    assert_fields("t", "at b\tc", &["1:at b", "2:c"]);
+}
+
+#[test]
+fn option_escape_sequences_are_processed() {
+    assert_fields(r"\t", "a b\tc", &["1:a b", "2:c"]);
+    assert_fields(r"\\", r"a\b", &["1:a", "2:b"]);
+}
+
+#[test]
+fn option_octal_and_control_escapes_are_processed() {
+    assert_fields(r"\072", "a:b:c", &["1:a", "2:b", "3:c"]);
+    assert_fields(r"\a", "a\x07b", &["1:a", "2:b"]);
+    assert_fields(r"\/", "a/b", &["1:a", "2:b"]);
+}
+
+#[test]
+fn assigned_fs_follows_the_same_rules() {
+    assert_eq!(
+        run(
+            r#"BEGIN { FS = "." } { print $2; n = split("1|2|3", parts, "|"); print n, parts[3] }"#,
+            &["a.b.c"],
+            None,
+        ),
+        vec!["b", "3 3"]
+    );
+}
+
+#[test]
+fn assigned_fs_applies_from_the_next_record() {
+    assert_eq!(
+        run(
+            r#"{ FS = ","; print $1, NF } NR == 1 { $0 = $0; print $1 }"#,
+            &["a,b c", "d,e f"],
+            None,
+        ),
+        vec!["a,b 2", "a", "d 2"]
+    );
+}
+
+#[test]
+fn fields_past_the_last_one_split_are_still_found() {
+    assert_eq!(
+        run(
+            r#"{ print $2; print $4; print $3; print NF; $6 = "z"; print }"#,
+            &["a:b:c:d"],
+            Some(":"),
+        ),
+        vec!["b", "d", "c", "4", "a b c d  z"]
+    );
+}
@@ -0,0 +1,191 @@
+use std::fs;
+
+use rawk_core::{Lexer, Parser, awk::Awk, format_program};
+use regex::Regex;
+
+/// The syntax tree of `source` with token positions removed, so that trees
+/// parsed from differently laid out sources compare equal.
+fn syntax_tree(source: &str) -> Option<String> {
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.try_parse_program().ok()?;
+    let tree = format!("{program:?}");
+    let spans = Regex::new(r"span: Location \{ start: \d+ \}").unwrap();
+    Some(spans.replace_all(&tree, "").into_owned())
+}
+
+fn run(script: &str) -> Vec<String> {
+    let awk = Awk::new(script).unwrap_or_else(|err| panic!("failed to parse AWK script: {err}"));
+    awk.run(
+        Vec::new(),
+        Some("onetrueawk-testdata/countries".to_string()),
+        None,
+    )
+}
+
+#[test]
+fn formatted_test_programs_reparse_into_the_same_tree() {
+    let mut formatted_count = 0;
+    let mut entries: Vec<_> = fs::read_dir("tests/onetrueawk-testdata")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "awk"))
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        let source = fs::read_to_string(&path).unwrap();
+        let Some(tree) = syntax_tree(&source) else {
+            continue;
+        };
+        let formatted = format_program(&source)
+            .unwrap_or_else(|err| panic!("{}: failed to format: {err}", path.display()));
+
+        assert_eq!(
+            syntax_tree(&formatted).as_deref(),
+            Some(tree.as_str()),
+            "{}: formatted program parses differently:\n{formatted}",
+            path.display()
+        );
+        assert_eq!(
+            format_program(&formatted).unwrap(),
+            formatted,
+            "{}: formatting is not idempotent",
+            path.display()
+        );
+        formatted_count += 1;
+    }
+
+    assert!(
+        formatted_count > 100,
+        "only {formatted_count} programs parsed"
+    );
+}
+
+#[test]
+fn formatted_program_produces_the_same_output() {
+    let source = include_str!("onetrueawk-testdata/p.26.awk");
+    let formatted = format_program(source).unwrap();
+
+    assert_eq!(run(&formatted), run(source));
+}
+
+#[test]
+fn formats_nested_control_flow_with_comments() {
+    let source = "\
+# tally the first column
+{ if ($1 > 0) count++   # positive
+  else if ($1<0) { neg++ } else zero++
+
+  # totals
+  sum+=$1 }
+END{for(i=0;i<3;i++);printf(\"%d %d\\n\",count,sum)}
+";
+
+    assert_eq!(
+        format_program(source).unwrap(),
+        "\
+# tally the first column
+{
+    if ($1 > 0) {
+        count++ # positive
+    } else if ($1 < 0) {
+        neg++
+    } else {
+        zero++
+    }
+
+    # totals
+    sum += $1
+}
+
+END {
+    for (i = 0; i < 3; i++) ;
+    printf \"%d %d\\n\", count, sum
+}
+"
+    );
+}
+
+#[test]
+fn keeps_comments_around_else_with_the_code_they_follow() {
+    let source = "\
+{
+    if ($1 > 0) {
+        print \"pos\" # body comment
+    }
+    # before else
+    else {
+        print \"neg\"
+    }
+    if (a) {
+        b = 1
+    } # after then
+    else if (c) {
+        d = 1 # last in else if
+    } else
+        e = 1 # last in else
+}
+";
+
+    let formatted = format_program(source).unwrap();
+
+    assert_eq!(syntax_tree(&formatted), syntax_tree(source));
+    assert_eq!(
+        formatted,
+        "\
+{
+    if ($1 > 0) {
+        print \"pos\" # body comment
+        # before else
+    } else {
+        print \"neg\"
+    }
+    if (a) {
+        b = 1
+    } else if (c) { # after then
+        d = 1 # last in else if
+    } else {
+        e = 1 # last in else
+    }
+}
+"
+    );
+    assert_eq!(format_program(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn keeps_parentheses_the_parser_needs() {
+    let source = r#"{ print ($1 > 2), (a, b) in c; x = (1 - 2) - (3 - 4); print -(-y), a (-b) }"#;
+
+    let formatted = format_program(source).unwrap();
+
+    assert_eq!(syntax_tree(&formatted), syntax_tree(source));
+    assert_eq!(
+        formatted,
+        "\
+{
+    print ($1 > 2), (a, b) in c
+    x = 1 - 2 - (3 - 4)
+    print - -y, a (-b)
+}
+"
+    );
+}
+
+#[test]
+fn writes_unary_plus_back() {
+    let source = r#"{ x = +$1; print +(+y), + +z, -+w }"#;
+
+    let formatted = format_program(source).unwrap();
+
+    assert_eq!(syntax_tree(&formatted), syntax_tree(source));
+    assert_eq!(
+        formatted,
+        "\
+{
+    x = +$1
+    print + +y, + +z, -+w
+}
+"
+    );
+}
@@ -0,0 +1,362 @@
+//! Where the functions and variables of a document are declared and used.
+
+use std::collections::HashMap;
+
+use rawk_core::{
+    Lexer, Parser,
+    token::{Token, TokenKind},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SymbolKind {
+    Function,
+    Global,
+    /// A parameter of the function whose definition starts at this offset.
+    Local(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Occurrence<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) start: usize,
+    pub(crate) kind: SymbolKind,
+    /// A function name in its definition or a parameter in a parameter list.
+    pub(crate) is_declaration: bool,
+    /// A variable or array element on the left of an assignment, or the
+    /// variable of a `for (k in a)` loop.
+    pub(crate) is_assignment: bool,
+}
+
+impl Occurrence<'_> {
+    pub(crate) fn end(&self) -> usize {
+        self.start + self.name.len()
+    }
+}
+
+pub(crate) struct Analysis<'a> {
+    tokens: Vec<Token<'a>>,
+    occurrences: Vec<Occurrence<'a>>,
+    parameters: HashMap<&'a str, Vec<&'a str>>,
+}
+
+impl<'a> Analysis<'a> {
+    pub(crate) fn new(source: &'a str) -> Self {
+        let tokens = tokens(source);
+        let mut analysis = Analysis {
+            tokens,
+            occurrences: Vec::new(),
+            parameters: HashMap::new(),
+        };
+        analysis.collect_occurrences();
+        analysis
+    }
+
+    /// The identifier, keyword or built-in function name at `offset`,
+    /// including the position just after it.
+    pub(crate) fn word_at(&self, offset: usize) -> Option<&Token<'a>> {
+        self.tokens.iter().find(|token| {
+            token
+                .literal
+                .starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_')
+                && token.kind != TokenKind::String
+                && token.kind != TokenKind::Regex
+                && (token.span.start..=token.span.start + token.literal.len()).contains(&offset)
+        })
+    }
+
+    pub(crate) fn occurrence_at(&self, offset: usize) -> Option<&Occurrence<'a>> {
+        self.occurrences
+            .iter()
+            .find(|occurrence| (occurrence.start..=occurrence.end()).contains(&offset))
+    }
+
+    /// Where the symbol of `occurrence` is defined: the function definition,
+    /// the parameter, or the first assignment of a global variable.
+    pub(crate) fn definition(&self, occurrence: &Occurrence<'a>) -> Option<&Occurrence<'a>> {
+        let mut same = self.same_symbol(occurrence);
+        match occurrence.kind {
+            SymbolKind::Function | SymbolKind::Local(_) => same.find(|other| other.is_declaration),
+            SymbolKind::Global => {
+                let mut first = None;
+                for other in same {
+                    if other.is_assignment {
+                        return Some(other);
+                    }
+                    first.get_or_insert(other);
+                }
+                first
+            }
+        }
+    }
+
+    pub(crate) fn references(
+        &self,
+        occurrence: &Occurrence<'a>,
+        include_declaration: bool,
+    ) -> Vec<&Occurrence<'a>> {
+        self.same_symbol(occurrence)
+            .filter(|other| include_declaration || !other.is_declaration)
+            .collect()
+    }
+
+    fn same_symbol(&self, occurrence: &Occurrence<'a>) -> impl Iterator<Item = &Occurrence<'a>> {
+        let (name, kind) = (occurrence.name, occurrence.kind);
+        self.occurrences
+            .iter()
+            .filter(move |other| other.name == name && other.kind == kind)
+    }
+
+    /// The parameters of the user-defined function `name`.
+    pub(crate) fn parameters(&self, name: &str) -> Option<&[&'a str]> {
+        self.parameters.get(name).map(Vec::as_slice)
+    }
+
+    fn collect_occurrences(&mut self) {
+        let tokens = &self.tokens;
+        // The start of the function being read and its parameters.
+        let mut function: Option<(usize, Vec<&'a str>)> = None;
+        let mut depth = 0usize;
+        let mut index = 0;
+
+        while index < tokens.len() {
+            let token = &tokens[index];
+            match token.kind {
+                TokenKind::Function => {
+                    let Some(name) = tokens
+                        .get(index + 1)
+                        .filter(|name| name.kind == TokenKind::Identifier)
+                    else {
+                        index += 1;
+                        continue;
+                    };
+                    self.occurrences.push(Occurrence {
+                        name: name.literal,
+                        start: name.span.start,
+                        kind: SymbolKind::Function,
+                        is_declaration: true,
+                        is_assignment: false,
+                    });
+
+                    let mut parameters = Vec::new();
+                    index += 2;
+                    while let Some(parameter) = tokens.get(index) {
+                        match parameter.kind {
+                            TokenKind::Identifier => {
+                                parameters.push(parameter.literal);
+                                self.occurrences.push(Occurrence {
+                                    name: parameter.literal,
+                                    start: parameter.span.start,
+                                    kind: SymbolKind::Local(token.span.start),
+                                    is_declaration: true,
+                                    is_assignment: false,
+                                });
+                            }
+                            TokenKind::LeftParen | TokenKind::Comma | TokenKind::NewLine => {}
+                            _ => break,
+                        }
+                        index += 1;
+                    }
+                    self.parameters.insert(name.literal, parameters.clone());
+                    function = Some((token.span.start, parameters));
+                    depth = 0;
+                    continue;
+                }
+                TokenKind::LeftCurlyBrace => depth += 1,
+                TokenKind::RightCurlyBrace => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        function = None;
+                    }
+                }
+                TokenKind::Identifier => {
+                    let parameter_of = function
+                        .as_ref()
+                        .filter(|(_, parameters)| parameters.contains(&token.literal))
+                        .map(|(start, _)| *start);
+                    let kind = if let Some(start) = parameter_of {
+                        SymbolKind::Local(start)
+                    } else if tokens.get(index + 1).is_some_and(|next| {
+                        next.kind == TokenKind::LeftParen
+                            && next.span.start == token.span.start + token.literal.len()
+                    }) {
+                        SymbolKind::Function
+                    } else {
+                        SymbolKind::Global
+                    };
+                    self.occurrences.push(Occurrence {
+                        name: token.literal,
+                        start: token.span.start,
+                        kind,
+                        is_declaration: false,
+                        is_assignment: kind != SymbolKind::Function && is_assigned(tokens, index),
+                    });
+                }
+                _ => {}
+            }
+            index += 1;
+        }
+    }
+}
+
+/// Whether the identifier at `index` is assigned: followed by an assignment
+/// operator, directly or after a subscript, or the variable of a `for (k in
+/// a)` loop.
+fn is_assigned(tokens: &[Token<'_>], index: usize) -> bool {
+    let mut next = index + 1;
+    if tokens
+        .get(next)
+        .is_some_and(|token| token.kind == TokenKind::LeftSquareBracket)
+    {
+        let mut depth = 0;
+        while let Some(token) = tokens.get(next) {
+            match token.kind {
+                TokenKind::LeftSquareBracket => depth += 1,
+                TokenKind::RightSquareBracket => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                TokenKind::Eof => return false,
+                _ => {}
+            }
+            next += 1;
+        }
+        next += 1;
+    }
+
+    let in_for_header = index >= 2
+        && tokens[index - 1].kind == TokenKind::LeftParen
+        && tokens[index - 2].kind == TokenKind::For
+        && tokens
+            .get(index + 1)
+            .is_some_and(|token| token.kind == TokenKind::In);
+
+    in_for_header
+        || tokens.get(next).is_some_and(|token| {
+            matches!(
+                token.kind,
+                TokenKind::Assign
+                    | TokenKind::AddAssign
+                    | TokenKind::SubtractAssign
+                    | TokenKind::MultiplyAssign
+                    | TokenKind::DivideAssign
+                    | TokenKind::ModuloAssign
+                    | TokenKind::PowerAssign
+            )
+        })
+}
+
+/// The tokens of `source`, ending with `Eof`.
+///
+/// The parser decides where a `/` starts a regular expression. While the
+/// document does not parse, the tokens after the error are read with the
+/// usual heuristic instead: a `/` starts a regular expression after an
+/// operator or opening bracket.
+fn tokens(source: &str) -> Vec<Token<'_>> {
+    let mut parser = Parser::new(Lexer::new(source));
+    if parser.try_parse_program().is_ok() {
+        let mut tokens = parser.tokens().to_vec();
+        if let Some(eof) = tokens.iter().position(|token| token.kind == TokenKind::Eof) {
+            tokens.truncate(eof + 1);
+        }
+        return tokens;
+    }
+
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    let mut regex_allowed = true;
+    loop {
+        let token = if regex_allowed {
+            lexer.next_token_regex_aware()
+        } else {
+            lexer.next_token()
+        };
+        regex_allowed = !matches!(
+            token.kind,
+            TokenKind::Identifier
+                | TokenKind::Number
+                | TokenKind::String
+                | TokenKind::Regex
+                | TokenKind::RightParen
+                | TokenKind::RightSquareBracket
+                | TokenKind::DollarSign
+                | TokenKind::Increment
+                | TokenKind::Decrement
+        );
+        let eof = token.kind == TokenKind::Eof;
+        tokens.push(token);
+        if eof {
+            return tokens;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names<'a>(occurrences: &[&Occurrence<'a>]) -> Vec<(&'a str, usize)> {
+        occurrences
+            .iter()
+            .map(|occurrence| (occurrence.name, occurrence.start))
+            .collect()
+    }
+
+    #[test]
+    fn parameters_shadow_globals_inside_their_function() {
+        let source = "function f(n) { return n + 1 }\n{ n = f(n) }";
+        let analysis = Analysis::new(source);
+
+        let parameter = analysis.occurrence_at(23).unwrap();
+        assert_eq!(parameter.kind, SymbolKind::Local(0));
+        assert_eq!(
+            names(&analysis.references(parameter, true)),
+            [("n", 11), ("n", 23)]
+        );
+
+        let global = analysis.occurrence_at(33).unwrap();
+        assert_eq!(global.kind, SymbolKind::Global);
+        assert_eq!(
+            names(&analysis.references(global, true)),
+            [("n", 33), ("n", 39)]
+        );
+    }
+
+    #[test]
+    fn definition_of_a_global_is_its_first_assignment() {
+        let source = "END { print total }\n{ totals[$1] += $2; total += $2 }";
+        let analysis = Analysis::new(source);
+
+        let read = analysis.occurrence_at(13).unwrap();
+
+        assert_eq!(analysis.definition(read).map(|d| d.start), Some(40));
+    }
+
+    #[test]
+    fn definition_of_a_call_is_the_function_name() {
+        let source = "BEGIN { print max(1, 2) }\nfunction max(a, b) { return a > b ? a : b }";
+        let analysis = Analysis::new(source);
+
+        let call = analysis.occurrence_at(14).unwrap();
+
+        assert_eq!(call.kind, SymbolKind::Function);
+        assert_eq!(analysis.definition(call).map(|d| d.start), Some(35));
+        assert_eq!(analysis.parameters("max"), Some(&["a", "b"][..]));
+    }
+
+    #[test]
+    fn unparsable_documents_are_still_analyzed() {
+        let source = "{ count[$1]++ }\nEND { for (k in count) print k, count[k]\n";
+        let analysis = Analysis::new(source);
+
+        let count = analysis.occurrence_at(2).unwrap();
+
+        assert_eq!(analysis.references(count, true).len(), 3);
+        assert_eq!(
+            analysis.definition(count).map(|d| d.start),
+            Some(2),
+            "no assignment, so the first use"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Hover documentation for built-in functions and special variables.
+
+/// Signatures and descriptions of the built-in functions.
+const FUNCTIONS: &[(&str, &str, &str)] = &[
+    ("atan2", "atan2(y, x)", "Arctangent of y/x in radians."),
+    (
+        "close",
+        "close(expr)",
+        "Close the file or pipe named by expr.",
+    ),
+    ("cos", "cos(x)", "Cosine of x, in radians."),
+    (
+        "csvquote",
+        "csvquote(s)",
+        "s quoted as a CSV field if it contains a comma, quote or newline.",
+    ),
+    ("exp", "exp(x)", "Exponential function of x."),
+    ("fflush", "fflush([expr])", "Flush buffered output."),
+    (
+        "getline",
+        "getline",
+        "Read the next input record into $0, updating NF, NR and FNR.",
+    ),
+    (
+        "gsub",
+        "gsub(ere, repl[, in])",
+        "Replace every match of ere in `in` (default $0) with repl; returns the number of replacements.",
+    ),
+    (
+        "index",
+        "index(s, t)",
+        "Position of the first occurrence of t in s, counting from 1, or 0.",
+    ),
+    ("int", "int(x)", "x truncated toward zero."),
+    (
+        "length",
+        "length[([s])]",
+        "Length of s in characters (default $0), or the number of elements of an array.",
+    ),
+    ("log", "log(x)", "Natural logarithm of x."),
+    (
+        "match",
+        "match(s, ere)",
+        "Position of the first match of ere in s, or 0; sets RSTART and RLENGTH.",
+    ),
+    (
+        "patsplit",
+        "patsplit(s, a[, fpat[, seps]])",
+        "Split s into a at the matches of fpat (default FPAT); returns the number of fields.",
+    ),
+    ("rand", "rand()", "Random number in [0, 1)."),
+    ("sin", "sin(x)", "Sine of x, in radians."),
+    (
+        "split",
+        "split(s, a[, fs])",
+        "Split s into array a on fs (default FS); returns the number of elements.",
+    ),
+    (
+        "sprintf",
+        "sprintf(fmt, expr, ...)",
+        "String formatted like printf.",
+    ),
+    ("sqrt", "sqrt(x)", "Square root of x."),
+    (
+        "srand",
+        "srand([expr])",
+        "Seed rand with expr (default the time of day); returns the previous seed.",
+    ),
+    (
+        "sub",
+        "sub(ere, repl[, in])",
+        "Replace the first match of ere in `in` (default $0) with repl; returns 1 or 0.",
+    ),
+    (
+        "substr",
+        "substr(s, m[, n])",
+        "At most n characters of s starting at position m.",
+    ),
+    (
+        "system",
+        "system(cmd)",
+        "Run cmd with the shell; returns its exit status.",
+    ),
+    (
+        "tolower",
+        "tolower(s)",
+        "s with uppercase letters changed to lowercase.",
+    ),
+    (
+        "toupper",
+        "toupper(s)",
+        "s with lowercase letters changed to uppercase.",
+    ),
+];
+
+/// Descriptions of the special variables.
+const VARIABLES: &[(&str, &str)] = &[
+    ("ARGC", "Number of elements in ARGV."),
+    ("ARGV", "Command-line arguments, indexed from 0."),
+    (
+        "CONVFMT",
+        "Format for converting numbers to strings (default \"%.6g\").",
+    ),
+    ("ENVIRON", "Environment variables, indexed by name."),
+    (
+        "FIELDWIDTHS",
+        "Space-separated column widths; when set, records are split into fixed-width fields.",
+    ),
+    ("FILENAME", "Name of the current input file."),
+    ("FNR", "Record number in the current file."),
+    (
+        "FPAT",
+        "Regular expression describing the contents of a field; when set, fields are its matches.",
+    ),
+    ("FS", "Input field separator (default a space)."),
+    ("NF", "Number of fields in the current record."),
+    ("NR", "Number of records read so far."),
+    (
+        "OFMT",
+        "Format for printing numbers with print (default \"%.6g\").",
+    ),
+    ("OFS", "Output field separator (default a space)."),
+    ("ORS", "Output record separator (default a newline)."),
+    ("RLENGTH", "Length of the string matched by match, or -1."),
+    ("RS", "Input record separator (default a newline)."),
+    ("RSTART", "Start of the string matched by match, or 0."),
+    (
+        "SUBSEP",
+        "Separator joining the subscripts of multidimensional array indexes.",
+    ),
+];
+
+/// Markdown documentation for the built-in function or special variable
+/// `name`.
+pub(crate) fn lookup(name: &str) -> Option<String> {
+    if let Some((_, signature, description)) = FUNCTIONS.iter().find(|(n, _, _)| *n == name) {
+        return Some(format!("```awk\n{signature}\n```\n{description}"));
+    }
+    VARIABLES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(name, description)| format!("```awk\n{name}\n```\n{description}"))
+}
@@ -0,0 +1,34 @@
+//! A language server for AWK scripts, speaking the Language Server Protocol
+//! over a byte stream such as stdio.
+//!
+//! Documents are synchronized in full. The server publishes parse errors and
+//! lint warnings as diagnostics, and answers hover, go-to-definition,
+//! find-references and document symbol requests.
+
+use std::io::{self, BufRead, Write};
+
+mod analysis;
+mod docs;
+mod position;
+mod server;
+mod transport;
+
+/// Serve requests read from `reader`, writing responses and notifications to
+/// `writer`, until the client sends `exit` or closes the stream. A message
+/// whose body is not valid JSON gets a parse error response.
+pub fn serve(mut reader: impl BufRead, mut writer: impl Write) -> io::Result<()> {
+    let mut server = server::Server::default();
+    while let Some(message) = transport::read_message(&mut reader)? {
+        let replies = match message {
+            Ok(message) => server.handle(&message),
+            Err(err) => vec![server::parse_error_response(&err)],
+        };
+        for reply in replies {
+            transport::write_message(&mut writer, &reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+    Ok(())
+}
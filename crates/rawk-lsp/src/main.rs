@@ -0,0 +1,5 @@
+use std::io;
+
+fn main() -> io::Result<()> {
+    rawk_lsp::serve(io::stdin().lock(), io::stdout().lock())
+}
@@ -0,0 +1,80 @@
+//! Conversion between byte offsets and protocol positions, which count
+//! lines from zero and columns in UTF-16 code units.
+
+use serde_json::{Value, json};
+
+/// The protocol position of byte `offset` of `source`.
+pub(crate) fn position(source: &str, offset: usize) -> Value {
+    let offset = floor_char_boundary(source, offset);
+    let line_start = source[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let line = source[..line_start].matches('\n').count();
+    let character: usize = source[line_start..offset]
+        .chars()
+        .map(char::len_utf16)
+        .sum();
+    json!({ "line": line, "character": character })
+}
+
+pub(crate) fn range(source: &str, start: usize, end: usize) -> Value {
+    json!({ "start": position(source, start), "end": position(source, end) })
+}
+
+/// The byte offset of a protocol position, clamped to the end of its line.
+pub(crate) fn offset(source: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or_default() as usize;
+    let character = position["character"].as_u64().unwrap_or_default() as usize;
+
+    let Some(line_start) = line_start(source, line) else {
+        return source.len();
+    };
+    let mut units = 0;
+    for (index, ch) in source[line_start..].char_indices() {
+        if units >= character || ch == '\n' {
+            return line_start + index;
+        }
+        units += ch.len_utf16();
+    }
+    source.len()
+}
+
+fn line_start(source: &str, line: usize) -> Option<usize> {
+    if line == 0 {
+        return Some(0);
+    }
+    source
+        .match_indices('\n')
+        .nth(line - 1)
+        .map(|(index, _)| index + 1)
+}
+
+fn floor_char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_count_utf16_code_units() {
+        let source = "BEGIN {\n  s = \"😀\"; n = 1\n}";
+        let n = source.find("n =").unwrap();
+
+        let position = position(source, n);
+
+        assert_eq!(position, json!({ "line": 1, "character": 12 }));
+        assert_eq!(offset(source, &position), n);
+    }
+
+    #[test]
+    fn offset_clamps_to_end_of_line() {
+        let source = "a\nbc\n";
+
+        assert_eq!(offset(source, &json!({ "line": 1, "character": 9 })), 4);
+        assert_eq!(offset(source, &json!({ "line": 7, "character": 0 })), 5);
+    }
+}
@@ -0,0 +1,293 @@
+//! Request and notification handling for open documents.
+
+use std::collections::HashMap;
+
+use rawk_core::{OutlineKind, lint_program, outline};
+use serde_json::{Value, json};
+
+use crate::{
+    analysis::{Analysis, SymbolKind},
+    docs,
+    position::{offset, range},
+};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+const SEVERITY_ERROR: u8 = 1;
+const SEVERITY_WARNING: u8 = 2;
+
+const SYMBOL_FUNCTION: u8 = 12;
+const SYMBOL_EVENT: u8 = 24;
+
+#[derive(Default)]
+pub(crate) struct Server {
+    /// The text of each open document by URI.
+    documents: HashMap<String, String>,
+    /// Set once the client sent the `exit` notification.
+    pub(crate) exited: bool,
+}
+
+impl Server {
+    /// Handle one message from the client and return the messages to send
+    /// back: a response for a request, and any notifications.
+    pub(crate) fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        let Some(id) = message.get("id") else {
+            return self.notification(method, params);
+        };
+        // A message with an id and no method is a response to the server,
+        // which sends no requests.
+        if method.is_empty() {
+            return Vec::new();
+        }
+
+        let response = match method {
+            "initialize" => Ok(initialize_result()),
+            "shutdown" => Ok(Value::Null),
+            "textDocument/hover" => self.with_document(params, hover),
+            "textDocument/definition" => self.with_document(params, definition),
+            "textDocument/references" => self.with_document(params, references),
+            "textDocument/documentSymbol" => self.with_document(params, document_symbols),
+            _ => Err((METHOD_NOT_FOUND, format!("unsupported method {method}"))),
+        };
+
+        vec![match response {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        }]
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "exit" => {
+                self.exited = true;
+                Vec::new()
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_string(), text.to_string());
+                vec![publish_diagnostics(uri, text)]
+            }
+            "textDocument/didChange" => {
+                // Full synchronization: the last change holds the whole text.
+                let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                else {
+                    return Vec::new();
+                };
+                self.documents.insert(uri.to_string(), text.to_string());
+                vec![publish_diagnostics(uri, text)]
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                })]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn with_document(
+        &self,
+        params: &Value,
+        handler: fn(&str, &str, &Value) -> Value,
+    ) -> Result<Value, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match self.documents.get(uri) {
+            Some(source) => Ok(handler(uri, source, params)),
+            None => Err((INVALID_PARAMS, format!("document {uri} is not open"))),
+        }
+    }
+}
+
+/// The response to a message whose body is not valid JSON, which has no id
+/// to answer to.
+pub(crate) fn parse_error_response(err: &serde_json::Error) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": { "code": PARSE_ERROR, "message": format!("invalid JSON: {err}") },
+    })
+}
+
+fn initialize_result() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": 1,
+            "hoverProvider": true,
+            "definitionProvider": true,
+            "referencesProvider": true,
+            "documentSymbolProvider": true,
+        },
+        "serverInfo": {
+            "name": "rawk-lsp",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+    })
+}
+
+/// Report the parse error of `source`, or the lint warnings when it parses.
+fn publish_diagnostics(uri: &str, source: &str) -> Value {
+    let diagnostics: Vec<Value> = match lint_program(source) {
+        Ok(warnings) => warnings
+            .iter()
+            .map(|warning| {
+                diagnostic(
+                    source,
+                    warning.start,
+                    warning.end,
+                    SEVERITY_WARNING,
+                    &warning.message(),
+                )
+            })
+            .collect(),
+        Err(err) => {
+            let start = err.token.span.start.min(source.len());
+            let end = (start + err.token.literal.len()).min(source.len());
+            vec![diagnostic(
+                source,
+                start,
+                end,
+                SEVERITY_ERROR,
+                &err.to_string(),
+            )]
+        }
+    };
+
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn diagnostic(source: &str, start: usize, end: usize, severity: u8, message: &str) -> Value {
+    json!({
+        "range": range(source, start, end),
+        "severity": severity,
+        "source": "rawk",
+        "message": message,
+    })
+}
+
+fn hover(_uri: &str, source: &str, params: &Value) -> Value {
+    let analysis = Analysis::new(source);
+    let offset = offset(source, &params["position"]);
+    let Some(token) = analysis.word_at(offset) else {
+        return Value::Null;
+    };
+
+    let contents = match analysis.occurrence_at(offset) {
+        Some(occurrence) if occurrence.kind == SymbolKind::Function => analysis
+            .parameters(occurrence.name)
+            .map(|parameters| {
+                format!(
+                    "```awk\nfunction {}({})\n```",
+                    occurrence.name,
+                    parameters.join(", ")
+                )
+            })
+            .or_else(|| docs::lookup(occurrence.name)),
+        Some(occurrence) if matches!(occurrence.kind, SymbolKind::Local(_)) => {
+            Some(format!("```awk\n(parameter) {}\n```", occurrence.name))
+        }
+        _ => docs::lookup(token.literal),
+    };
+
+    match contents {
+        Some(contents) => json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": range(source, token.span.start, token.span.start + token.literal.len()),
+        }),
+        None => Value::Null,
+    }
+}
+
+fn definition(uri: &str, source: &str, params: &Value) -> Value {
+    let analysis = Analysis::new(source);
+    let offset = offset(source, &params["position"]);
+    analysis
+        .occurrence_at(offset)
+        .and_then(|occurrence| analysis.definition(occurrence))
+        .map_or(Value::Null, |definition| {
+            json!({
+                "uri": uri,
+                "range": range(source, definition.start, definition.end()),
+            })
+        })
+}
+
+fn references(uri: &str, source: &str, params: &Value) -> Value {
+    let analysis = Analysis::new(source);
+    let offset = offset(source, &params["position"]);
+    let include_declaration = params["context"]["includeDeclaration"]
+        .as_bool()
+        .unwrap_or(true);
+    let Some(occurrence) = analysis.occurrence_at(offset) else {
+        return Value::Null;
+    };
+
+    analysis
+        .references(occurrence, include_declaration)
+        .iter()
+        .map(|reference| {
+            json!({
+                "uri": uri,
+                "range": range(source, reference.start, reference.end()),
+            })
+        })
+        .collect()
+}
+
+fn document_symbols(_uri: &str, source: &str, _params: &Value) -> Value {
+    let Ok(items) = outline(source) else {
+        return json!([]);
+    };
+
+    items
+        .iter()
+        .map(|item| {
+            let (name, kind, detail) = match &item.kind {
+                OutlineKind::Begin => ("BEGIN".to_string(), SYMBOL_EVENT, None),
+                OutlineKind::BeginFile => ("BEGINFILE".to_string(), SYMBOL_EVENT, None),
+                OutlineKind::End => ("END".to_string(), SYMBOL_EVENT, None),
+                OutlineKind::EndFile => ("ENDFILE".to_string(), SYMBOL_EVENT, None),
+                OutlineKind::Pattern(pattern) => (pattern.clone(), SYMBOL_EVENT, None),
+                OutlineKind::Action => ("{ ... }".to_string(), SYMBOL_EVENT, None),
+                OutlineKind::Function { name, parameters } => (
+                    name.to_string(),
+                    SYMBOL_FUNCTION,
+                    Some(format!("function {name}({})", parameters.join(", "))),
+                ),
+            };
+            let selection_end = match &item.kind {
+                OutlineKind::Function { name, .. } => item.name_start + name.len(),
+                _ => item.start,
+            };
+            let mut symbol = json!({
+                "name": name,
+                "kind": kind,
+                "range": range(source, item.start, item.end),
+                "selectionRange": range(source, item.name_start, selection_end),
+            });
+            if let Some(detail) = detail {
+                symbol["detail"] = json!(detail);
+            }
+            symbol
+        })
+        .collect()
+}
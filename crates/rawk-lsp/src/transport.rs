@@ -0,0 +1,92 @@
+//! Language Server Protocol message framing: a `Content-Length` header, a
+//! blank line and a JSON body.
+
+use std::io::{self, BufRead, Write};
+
+use serde_json::Value;
+
+/// Read the next message, or `None` when the client closed the stream. A
+/// body that is not valid JSON is returned as its parse error, with the
+/// stream left at the start of the next message.
+pub(crate) fn read_message(
+    reader: &mut impl BufRead,
+) -> io::Result<Option<serde_json::Result<Value>>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            content_length = Some(value.trim().parse::<usize>().map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad Content-Length: {err}"),
+                )
+            })?);
+        }
+    }
+
+    let Some(content_length) = content_length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without Content-Length header",
+        ));
+    };
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)))
+}
+
+pub(crate) fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn written_message_reads_back() {
+        let message = json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} });
+        let mut buffer = Vec::new();
+
+        write_message(&mut buffer, &message).unwrap();
+        write_message(&mut buffer, &message).unwrap();
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(
+            read_message(&mut reader).unwrap().unwrap().unwrap(),
+            message
+        );
+        assert_eq!(
+            read_message(&mut reader).unwrap().unwrap().unwrap(),
+            message
+        );
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn malformed_body_is_returned_as_an_error_before_the_next_message() {
+        let message = json!({ "jsonrpc": "2.0", "method": "exit" });
+        let mut buffer = b"Content-Length: 9\r\n\r\n{invalid}".to_vec();
+        write_message(&mut buffer, &message).unwrap();
+
+        let mut reader = buffer.as_slice();
+        assert!(read_message(&mut reader).unwrap().unwrap().is_err());
+        assert_eq!(
+            read_message(&mut reader).unwrap().unwrap().unwrap(),
+            message
+        );
+    }
+}
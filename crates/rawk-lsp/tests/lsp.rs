@@ -0,0 +1,293 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use serde_json::{Value, json};
+
+const URI: &str = "file:///tmp/report.awk";
+
+const SOURCE: &str = "\
+function max(a, b) {
+    return a > b ? a : b
+}
+
+BEGIN { FS = \",\" }
+
+$3 > 0 {
+    total += $3
+    biggest = max(biggest, $3)
+}
+
+END { print total, biggest, NR }
+";
+
+/// A minimal language client talking to the server binary over stdio.
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_rawk-lsp"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to start rawk-lsp");
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut client = Client {
+            child,
+            stdin,
+            stdout,
+            next_id: 1,
+        };
+
+        let result = client.request("initialize", json!({ "capabilities": {} }));
+        assert_eq!(result["serverInfo"]["name"], "rawk-lsp");
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            self.stdout.read_line(&mut header).unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(length) = header.strip_prefix("Content-Length: ") {
+                content_length = length.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        self.stdout.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Send a request and return the `result` of its response.
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        let response = self.receive();
+        assert_eq!(response["id"], id, "unexpected message {response}");
+        response["result"].clone()
+    }
+
+    /// Open a document and return the diagnostics published for it.
+    fn open(&mut self, text: &str) -> Value {
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": { "uri": URI, "languageId": "awk", "version": 1, "text": text },
+            }),
+        );
+        self.diagnostics()
+    }
+
+    fn change(&mut self, text: &str) -> Value {
+        self.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": text }],
+            }),
+        );
+        self.diagnostics()
+    }
+
+    fn diagnostics(&mut self) -> Value {
+        let notification = self.receive();
+        assert_eq!(notification["method"], "textDocument/publishDiagnostics");
+        assert_eq!(notification["params"]["uri"], URI);
+        notification["params"]["diagnostics"].clone()
+    }
+
+    fn at(&mut self, method: &str, line: u32, character: u32) -> Value {
+        self.request(
+            method,
+            json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": true },
+            }),
+        )
+    }
+
+    fn shutdown(mut self) {
+        assert_eq!(self.request("shutdown", Value::Null), Value::Null);
+        self.notify("exit", Value::Null);
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+fn range(start_line: u32, start: u32, end_line: u32, end: u32) -> Value {
+    json!({
+        "start": { "line": start_line, "character": start },
+        "end": { "line": end_line, "character": end },
+    })
+}
+
+#[test]
+fn parse_errors_are_published_as_you_type() {
+    let mut client = Client::start();
+
+    assert_eq!(client.open(SOURCE), json!([]));
+
+    let diagnostics = client.change("BEGIN { print (1 }\n");
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["range"], range(0, 17, 0, 18));
+
+    assert_eq!(client.change(SOURCE), json!([]));
+    client.shutdown();
+}
+
+#[test]
+fn lint_warnings_are_published() {
+    let mut client = Client::start();
+
+    let diagnostics = client.open("{ print totl }\n");
+
+    assert_eq!(
+        diagnostics,
+        json!([{
+            "range": range(0, 8, 0, 12),
+            "severity": 2,
+            "source": "rawk",
+            "message": "variable totl is used but never assigned",
+        }])
+    );
+
+    let diagnostics =
+        client.change("{ if (n = 1)\n    print f(1, 2) }\nfunction f(a) { return a }\n");
+    assert_eq!(diagnostics[0]["range"], range(0, 6, 0, 11));
+    assert_eq!(diagnostics[1]["range"], range(1, 10, 1, 17));
+    client.shutdown();
+}
+
+#[test]
+fn hover_documents_builtins_special_variables_and_functions() {
+    let mut client = Client::start();
+    client.open(SOURCE);
+
+    let hover = client.at("textDocument/hover", 11, 30);
+    assert_eq!(hover["range"], range(11, 28, 11, 30));
+    assert!(
+        hover["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .contains("Number of records read so far."),
+        "{hover}"
+    );
+
+    let hover = client.at("textDocument/hover", 8, 16);
+    assert_eq!(
+        hover["contents"]["value"],
+        "```awk\nfunction max(a, b)\n```"
+    );
+
+    assert_eq!(client.at("textDocument/hover", 7, 6), Value::Null);
+    client.shutdown();
+}
+
+#[test]
+fn definition_and_references_of_functions_and_globals() {
+    let mut client = Client::start();
+    client.open(SOURCE);
+
+    let definition = client.at("textDocument/definition", 8, 15);
+    assert_eq!(
+        definition,
+        json!({ "uri": URI, "range": range(0, 9, 0, 12) })
+    );
+
+    let definition = client.at("textDocument/definition", 11, 14);
+    assert_eq!(definition["range"], range(7, 4, 7, 9));
+
+    let references = client.at("textDocument/references", 7, 4);
+    let ranges: Vec<Value> = references
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|location| location["range"].clone())
+        .collect();
+    assert_eq!(ranges, [range(7, 4, 7, 9), range(11, 12, 11, 17)]);
+
+    let references = client.at("textDocument/references", 1, 11);
+    assert_eq!(references.as_array().unwrap().len(), 3);
+    client.shutdown();
+}
+
+#[test]
+fn document_symbols_list_rules_and_functions() {
+    let mut client = Client::start();
+    client.open(SOURCE);
+
+    let symbols = client.request(
+        "textDocument/documentSymbol",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+
+    let summary: Vec<(Value, Value)> = symbols
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|symbol| (symbol["name"].clone(), symbol["kind"].clone()))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (json!("max"), json!(12)),
+            (json!("BEGIN"), json!(24)),
+            (json!("$3 > 0"), json!(24)),
+            (json!("END"), json!(24)),
+        ]
+    );
+    assert_eq!(symbols[0]["range"], range(0, 0, 2, 1));
+    assert_eq!(symbols[0]["selectionRange"], range(0, 9, 0, 12));
+    assert_eq!(symbols[0]["detail"], "function max(a, b)");
+    client.shutdown();
+}
+
+#[test]
+fn unknown_requests_get_an_error_response() {
+    let mut client = Client::start();
+
+    client.send(json!({ "jsonrpc": "2.0", "id": 99, "method": "textDocument/rename" }));
+    let response = client.receive();
+
+    assert_eq!(response["id"], 99);
+    assert_eq!(response["error"]["code"], -32601);
+    client.shutdown();
+}
+
+#[test]
+fn malformed_messages_get_a_parse_error_and_the_server_keeps_serving() {
+    let mut client = Client::start();
+
+    write!(client.stdin, "Content-Length: 9\r\n\r\n{{invalid}}").unwrap();
+    client.stdin.flush().unwrap();
+    let response = client.receive();
+
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(response["error"]["code"], -32700);
+    assert_eq!(client.open(SOURCE), json!([]));
+    client.shutdown();
+}